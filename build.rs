@@ -1,3 +1,22 @@
+use std::process::Command;
+
 fn main() {
-    println!("cargo::rustc-link-lib=jpeg")
+    // Windows builds link OpenCV's self-contained opencv_world library,
+    // which has no separate libjpeg.
+    if std::env::var("CARGO_CFG_TARGET_OS").as_deref() != Ok("windows") {
+        println!("cargo::rustc-link-lib=jpeg");
+    }
+
+    // Identifies the build in bug reports, `unknown` outside a git checkout.
+    let git_hash = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo::rustc-env=GIT_HASH={git_hash}");
+    println!("cargo::rerun-if-changed=.git/HEAD");
+    println!("cargo::rerun-if-changed=.git/refs");
 }
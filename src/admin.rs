@@ -0,0 +1,1561 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    net::ToSocketAddrs,
+};
+
+use chrono::{DateTime, Local, NaiveTime, TimeDelta, Weekday};
+use dioxus::{
+    desktop::{tao::window::Fullscreen, window},
+    prelude::*,
+};
+
+use crate::{
+    AttendanceContext, MIN_SCAN_SPACING_SECS, QrType, SCAN_MODE, VIDEO_ADDR, VideoBind,
+    VideoChannels,
+    api::{ScanAction, ScanSubmission},
+    backup::{self, upload_backup},
+    badges,
+    config::{self, Config, DataPath},
+    db_worker::DbHandle,
+    feedback,
+    lock::{KioskLock, PinSettings},
+    platform::FULLSCREEN_KEY,
+    schedule, signout, snapshot,
+    sqlite::{
+        BACKUP_DIR_KEY, BackingDatabase, Contact, DbError, IntegrityIssue, LAST_BACKUP_ERROR_KEY,
+        Member, REGISTERED_GUEST_PREFIX, RosterError, ScanEditError, date_range, local_datetime,
+    },
+    version::{GIT_HASH, VERSION},
+    video::{SCAN_MODE_KEY, ScanMode, VIDEO_BIND_KEY},
+};
+
+/// Source recorded for scans entered by hand.
+const MANUAL_SOURCE: &str = "manual";
+
+/// `read`'s result, read again whenever `revision` changes. `None` until
+/// read, or when it failed, which the error banner shows.
+fn use_db_read<T: Clone + Send + 'static>(
+    backing_db: &DbHandle,
+    revision: Signal<u64>,
+    read: impl Fn(&mut BackingDatabase) -> Result<T, DbError> + Clone + Send + 'static,
+) -> Option<T> {
+    let AttendanceContext { db_error, .. } = use_context();
+    let backing_db = backing_db.clone();
+    let loaded = use_resource(move || {
+        let backing_db = backing_db.clone();
+        let read = read.clone();
+        revision.read();
+        async move { db_error.check(backing_db.try_run(read).await) }
+    });
+    loaded().flatten()
+}
+
+/// Runs `write` in the background, then bumps `revision` to read again.
+/// Failures show in the error banner.
+fn spawn_write<T: Send + 'static>(
+    backing_db: &DbHandle,
+    mut revision: Signal<u64>,
+    write: impl FnOnce(&mut BackingDatabase) -> Result<T, DbError> + Send + 'static,
+) {
+    let AttendanceContext { db_error, .. } = consume_context();
+    let backing_db = backing_db.clone();
+    spawn(async move {
+        db_error.check(backing_db.try_run(write).await);
+        *revision.write() += 1;
+    });
+}
+
+/// Toggles presence for someone without their badge, through the scan loop
+/// so scan spacing and rules still apply.
+#[component]
+pub fn ManualScan() -> Element {
+    let AttendanceContext {
+        backing_db,
+        scan_submissions_tx,
+        mut process_change,
+        db_error,
+        ..
+    } = use_context();
+    let lock: KioskLock = use_context();
+
+    let mut name = use_signal(String::new);
+    // (label, payload), registered guests are listed by name to sign out.
+    let mut roster = use_resource({
+        let backing_db = backing_db.clone();
+        move || {
+            let backing_db = backing_db.clone();
+            async move {
+                let loaded = backing_db
+                    .try_run(|db| {
+                        let guests = db
+                            .get_present()?
+                            .into_iter()
+                            .filter(|(id, _)| id.starts_with(REGISTERED_GUEST_PREFIX))
+                            .map(|(id, _)| Ok((db.display_name(&id)?, id)))
+                            .collect::<Result<Vec<_>, DbError>>()?;
+                        Ok(db
+                            .get_mentors()?
+                            .into_iter()
+                            .chain(db.get_students()?)
+                            .map(|name| (name.clone(), name))
+                            .chain(guests)
+                            .collect::<Vec<_>>())
+                    })
+                    .await;
+                db_error.check(loaded)
+            }
+        }
+    });
+
+    // Presence is only toggled by hand with the PIN, see `KioskLock`.
+    let toggle = use_callback(move |()| {
+        let payload = name.read().trim().to_string();
+        if payload.is_empty() {
+            return;
+        }
+        let (reply, outcome) = async_channel::bounded(1);
+        let submission = ScanSubmission {
+            payload,
+            source: MANUAL_SOURCE.to_string(),
+            reply,
+        };
+        let scan_submissions_tx = scan_submissions_tx.clone();
+        let backing_db = backing_db.clone();
+        name.set(String::new());
+        spawn(async move {
+            let label = backing_db
+                .try_run({
+                    let payload = submission.payload.clone();
+                    move |db| db.display_name(&payload)
+                })
+                .await
+                .unwrap_or_else(|_| submission.payload.clone());
+            if scan_submissions_tx.send(submission).await.is_err() {
+                return;
+            }
+            match outcome.recv().await {
+                Ok(ScanAction::Rejected(reason)) => {
+                    process_change.set(format!("MANUAL REJECTED {label}: {reason}"));
+                }
+                Ok(action) => process_change.set(format!("MANUAL {action} {label}")),
+                Err(_) => (),
+            }
+        });
+    });
+
+    rsx! {
+        input {
+            list: "manual-scan-roster",
+            placeholder: "Name",
+            value: "{name}",
+            oninput: move |e| name.set(e.value()),
+            // Picks up guests registered since it was last read.
+            onfocus: move |_| roster.restart(),
+        }
+        datalist {
+            id: "manual-scan-roster",
+            for (label, payload) in roster().flatten().unwrap_or_default() {
+                option { key: "{payload}", value: "{payload}", "{label}" }
+            }
+        }
+        button {
+            onclick: move |_| lock.guard(toggle),
+            "Toggle Presence"
+        }
+    }
+}
+
+/// Source recorded for guests scanned in on registration.
+const REGISTER_SOURCE: &str = "register";
+
+/// Registers a guest without a badge and signs them in.
+#[component]
+pub fn RegisterGuest() -> Element {
+    let AttendanceContext {
+        backing_db,
+        scan_submissions_tx,
+        mut process_change,
+        ..
+    } = use_context();
+
+    let mut open = use_signal(|| false);
+    let mut name = use_signal(String::new);
+    let mut affiliation = use_signal(String::new);
+
+    rsx! {
+        button {
+            onclick: move |_| open.toggle(),
+            if open() { "Cancel" } else { "Register Guest" }
+        }
+        if open() {
+            div {
+                input {
+                    placeholder: "Guest name",
+                    value: "{name}",
+                    oninput: move |e| name.set(e.value()),
+                }
+                input {
+                    placeholder: "Affiliation (optional)",
+                    value: "{affiliation}",
+                    oninput: move |e| affiliation.set(e.value()),
+                }
+                button {
+                    onclick: move |_| {
+                        let guest = name.read().trim().to_string();
+                        if guest.is_empty() {
+                            return;
+                        }
+                        let affiliation = affiliation.take();
+
+                        let backing_db = backing_db.clone();
+                        let scan_submissions_tx = scan_submissions_tx.clone();
+                        name.set(String::new());
+                        open.set(false);
+
+                        spawn(async move {
+                            let registered = backing_db
+                                .try_run({
+                                    let guest = guest.clone();
+                                    move |db| {
+                                        let affiliation = Some(affiliation.trim()).filter(|a| !a.is_empty());
+                                        db.register_guest(&guest, affiliation)
+                                    }
+                                })
+                                .await;
+                            let id = match registered {
+                                Ok(id) => id,
+                                Err(e) => {
+                                    process_change.set(format!("GUEST NOT REGISTERED {guest}: {e}"));
+                                    return;
+                                }
+                            };
+                            let (reply, outcome) = async_channel::bounded(1);
+                            let submission = ScanSubmission {
+                                payload: id,
+                                source: REGISTER_SOURCE.to_string(),
+                                reply,
+                            };
+                            if scan_submissions_tx.send(submission).await.is_err() {
+                                return;
+                            }
+                            match outcome.recv().await {
+                                Ok(ScanAction::Rejected(reason)) => {
+                                    process_change.set(format!("GUEST REJECTED {guest}: {reason}"));
+                                }
+                                Ok(action) => process_change.set(format!("GUEST {action} {guest}")),
+                                Err(_) => (),
+                            }
+                        });
+                    },
+                    "Sign In"
+                }
+            }
+        }
+    }
+}
+
+/// Writes every scan to a dated CSV in the exports folder of the data
+/// directory.
+#[component]
+pub fn ExportButton() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        ..
+    } = use_context();
+
+    rsx! {
+        button {
+            onclick: move |_| {
+                let dir = config::data_path(DataPath::Exports);
+                let path = dir.join(format!("attendance-{}.csv", Local::now().format("%Y-%m-%d")));
+                let backing_db = backing_db.clone();
+                spawn(async move {
+                    let result = match fs::create_dir_all(&dir).and_then(|()| File::create(&path)) {
+                        Ok(file) => backing_db
+                            .run(|db| db.export_attendance_csv(None, BufWriter::new(file)))
+                            .await
+                            .map_err(|e| e.to_string())
+                            .and_then(|exported| exported.map_err(|e| e.to_string())),
+                        Err(e) => Err(e.to_string()),
+                    };
+
+                    match result {
+                        Ok(count) => process_change.set(format!("EXPORTED {count} SCANS TO {}", path.display())),
+                        Err(e) => process_change.set(format!("EXPORT FAILED {}: {e}", path.display())),
+                    }
+                });
+            },
+            "Export"
+        }
+    }
+}
+
+/// Writes this week's hours report as Markdown and CSV to the reports folder
+/// of the data directory.
+#[component]
+pub fn ReportButton() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        ..
+    } = use_context();
+
+    rsx! {
+        button {
+            onclick: move |_| {
+                let today = Local::now().date_naive();
+                let monday = today.week(Weekday::Mon).first_day();
+                let range = date_range(Some(monday), Some(today)).unwrap();
+                let backing_db = backing_db.clone();
+                spawn(async move {
+                    let report = match backing_db.try_run(move |db| db.report(range)).await {
+                        Ok(report) => report,
+                        Err(e) => {
+                            process_change.set(format!("REPORT FAILED: {e}"));
+                            return;
+                        }
+                    };
+
+                    let dir = config::data_path(DataPath::Reports);
+                    let stem = dir.join(format!("week-{}", monday.format("%Y-%m-%d")));
+                    let markdown = stem.with_extension("md");
+                    let result = fs::create_dir_all(&dir)
+                        .and_then(|()| fs::write(&markdown, report.to_markdown()))
+                        .and_then(|()| fs::write(stem.with_extension("csv"), report.to_csv()));
+
+                    match result {
+                        Ok(()) => process_change.set(format!("WROTE REPORT TO {}", markdown.display())),
+                        Err(e) => process_change.set(format!("REPORT FAILED {}: {e}", dir.display())),
+                    }
+                });
+            },
+            "Weekly Report"
+        }
+    }
+}
+
+/// Writes a PNG badge for everyone on the roster to the badges folder of the
+/// data directory.
+#[component]
+fn PrintBadgesButton() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        ..
+    } = use_context();
+
+    rsx! {
+        button {
+            onclick: move |_| {
+                let dir = config::data_path(DataPath::Badges);
+                let backing_db = backing_db.clone();
+                spawn(async move {
+                    let generated = backing_db
+                        .run({
+                            let dir = dir.clone();
+                            move |db| badges::generate_badges(db, &dir)
+                        })
+                        .await
+                        .map_err(|e| e.to_string())
+                        .and_then(|generated| generated.map_err(|e| e.to_string()));
+                    match generated {
+                        Ok(written) => {
+                            process_change.set(format!("PRINTED {} BADGES TO {}", written.len(), dir.display()))
+                        }
+                        Err(e) => process_change.set(format!("BADGES FAILED: {e}")),
+                    }
+                });
+            },
+            "Print Badges"
+        }
+    }
+}
+
+/// Backups older than this are highlighted.
+const STALE_BACKUP_AGE: TimeDelta = TimeDelta::weeks(1);
+
+#[component]
+pub fn AdminPanel() -> Element {
+    rsx! {
+        h3 { "Edit Scans" }
+        ScanEditor {}
+
+        hr {}
+        h3 { "Roster" }
+        RosterSettings {}
+        PrintBadgesButton {}
+
+        hr {}
+        h3 { "Database" }
+        DatabaseSettings {}
+        IntegritySettings {}
+
+        hr {}
+        h3 { "Scanning" }
+        ScanModeSettings {}
+        br {}
+        ScanSpacingSettings {}
+        br {}
+        FeedbackSettings {}
+
+        hr {}
+        h3 { "Occupancy" }
+        OccupancySettings {}
+
+        hr {}
+        h3 { "Auto Sign-out" }
+        SignOutSettings {}
+
+        hr {}
+        h3 { "Video Stream" }
+        VideoSettings {}
+
+        hr {}
+        h3 { "Snapshots" }
+        SnapshotSettings {}
+
+        hr {}
+        h3 { "Schedule" }
+        ScheduleSettings {}
+
+        hr {}
+        h3 { "Text Contacts" }
+        ContactSettings {}
+
+        hr {}
+        h3 { "Kiosk Lock" }
+        PinSettings {}
+        br {}
+        WindowSettings {}
+
+        hr {}
+        h3 { "About" }
+        p { "Version {VERSION} ({GIT_HASH})" }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RosterEdit {
+    AddMentor,
+    AddStudent,
+    PurgeMentor,
+    PurgeStudent,
+    Promote,
+    Deactivate,
+    Reactivate,
+}
+
+impl RosterEdit {
+    /// Shown in the process change banner.
+    fn label(self) -> &'static str {
+        match self {
+            Self::AddMentor => "ADDED MENTOR",
+            Self::AddStudent => "ADDED STUDENT",
+            Self::PurgeMentor => "PURGED MENTOR",
+            Self::PurgeStudent => "PURGED STUDENT",
+            Self::Promote => "PROMOTED",
+            Self::Deactivate => "DEACTIVATED",
+            Self::Reactivate => "REACTIVATED",
+        }
+    }
+}
+
+/// Adds, removes, promotes, and deactivates mentors and students, taking
+/// effect on the next scan. Inactive members are greyed out.
+#[component]
+fn RosterSettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        refresh_tx,
+        mut process_change,
+        db_error,
+        ..
+    } = use_context();
+    let backing_db_edit = backing_db.clone();
+    let backing_db_import = backing_db.clone();
+    let refresh_import_tx = refresh_tx.clone();
+
+    let mut name = use_signal(String::new);
+    // Roster for CSV rows without a type column.
+    let mut import_role = use_signal(|| QrType::Student);
+    // Bumped to re-read the rosters after an edit.
+    let mut revision = use_signal(|| 0_u64);
+
+    let (mentors, students): (Vec<_>, Vec<_>) =
+        use_db_read(&backing_db, revision, |db| db.get_all_members(true))
+            .unwrap_or_default()
+            .into_iter()
+            .partition(|member| member.role == QrType::Mentor);
+
+    let edit = use_callback(move |(edit, name): (RosterEdit, String)| {
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return;
+        }
+
+        let backing_db = backing_db_edit.clone();
+        let refresh_tx = refresh_tx.clone();
+        spawn(async move {
+            let changed = backing_db
+                .run({
+                    let name = name.clone();
+                    move |db| match edit {
+                        RosterEdit::AddMentor => db.add_mentor(&name),
+                        RosterEdit::AddStudent => db.add_student(&name),
+                        RosterEdit::PurgeMentor => db.remove_mentor(&name).map_err(RosterError::Db),
+                        RosterEdit::PurgeStudent => {
+                            db.remove_student(&name).map_err(RosterError::Db)
+                        }
+                        RosterEdit::Promote => {
+                            db.move_student_to_mentor(&name).map_err(RosterError::Db)
+                        }
+                        RosterEdit::Deactivate => {
+                            db.set_member_active(&name, false).map_err(RosterError::Db)
+                        }
+                        RosterEdit::Reactivate => {
+                            db.set_member_active(&name, true).map_err(RosterError::Db)
+                        }
+                    }
+                })
+                .await
+                .map_err(RosterError::Db)
+                .and_then(|changed| changed);
+
+            match changed {
+                Ok(true) => {
+                    process_change.set(format!("{} {name}", edit.label()));
+                    let _ = refresh_tx.try_send(());
+                    *revision.write() += 1;
+                }
+                Ok(false) => process_change.set(format!("ROSTER UNCHANGED {name}")),
+                Err(RosterError::Db(e)) => {
+                    process_change.set(format!("ROSTER UNCHANGED {name}"));
+                    db_error.check::<()>(Err(e));
+                }
+                Err(e) => process_change.set(format!("ROSTER UNCHANGED {e}")),
+            }
+        });
+    });
+
+    rsx! {
+        input {
+            placeholder: "Name",
+            value: "{name}",
+            oninput: move |e| name.set(e.value()),
+        }
+        button {
+            onclick: move |_| {
+                edit((RosterEdit::AddStudent, name.take()));
+            },
+            "Add Student"
+        }
+        button {
+            onclick: move |_| {
+                edit((RosterEdit::AddMentor, name.take()));
+            },
+            "Add Mentor"
+        }
+        br {}
+        label {
+            "Import CSV: "
+            input {
+                r#type: "file",
+                accept: ".csv",
+                onchange: move |e: FormEvent| {
+                    let files = e.files();
+                    let role = import_role();
+                    let backing_db = backing_db_import.clone();
+                    let refresh_tx = refresh_import_tx.clone();
+                    spawn(async move {
+                        for file in files {
+                            let path = file.path();
+                            let result = backing_db
+                                .run(move |db| db.import_roster_csv(&path, role))
+                                .await
+                                .map_err(|e| e.to_string())
+                                .and_then(|imported| imported.map_err(|e| e.to_string()));
+                            match result {
+                                Ok(report) => {
+                                    process_change.set(format!("IMPORTED {}: {report}", file.name()));
+                                    let _ = refresh_tx.try_send(());
+                                }
+                                Err(e) => process_change.set(format!("IMPORT FAILED {}: {e}", file.name())),
+                            }
+                        }
+                        *revision.write() += 1;
+                    });
+                },
+            }
+        }
+        select {
+            onchange: move |e| {
+                import_role.set(if e.value() == "Mentors" { QrType::Mentor } else { QrType::Student })
+            },
+            option { "Students" }
+            option { "Mentors" }
+        }
+        h4 { "Mentors" }
+        for Member { name: mentor, active, .. } in mentors {
+            div {
+                key: "{mentor}",
+                color: if active { "inherit" } else { "gray" },
+                "{mentor} "
+                ActiveToggle { name: mentor.clone(), active, edit }
+                if !active {
+                    PurgeButton {
+                        name: mentor.clone(),
+                        roster_edit: RosterEdit::PurgeMentor,
+                        edit,
+                    }
+                }
+            }
+        }
+        h4 { "Students" }
+        for Member { name: student, active, .. } in students {
+            div {
+                key: "{student}",
+                color: if active { "inherit" } else { "gray" },
+                "{student} "
+                if active {
+                    button {
+                        onclick: {
+                            let student = student.clone();
+                            move |_| edit((RosterEdit::Promote, student.clone()))
+                        },
+                        "Promote"
+                    }
+                }
+                ActiveToggle { name: student.clone(), active, edit }
+                if !active {
+                    PurgeButton {
+                        name: student.clone(),
+                        roster_edit: RosterEdit::PurgeStudent,
+                        edit,
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Deactivate or Reactivate for one roster entry.
+#[component]
+fn ActiveToggle(name: String, active: bool, edit: Callback<(RosterEdit, String)>) -> Element {
+    let (roster_edit, label) = if active {
+        (RosterEdit::Deactivate, "Deactivate")
+    } else {
+        (RosterEdit::Reactivate, "Reactivate")
+    };
+    rsx! {
+        button { onclick: move |_| edit((roster_edit, name.clone())), "{label}" }
+    }
+}
+
+/// Deletes an inactive member from their roster on a second click.
+/// Deactivating is the usual way off the roster, purging is for mistakes.
+#[component]
+fn PurgeButton(
+    name: String,
+    roster_edit: RosterEdit,
+    edit: Callback<(RosterEdit, String)>,
+) -> Element {
+    let mut armed = use_signal(|| false);
+    rsx! {
+        button {
+            onclick: move |_| {
+                if armed() {
+                    armed.set(false);
+                    edit((roster_edit, name.clone()));
+                } else {
+                    armed.set(true);
+                }
+            },
+            onblur: move |_| armed.set(false),
+            if armed() { "Confirm purge" } else { "Purge" }
+        }
+    }
+}
+
+/// Phone numbers texted on check-in and check-out.
+#[component]
+fn ContactSettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        ..
+    } = use_context();
+    let backing_db_add = backing_db.clone();
+    let backing_db_edit = backing_db.clone();
+
+    let mut name = use_signal(String::new);
+    let mut phone = use_signal(String::new);
+    // Bumped to re-read contacts after an edit.
+    let revision = use_signal(|| 0_u64);
+
+    let contacts = use_db_read(&backing_db, revision, |db| db.get_contacts()).unwrap_or_default();
+
+    let set_contact = use_callback(move |contact: Contact| {
+        spawn_write(&backing_db_edit, revision, move |db| {
+            db.set_contact(&contact)
+        });
+    });
+    let remove_contact = use_callback(move |contact: Contact| {
+        spawn_write(&backing_db, revision, {
+            let contact = contact.clone();
+            move |db| db.remove_contact(&contact.name, &contact.phone)
+        });
+        process_change.set(format!(
+            "REMOVED CONTACT {} {}",
+            contact.name, contact.phone
+        ));
+    });
+
+    rsx! {
+        if contacts.is_empty() {
+            p { "No contacts" }
+        }
+        for contact in contacts {
+            div {
+                key: "{contact.name} {contact.phone}",
+                "{contact.name} {contact.phone} "
+                label {
+                    input {
+                        r#type: "checkbox",
+                        checked: contact.notify_in,
+                        onchange: {
+                            let contact = contact.clone();
+                            move |e: FormEvent| {
+                                set_contact(Contact {
+                                    notify_in: e.checked(),
+                                    ..contact.clone()
+                                })
+                            }
+                        },
+                    }
+                    "In "
+                }
+                label {
+                    input {
+                        r#type: "checkbox",
+                        checked: contact.notify_out,
+                        onchange: {
+                            let contact = contact.clone();
+                            move |e: FormEvent| {
+                                set_contact(Contact {
+                                    notify_out: e.checked(),
+                                    ..contact.clone()
+                                })
+                            }
+                        },
+                    }
+                    "Out "
+                }
+                button {
+                    onclick: move |_| remove_contact(contact.clone()),
+                    "Remove"
+                }
+            }
+        }
+        input {
+            placeholder: "Name",
+            value: "{name}",
+            oninput: move |e| name.set(e.value()),
+        }
+        input {
+            placeholder: "Phone, e.g. +15555550100",
+            value: "{phone}",
+            oninput: move |e| phone.set(e.value()),
+        }
+        button {
+            onclick: move |_| {
+                let contact = Contact {
+                    name: name.read().trim().to_string(),
+                    phone: phone.read().trim().to_string(),
+                    notify_in: true,
+                    notify_out: true,
+                };
+                if contact.name.is_empty() || contact.phone.is_empty() {
+                    return;
+                }
+
+                process_change.set(format!("ADDED CONTACT {} {}", contact.name, contact.phone));
+                spawn_write(&backing_db_add, revision, move |db| db.set_contact(&contact));
+                name.set(String::new());
+                phone.set(String::new());
+            },
+            "Add"
+        }
+    }
+}
+
+/// Upcoming meetings, with a manual calendar sync.
+#[component]
+fn ScheduleSettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        ..
+    } = use_context();
+    let config: Config = use_context();
+    let backing_db_sync = backing_db.clone();
+
+    // Bumped to re-read meetings after a sync.
+    let mut revision = use_signal(|| 0_u64);
+    let mut sync_error = use_signal(|| None::<String>);
+    let mut syncing = use_signal(|| false);
+
+    let (last_sync, upcoming) = use_db_read(&backing_db, revision, |db| {
+        let now = Local::now();
+        Ok((
+            schedule::last_sync(db)?,
+            db.meetings_between(now, now + TimeDelta::weeks(1))?,
+        ))
+    })
+    .unwrap_or_default();
+
+    let last_sync = last_sync.map_or_else(
+        || "Never".to_string(),
+        |last| last.format("%m-%d-%Y %H:%M:%S").to_string(),
+    );
+    let upcoming: Vec<_> = upcoming
+        .into_iter()
+        .map(|meeting| {
+            format!(
+                "{} {}-{} {}",
+                meeting.start.format("%a %m-%d"),
+                meeting.start.format("%H:%M"),
+                meeting.end.format("%H:%M"),
+                meeting.summary
+            )
+        })
+        .collect();
+
+    rsx! {
+        p { "Last calendar sync: {last_sync}" }
+        if upcoming.is_empty() {
+            p { "No meetings this week" }
+        }
+        for meeting in upcoming {
+            p { "{meeting}" }
+        }
+        button {
+            disabled: syncing(),
+            onclick: move |_| {
+                let backing_db = backing_db_sync.clone();
+                let config = config.clone();
+                syncing.set(true);
+                async move {
+                    match schedule::sync(&backing_db, &config).await {
+                        Ok(count) => {
+                            process_change.set(format!("SYNCED {count} MEETINGS"));
+                            sync_error.set(None);
+                        }
+                        Err(e) => sync_error.set(Some(format!("Sync failed: {e}"))),
+                    }
+                    syncing.set(false);
+                    *revision.write() += 1;
+                }
+            },
+            if syncing() { "Syncing..." } else { "Sync now" }
+        }
+        if let Some(error) = sync_error() {
+            p { color: "red", "{error}" }
+        }
+    }
+}
+
+/// Picks between QR badges and barcoded ID cards, taking effect on the next
+/// frame.
+#[component]
+fn ScanModeSettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        db_error,
+        ..
+    } = use_context();
+
+    let mut mode = use_signal(|| *SCAN_MODE.read().unwrap());
+
+    rsx! {
+        label {
+            "Read: "
+            select {
+                onchange: move |e| {
+                    let Ok(new_mode) = e.value().parse::<ScanMode>() else {
+                        return;
+                    };
+                    *SCAN_MODE.write().unwrap() = new_mode;
+                    let backing_db = backing_db.clone();
+                    spawn(async move {
+                        db_error.check(backing_db.try_run(move |db| db.set_setting(SCAN_MODE_KEY, new_mode)).await);
+                    });
+                    mode.set(new_mode);
+                    process_change.set(format!("READING {}", new_mode.label().to_uppercase()));
+                },
+                for option_mode in ScanMode::ALL {
+                    option {
+                        value: "{option_mode}",
+                        selected: option_mode == mode(),
+                        "{option_mode.label()}"
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sets the minimum time between two scans of the same badge, and after
+/// signing in before a scan signs out, taking effect on the next scan.
+#[component]
+fn ScanSpacingSettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        ..
+    } = use_context();
+    let config: Config = use_context();
+
+    // Bumped to re-read the setting after changes.
+    let mut revision = use_signal(|| 0_u64);
+
+    let (spacing, min_dwell) = use_db_read(&backing_db, revision, move |db| {
+        Ok((config.scan_spacing_secs(db)?, db.min_dwell_secs()?))
+    })
+    .unwrap_or((MIN_SCAN_SPACING_SECS, 0));
+    let backing_db_dwell = backing_db.clone();
+
+    rsx! {
+        label {
+            "Seconds between scans of one badge: "
+            input {
+                r#type: "number",
+                min: 0,
+                value: "{spacing}",
+                onchange: move |e| {
+                    if let Ok(secs) = e.value().trim().parse::<i64>()
+                        && secs >= 0
+                    {
+                        spawn_write(&backing_db, revision, move |db| db.set_scan_spacing_secs(secs));
+                        process_change.set(format!("SCAN SPACING {secs}S"));
+                    } else {
+                        *revision.write() += 1;
+                    }
+                },
+            }
+        }
+        br {}
+        label {
+            "Seconds signed in before a scan signs out: "
+            input {
+                r#type: "number",
+                min: 0,
+                value: "{min_dwell}",
+                onchange: move |e| {
+                    if let Ok(secs) = e.value().trim().parse::<i64>()
+                        && secs >= 0
+                    {
+                        spawn_write(&backing_db_dwell, revision, move |db| db.set_min_dwell_secs(secs));
+                        process_change.set(format!("MINIMUM STAY {secs}S"));
+                    } else {
+                        *revision.write() += 1;
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Mutes the sounds played for each scan.
+#[component]
+fn FeedbackSettings() -> Element {
+    let AttendanceContext { backing_db, .. } = use_context();
+
+    // Bumped to re-read the setting after changes.
+    let revision = use_signal(|| 0_u64);
+
+    let muted = use_db_read(&backing_db, revision, |db| {
+        db.get_setting(feedback::MUTED_KEY)
+    })
+    .flatten()
+    .unwrap_or(false);
+
+    rsx! {
+        label {
+            input {
+                r#type: "checkbox",
+                checked: !muted,
+                onchange: move |e: FormEvent| {
+                    let muted = !e.checked();
+                    spawn_write(&backing_db, revision, move |db| db.set_setting(feedback::MUTED_KEY, muted));
+                },
+            }
+            "Play a sound for each scan"
+        }
+    }
+}
+
+/// Switches between fullscreen and a normal window, kept for later launches.
+#[component]
+fn WindowSettings() -> Element {
+    let AttendanceContext { backing_db, .. } = use_context();
+
+    // Bumped to re-read the setting after changes.
+    let revision = use_signal(|| 0_u64);
+
+    let fullscreen = use_db_read(&backing_db, revision, |db| db.get_setting(FULLSCREEN_KEY))
+        .flatten()
+        .unwrap_or(true);
+
+    rsx! {
+        label {
+            input {
+                r#type: "checkbox",
+                checked: fullscreen,
+                onchange: move |e: FormEvent| {
+                    let fullscreen = e.checked();
+                    spawn_write(&backing_db, revision, move |db| db.set_setting(FULLSCREEN_KEY, fullscreen));
+                    window().window.set_fullscreen(fullscreen.then_some(Fullscreen::Borderless(None)));
+                },
+            }
+            "Fullscreen"
+        }
+        p { "Ctrl+Shift+Q exits the kiosk, asking for the PIN first when one is set." }
+    }
+}
+
+/// Sets the most people allowed present before the header turns red.
+#[component]
+fn OccupancySettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        ..
+    } = use_context();
+
+    // Bumped to re-read the setting after changes.
+    let mut revision = use_signal(|| 0_u64);
+
+    let max = use_db_read(&backing_db, revision, |db| db.max_occupancy())
+        .flatten()
+        .map(|max| max.to_string())
+        .unwrap_or_default();
+
+    rsx! {
+        label {
+            "Most people present at once: "
+            input {
+                r#type: "number",
+                min: 1,
+                placeholder: "No limit",
+                value: "{max}",
+                onchange: move |e| {
+                    let value = e.value();
+                    let value = value.trim();
+                    if value.is_empty() {
+                        spawn_write(&backing_db, revision, move |db| db.set_max_occupancy(None));
+                        process_change.set("NO OCCUPANCY LIMIT".to_string());
+                    } else if let Ok(max) = value.parse::<usize>()
+                        && max > 0
+                    {
+                        spawn_write(&backing_db, revision, move |db| db.set_max_occupancy(Some(max)));
+                        process_change.set(format!("OCCUPANCY LIMIT {max}"));
+                    } else {
+                        *revision.write() += 1;
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Sets the nightly sign-out time, taking effect within a minute.
+#[component]
+fn SignOutSettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        ..
+    } = use_context();
+
+    // Bumped to re-read the setting after changes.
+    let mut revision = use_signal(|| 0_u64);
+
+    let time = use_db_read(&backing_db, revision, |db| {
+        db.get_setting(signout::TIME_KEY)
+    })
+    .flatten()
+    .unwrap_or(signout::DEFAULT_TIME)
+    .format("%H:%M");
+
+    rsx! {
+        label {
+            "Sign out everyone still present at: "
+            input {
+                r#type: "time",
+                value: "{time}",
+                onchange: move |e| {
+                    if let Ok(time) = NaiveTime::parse_from_str(e.value().trim(), "%H:%M") {
+                        spawn_write(&backing_db, revision, move |db| db.set_setting(signout::TIME_KEY, time.format("%H:%M")));
+                        process_change.set(format!("AUTO SIGN-OUT AT {}", time.format("%H:%M")));
+                    } else {
+                        *revision.write() += 1;
+                    }
+                },
+            }
+        }
+        p { "A time earlier today signs out everyone who arrived before it right away." }
+    }
+}
+
+/// Moves the preview stream to another address, without reopening the
+/// camera.
+#[component]
+fn VideoSettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        db_error,
+        ..
+    } = use_context();
+    let VideoChannels { video_bind_tx, .. } = use_context();
+    let VideoBind(fixed_bind) = use_context();
+
+    let mut bind = use_signal(|| fixed_bind.clone().unwrap_or_default());
+    use_hook(|| {
+        if fixed_bind.is_none() {
+            let backing_db = backing_db.clone();
+            spawn(async move {
+                let stored = backing_db
+                    .try_run(|db| db.get_setting::<String>(VIDEO_BIND_KEY))
+                    .await
+                    .ok()
+                    .flatten();
+                bind.set(
+                    stored
+                        .or_else(|| VIDEO_ADDR.read().unwrap().map(|addr| addr.to_string()))
+                        .unwrap_or_default(),
+                );
+            });
+        }
+    });
+    let mut bind_error = use_signal(|| None::<String>);
+
+    let listening = VIDEO_ADDR
+        .read()
+        .unwrap()
+        .map_or_else(|| "Not listening".to_string(), |addr| addr.to_string());
+
+    rsx! {
+        p { "Listening on: {listening}" }
+        label {
+            "Address: "
+            input {
+                disabled: fixed_bind.is_some(),
+                value: "{bind}",
+                oninput: move |e| bind.set(e.value()),
+            }
+        }
+        button {
+            disabled: fixed_bind.is_some(),
+            onclick: move |_| {
+                let new_bind = bind().trim().to_string();
+                if let Err(e) = new_bind.to_socket_addrs() {
+                    bind_error.set(Some(format!("Invalid address {new_bind}: {e}")));
+                    return;
+                }
+
+                let backing_db = backing_db.clone();
+                spawn({
+                    let new_bind = new_bind.clone();
+                    async move {
+                        db_error.check(backing_db.try_run(move |db| db.set_setting(VIDEO_BIND_KEY, new_bind)).await);
+                    }
+                });
+                let _ = video_bind_tx.try_send(new_bind.clone());
+                bind_error.set(None);
+                process_change.set(format!("STREAM MOVED TO {new_bind}"));
+            },
+            "Save"
+        }
+        if fixed_bind.is_some() {
+            p { "Set by the command line" }
+        }
+        if let Some(error) = bind_error() {
+            p { color: "red", "{error}" }
+        }
+    }
+}
+
+/// Turns scan snapshots on or off, and sets how long they are kept.
+#[component]
+fn SnapshotSettings() -> Element {
+    let AttendanceContext { backing_db, .. } = use_context();
+    let backing_db_retention = backing_db.clone();
+
+    // Bumped to re-read settings after changes.
+    let mut revision = use_signal(|| 0_u64);
+
+    let (enabled, retention_days) = use_db_read(&backing_db, revision, |db| {
+        Ok((
+            snapshot::enabled(db)?,
+            db.get_setting(snapshot::RETENTION_DAYS_KEY)?
+                .unwrap_or(snapshot::DEFAULT_RETENTION_DAYS),
+        ))
+    })
+    .unwrap_or((false, snapshot::DEFAULT_RETENTION_DAYS));
+
+    rsx! {
+        label {
+            input {
+                r#type: "checkbox",
+                checked: enabled,
+                onchange: move |e: FormEvent| {
+                    let enabled = e.checked();
+                    spawn_write(&backing_db, revision, move |db| db.set_setting(snapshot::ENABLED_KEY, enabled));
+                },
+            }
+            "Save the camera frame with each scan"
+        }
+        br {}
+        label {
+            "Keep for days: "
+            input {
+                r#type: "number",
+                min: 1,
+                value: "{retention_days}",
+                onchange: move |e| {
+                    if let Ok(days) = e.value().trim().parse::<u32>()
+                        && days > 0
+                    {
+                        spawn_write(&backing_db_retention, revision, move |db| db.set_setting(snapshot::RETENTION_DAYS_KEY, days));
+                    } else {
+                        *revision.write() += 1;
+                    }
+                },
+            }
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+/// Shows where the database lives and allows manual backups.
+#[component]
+fn DatabaseSettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        ..
+    } = use_context();
+    let backing_db_dir = backing_db.clone();
+    let backing_db_backup = backing_db.clone();
+
+    // Bumped to re-read database info after changes.
+    let mut revision = use_signal(|| 0_u64);
+    let mut backup_error = use_signal(|| None::<String>);
+
+    let Some((
+        stats,
+        backup_dir,
+        last_backup,
+        last_backup_error,
+        remote_target,
+        last_remote_backup,
+    )) = use_db_read(&backing_db, revision, |db| {
+        Ok((
+            db.stats(),
+            db.backup_dir()?,
+            db.last_backup()?,
+            db.get_setting::<String>(LAST_BACKUP_ERROR_KEY)?
+                .filter(|error| !error.is_empty()),
+            backup::remote_target(db)?,
+            backup::last_remote_backup(db)?,
+        ))
+    })
+    else {
+        return rsx! {};
+    };
+    let last_remote_backup = last_remote_backup.map_or_else(
+        || "Never".to_string(),
+        |last| last.format("%m-%d-%Y %H:%M:%S").to_string(),
+    );
+
+    let path = stats.path.as_ref().map_or_else(
+        || "In memory".to_string(),
+        |path| {
+            path.canonicalize()
+                .unwrap_or_else(|_| path.clone())
+                .display()
+                .to_string()
+        },
+    );
+    let size = format_bytes(stats.size);
+    let wal_size = format_bytes(stats.wal_size);
+    let backup_dir = backup_dir.display().to_string();
+    let backup_stale = last_backup.is_none_or(|last| Local::now() - last > STALE_BACKUP_AGE);
+    let last_backup = last_backup.map_or_else(
+        || "Never".to_string(),
+        |last| last.format("%m-%d-%Y %H:%M:%S").to_string(),
+    );
+
+    rsx! {
+        p { "Location: {path}" }
+        p { "Size: {size} (WAL: {wal_size})" }
+        p {
+            color: if backup_stale { "orange" } else { "inherit" },
+            "Last backup: {last_backup}"
+        }
+        if let Some(error) = last_backup_error {
+            p { color: "red", "Last backup failed: {error}" }
+        }
+        if let Some(remote_target) = remote_target {
+            p { "Last remote backup: {last_remote_backup} ({remote_target})" }
+        }
+        label {
+            "Backup directory: "
+            input {
+                value: "{backup_dir}",
+                onchange: move |e| {
+                    let dir = e.value().trim().to_string();
+                    spawn_write(&backing_db_dir, revision, move |db| db.set_setting(BACKUP_DIR_KEY, dir));
+                },
+            }
+        }
+        br {}
+        button {
+            onclick: move |_| {
+                let backing_db = backing_db_backup.clone();
+                spawn(async move {
+                    let result = backing_db.backup().await;
+                    *revision.write() += 1;
+                    match result {
+                        Ok(path) => {
+                            process_change.set(format!("BACKED UP {}", path.display()));
+                            backup_error.set(None);
+
+                            match upload_backup(backing_db, path).await {
+                                Some(Ok(_)) => {
+                                    process_change.set("BACKUP UPLOADED".to_string());
+                                }
+                                Some(Err(e)) => {
+                                    process_change.set("REMOTE BACKUP FAILED".to_string());
+                                    backup_error.set(Some(format!("Remote backup failed: {e}")));
+                                }
+                                None => (),
+                            }
+                            *revision.write() += 1;
+                        }
+                        Err(e) => backup_error.set(Some(format!("Backup failed: {e}"))),
+                    }
+                });
+            },
+            "Back up now"
+        }
+        if let Some(error) = backup_error() {
+            p { color: "red", "{error}" }
+        }
+    }
+}
+
+/// Lists [`IntegrityIssue`]s, with fixes for those that have an obvious one.
+#[component]
+fn IntegritySettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        refresh_tx,
+        mut process_change,
+        db_error,
+        ..
+    } = use_context();
+    let backing_db_resolve = backing_db.clone();
+
+    // Bumped to re-run the check after a fix.
+    let mut revision = use_signal(|| 0_u64);
+
+    let issues = use_db_read(&backing_db, revision, |db| db.integrity_check()).unwrap_or_default();
+
+    let resolve = use_callback(move |(name, keep): (String, Option<QrType>)| {
+        let backing_db = backing_db_resolve.clone();
+        let refresh_tx = refresh_tx.clone();
+        spawn(async move {
+            let resolved = backing_db
+                .try_run({
+                    let name = name.clone();
+                    move |db| match keep {
+                        Some(QrType::Mentor) => db.remove_student(&name),
+                        Some(QrType::Student) => db.remove_mentor(&name),
+                        Some(QrType::Guest) | None => db.remove_presence(&name),
+                    }
+                })
+                .await;
+            if db_error.check(resolved) == Some(true) {
+                process_change.set(format!("RESOLVED {name}"));
+                let _ = refresh_tx.try_send(());
+            }
+            *revision.write() += 1;
+        });
+    });
+
+    rsx! {
+        if issues.is_empty() {
+            p { "Integrity check: no issues" }
+        } else {
+            p { color: "orange", "Integrity check: {issues.len()} issues" }
+            ul {
+                for issue in issues {
+                    li {
+                        "{issue} "
+                        match issue {
+                            IntegrityIssue::OnBothRosters(name) => rsx! {
+                                button {
+                                    onclick: {
+                                        let name = name.clone();
+                                        move |_| resolve((name.clone(), Some(QrType::Mentor)))
+                                    },
+                                    "Keep Mentor"
+                                }
+                                button {
+                                    onclick: move |_| resolve((name.clone(), Some(QrType::Student))),
+                                    "Keep Student"
+                                }
+                            },
+                            IntegrityIssue::OrphanedPresence(name) => rsx! {
+                                button { onclick: move |_| resolve((name.clone(), None)), "Remove" }
+                            },
+                            // No telling when the scan really was.
+                            IntegrityIssue::ImpossibleTimestamp { .. } => rsx! {},
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lists a person's scans for today and allows correcting their times.
+#[component]
+fn ScanEditor() -> Element {
+    let AttendanceContext {
+        backing_db,
+        refresh_tx,
+        mut process_change,
+        db_error,
+        ..
+    } = use_context();
+    let backing_db_edit = backing_db.clone();
+
+    let mut name = use_signal(String::new);
+    // Bumped to re-query scans after an edit.
+    let mut edits = use_signal(|| 0_u64);
+
+    let scans = use_resource(move || {
+        let backing_db = backing_db.clone();
+        edits.read();
+        let name = name.read().trim().to_string();
+        async move {
+            let scans = backing_db
+                .try_run(move |db| {
+                    let scans = db.scans_on(&name, Local::now().date_naive())?;
+                    // Scans before today decide whether today starts signed in.
+                    let before = match scans.first() {
+                        Some(first) => db.scan_count_before(&name, *first)?,
+                        None => 0,
+                    };
+                    Ok((before, scans))
+                })
+                .await;
+            db_error.check(scans)
+        }
+    });
+
+    let edit_scan = use_callback(move |(old, new): (DateTime<Local>, DateTime<Local>)| {
+        let name = name.read().trim().to_string();
+        let backing_db = backing_db_edit.clone();
+        let refresh_tx = refresh_tx.clone();
+        spawn(async move {
+            let result = backing_db
+                .run({
+                    let name = name.clone();
+                    move |db| db.update_scan_timestamp(&name, old, new)
+                })
+                .await;
+
+            match result {
+                Ok(Ok(())) => {
+                    process_change.set(format!(
+                        "EDITED {name} {} -> {}",
+                        old.format("%H:%M:%S"),
+                        new.format("%H:%M:%S")
+                    ));
+                    let _ = refresh_tx.try_send(());
+                }
+                Ok(Err(ScanEditError::Db(e))) | Err(e) => {
+                    process_change.set(format!("EDIT FAILED {name}"));
+                    db_error.check::<()>(Err(e));
+                }
+                Ok(Err(e)) => process_change.set(format!("EDIT REJECTED {name}: {e}")),
+            }
+            *edits.write() += 1;
+        });
+    });
+
+    let (before, scans) = scans().flatten().unwrap_or_default();
+    let rows: Vec<_> = scans
+        .iter()
+        .enumerate()
+        .map(|(idx, scan)| {
+            let direction = if (before + idx) % 2 == 0 { "In" } else { "Out" };
+            (*scan, direction, scan.format("%H:%M:%S").to_string())
+        })
+        .collect();
+
+    rsx! {
+        input {
+            placeholder: "Name",
+            value: "{name}",
+            oninput: move |e| name.set(e.value()),
+        }
+        for (scan, direction, time) in rows {
+            div {
+                key: "{scan.timestamp()}",
+                "{direction} "
+                input {
+                    r#type: "time",
+                    step: "1",
+                    value: "{time}",
+                    onchange: move |e| {
+                        let new_time = NaiveTime::parse_from_str(&e.value(), "%H:%M:%S")
+                            .or_else(|_| NaiveTime::parse_from_str(&e.value(), "%H:%M"));
+                        if let Ok(new_time) = new_time {
+                            edit_scan((scan, local_datetime(scan.date_naive(), new_time)));
+                        }
+                    },
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,959 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! JSON API for pulling attendance into other systems, and for submitting
+//! scans from companion devices.
+//!
+//! Disabled by default. Every endpoint except `/api/health` requires an
+//! `Authorization: Bearer <token>` header matching the configured token, or
+//! a `token` query parameter on `GET` requests. `/` and `/dashboard` serve a
+//! page that asks for the token, polls the API, and shows the camera preview
+//! from `/api/stream`. Clients without the token are rate limited per
+//! address.
+//!
+//! Roster edits are written through the kiosk's own database handle, the
+//! same as admin panel edits.
+//! While serving, the kiosk is advertised on the LAN over mDNS. Requests
+//! are read and answered by [`web`](crate::web).
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::Display,
+    io,
+    net::IpAddr,
+    rc::Rc,
+    sync::{RwLock, atomic::Ordering},
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, NaiveDate};
+use dioxus::prelude::spawn;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use subtle::ConstantTimeEq;
+use tokio::net::{TcpListener, TcpStream};
+
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    API_STREAM_CLIENTS, CAMERA_CONNECTED, QrType,
+    attendance::PresentRows,
+    db_worker::DbHandle,
+    discovery::{Advertisement, kiosk_name},
+    sqlite::{BackingDatabase, DbError, RosterError, date_range},
+    version::{GIT_HASH, VERSION},
+    web::{Head, percent_decode, query_param, read_body, respond},
+};
+
+/// Kept current by the scan loop.
+pub static PRESENT: PresentSnapshot = PresentSnapshot::new();
+
+pub const ENABLED_KEY: &str = "api.enabled";
+pub const PORT_KEY: &str = "api.port";
+pub const TOKEN_KEY: &str = "api.token";
+
+/// Polls `/api/present`, holds no data itself.
+const DASHBOARD_HTML: &str = include_str!("../assets/dashboard.html");
+
+const DEFAULT_PORT: u16 = 2344;
+/// Most scans returned by one `/api/changes` request.
+pub const CHANGES_PAGE_SIZE: usize = 500;
+/// Clients that take longer to send a request are dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Scans from one source closer together than this are refused.
+const SOURCE_SPACING: Duration = Duration::from_secs(1);
+/// Longest wait for the scan loop to handle a submitted scan.
+pub const SCAN_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Requests without the token allowed per address in [`UNAUTHORIZED_WINDOW`].
+const UNAUTHORIZED_LIMIT: u32 = 10;
+const UNAUTHORIZED_WINDOW: Duration = Duration::from_secs(60);
+
+/// A scan submitted over `POST /api/scan`, handled by the scan loop like a
+/// camera read.
+#[derive(Debug)]
+pub struct ScanSubmission {
+    pub payload: String,
+    pub source: String,
+    pub reply: async_channel::Sender<ScanAction>,
+}
+
+/// What the scan loop did with a scan.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanAction {
+    /// Signed in.
+    Added(String),
+    /// Signed out.
+    Removed(String),
+    /// Not recorded, with the reason.
+    Rejected(String),
+}
+
+impl Display for ScanAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Added(_) => write!(f, "ADDED"),
+            Self::Removed(_) => write!(f, "REMOVED"),
+            Self::Rejected(_) => write!(f, "REJECTED"),
+        }
+    }
+}
+
+impl ScanAction {
+    /// `{"action": ..., "name": ..., "reason": ...}`, as returned for
+    /// submitted scans.
+    pub fn to_json(&self) -> Value {
+        let (name, reason) = match self {
+            Self::Added(name) | Self::Removed(name) => (Some(name), None),
+            Self::Rejected(reason) => (None, Some(reason)),
+        };
+        json!({ "action": self.to_string(), "name": name, "reason": reason })
+    }
+}
+
+#[derive(Deserialize)]
+struct ScanRequest {
+    /// Badge text, or just a roster name.
+    #[serde(alias = "name")]
+    payload: String,
+    #[serde(default)]
+    source: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RosterRequest {
+    name: String,
+    role: RosterRole,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RosterRole {
+    Mentor,
+    Student,
+}
+
+/// Forwards submitted scans, limiting how fast each source can submit.
+struct ScanIntake {
+    submissions: async_channel::Sender<ScanSubmission>,
+    last_by_source: RefCell<HashMap<String, Instant>>,
+}
+
+/// Changes made over the API, written like admin panel edits.
+pub struct ApiWriter {
+    pub backing_db: DbHandle,
+    /// Reloads the scan loop's rosters after an edit.
+    pub refresh_tx: async_channel::Sender<()>,
+}
+
+/// Requests without the token by address, with when the count started.
+#[derive(Default)]
+struct UnauthorizedLimiter {
+    by_addr: RefCell<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl UnauthorizedLimiter {
+    /// Counts a request from `addr`, false once over the limit.
+    fn allow(&self, addr: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut by_addr = self.by_addr.borrow_mut();
+        // Keeps the map from growing with every address ever seen.
+        by_addr.retain(|_, (start, _)| now - *start < UNAUTHORIZED_WINDOW);
+
+        let (_, count) = by_addr.entry(addr).or_insert((now, 0));
+        *count += 1;
+        *count <= UNAUTHORIZED_LIMIT
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiSettings {
+    pub port: u16,
+    pub token: String,
+    /// Advertised over mDNS.
+    pub name: String,
+}
+
+impl ApiSettings {
+    /// `None` when the API is disabled, or enabled without a token.
+    pub fn load(db: &BackingDatabase) -> Result<Option<Self>, DbError> {
+        if !db.get_setting(ENABLED_KEY)?.unwrap_or(false) {
+            return Ok(None);
+        }
+
+        let Some(token) = db
+            .get_setting::<String>(TOKEN_KEY)?
+            .filter(|token| !token.is_empty())
+        else {
+            warn!(target: "api", "HTTP API enabled without {TOKEN_KEY}, not starting");
+            return Ok(None);
+        };
+
+        Ok(Some(Self {
+            port: db.get_setting(PORT_KEY)?.unwrap_or(DEFAULT_PORT),
+            token,
+            name: kiosk_name(db)?,
+        }))
+    }
+}
+
+/// Serves API requests until the app exits.
+///
+/// Queries go through a separate read-only connection to `database`, on its
+/// own worker, so they cannot block or modify scan writes. Submitted scans are sent to
+/// `submissions`.
+pub async fn serve(
+    database: String,
+    settings: ApiSettings,
+    submissions: async_channel::Sender<ScanSubmission>,
+    writer: ApiWriter,
+) {
+    let opened = DbHandle::try_spawn({
+        let database = database.clone();
+        move || BackingDatabase::open_read_only(&database)
+    });
+    let db = match opened {
+        Ok(db) => db,
+        Err(e) => {
+            error!(target: "api", "HTTP API failed to open {database}: {e}");
+            return;
+        }
+    };
+
+    let listener = match TcpListener::bind(("0.0.0.0", settings.port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(target: "api", "HTTP API failed to bind port {}: {e}", settings.port);
+            return;
+        }
+    };
+    info!(target: "api", "HTTP API listening on port {}", settings.port);
+
+    // Held for as long as the API serves, sending a goodbye when dropped.
+    let kiosk_id = match db.run(|db| db.kiosk_id().to_string()).await {
+        Ok(kiosk_id) => kiosk_id,
+        Err(e) => {
+            error!(target: "api", "HTTP API failed to start: {e}");
+            return;
+        }
+    };
+    let _advertisement = match Advertisement::start(&settings.name, &kiosk_id, settings.port) {
+        Ok(advertisement) => Some(advertisement),
+        Err(e) => {
+            warn!(target: "discovery", "mDNS advertisement failed: {e}");
+            None
+        }
+    };
+
+    let token: Rc<str> = settings.token.into();
+    let intake = Rc::new(ScanIntake {
+        submissions,
+        last_by_source: RefCell::default(),
+    });
+    let writer = Rc::new(writer);
+    let limiter = Rc::new(UnauthorizedLimiter::default());
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(target: "api", "HTTP API accept failed: {e}");
+                continue;
+            }
+        };
+
+        let db = db.clone();
+        let token = token.clone();
+        let intake = intake.clone();
+        let writer = writer.clone();
+        let limiter = limiter.clone();
+        spawn(async move {
+            let request = Request {
+                db: &db,
+                token: &token,
+                intake: &intake,
+                writer: &writer,
+                limiter: &limiter,
+                addr: addr.ip(),
+            };
+            // Timeouts are dropped silently, as they are usually idle clients.
+            if let Ok(Err(e)) = tokio::time::timeout(REQUEST_TIMEOUT, handle(stream, request)).await
+            {
+                debug!(target: "api", "HTTP API request failed: {e}");
+            }
+        });
+    }
+}
+
+/// What one connection is served with.
+struct Request<'a> {
+    db: &'a DbHandle,
+    token: &'a str,
+    intake: &'a ScanIntake,
+    writer: &'a ApiWriter,
+    limiter: &'a UnauthorizedLimiter,
+    addr: IpAddr,
+}
+
+async fn handle(mut stream: TcpStream, request: Request<'_>) -> io::Result<()> {
+    let Request {
+        db,
+        token,
+        intake,
+        writer,
+        limiter,
+        addr,
+    } = request;
+    let (read, mut write) = stream.split();
+    let (head, mut reader) = Head::read(read).await?;
+    let Head {
+        method,
+        path,
+        query,
+        bearer,
+        content_length,
+    } = &head;
+    let (method, path) = (method.as_str(), path.as_str());
+
+    // Compared in constant time, so response timing does not reveal how
+    // much of a guess matched.
+    let matches = |given: &str| bool::from(given.as_bytes().ct_eq(token.as_bytes()));
+    let mut authorized = bearer.as_deref().is_some_and(matches);
+    // For links opened on a phone, which cannot set headers.
+    if method == "GET" && query_param(query, "token").is_some_and(|given| matches(&given)) {
+        authorized = true;
+    }
+
+    // Handed to the video threads, which write frames until the viewer
+    // leaves.
+    if (method, path) == ("GET", "/api/stream")
+        && authorized
+        && CAMERA_CONNECTED.load(Ordering::Relaxed)
+    {
+        drop(reader);
+        let stream = stream.into_std()?;
+        stream.set_nonblocking(false)?;
+        API_STREAM_CLIENTS.add(stream);
+        return Ok(());
+    }
+
+    // Kept current by the scan loop, the database is only asked until it
+    // first loads.
+    if (method, path) == ("GET", "/api/present")
+        && authorized
+        && let Some(present) = PRESENT.to_json()
+    {
+        return respond(
+            &mut write,
+            "200 OK",
+            "application/json",
+            "",
+            &present.to_string(),
+        )
+        .await;
+    }
+
+    let (status, content_type, body) = match (method, path) {
+        _ if !authorized && !limiter.allow(addr) => (
+            "429 Too Many Requests",
+            "application/json",
+            json!({ "error": "too many requests" }).to_string(),
+        ),
+        ("GET", "/" | "/dashboard") => {
+            ("200 OK", "text/html; charset=utf-8", DASHBOARD_HTML.into())
+        }
+        ("GET", "/api/stream") if authorized => (
+            "503 Service Unavailable",
+            "application/json",
+            json!({ "error": "camera unavailable" }).to_string(),
+        ),
+        ("POST", "/api/scan") if authorized => {
+            let (status, body) = match read_body(&mut reader, *content_length).await? {
+                Some(body) => submit_scan(intake, &body).await,
+                None => body_too_large(),
+            };
+            (status, "application/json", body.to_string())
+        }
+        ("POST", "/api/roster") if authorized => {
+            let (status, body) = match read_body(&mut reader, *content_length).await? {
+                Some(body) => add_to_roster(writer, &body).await,
+                None => body_too_large(),
+            };
+            (status, "application/json", body.to_string())
+        }
+        ("DELETE", _) if authorized && path.starts_with("/api/roster/") => {
+            let name = percent_decode(&path["/api/roster/".len()..]);
+            let (status, body) = match name {
+                Some(name) => remove_from_roster(writer, name).await,
+                None => (
+                    "400 Bad Request",
+                    json!({ "error": "malformed name in path" }),
+                ),
+            };
+            (status, "application/json", body.to_string())
+        }
+        _ => {
+            let (method, path, query) = (method.to_string(), path.to_string(), query.clone());
+            let (status, body) = db
+                .run(move |db| route(db, authorized, &method, &path, &query))
+                .await
+                .unwrap_or_else(|e| server_error(&e));
+            (status, "application/json", body.to_string())
+        }
+    };
+
+    let authenticate = if status.starts_with("401") {
+        "WWW-Authenticate: Bearer\r\n"
+    } else {
+        ""
+    };
+    respond(&mut write, status, content_type, authenticate, &body).await
+}
+
+fn route(
+    db: &BackingDatabase,
+    authorized: bool,
+    method: &str,
+    path: &str,
+    query: &str,
+) -> (&'static str, Value) {
+    match (method, path) {
+        ("GET", "/api/health") => (
+            "200 OK",
+            json!({
+                "status": "ok",
+                "time": Local::now().to_rfc3339(),
+                "version": VERSION,
+                "git_hash": GIT_HASH,
+            }),
+        ),
+        _ if !authorized => ("401 Unauthorized", json!({ "error": "unauthorized" })),
+        ("GET", "/api/present") => match present(db) {
+            Ok(present) => ("200 OK", present),
+            Err(e) => server_error(&e),
+        },
+        ("GET", "/api/attendance") => attendance(db, query),
+        ("GET", "/api/roster") => match db
+            .get_mentors()
+            .and_then(|mentors| Ok(json!({ "mentors": mentors, "students": db.get_students()? })))
+        {
+            Ok(roster) => ("200 OK", roster),
+            Err(e) => server_error(&e),
+        },
+        // `/sync` is the name peers were first documented to pull from.
+        ("GET", "/api/changes" | "/sync") => changes(db, query),
+        (
+            _,
+            "/api/present" | "/api/attendance" | "/api/roster" | "/api/changes" | "/api/scan"
+            | "/api/stream" | "/sync",
+        ) => (
+            "405 Method Not Allowed",
+            json!({ "error": "method not allowed" }),
+        ),
+        (_, path) if path.starts_with("/api/roster/") => (
+            "405 Method Not Allowed",
+            json!({ "error": "method not allowed" }),
+        ),
+        _ => ("404 Not Found", json!({ "error": "not found" })),
+    }
+}
+
+fn body_too_large() -> (&'static str, Value) {
+    (
+        "413 Payload Too Large",
+        json!({ "error": "request body too large" }),
+    )
+}
+
+/// Adds `{"name": ..., "role": "mentor" | "student"}` to that roster.
+async fn add_to_roster(writer: &ApiWriter, body: &[u8]) -> (&'static str, Value) {
+    let request: RosterRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => {
+            return (
+                "400 Bad Request",
+                json!({ "error": format!("expected {{\"name\": ..., \"role\": \"mentor\" or \"student\"}}: {e}") }),
+            );
+        }
+    };
+    let name = request.name.trim().to_string();
+    if name.is_empty() {
+        return ("400 Bad Request", json!({ "error": "name is empty" }));
+    }
+
+    let role = request.role;
+    let added = writer
+        .backing_db
+        .run({
+            let name = name.clone();
+            move |db| match role {
+                RosterRole::Mentor => db.add_mentor(&name),
+                RosterRole::Student => db.add_student(&name),
+            }
+        })
+        .await;
+    let role = match role {
+        RosterRole::Mentor => QrType::Mentor,
+        RosterRole::Student => QrType::Student,
+    };
+    match added {
+        Ok(Ok(true)) => {
+            info!(target: "api", %name, %role, "Added to roster");
+            let _ = writer.refresh_tx.try_send(());
+            (
+                "201 Created",
+                json!({ "added": name, "role": role.to_string() }),
+            )
+        }
+        Ok(Ok(false)) => (
+            "409 Conflict",
+            json!({ "error": format!("{name} is already a {role}") }),
+        ),
+        Ok(Err(RosterError::Db(e))) | Err(e) => server_error(&e),
+        Ok(Err(e)) => ("409 Conflict", json!({ "error": e.to_string() })),
+    }
+}
+
+/// Deactivates `name`, see [`BackingDatabase::set_member_active`].
+async fn remove_from_roster(writer: &ApiWriter, name: String) -> (&'static str, Value) {
+    let removed = writer
+        .backing_db
+        .try_run({
+            let name = name.clone();
+            move |db| {
+                Ok(match db.roster_entry(&name)? {
+                    Some((role, true)) => db.set_member_active(&name, false)?.then_some(role),
+                    Some((_, false)) | None => None,
+                })
+            }
+        })
+        .await;
+
+    match removed {
+        Ok(Some(role)) => {
+            info!(target: "api", %name, %role, "Deactivated");
+            let _ = writer.refresh_tx.try_send(());
+            (
+                "200 OK",
+                json!({ "deactivated": name, "role": role.to_string() }),
+            )
+        }
+        Ok(None) => (
+            "404 Not Found",
+            json!({ "error": format!("{name} is not an active member") }),
+        ),
+        Err(e) => server_error(&e),
+    }
+}
+
+/// Response for a request the database could not handle.
+fn server_error(e: &DbError) -> (&'static str, Value) {
+    error!(target: "api", "HTTP API request failed: {e}");
+    (
+        "500 Internal Server Error",
+        json!({ "error": "database error" }),
+    )
+}
+
+/// Hands a scan to the scan loop and waits for the outcome.
+async fn submit_scan(intake: &ScanIntake, body: &[u8]) -> (&'static str, Value) {
+    let request: ScanRequest = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => {
+            return (
+                "400 Bad Request",
+                json!({ "error": format!("expected {{\"payload\": ..., \"source\": ...}}: {e}") }),
+            );
+        }
+    };
+    let source = request
+        .source
+        .map(|source| source.trim().to_string())
+        .filter(|source| !source.is_empty())
+        .unwrap_or_else(|| "api".to_string());
+
+    {
+        let now = Instant::now();
+        let mut last_by_source = intake.last_by_source.borrow_mut();
+        if last_by_source
+            .get(&source)
+            .is_some_and(|last| now - *last < SOURCE_SPACING)
+        {
+            return (
+                "429 Too Many Requests",
+                json!({ "error": "too many scans from this source" }),
+            );
+        }
+        last_by_source.insert(source.clone(), now);
+    }
+
+    let (reply, outcome) = async_channel::bounded(1);
+    let submission = ScanSubmission {
+        payload: request.payload,
+        source,
+        reply,
+    };
+    if intake.submissions.send(submission).await.is_err() {
+        return (
+            "503 Service Unavailable",
+            json!({ "error": "kiosk is not accepting scans" }),
+        );
+    }
+
+    match tokio::time::timeout(SCAN_REPLY_TIMEOUT, outcome.recv()).await {
+        Ok(Ok(action)) => ("200 OK", action.to_json()),
+        _ => (
+            "503 Service Unavailable",
+            json!({ "error": "kiosk did not handle the scan in time" }),
+        ),
+    }
+}
+
+/// Present people, split the same way as the kiosk display.
+pub fn present(db: &BackingDatabase) -> Result<Value, DbError> {
+    let mentors = db.get_mentors()?;
+    let students = db.get_students()?;
+    let present = db.get_present()?;
+
+    let mut sections = [Vec::new(), Vec::new(), Vec::new()];
+    for (name, since) in present {
+        let section = if mentors.contains(&name) {
+            0
+        } else if students.contains(&name) {
+            1
+        } else if name.starts_with("Guest") {
+            2
+        } else {
+            continue;
+        };
+        sections[section].push((name, since));
+    }
+    Ok(present_json(&sections))
+}
+
+/// `{"mentors": ..., "students": ..., "guests": ...}` from those sections.
+fn present_json(sections: &[PresentRows; 3]) -> Value {
+    let [mentors, students, guests] = sections.each_ref().map(|rows| {
+        rows.iter()
+            .map(|(name, since)| json!({ "name": name, "since": since.to_rfc3339() }))
+            .collect::<Vec<_>>()
+    });
+    json!({ "mentors": mentors, "students": students, "guests": guests })
+}
+
+/// Who is present, as last shown by the scan loop, so `/api/present` needs
+/// no query.
+#[derive(Debug, Default)]
+pub struct PresentSnapshot(RwLock<Option<[PresentRows; 3]>>);
+
+impl PresentSnapshot {
+    pub const fn new() -> Self {
+        Self(RwLock::new(None))
+    }
+
+    pub fn set(&self, role: QrType, rows: PresentRows) {
+        let section = match role {
+            QrType::Mentor => 0,
+            QrType::Student => 1,
+            QrType::Guest => 2,
+        };
+        self.0.write().unwrap().get_or_insert_default()[section] = rows;
+    }
+
+    /// As returned by [`present`], `None` before the scan loop first loads
+    /// who is present.
+    pub fn to_json(&self) -> Option<Value> {
+        self.0.read().unwrap().as_ref().map(present_json)
+    }
+}
+
+/// Scans recorded by this kiosk after the `since` sequence number, for peers.
+fn changes(db: &BackingDatabase, query: &str) -> (&'static str, Value) {
+    let since = query_param(query, "since").map_or(Ok(0), |since| since.parse::<i64>());
+
+    match since.map(|since| db.changes_since(since, CHANGES_PAGE_SIZE)) {
+        Ok(Ok(changes)) => (
+            "200 OK",
+            json!({
+                "kiosk_id": db.kiosk_id(),
+                "changes": changes,
+            }),
+        ),
+        Ok(Err(e)) => server_error(&e),
+        Err(_) => (
+            "400 Bad Request",
+            json!({ "error": "invalid since, expected a sequence number" }),
+        ),
+    }
+}
+
+/// Scans between the optional `from` and `to` days (inclusive).
+fn attendance(db: &BackingDatabase, query: &str) -> (&'static str, Value) {
+    let mut from = None;
+    let mut to = None;
+
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        let field = match key.as_ref() {
+            "from" => &mut from,
+            "to" => &mut to,
+            _ => continue,
+        };
+
+        match NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
+            Ok(date) => *field = Some(date),
+            Err(_) => {
+                return (
+                    "400 Bad Request",
+                    json!({ "error": format!("invalid {key} date, expected YYYY-MM-DD") }),
+                );
+            }
+        }
+    }
+
+    match db.attendance(date_range(from, to)) {
+        Ok(rows) => (
+            "200 OK",
+            rows.into_iter()
+                .map(|row| {
+                    json!({
+                        "name": row.name,
+                        "timestamp": row.timestamp.to_rfc3339(),
+                        "type": row.qr_type,
+                    })
+                })
+                .collect(),
+        ),
+        Err(e) => {
+            error!(target: "api", "HTTP API attendance query failed: {e}");
+            (
+                "500 Internal Server Error",
+                json!({ "error": "database error" }),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        task::LocalSet,
+    };
+
+    use super::*;
+    use crate::{
+        attendance::{self, AttendanceView, ScanInputs, Task},
+        config::Config,
+        observer::ObserverRegistry,
+    };
+
+    #[test]
+    fn snapshot_empty_until_loaded() {
+        let snapshot = PresentSnapshot::new();
+        assert_eq!(snapshot.to_json(), None);
+
+        let since = Local.timestamp_opt(1_700_000_000, 0).unwrap();
+        snapshot.set(QrType::Student, vec![("Ada".to_string(), since)]);
+        assert_eq!(
+            snapshot.to_json(),
+            Some(json!({
+                "mentors": [],
+                "students": [{ "name": "Ada", "since": since.to_rfc3339() }],
+                "guests": [],
+            }))
+        );
+
+        snapshot.set(QrType::Student, Vec::new());
+        assert_eq!(
+            snapshot.to_json(),
+            Some(json!({ "mentors": [], "students": [], "guests": [] }))
+        );
+    }
+
+    #[test]
+    fn changes_served_at_both_paths() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.add_scan("Ada", Local.timestamp_opt(1_700_000_000, 0).unwrap(), None)
+            .unwrap();
+
+        let (status, body) = route(&db, true, "GET", "/sync", "since=0");
+        assert_eq!(status, "200 OK");
+        assert_eq!(body["changes"][0]["name"], "Ada");
+        assert_eq!(route(&db, true, "GET", "/api/changes", "since=0").1, body);
+        assert_eq!(
+            route(&db, true, "GET", "/sync", "since=1").1["changes"],
+            json!([])
+        );
+        assert_eq!(route(&db, false, "GET", "/sync", "").0, "401 Unauthorized");
+    }
+
+    #[derive(Clone)]
+    struct TestView;
+
+    impl AttendanceView for TestView {
+        fn spawn(&self, task: Task) {
+            tokio::task::spawn_local(task);
+        }
+    }
+
+    /// Sends `request` to [`handle`], returning the status line and body.
+    async fn send(request: Request<'_>, raw: &str) -> (String, Value) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        client.write_all(raw.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        let (handled, read) = tokio::join!(
+            handle(server, request),
+            client.read_to_string(&mut response)
+        );
+        handled.unwrap();
+        read.unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_string();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn post_scan(body: &str) -> String {
+        format!(
+            "POST /api/scan HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[tokio::test]
+    async fn submitted_scan_recorded() {
+        LocalSet::new()
+            .run_until(async {
+                let backing_db = DbHandle::spawn(|| BackingDatabase::new(None).unwrap());
+                backing_db
+                    .run(|db| db.add_student("Alice"))
+                    .await
+                    .unwrap()
+                    .unwrap();
+
+                let (_qr_reads_tx, qr_reads_rx) = async_channel::unbounded();
+                let (submissions_tx, submissions_rx) = async_channel::unbounded();
+                let (_sign_out_confirmed_tx, sign_out_confirmed_rx) = async_channel::unbounded();
+                let (refresh_tx, refresh_rx) = async_channel::unbounded();
+                tokio::task::spawn_local(attendance::run(
+                    ScanInputs {
+                        qr_reads_rx,
+                        submissions_rx,
+                        sign_out_confirmed_rx,
+                        refresh_rx,
+                    },
+                    backing_db.clone(),
+                    Config::default(),
+                    ObserverRegistry::new(),
+                    TestView,
+                ));
+
+                let intake = ScanIntake {
+                    submissions: submissions_tx,
+                    last_by_source: RefCell::default(),
+                };
+                let writer = ApiWriter {
+                    backing_db: backing_db.clone(),
+                    refresh_tx,
+                };
+                let limiter = UnauthorizedLimiter::default();
+                let request = || Request {
+                    db: &backing_db,
+                    token: "secret",
+                    intake: &intake,
+                    writer: &writer,
+                    limiter: &limiter,
+                    addr: IpAddr::from([127, 0, 0, 1]),
+                };
+
+                let (status, body) = send(
+                    request(),
+                    &post_scan(r#"{"payload": "Alice", "source": "phone-amy"}"#),
+                )
+                .await;
+                assert_eq!(status, "HTTP/1.1 200 OK");
+                assert_eq!(
+                    body,
+                    json!({ "action": "ADDED", "name": "Alice", "reason": null })
+                );
+
+                // Too soon after the last scan from the same phone.
+                let (status, _) = send(
+                    request(),
+                    &post_scan(r#"{"payload": "Alice", "source": "phone-amy"}"#),
+                )
+                .await;
+                assert_eq!(status, "HTTP/1.1 429 Too Many Requests");
+
+                let (status, body) = send(
+                    request(),
+                    &post_scan(r#"{"payload": "Mallory", "source": "phone-bo"}"#),
+                )
+                .await;
+                assert_eq!(status, "HTTP/1.1 200 OK");
+                assert_eq!(body["action"], "REJECTED");
+
+                let (scans, present, audit) = backing_db
+                    .try_run(|db| {
+                        Ok((
+                            db.all_scans("Alice")?.len(),
+                            db.get_present()?,
+                            db.audit_entries(0, 10)?,
+                        ))
+                    })
+                    .await
+                    .unwrap();
+                assert_eq!(scans, 1);
+                assert_eq!(present.len(), 1);
+                assert_eq!(present[0].0, "Alice");
+                assert!(audit.iter().any(|entry| entry.action == "scan_added"
+                    && entry.subject == "Alice"
+                    && entry.detail.starts_with("phone-amy at ")));
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn scans_need_the_token() {
+        let backing_db = DbHandle::spawn(|| BackingDatabase::new(None).unwrap());
+        let (submissions_tx, submissions_rx) = async_channel::unbounded();
+        let (refresh_tx, _refresh_rx) = async_channel::unbounded();
+        let intake = ScanIntake {
+            submissions: submissions_tx,
+            last_by_source: RefCell::default(),
+        };
+        let writer = ApiWriter {
+            backing_db: backing_db.clone(),
+            refresh_tx,
+        };
+        let limiter = UnauthorizedLimiter::default();
+        let request = Request {
+            db: &backing_db,
+            token: "secret",
+            intake: &intake,
+            writer: &writer,
+            limiter: &limiter,
+            addr: IpAddr::from([127, 0, 0, 1]),
+        };
+
+        let body = r#"{"payload": "Alice"}"#;
+        let (status, _) = send(
+            request,
+            &format!(
+                "POST /api/scan HTTP/1.1\r\nAuthorization: Bearer wrong\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            ),
+        )
+        .await;
+        assert_eq!(status, "HTTP/1.1 401 Unauthorized");
+        assert!(submissions_rx.is_empty());
+    }
+}
@@ -4,53 +4,379 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::sync::atomic::AtomicU32;
+//! Lock-free ring buffer with a fixed number of writers and readers, each
+//! reader seeing every value.
+//!
+//! Readers borrow values in place through handles, which advance the
+//! reader on drop. A writer never reuses a slot a reader may still read:
+//! write positions are published with [`Ordering::Release`] after the slot
+//! is filled, and read pointers with [`Ordering::Release`] when a handle
+//! drops, each loaded with [`Ordering::Acquire`] on the other side. Blocked
+//! readers sleep on the low 32 bits of the write position, which may wake
+//! them spuriously but never misses a write.
+//!
+//! Built with `--cfg loom`, the atomics, slots, and waits come from loom, so
+//! `RUSTFLAGS="--cfg loom" cargo test --lib --release atomic_buf` model
+//! checks the tests in `loom_tests`. Timed waits then park like on targets
+//! without a futex, as loom can not model one.
+
 use std::{
     array,
-    cell::UnsafeCell,
-    hint::spin_loop,
+    fmt::{self, Debug},
     marker::PhantomData,
     ops::Deref,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+#[cfg(loom)]
+use loom::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize},
+    },
+    thread::{self, yield_now},
+};
+#[cfg(not(loom))]
+use std::{
+    cell::UnsafeCell,
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize},
     thread::yield_now,
 };
+#[cfg(all(not(loom), not(target_os = "linux")))]
+use std::{sync::Mutex, thread};
+
+// Each read pointer holds a count of slots consumed, shifted left by one,
+// with the low bit claimed while a read handle is alive. The count never
+// repeats, so a writer moving an unclaimed reader forward can not be
+// confused with the reader's own progress.
+const CLAIMED: usize = 1;
+
+/// Stored in `wait_for_step` on close. Never a write position, so readers
+/// waiting on one wake and see the buffer closed.
+const CLOSED_STEP: u32 = u32::MAX;
+
+/// Buffer index of a read pointer value.
+const fn read_slot<const N: usize>(read_word: usize) -> usize {
+    (read_word >> 1) % N
+}
+
+/// Read pointer value `count` slots past `read_word`, unclaimed.
+const fn skip_read_word(read_word: usize, count: usize) -> usize {
+    ((read_word >> 1).wrapping_add(count)) << 1
+}
+
+/// Read pointer value one slot past `read_word`, unclaimed.
+const fn next_read_word(read_word: usize) -> usize {
+    skip_read_word(read_word, 1)
+}
+
+/// Values between a read pointer and the write position, including a
+/// claimed one.
+const fn queued<const N: usize>(write_pos: usize, read_word: usize) -> usize {
+    (write_pos + N - read_slot::<N>(read_word)) % N
+}
+
+/// One buffered value.
+///
+/// Slot access goes through here so loom tracks it, for as long as a read
+/// handle holds the value.
+struct Slot<T>(UnsafeCell<T>);
+
+impl<T> Debug for Slot<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Slot { .. }")
+    }
+}
+
+/// A read handle's access to its slot, checked by loom until it drops.
+struct SlotAccess<T> {
+    #[cfg(loom)]
+    _ptr: loom::cell::ConstPtr<T>,
+    _value: PhantomData<*const T>,
+}
+
+impl<T> Debug for SlotAccess<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SlotAccess")
+    }
+}
+
+impl<T> Slot<T> {
+    fn new(value: T) -> Self {
+        Self(UnsafeCell::new(value))
+    }
+
+    /// SAFETY: the slot must not be written until the access drops.
+    unsafe fn borrow(&self) -> (&T, SlotAccess<T>) {
+        #[cfg(not(loom))]
+        let value = unsafe { &*self.0.get() };
+        #[cfg(loom)]
+        let (value, ptr) = {
+            let ptr = self.0.get();
+            // SAFETY: the reference is only used while `ptr` lives in the
+            // same handle.
+            (unsafe { &*std::ptr::from_ref(ptr.deref()) }, ptr)
+        };
+
+        (
+            value,
+            SlotAccess {
+                #[cfg(loom)]
+                _ptr: ptr,
+                _value: PhantomData,
+            },
+        )
+    }
+
+    /// SAFETY: nothing else may access the slot during `fill`.
+    unsafe fn fill(&self, fill: impl FnOnce(&mut T)) {
+        #[cfg(not(loom))]
+        fill(unsafe { &mut *self.0.get() });
+        #[cfg(loom)]
+        self.0.with_mut(|value| fill(unsafe { &mut *value }));
+    }
+}
+
+/// Blocks while `atomic` holds `value`, until woken or `deadline` passes.
+/// May return spuriously.
+#[cfg(all(target_os = "linux", not(loom)))]
+fn futex_wait_until(atomic: &AtomicU32, value: u32, deadline: Instant) {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let timeout = libc::timespec {
+        tv_sec: remaining.as_secs() as libc::time_t,
+        tv_nsec: remaining.subsec_nanos() as libc::c_long,
+    };
+
+    // SAFETY: the futex word and timeout outlive the call. Woken by
+    // `atomic_wait::wake_all`, which also uses private futexes.
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            atomic.as_ptr(),
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            value,
+            &timeout as *const libc::timespec,
+        );
+    }
+}
+
+/// A reader's thread while it is in a timed wait, unparked on writes.
+///
+/// `atomic_wait` has no timed wait, and only Linux has a futex to fall back
+/// on.
+#[cfg(any(loom, not(target_os = "linux")))]
+struct Parker(Mutex<Option<thread::Thread>>);
+
+#[cfg(any(loom, not(target_os = "linux")))]
+impl Debug for Parker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Parker")
+    }
+}
+
+#[cfg(any(loom, not(target_os = "linux")))]
+impl Parker {
+    fn new() -> Self {
+        Self(Mutex::new(None))
+    }
+
+    fn unpark(&self) {
+        if let Some(thread) = &*self.0.lock().unwrap() {
+            thread.unpark();
+        }
+    }
+}
+
+/// Blocks while `atomic` holds `value`, until unparked or `deadline` passes.
+/// May return spuriously.
+#[cfg(any(loom, not(target_os = "linux")))]
+fn park_until(atomic: &AtomicU32, parker: &Parker, value: u32, deadline: Instant) {
+    // Registered before the check, so a write after the check unparks this
+    // thread. A write before it is seen by the check.
+    *parker.0.lock().unwrap() = Some(thread::current());
+    if atomic.load(Ordering::Acquire) == value {
+        #[cfg(not(loom))]
+        thread::park_timeout(deadline.saturating_duration_since(Instant::now()));
+        // Loom has no clock, a wait is either over or until unparked.
+        #[cfg(loom)]
+        if Instant::now() < deadline {
+            thread::park();
+        }
+    }
+    *parker.0.lock().unwrap() = None;
+}
+
+/// Blocks while `atomic` holds `value`. May return spuriously.
+fn wait(atomic: &AtomicU32, value: u32) {
+    #[cfg(not(loom))]
+    atomic_wait::wait(atomic, value);
+    // Lets the model run the writer instead.
+    #[cfg(loom)]
+    if atomic.load(Ordering::Acquire) == value {
+        yield_now();
+    }
+}
 
 #[derive(Debug)]
 /// Shared resources for a ring buffer shareable between threads.
+///
+/// Holds up to `N - 1` values per reader, one slot always separates the
+/// writer from the slowest attached reader.
 pub struct AtomicBuffer<T: Send + Sync, const N: usize, const READERS: usize> {
-    data: Box<[UnsafeCell<T>; N]>,
+    data: Box<[Slot<T>; N]>,
     write_ptr: AtomicUsize,
     read_ptrs: [AtomicUsize; READERS],
+    /// Readers the writer ignores, see [`AtomicBufferReader::detach`].
+    detached: [AtomicBool; READERS],
+    /// Most values queued per reader, see [`LagPolicy`].
+    max_lags: [AtomicUsize; READERS],
     wait_for_step: AtomicU32,
+    dropped_writes: AtomicU64,
+    closed: AtomicBool,
+    /// Ticket lock serializing writers, the next ticket to hand out.
+    write_ticket: AtomicUsize,
+    /// Ticket lock serializing writers, the ticket allowed to write.
+    write_turn: AtomicUsize,
+    /// Writers yet to close, the buffer closes with the last one.
+    open_writers: AtomicUsize,
+    #[cfg(any(loom, not(target_os = "linux")))]
+    parkers: [Parker; READERS],
 }
 
 #[derive(Debug)]
 pub struct AtomicBufferWriter<'a, T: Send + Sync, const N: usize, const READERS: usize> {
-    data: &'a [UnsafeCell<T>; N],
+    data: &'a [Slot<T>; N],
     write_ptr: &'a AtomicUsize,
     read_ptrs: &'a [AtomicUsize; READERS],
+    detached: &'a [AtomicBool; READERS],
+    max_lags: &'a [AtomicUsize; READERS],
     wait_for_step: &'a AtomicU32,
+    dropped_writes: &'a AtomicU64,
+    closed: &'a AtomicBool,
+    write_ticket: &'a AtomicUsize,
+    write_turn: &'a AtomicUsize,
+    open_writers: &'a AtomicUsize,
+    /// Set once this writer closed, later writes are discarded.
+    writer_closed: bool,
+    #[cfg(any(loom, not(target_os = "linux")))]
+    parkers: &'a [Parker; READERS],
+}
+
+/// A writer's turn in the ticket lock, passed to the next writer on drop.
+struct WriteTurn<'a>(&'a AtomicUsize);
+
+impl Drop for WriteTurn<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Waits for the writer holding the next ticket before this one.
+fn take_turn<'a>(write_ticket: &AtomicUsize, write_turn: &'a AtomicUsize) -> WriteTurn<'a> {
+    let ticket = write_ticket.fetch_add(1, Ordering::Relaxed);
+    // Writes are short, a single copy into the slot.
+    while write_turn.load(Ordering::Acquire) != ticket {
+        spin_loop();
+        yield_now();
+    }
+    WriteTurn(write_turn)
 }
 
 #[derive(Debug)]
 pub struct AtomicBufferReader<'a, T: Send + Sync, const N: usize> {
-    data: &'a [UnsafeCell<T>; N],
+    data: &'a [Slot<T>; N],
     write_ptr: &'a AtomicUsize,
     read_ptr: &'a AtomicUsize,
+    detached: &'a AtomicBool,
+    max_lag: &'a AtomicUsize,
     wait_for_step: &'a AtomicU32,
+    closed: &'a AtomicBool,
+    #[cfg(any(loom, not(target_os = "linux")))]
+    parker: &'a Parker,
+}
+
+/// How far a reader may fall behind the writer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// Up to the buffer size, after which the writer waits or overwrites
+    /// depending on the write.
+    #[default]
+    Unbounded,
+    /// The writer skips the reader's oldest values once more than
+    /// `max_lag` are queued, at least 1. A held value is never skipped, the
+    /// reader is moved on the first write after its handle drops.
+    SkipOldest { max_lag: usize },
+}
+
+/// Why no value was read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadError {
+    /// Nothing is buffered, or nothing arrived before the deadline.
+    Empty,
+    /// The writer closed the buffer and every value has been read.
+    Closed,
+}
+
+/// Outcome of a claim attempt.
+enum Claim {
+    Ready(usize),
+    /// Holds the write pointer to wait on.
+    Empty(usize),
+    Closed,
 }
 
 /// Provides a safe handle to the buffered value.
 ///
-/// Advances the read pointer on [`Drop`]. If this type is forgotten,
-/// the next handle will return the same value.
+/// Advances the read pointer on [`Drop`], unless it came from
+/// [`AtomicBufferReader::peek`]. If this type is forgotten, the next handle
+/// will return the same value, and the writer stops once it wraps around to
+/// it.
 #[derive(Debug)]
 pub struct AtomicBufferReadHandle<'a, T: Send + Sync, const N: usize> {
     pub value: &'a T,
     read_ptr: &'a AtomicUsize,
+    /// Slots the read pointer moves on drop.
+    advance: usize,
+    _access: SlotAccess<T>,
+    _data_len: PhantomData<[(); N]>,
+}
+
+/// Provides a safe handle to two consecutive buffered values, from
+/// [`AtomicBufferReader::read_pair`].
+///
+/// Advances the read pointer past both on [`Drop`].
+#[derive(Debug)]
+pub struct AtomicBufferPairHandle<'a, T: Send + Sync, const N: usize> {
+    /// The older value.
+    pub first: &'a T,
+    pub second: &'a T,
+    read_ptr: &'a AtomicUsize,
+    _access: [SlotAccess<T>; 2],
     _data_len: PhantomData<[(); N]>,
 }
 
+/// How far behind the readers are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferStats<const READERS: usize> {
+    /// Values waiting for each reader, in split order.
+    pub queued: [usize; READERS],
+    /// Writes discarded because a reader was too far behind.
+    pub dropped_writes: u64,
+}
+
+impl<const READERS: usize> Default for BufferStats<READERS> {
+    fn default() -> Self {
+        Self {
+            queued: [0; READERS],
+            dropped_writes: 0,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct AtomicBufferSplit<'a, T: Send + Sync, const N: usize, const READERS: usize> {
     pub write_ptr: AtomicBufferWriter<'a, T, N, READERS>,
@@ -87,6 +413,9 @@ unsafe impl<T: Send + Sync, const N: usize> Sync for AtomicBufferReader<'_, T, N
 unsafe impl<T: Send + Sync, const N: usize> Send for AtomicBufferReadHandle<'_, T, N> {}
 unsafe impl<T: Send + Sync, const N: usize> Sync for AtomicBufferReadHandle<'_, T, N> {}
 
+unsafe impl<T: Send + Sync, const N: usize> Send for AtomicBufferPairHandle<'_, T, N> {}
+unsafe impl<T: Send + Sync, const N: usize> Sync for AtomicBufferPairHandle<'_, T, N> {}
+
 // ---------- ---------- //
 
 impl<T, const N: usize, const READERS: usize> AtomicBuffer<T, N, READERS>
@@ -95,105 +424,345 @@ where
 {
     /// Creates a ring buffer shareable between threads.
     ///
-    /// Has one writer and a static number of readers.
-    /// [`Self::split`] must be used to get writers and readers.
+    /// Has a static number of writers and readers. [`Self::split`] or
+    /// [`Self::split_writers`] must be used to get writers and readers.
     pub fn new() -> Self {
         Self {
-            data: Box::new(array::from_fn(|_idx| UnsafeCell::new(T::default()))),
-            write_ptr: 0.into(),
-            read_ptrs: [0; READERS].map(AtomicUsize::from),
-            wait_for_step: 0.into(),
+            data: Box::new(array::from_fn(|_idx| Slot::new(T::default()))),
+            // Loom's atomics have no `From`.
+            write_ptr: AtomicUsize::new(0),
+            read_ptrs: array::from_fn(|_idx| AtomicUsize::new(0)),
+            detached: array::from_fn(|_idx| AtomicBool::new(false)),
+            max_lags: array::from_fn(|_idx| AtomicUsize::new(usize::MAX)),
+            wait_for_step: AtomicU32::new(0),
+            dropped_writes: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            write_ticket: AtomicUsize::new(0),
+            write_turn: AtomicUsize::new(0),
+            open_writers: AtomicUsize::new(0),
+            #[cfg(any(loom, not(target_os = "linux")))]
+            parkers: array::from_fn(|_idx| Parker::new()),
         }
     }
 }
 
+impl<T, const N: usize, const READERS: usize> Default for AtomicBuffer<T, N, READERS>
+where
+    T: Send + Sync + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T, const N: usize, const READERS: usize> AtomicBuffer<T, N, READERS>
 where
     T: Send + Sync,
 {
+    fn writer(&self) -> AtomicBufferWriter<'_, T, N, READERS> {
+        AtomicBufferWriter {
+            data: &self.data,
+            write_ptr: &self.write_ptr,
+            read_ptrs: &self.read_ptrs,
+            detached: &self.detached,
+            max_lags: &self.max_lags,
+            wait_for_step: &self.wait_for_step,
+            dropped_writes: &self.dropped_writes,
+            closed: &self.closed,
+            write_ticket: &self.write_ticket,
+            write_turn: &self.write_turn,
+            open_writers: &self.open_writers,
+            writer_closed: false,
+            #[cfg(any(loom, not(target_os = "linux")))]
+            parkers: &self.parkers,
+        }
+    }
+
+    fn readers(&self) -> [AtomicBufferReader<'_, T, N>; READERS] {
+        array::from_fn(|idx| AtomicBufferReader {
+            data: &self.data,
+            write_ptr: &self.write_ptr,
+            read_ptr: &self.read_ptrs[idx],
+            detached: &self.detached[idx],
+            max_lag: &self.max_lags[idx],
+            wait_for_step: &self.wait_for_step,
+            closed: &self.closed,
+            #[cfg(any(loom, not(target_os = "linux")))]
+            parker: &self.parkers[idx],
+        })
+    }
+
     pub fn split(&mut self) -> AtomicBufferSplit<'_, T, N, READERS> {
+        self.open_writers.store(1, Ordering::Relaxed);
         AtomicBufferSplit {
-            write_ptr: AtomicBufferWriter {
-                data: &self.data,
-                write_ptr: &self.write_ptr,
-                read_ptrs: &self.read_ptrs,
-                wait_for_step: &self.wait_for_step,
-            },
-            read_ptrs: self
-                .read_ptrs
-                .each_ref()
-                .map(|read_ptr| AtomicBufferReader {
-                    data: &self.data,
-                    write_ptr: &self.write_ptr,
-                    read_ptr,
-                    wait_for_step: &self.wait_for_step,
-                }),
+            write_ptr: self.writer(),
+            read_ptrs: self.readers(),
         }
     }
+
+    /// [`Self::split`] with `W` writers, e.g. one per camera.
+    ///
+    /// Writers take turns through a ticket lock, so values from different
+    /// writers are interleaved in the order their writes started. The
+    /// buffer closes once every writer has closed.
+    #[allow(clippy::type_complexity)]
+    pub fn split_writers<const W: usize>(
+        &mut self,
+    ) -> (
+        [AtomicBufferWriter<'_, T, N, READERS>; W],
+        [AtomicBufferReader<'_, T, N>; READERS],
+    ) {
+        self.open_writers.store(W, Ordering::Relaxed);
+        (array::from_fn(|_idx| self.writer()), self.readers())
+    }
 }
 
 impl<T, const N: usize, const READERS: usize> AtomicBufferWriter<'_, T, N, READERS>
 where
     T: Send + Sync,
 {
-    fn write_inner<U>(&mut self, value: U, write_pos: usize, next_write_pos: usize) -> bool
-    where
-        T: From<U>,
-    {
+    /// Wakes readers waiting on `wait_for_step`, with or without a timeout.
+    fn wake_readers(&self) {
+        #[cfg(not(loom))]
+        atomic_wait::wake_all(self.wait_for_step);
+        #[cfg(any(loom, not(target_os = "linux")))]
+        for parker in self.parkers {
+            parker.unpark();
+        }
+    }
+
+    /// Moves unclaimed readers past [`LagPolicy::SkipOldest`] back to their
+    /// limit. Readers that claim meanwhile are moved on a later write.
+    fn skip_lagging(&self, write_pos: usize) {
+        for (read_ptr, max_lag) in self.read_ptrs.iter().zip(self.max_lags) {
+            let max_lag = max_lag.load(Ordering::Relaxed);
+            let read_word = read_ptr.load(Ordering::Acquire);
+            let lag = queued::<N>(write_pos, read_word);
+            if read_word & CLAIMED == 0 && lag > max_lag {
+                // Fails while the reader claims or advances, either way
+                // the handle keeps its slot.
+                let _ = read_ptr.compare_exchange(
+                    read_word,
+                    skip_read_word(read_word, lag - max_lag),
+                    Ordering::AcqRel,
+                    Ordering::Relaxed,
+                );
+            }
+        }
+    }
+
+    /// Read pointers of readers that are not detached.
+    fn attached_read_ptrs(&self) -> impl Iterator<Item = &AtomicUsize> {
+        // SeqCst pairs with the store in `AtomicBufferReader::attach`.
+        self.read_ptrs
+            .iter()
+            .zip(self.detached)
+            .filter(|(_, detached)| !detached.load(Ordering::SeqCst))
+            .map(|(read_ptr, _)| read_ptr)
+    }
+
+    /// Whether any reader still holds the slot at `write_pos`, which is only
+    /// possible after [`Self::write_overwrite`] passed a reader.
+    fn slot_held(&self, write_pos: usize) -> bool {
+        self.attached_read_ptrs().any(|read_ptr| {
+            let read_word = read_ptr.load(Ordering::Acquire);
+            read_word & CLAIMED != 0 && read_slot::<N>(read_word) == write_pos
+        })
+    }
+
+    /// SAFETY: no reader may hold the slot at `write_pos`, and no reader
+    /// may be waiting on `next_write_pos` unless it holds that slot.
+    unsafe fn write_unchecked(
+        &mut self,
+        fill: impl FnOnce(&mut T),
+        write_pos: usize,
+        next_write_pos: usize,
+    ) {
+        // SAFETY: ring buffer logic means this is not read until after
+        // the value is fully written.
+        unsafe { self.data[write_pos].fill(fill) };
+
+        // The release ordering is coupled with a load ordering in other
+        // threads that guarantee next_item is valid. SeqCst also orders it
+        // against readers reattaching, so at most one write misses a
+        // reattached reader, and that write fills the slot it waits on.
+        self.write_ptr.store(next_write_pos, Ordering::SeqCst);
+
+        self.skip_lagging(next_write_pos);
+
+        // Minimize spurious waits.
+        // The u32 cast is only an issue when the size is > u32 and
+        // there could be an overlap with truncation.
+        // A wake will still occur on the next written value.
+        self.wait_for_step
+            .store(next_write_pos as u32, Ordering::Relaxed);
+        // Notify any readers who queued instead of busy waiting.
+        self.wake_readers();
+    }
+
+    fn write_inner(
+        &mut self,
+        fill: impl FnOnce(&mut T),
+        write_pos: usize,
+        next_write_pos: usize,
+    ) -> bool {
         // Ring implementation drops an index for simple comparison.
         // Write would only be invalidating reads if the next write idx overlaps.
-        if self
-            .read_ptrs
-            .iter()
-            .any(|read_ptr| read_ptr.load(Ordering::Relaxed) == next_write_pos)
+        if self.slot_held(write_pos)
+            || self
+                .attached_read_ptrs()
+                .any(|read_ptr| read_slot::<N>(read_ptr.load(Ordering::Acquire)) == next_write_pos)
         {
+            self.dropped_writes.fetch_add(1, Ordering::Relaxed);
             false
         } else {
-            let next_item_ptr = &self.data[write_pos];
-
-            // SAFETY: ring buffer logic means this is not read until after
-            // the value is fully written.
-            let next_item = unsafe { &mut *next_item_ptr.get() };
-            *next_item = value.into();
-
-            // The release ordering is coupled with a load ordering in other
-            // threads that guarantee next_item is valid.
-            self.write_ptr.store(next_write_pos, Ordering::Release);
-
-            // Minimize spurious waits.
-            // The u32 cast is only an issue when the size is > u32 and
-            // there could be an overlap with truncation.
-            // A wake will still occur on the next written value.
-            self.wait_for_step
-                .store(next_write_pos as u32, Ordering::Relaxed);
-            // Notify any readers who queued instead of busy waiting.
-            atomic_wait::wake_all(self.wait_for_step);
-
+            // SAFETY: checked above, readers only move away from these
+            // positions.
+            unsafe { self.write_unchecked(fill, write_pos, next_write_pos) };
             true
         }
     }
+
+    /// Will write to the next index, discarding the oldest value for any
+    /// reader that is behind.
+    ///
+    /// Returns false, discarding `value` instead, only while a reader that
+    /// was passed this way still holds its value, or after [`Self::close`].
+    pub fn write_overwrite<U>(&mut self, value: U) -> bool
+    where
+        T: From<U>,
+    {
+        self.write_overwrite_with(|slot| *slot = value.into())
+    }
+
+    /// [`Self::write_overwrite`], filling the slot's previous value in place
+    /// with `fill`. Reusing the slot's allocations avoids allocating per
+    /// write.
+    pub fn write_overwrite_with(&mut self, fill: impl FnOnce(&mut T)) -> bool {
+        if self.writer_closed {
+            return false;
+        }
+        let _turn = take_turn(self.write_ticket, self.write_turn);
+        let write_pos = self.write_ptr.load(Ordering::Relaxed);
+        let next_write_pos = write_pos.wrapping_add(1) % N;
+
+        for read_ptr in self.attached_read_ptrs() {
+            let mut read_word = read_ptr.load(Ordering::Acquire);
+            loop {
+                let read_pos = read_slot::<N>(read_word);
+                if read_word & CLAIMED != 0 {
+                    // A reader holding its oldest value is passed by the
+                    // write pointer. It continues with the next oldest value
+                    // once the handle drops.
+                    if read_pos == write_pos {
+                        self.dropped_writes.fetch_add(1, Ordering::Relaxed);
+                        return false;
+                    }
+                    break;
+                }
+                if read_pos != next_write_pos {
+                    break;
+                }
+
+                // Only succeeds while the reader has not claimed the slot,
+                // otherwise the claim is handled on the next pass.
+                match read_ptr.compare_exchange_weak(
+                    read_word,
+                    next_read_word(read_word),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => read_word = current,
+                }
+            }
+        }
+
+        // SAFETY: the claimed slot at write_pos was rejected, and any reader
+        // at next_write_pos was moved forward or holds that slot.
+        unsafe { self.write_unchecked(fill, write_pos, next_write_pos) };
+        true
+    }
+
+    /// Stops readers once they have read every value written so far, e.g.
+    /// on shutdown. Writes after are discarded.
+    ///
+    /// With several writers, the buffer only closes with the last of them.
+    pub fn close(&mut self) {
+        if std::mem::replace(&mut self.writer_closed, true) {
+            return;
+        }
+        if self.open_writers.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.closed.store(true, Ordering::Release);
+            self.wait_for_step.store(CLOSED_STEP, Ordering::Relaxed);
+            self.wake_readers();
+        }
+    }
+
+    /// Writes discarded so far.
+    pub fn dropped_writes(&self) -> u64 {
+        self.dropped_writes.load(Ordering::Relaxed)
+    }
+
+    /// Current backlog of every reader, 0 for detached readers. The buffer
+    /// itself is borrowed while split, so this lives on the writer.
+    pub fn stats(&self) -> BufferStats<READERS> {
+        let write_pos = self.write_ptr.load(Ordering::Relaxed);
+        BufferStats {
+            queued: array::from_fn(|idx| {
+                if self.detached[idx].load(Ordering::Relaxed) {
+                    0
+                } else {
+                    queued::<N>(write_pos, self.read_ptrs[idx].load(Ordering::Relaxed))
+                }
+            }),
+            dropped_writes: self.dropped_writes(),
+        }
+    }
+
     /// Will write to the next index if there is capacity.
     ///
-    /// Returns true if a write succeeded. Returns false if the buffer is full.
+    /// Returns true if a write succeeded. Returns false if the buffer is full,
+    /// or after [`Self::close`].
     pub fn try_write<U>(&mut self, value: U) -> bool
     where
         T: From<U>,
     {
+        self.write_with(|slot| *slot = value.into())
+    }
+
+    /// [`Self::try_write`], filling the slot's previous value in place with
+    /// `fill`. `fill` is not called when the buffer is full.
+    pub fn write_with(&mut self, fill: impl FnOnce(&mut T)) -> bool {
+        if self.writer_closed {
+            return false;
+        }
+        let _turn = take_turn(self.write_ticket, self.write_turn);
         let write_pos = self.write_ptr.load(Ordering::Relaxed);
         let next_write_pos = write_pos.wrapping_add(1) % N;
-        self.write_inner(value, write_pos, next_write_pos)
+        self.write_inner(fill, write_pos, next_write_pos)
     }
 
     /// Will write to the next index, spinning until there is capacity.
-    #[allow(dead_code)]
+    /// Discards `value` after [`Self::close`].
     pub fn write_spin<U>(&mut self, value: &U)
     where
         T: for<'a> From<&'a U>,
         U: ?Sized,
     {
-        let write_pos = self.write_ptr.load(Ordering::Relaxed);
-        let next_write_pos = write_pos.wrapping_add(1) % N;
-        while !self.write_inner(value, write_pos, next_write_pos) {
+        if self.writer_closed {
+            return;
+        }
+        loop {
+            // Released between attempts so other writers are not stalled
+            // behind a full buffer.
+            let turn = take_turn(self.write_ticket, self.write_turn);
+            let write_pos = self.write_ptr.load(Ordering::Relaxed);
+            let next_write_pos = write_pos.wrapping_add(1) % N;
+            if self.write_inner(|slot| *slot = value.into(), write_pos, next_write_pos) {
+                return;
+            }
+            drop(turn);
             spin_loop();
             yield_now();
         }
@@ -204,57 +773,308 @@ impl<T, const N: usize> AtomicBufferReader<'_, T, N>
 where
     T: Send + Sync,
 {
-    /// SAFETY: The read pointer must not equal the write pointer.
+    /// SAFETY: The read pointer must be claimed at `read_pos`.
     ///
     /// The write pointer must also have been loaded with [`Ordering::Acquire`]
     /// to sync the underlying data.
     unsafe fn read_inner(&mut self, read_pos: usize) -> AtomicBufferReadHandle<'_, T, N> {
-        let value_ptr = &self.data[read_pos];
-
         // SAFETY: prior checks ensured that the writer is not mutating
         // this value. It will be kept valid until at least the handle is
         // dropped. Any simultaneous readers cannot modify this data.
-        let value = unsafe { &*value_ptr.get() };
+        let (value, access) = unsafe { self.data[read_pos].borrow() };
 
         AtomicBufferReadHandle {
             value,
             read_ptr: self.read_ptr,
+            advance: 1,
+            _access: access,
             _data_len: PhantomData,
         }
     }
 
+    /// Values waiting for this reader, 0 while detached.
+    pub fn len(&self) -> usize {
+        if self.is_detached() {
+            return 0;
+        }
+        queued::<N>(
+            self.write_ptr.load(Ordering::Relaxed),
+            self.read_ptr.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Blocks while `wait_for_step` holds `step`, until woken or `deadline`
+    /// passes. May return spuriously.
+    fn wait_until(&self, step: u32, deadline: Instant) {
+        #[cfg(all(target_os = "linux", not(loom)))]
+        futex_wait_until(self.wait_for_step, step, deadline);
+        #[cfg(any(loom, not(target_os = "linux")))]
+        park_until(self.wait_for_step, self.parker, step, deadline);
+    }
+
+    pub fn set_lag_policy(&mut self, policy: LagPolicy) {
+        let max_lag = match policy {
+            LagPolicy::Unbounded => usize::MAX,
+            LagPolicy::SkipOldest { max_lag } => max_lag.max(1),
+        };
+        self.max_lag.store(max_lag, Ordering::Relaxed);
+    }
+
+    /// Stops the writer from waiting on or moving this reader, e.g. while
+    /// nobody consumes its values. The writer then never counts the buffer
+    /// as full because of it.
+    ///
+    /// The next read reattaches, skipping everything written meanwhile.
+    pub fn detach(&mut self) {
+        self.detached.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.detached.load(Ordering::Relaxed)
+    }
+
+    /// Reattaches a detached reader at the write pointer. The slots it
+    /// skips may have been overwritten while detached, so none are read.
+    fn attach(&self) {
+        // Only this reader sets its flag.
+        if !self.detached.load(Ordering::Relaxed) {
+            return;
+        }
+        // SeqCst so the writer sees this reader again before the jump below
+        // loads the write pointer.
+        self.detached.store(false, Ordering::SeqCst);
+        self.skip_queued();
+    }
+
+    /// Moves the read pointer to the write pointer, returning how many
+    /// values were skipped.
+    fn skip_queued(&self) -> usize {
+        let mut read_word = self.read_ptr.load(Ordering::Relaxed);
+        loop {
+            // Skipped values are never read, so their memory needs no
+            // synchronizing.
+            let skipped = queued::<N>(self.write_ptr.load(Ordering::SeqCst), read_word);
+            // Release matches a handle drop, the writer may reuse the slots.
+            match self.read_ptr.compare_exchange_weak(
+                read_word,
+                skip_read_word(read_word, skipped),
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return skipped,
+                // Moved forward by a writer discarding the oldest value.
+                Err(current) => read_word = current,
+            }
+        }
+    }
+
+    /// Claims the next buffered value, returning its position.
+    fn try_claim(&self) -> Claim {
+        self.attach();
+        let mut read_word = self.read_ptr.load(Ordering::Relaxed);
+        loop {
+            let read_pos = read_slot::<N>(read_word);
+            // Equality check also synchronizes buffer memory.
+            let write_pos = self.write_ptr.load(Ordering::Acquire);
+            if read_pos == write_pos {
+                // Closing follows the last write, so a close seen here with
+                // nothing new buffered means nothing else will be.
+                if self.closed.load(Ordering::Acquire)
+                    && read_pos == self.write_ptr.load(Ordering::Acquire)
+                {
+                    return Claim::Closed;
+                }
+                return Claim::Empty(write_pos);
+            }
+
+            match self.read_ptr.compare_exchange_weak(
+                read_word,
+                read_word | CLAIMED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Claim::Ready(read_pos),
+                // Moved forward by a writer discarding the oldest value.
+                Err(current) => read_word = current,
+            }
+        }
+    }
+
     /// Will return the next buffered value if available.
-    pub fn try_read(&mut self) -> Option<AtomicBufferReadHandle<'_, T, N>> {
-        let read_pos = self.read_ptr.load(Ordering::Relaxed);
-        // Equality check also synchronizes buffer memory.
-        if read_pos != self.write_ptr.load(Ordering::Acquire) {
-            // SAFETY: write_ptr != read_ptr and was acquired.
-            Some(unsafe { self.read_inner(read_pos) })
-        } else {
-            None
+    pub fn try_read(&mut self) -> Result<AtomicBufferReadHandle<'_, T, N>, ReadError> {
+        match self.try_claim() {
+            // SAFETY: claimed after write_ptr was acquired.
+            Claim::Ready(read_pos) => Ok(unsafe { self.read_inner(read_pos) }),
+            Claim::Empty(_) => Err(ReadError::Empty),
+            Claim::Closed => Err(ReadError::Closed),
         }
     }
 
+    /// Will return the next buffered value if available, without consuming
+    /// it. The next read returns the same value.
+    ///
+    /// The slot is claimed while the handle lives, the same as a read, so the
+    /// writer can not overwrite it.
+    pub fn peek(&mut self) -> Result<AtomicBufferReadHandle<'_, T, N>, ReadError> {
+        match self.try_claim() {
+            Claim::Ready(read_pos) => {
+                // SAFETY: claimed after write_ptr was acquired.
+                let mut handle = unsafe { self.read_inner(read_pos) };
+                handle.advance = 0;
+                Ok(handle)
+            }
+            Claim::Empty(_) => Err(ReadError::Empty),
+            Claim::Closed => Err(ReadError::Closed),
+        }
+    }
+
+    /// Will return the next two buffered values if both are available, e.g.
+    /// to compare consecutive frames.
+    ///
+    /// Only the first slot is claimed. The writer stops at a claimed slot,
+    /// and reaches the second slot only after the first, so neither is
+    /// overwritten while the handle lives. Fails with [`ReadError::Closed`]
+    /// once fewer than two values remain in a closed buffer.
+    pub fn read_pair(&mut self) -> Result<AtomicBufferPairHandle<'_, T, N>, ReadError> {
+        self.attach();
+        let mut read_word = self.read_ptr.load(Ordering::Relaxed);
+        let read_pos = loop {
+            // Also synchronizes buffer memory for both slots.
+            let write_pos = self.write_ptr.load(Ordering::Acquire);
+            if queued::<N>(write_pos, read_word) < 2 {
+                if self.closed.load(Ordering::Acquire)
+                    && write_pos == self.write_ptr.load(Ordering::Acquire)
+                {
+                    return Err(ReadError::Closed);
+                }
+                return Err(ReadError::Empty);
+            }
+
+            match self.read_ptr.compare_exchange_weak(
+                read_word,
+                read_word | CLAIMED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break read_slot::<N>(read_word),
+                // Moved forward by a writer discarding the oldest value.
+                Err(current) => read_word = current,
+            }
+        };
+
+        // SAFETY: both slots were written before write_ptr was acquired, and
+        // are protected by the claim as documented above.
+        let ((first, first_access), (second, second_access)) = unsafe {
+            (
+                self.data[read_pos].borrow(),
+                self.data[(read_pos + 1) % N].borrow(),
+            )
+        };
+        Ok(AtomicBufferPairHandle {
+            first,
+            second,
+            read_ptr: self.read_ptr,
+            _access: [first_access, second_access],
+            _data_len: PhantomData,
+        })
+    }
+
     /// Will return the next buffered value, spinning until available.
-    pub fn read_spin(&mut self) -> AtomicBufferReadHandle<'_, T, N> {
-        let read_pos = self.read_ptr.load(Ordering::Relaxed);
+    ///
+    /// Only fails with [`ReadError::Closed`].
+    pub fn read_spin(&mut self) -> Result<AtomicBufferReadHandle<'_, T, N>, ReadError> {
+        let read_pos = loop {
+            match self.try_claim() {
+                Claim::Ready(read_pos) => break read_pos,
+                Claim::Empty(write_pos) => wait(self.wait_for_step, write_pos as u32),
+                Claim::Closed => return Err(ReadError::Closed),
+            }
+        };
 
-        // In the case that the write pointer hasn't advanced, this saves on
-        // expensive memory synchronization.
-        loop {
-            let write_ptr_value = self.write_ptr.load(Ordering::Relaxed);
-            if read_pos != write_ptr_value {
-                break;
+        // SAFETY: claimed after write_ptr was acquired.
+        Ok(unsafe { self.read_inner(read_pos) })
+    }
+
+    /// Will return the next buffered value, waiting up to `timeout` for one.
+    pub fn read_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<AtomicBufferReadHandle<'_, T, N>, ReadError> {
+        self.read_deadline(Instant::now() + timeout)
+    }
+
+    /// Will return the next buffered value, waiting until `deadline` for one.
+    pub fn read_deadline(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<AtomicBufferReadHandle<'_, T, N>, ReadError> {
+        let read_pos = loop {
+            match self.try_claim() {
+                Claim::Ready(read_pos) => break read_pos,
+                Claim::Empty(_) if Instant::now() >= deadline => return Err(ReadError::Empty),
+                Claim::Empty(write_pos) => self.wait_until(write_pos as u32, deadline),
+                Claim::Closed => return Err(ReadError::Closed),
             }
+        };
 
-            atomic_wait::wait(self.wait_for_step, write_ptr_value as u32);
+        // SAFETY: claimed after write_ptr was acquired.
+        Ok(unsafe { self.read_inner(read_pos) })
+    }
+
+    /// Skips every buffered value, returning how many were skipped.
+    ///
+    /// Loads the write pointer once and moves the read pointer straight to
+    /// it, instead of claiming and releasing each value.
+    pub fn drain(&mut self) -> usize {
+        if self.is_detached() {
+            self.attach();
+            return 0;
         }
+        self.skip_queued()
+    }
+
+    /// Will return the newest buffered value if available, skipping every
+    /// older one.
+    ///
+    /// The write pointer is loaded with [`Ordering::Acquire`] before the
+    /// jump, so the newest value is fully written. The writer only reaches
+    /// that slot again after moving this reader forward, which fails the
+    /// claim and retries with a fresh write pointer.
+    pub fn read_latest(&mut self) -> Result<AtomicBufferReadHandle<'_, T, N>, ReadError> {
+        self.attach();
+        let mut read_word = self.read_ptr.load(Ordering::Relaxed);
+        let read_pos = loop {
+            let write_pos = self.write_ptr.load(Ordering::Acquire);
+            let waiting = queued::<N>(write_pos, read_word);
+            if waiting == 0 {
+                // Same as `try_claim`, a close seen with nothing new buffered
+                // means nothing else will be.
+                if self.closed.load(Ordering::Acquire)
+                    && write_pos == self.write_ptr.load(Ordering::Acquire)
+                {
+                    return Err(ReadError::Closed);
+                }
+                return Err(ReadError::Empty);
+            }
 
-        // Synchronizes the buffer memory.
-        assert_ne!(read_pos, self.write_ptr.load(Ordering::Acquire));
+            let latest_word = skip_read_word(read_word, waiting - 1);
+            match self.read_ptr.compare_exchange_weak(
+                read_word,
+                latest_word | CLAIMED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break read_slot::<N>(latest_word),
+                Err(current) => read_word = current,
+            }
+        };
 
-        // SAFETY: write_ptr != read_ptr and was acquired.
-        unsafe { self.read_inner(read_pos) }
+        // SAFETY: claimed after write_ptr was acquired.
+        Ok(unsafe { self.read_inner(read_pos) })
     }
 }
 
@@ -283,9 +1103,780 @@ where
 {
     fn drop(&mut self) {
         // This will not race, since there is only one handle for a read
-        // pointer at a time. No other code changes the value of a read pointer.
-        let ptr_val = self.read_ptr.load(Ordering::Relaxed);
+        // pointer at a time. Writers only move unclaimed read pointers.
+        let read_word = self.read_ptr.load(Ordering::Relaxed);
+        // Release so the writer reuses the slot only after this read.
+        self.read_ptr
+            .store(skip_read_word(read_word, self.advance), Ordering::Release);
+    }
+}
+
+impl<T, const N: usize> Drop for AtomicBufferPairHandle<'_, T, N>
+where
+    T: Send + Sync,
+{
+    fn drop(&mut self) {
+        // Same as a single handle, past both slots.
+        let read_word = self.read_ptr.load(Ordering::Relaxed);
         self.read_ptr
-            .store(ptr_val.wrapping_add(1) % N, Ordering::Relaxed);
+            .store(skip_read_word(read_word, 2), Ordering::Release);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use loom::thread;
+
+    use super::*;
+
+    /// Bounds preemptions, which keeps the models to seconds while still
+    /// covering the interleavings of these small buffers that matter.
+    fn model(f: impl Fn() + Sync + Send + 'static) {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(3);
+        builder.check(f);
+    }
+
+    /// Split of a buffer kept for the rest of the model run, so its halves
+    /// can move into loom threads.
+    fn leaked<const N: usize, const READERS: usize>()
+    -> AtomicBufferSplit<'static, usize, N, READERS> {
+        Box::leak(Box::new(AtomicBuffer::new())).split()
+    }
+
+    /// Retries until a reader frees a slot.
+    fn write<const N: usize, const READERS: usize>(
+        writer: &mut AtomicBufferWriter<'_, usize, N, READERS>,
+        value: usize,
+    ) {
+        while !writer.try_write(value) {
+            thread::yield_now();
+        }
+    }
+
+    /// Retries until a value is written.
+    fn read<const N: usize>(reader: &mut AtomicBufferReader<'_, usize, N>) -> usize {
+        loop {
+            match reader.try_read() {
+                Ok(handle) => return *handle,
+                Err(ReadError::Empty) => thread::yield_now(),
+                Err(ReadError::Closed) => panic!("closed"),
+            }
+        }
+    }
+
+    #[test]
+    fn one_writer_two_readers() {
+        model(|| {
+            let AtomicBufferSplit {
+                write_ptr: mut writer,
+                read_ptrs: [mut first, mut second],
+            } = leaked::<3, 2>();
+
+            let writes = thread::spawn(move || {
+                for value in 1..=2 {
+                    write(&mut writer, value);
+                }
+            });
+            let second_reads = thread::spawn(move || [read(&mut second), read(&mut second)]);
+
+            assert_eq!([read(&mut first), read(&mut first)], [1, 2]);
+            assert_eq!(second_reads.join().unwrap(), [1, 2]);
+            writes.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn two_writers() {
+        model(|| {
+            let buffer: &'static mut AtomicBuffer<usize, 4, 1> =
+                Box::leak(Box::new(AtomicBuffer::new()));
+            let ([mut front, mut back], [mut reader]) = buffer.split_writers::<2>();
+
+            let front_writes = thread::spawn(move || write(&mut front, 1));
+            let back_writes = thread::spawn(move || write(&mut back, 2));
+
+            let mut values = [read(&mut reader), read(&mut reader)];
+            values.sort_unstable();
+            assert_eq!(values, [1, 2]);
+            front_writes.join().unwrap();
+            back_writes.join().unwrap();
+        });
+    }
+
+    /// The buffer closes with the last writer, after both values.
+    #[test]
+    fn two_writers_close() {
+        model(|| {
+            let buffer: &'static mut AtomicBuffer<usize, 4, 1> =
+                Box::leak(Box::new(AtomicBuffer::new()));
+            let (writers, [mut reader]) = buffer.split_writers::<2>();
+
+            let writes: Vec<_> = writers
+                .into_iter()
+                .zip(1..)
+                .map(|(mut writer, value)| {
+                    thread::spawn(move || {
+                        write(&mut writer, value);
+                        writer.close();
+                    })
+                })
+                .collect();
+
+            let mut values = Vec::new();
+            while let Ok(handle) = reader.read_spin() {
+                values.push(*handle);
+            }
+            values.sort_unstable();
+            assert_eq!(values, [1, 2]);
+            for writes in writes {
+                writes.join().unwrap();
+            }
+        });
+    }
+
+    /// A write racing a reader going to sleep always wakes it.
+    #[test]
+    fn write_wakes_timed_read() {
+        model(|| {
+            let AtomicBufferSplit {
+                write_ptr: mut writer,
+                read_ptrs: [mut reader],
+            } = leaked::<2, 1>();
+
+            let writes = thread::spawn(move || write(&mut writer, 1));
+            let handle = reader.read_timeout(Duration::from_secs(3600)).unwrap();
+            assert_eq!(*handle, 1);
+            writes.join().unwrap();
+        });
+    }
+
+    /// A write racing an expiring deadline is either read or left for the
+    /// next read.
+    #[test]
+    fn timed_read_timeout_race() {
+        model(|| {
+            let AtomicBufferSplit {
+                write_ptr: mut writer,
+                read_ptrs: [mut reader],
+            } = leaked::<2, 1>();
+
+            let writes = thread::spawn(move || write(&mut writer, 1));
+            let timed_read = reader.read_deadline(Instant::now()).map(|handle| *handle);
+            writes.join().unwrap();
+            match timed_read {
+                Ok(value) => {
+                    assert_eq!(value, 1);
+                    assert_eq!(reader.try_read().unwrap_err(), ReadError::Empty);
+                }
+                Err(error) => {
+                    assert_eq!(error, ReadError::Empty);
+                    assert_eq!(*reader.try_read().unwrap(), 1);
+                }
+            }
+        });
+    }
+
+    /// Capacity 1, so every write after the first wraps onto the slot the
+    /// reader just released.
+    #[test]
+    fn wraparound_at_capacity() {
+        model(|| {
+            let AtomicBufferSplit {
+                write_ptr: mut writer,
+                read_ptrs: [mut reader],
+            } = leaked::<2, 1>();
+
+            let writes = thread::spawn(move || {
+                for value in 1..=3 {
+                    write(&mut writer, value);
+                }
+            });
+
+            for value in 1..=3 {
+                assert_eq!(read(&mut reader), value);
+            }
+            writes.join().unwrap();
+        });
+    }
+
+    /// A reader racing [`AtomicBufferWriter::write_overwrite`] only sees
+    /// whole values, oldest first, and never one being written.
+    #[test]
+    fn overwrite_race() {
+        model(|| {
+            let AtomicBufferSplit {
+                write_ptr: mut writer,
+                read_ptrs: [mut reader],
+            } = leaked::<3, 1>();
+
+            let writes = thread::spawn(move || {
+                (1..=4_usize).fold(false, |_, value| writer.write_overwrite(value))
+            });
+            let mut values = Vec::new();
+            for _ in 0..3 {
+                if let Ok(handle) = reader.try_read() {
+                    values.push(*handle);
+                }
+            }
+            let last_written = writes.join().unwrap();
+            values.extend(std::iter::from_fn(|| {
+                reader.try_read().ok().map(|handle| *handle)
+            }));
+
+            assert!(values.windows(2).all(|pair| pair[0] < pair[1]));
+            if last_written {
+                assert_eq!(values.last(), Some(&4));
+            }
+        });
+    }
+
+    /// See [`AtomicBufferReadHandle`], a forgotten handle leaves its slot
+    /// claimed and unread.
+    #[test]
+    fn forgotten_handle_rereads() {
+        model(|| {
+            let AtomicBufferSplit {
+                write_ptr: mut writer,
+                read_ptrs: [mut reader],
+            } = leaked::<3, 1>();
+            write(&mut writer, 1);
+
+            let writes =
+                thread::spawn(move || [writer.try_write(2_usize), writer.try_write(3_usize)]);
+            std::mem::forget(reader.try_read().unwrap());
+            let handle = reader.try_read().unwrap();
+            assert_eq!(*handle, 1);
+            // The writer stops at the held slot instead of wrapping onto it.
+            assert_eq!(writes.join().unwrap(), [true, false]);
+            assert_eq!(*handle, 1);
+
+            drop(handle);
+            assert_eq!(*reader.try_read().unwrap(), 2);
+        });
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use std::{
+        sync::{Mutex, atomic::AtomicUsize},
+        thread,
+    };
+
+    use super::*;
+
+    /// Retries until a reader frees a slot.
+    fn write<const N: usize, const READERS: usize>(
+        writer: &mut AtomicBufferWriter<'_, usize, N, READERS>,
+        value: usize,
+    ) {
+        while !writer.try_write(value) {
+            thread::yield_now();
+        }
+    }
+
+    /// Every value readable right now, in order.
+    fn read_all<const N: usize>(reader: &mut AtomicBufferReader<'_, usize, N>) -> Vec<usize> {
+        std::iter::from_fn(|| reader.try_read().ok().map(|handle| *handle)).collect()
+    }
+
+    #[test]
+    fn spsc_ordering() {
+        let mut buffer = AtomicBuffer::<usize, 8, 1>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader],
+        } = buffer.split();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for value in 0..1000 {
+                    write(&mut writer, value);
+                }
+                writer.close();
+            });
+
+            let mut expected = 0;
+            while let Ok(handle) = reader.read_spin() {
+                assert_eq!(*handle, expected);
+                expected += 1;
+            }
+            assert_eq!(expected, 1000);
+        });
+    }
+
+    #[test]
+    fn overwrite_keeps_newest() {
+        let mut buffer = AtomicBuffer::<usize, 4, 1>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader],
+        } = buffer.split();
+
+        for value in 0..10_usize {
+            assert!(writer.write_overwrite(value));
+        }
+        assert_eq!(read_all(&mut reader), [7, 8, 9]);
+        assert_eq!(writer.dropped_writes(), 0);
+    }
+
+    #[test]
+    fn overwrite_stops_at_held_slot() {
+        let mut buffer = AtomicBuffer::<usize, 3, 1>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader],
+        } = buffer.split();
+
+        write(&mut writer, 1);
+        write(&mut writer, 2);
+        let handle = reader.try_read().unwrap();
+        // Passes the held value, then stops at it.
+        assert!(writer.write_overwrite(3_usize));
+        assert!(!writer.write_overwrite(4_usize));
+        assert_eq!(*handle, 1);
+        assert_eq!(writer.dropped_writes(), 1);
+
+        drop(handle);
+        assert_eq!(read_all(&mut reader), [2, 3]);
+    }
+
+    #[test]
+    fn skip_oldest_lag() {
+        let mut buffer = AtomicBuffer::<usize, 8, 2>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut slow, mut other],
+        } = buffer.split();
+        slow.set_lag_policy(LagPolicy::SkipOldest { max_lag: 2 });
+
+        for value in 1..=5 {
+            write(&mut writer, value);
+        }
+        assert_eq!(writer.stats().queued, [2, 5]);
+        assert_eq!(read_all(&mut slow), [4, 5]);
+        assert_eq!(read_all(&mut other), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn detach_attach() {
+        let mut buffer = AtomicBuffer::<usize, 3, 2>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut active, mut idle],
+        } = buffer.split();
+
+        idle.detach();
+        assert!(idle.is_detached());
+        // The idle reader never fills the buffer.
+        for value in 0..10_usize {
+            assert!(writer.try_write(value));
+            assert_eq!(*active.try_read().unwrap(), value);
+        }
+        assert_eq!(idle.len(), 0);
+
+        // Reattaches past everything written meanwhile.
+        assert_eq!(idle.try_read().unwrap_err(), ReadError::Empty);
+        assert!(!idle.is_detached());
+        assert!(writer.try_write(10_usize));
+        assert_eq!(read_all(&mut idle), [10]);
+        assert_eq!(read_all(&mut active), [10]);
+    }
+
+    #[test]
+    fn two_writers_keep_order() {
+        let mut buffer = AtomicBuffer::<usize, 4, 1>::new();
+        let ([mut even, mut odd], [mut reader]) = buffer.split_writers::<2>();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for value in (0..1000).step_by(2) {
+                    write(&mut even, value);
+                }
+                even.close();
+            });
+            scope.spawn(move || {
+                for value in (1..1000).step_by(2) {
+                    write(&mut odd, value);
+                }
+                odd.close();
+            });
+
+            let mut last = [None, None];
+            let mut count = 0;
+            while let Ok(handle) = reader.read_spin() {
+                let value = *handle;
+                let last = &mut last[value % 2];
+                assert!(last.is_none_or(|last| last < value));
+                *last = Some(value);
+                count += 1;
+            }
+            assert_eq!(count, 1000);
+        });
+    }
+
+    /// Writers take turns in the order they asked for one.
+    #[test]
+    fn ticket_lock_fairness() {
+        let write_ticket = AtomicUsize::new(0);
+        let write_turn = AtomicUsize::new(0);
+        let order = Mutex::new(Vec::new());
+
+        thread::scope(|scope| {
+            let first_turn = take_turn(&write_ticket, &write_turn);
+            for writer in 1..=2 {
+                let (write_ticket, write_turn, order) = (&write_ticket, &write_turn, &order);
+                scope.spawn(move || {
+                    let _turn = take_turn(write_ticket, write_turn);
+                    order.lock().unwrap().push(writer);
+                });
+                // Queued behind the held turn before the next one asks.
+                while write_ticket.load(Ordering::Relaxed) <= writer {
+                    thread::yield_now();
+                }
+            }
+            drop(first_turn);
+        });
+        assert_eq!(order.into_inner().unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn read_timeout_on_empty() {
+        let mut buffer = AtomicBuffer::<usize, 4, 1>::new();
+        let AtomicBufferSplit {
+            read_ptrs: [mut reader],
+            ..
+        } = buffer.split();
+
+        let timeout = Duration::from_millis(50);
+        let start = Instant::now();
+        assert_eq!(reader.read_timeout(timeout).unwrap_err(), ReadError::Empty);
+        assert!(start.elapsed() >= timeout);
+    }
+
+    #[test]
+    fn write_wakes_timed_read() {
+        let mut buffer = AtomicBuffer::<usize, 4, 1>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader],
+        } = buffer.split();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                write(&mut writer, 42);
+            });
+
+            let start = Instant::now();
+            assert_eq!(*reader.read_timeout(Duration::from_secs(30)).unwrap(), 42);
+            assert!(start.elapsed() < Duration::from_secs(10));
+        });
+    }
+
+    /// The write position laps the reader's slot index, while the reader's
+    /// own count keeps growing past `N`.
+    #[test]
+    fn stats_after_wraparound() {
+        let mut buffer = AtomicBuffer::<usize, 4, 2>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader, mut idle],
+        } = buffer.split();
+        idle.detach();
+
+        for round in 0..5 {
+            for value in 0..3 {
+                write(&mut writer, round * 3 + value);
+            }
+            assert_eq!(reader.len(), 3);
+            assert_eq!(read_all(&mut reader).len(), 3);
+            assert!(reader.is_empty());
+        }
+
+        // Write position 2, behind the reader at slot 3.
+        for value in 15..18 {
+            write(&mut writer, value);
+        }
+        assert_eq!(reader.len(), 3);
+        assert_eq!(writer.stats().queued, [3, 0]);
+
+        // Full, so the next write is dropped.
+        assert!(!writer.try_write(18_usize));
+        assert_eq!(
+            writer.stats(),
+            BufferStats {
+                queued: [3, 0],
+                dropped_writes: 1,
+            }
+        );
+        assert_eq!(read_all(&mut reader), [15, 16, 17]);
+    }
+
+    #[test]
+    fn close_wakes_read_spin() {
+        let mut buffer = AtomicBuffer::<usize, 4, 1>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader],
+        } = buffer.split();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                write(&mut writer, 1);
+                thread::sleep(Duration::from_millis(20));
+                writer.close();
+                // Discarded after closing.
+                assert!(!writer.try_write(2_usize));
+            });
+
+            let start = Instant::now();
+            assert_eq!(*reader.read_spin().unwrap(), 1);
+            assert_eq!(reader.read_spin().unwrap_err(), ReadError::Closed);
+            assert!(start.elapsed() < Duration::from_secs(10));
+            assert_eq!(reader.try_read().unwrap_err(), ReadError::Closed);
+        });
+    }
+
+    #[test]
+    fn drain_counts_skipped() {
+        let mut buffer = AtomicBuffer::<usize, 8, 2>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader, mut idle],
+        } = buffer.split();
+        idle.detach();
+
+        for value in 0..5 {
+            write(&mut writer, value);
+        }
+        assert_eq!(reader.drain(), 5);
+        assert!(reader.is_empty());
+        assert_eq!(reader.drain(), 0);
+        // Reattaches instead, with nothing to skip.
+        assert_eq!(idle.drain(), 0);
+        assert!(!idle.is_detached());
+
+        write(&mut writer, 5);
+        assert_eq!(read_all(&mut reader), [5]);
+        assert_eq!(read_all(&mut idle), [5]);
+    }
+
+    #[test]
+    fn read_latest_skips_to_newest() {
+        let mut buffer = AtomicBuffer::<usize, 8, 1>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader],
+        } = buffer.split();
+
+        assert_eq!(reader.read_latest().unwrap_err(), ReadError::Empty);
+        for value in 0..5 {
+            write(&mut writer, value);
+        }
+        assert_eq!(*reader.read_latest().unwrap(), 4);
+        assert!(reader.is_empty());
+        writer.close();
+        assert_eq!(reader.read_latest().unwrap_err(), ReadError::Closed);
+    }
+
+    /// Every element of a slot holds the same value, so a partly written
+    /// slot read after the jump would show.
+    #[test]
+    fn read_latest_is_fully_written() {
+        let mut buffer = AtomicBuffer::<[usize; 32], 4, 1>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader],
+        } = buffer.split();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                // Miri runs the test, at a fraction of the speed.
+                let writes = if cfg!(miri) { 200 } else { 10_000 };
+                for value in 1..=writes {
+                    writer.write_overwrite_with(|slot| slot.fill(value));
+                }
+                writer.close();
+            });
+
+            let mut last = 0;
+            loop {
+                match reader.read_latest() {
+                    Ok(handle) => {
+                        let value = handle[0];
+                        assert!(handle.iter().all(|element| *element == value));
+                        assert!(value > last);
+                        last = value;
+                    }
+                    Err(ReadError::Empty) => thread::yield_now(),
+                    Err(ReadError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn peek_does_not_consume() {
+        let mut buffer = AtomicBuffer::<usize, 4, 1>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader],
+        } = buffer.split();
+
+        assert_eq!(reader.peek().unwrap_err(), ReadError::Empty);
+        write(&mut writer, 1);
+        write(&mut writer, 2);
+        assert_eq!(*reader.peek().unwrap(), 1);
+        assert_eq!(*reader.peek().unwrap(), 1);
+        assert_eq!(read_all(&mut reader), [1, 2]);
+    }
+
+    #[test]
+    fn read_pair_holds_both_slots() {
+        let mut buffer = AtomicBuffer::<usize, 3, 1>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader],
+        } = buffer.split();
+
+        write(&mut writer, 1);
+        assert_eq!(reader.read_pair().unwrap_err(), ReadError::Empty);
+        write(&mut writer, 2);
+
+        let pair = reader.read_pair().unwrap();
+        // Fills the free slot, then stops at the pair.
+        assert!(writer.write_overwrite(3_usize));
+        assert!(!writer.write_overwrite(4_usize));
+        assert_eq!((*pair.first, *pair.second), (1, 2));
+        drop(pair);
+
+        assert_eq!(read_all(&mut reader), [3]);
+        write(&mut writer, 5);
+        writer.close();
+        assert_eq!(reader.read_pair().unwrap_err(), ReadError::Closed);
+        assert_eq!(read_all(&mut reader), [5]);
+    }
+
+    /// Capacity 1, so an attached reader that never reads would stop the
+    /// writer after one value.
+    #[test]
+    fn writer_passes_detached_reader() {
+        let mut buffer = AtomicBuffer::<usize, 2, 2>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut reader, mut idle],
+        } = buffer.split();
+        idle.detach();
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for value in 0..1000 {
+                    write(&mut writer, value);
+                }
+                writer.close();
+            });
+
+            let mut expected = 0;
+            while let Ok(handle) = reader.read_spin() {
+                assert_eq!(*handle, expected);
+                expected += 1;
+            }
+            assert_eq!(expected, 1000);
+        });
+        assert_eq!(idle.try_read().unwrap_err(), ReadError::Closed);
+    }
+
+    /// A reader too slow for the writer only sees recent values, while the
+    /// others see every value.
+    #[test]
+    fn slow_consumer_skips_oldest() {
+        let mut buffer = AtomicBuffer::<usize, 8, 2>::new();
+        let AtomicBufferSplit {
+            write_ptr: mut writer,
+            read_ptrs: [mut slow, mut fast],
+        } = buffer.split();
+        slow.set_lag_policy(LagPolicy::SkipOldest { max_lag: 2 });
+
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for value in 0..200 {
+                    write(&mut writer, value);
+                }
+                writer.close();
+            });
+            let slow_reads = scope.spawn(move || {
+                let mut values = Vec::new();
+                while let Ok(handle) = slow.read_spin() {
+                    values.push(*handle);
+                    drop(handle);
+                    thread::sleep(Duration::from_millis(1));
+                }
+                values
+            });
+
+            let mut expected = 0;
+            while let Ok(handle) = fast.read_spin() {
+                assert_eq!(*handle, expected);
+                expected += 1;
+            }
+            assert_eq!(expected, 200);
+
+            let slow_values = slow_reads.join().unwrap();
+            assert!(slow_values.len() < 200);
+            assert!(slow_values.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(slow_values.last(), Some(&199));
+        });
+    }
+
+    /// CPU time used by the calling thread so far.
+    #[cfg(unix)]
+    fn thread_cpu_time() -> Duration {
+        let mut time = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: `time` is a valid timespec to write to.
+        unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    }
+
+    /// CPU time of a reader waiting a second on an idle buffer, blocked in
+    /// [`AtomicBufferReader::read_timeout`] against retrying with
+    /// `yield_now` the way waits without a futex used to. Linux measures the
+    /// futex, other targets the parker. Run with
+    /// `cargo test --release idle_reader_cpu -- --ignored --nocapture`.
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "benchmark"]
+    fn idle_reader_cpu() {
+        const IDLE: Duration = Duration::from_secs(1);
+        let mut buffer = AtomicBuffer::<usize, 4, 1>::new();
+        let AtomicBufferSplit {
+            read_ptrs: [mut reader],
+            ..
+        } = buffer.split();
+
+        let started = thread_cpu_time();
+        let deadline = Instant::now() + IDLE;
+        while Instant::now() < deadline {
+            assert!(matches!(
+                reader.read_deadline(deadline),
+                Err(ReadError::Empty)
+            ));
+        }
+        let blocked = thread_cpu_time() - started;
+
+        let started = thread_cpu_time();
+        let deadline = Instant::now() + IDLE;
+        while Instant::now() < deadline {
+            assert!(reader.try_read().is_err());
+            thread::yield_now();
+        }
+        let spinning = thread_cpu_time() - started;
+
+        println!("Idle for {IDLE:?}: blocked used {blocked:?} of CPU, spinning {spinning:?}");
+        assert!(blocked * 10 < spinning);
     }
 }
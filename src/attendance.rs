@@ -0,0 +1,626 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The scan loop, shared by the kiosk window and headless mode.
+//!
+//! Camera reads, submitted scans, and confirmed sign-outs are checked,
+//! recorded, and passed to observers here. Results are shown through an
+//! [`AttendanceView`], which the kiosk implements with its signals.
+
+use std::{collections::HashMap, future::Future, pin::Pin, time::Duration};
+
+use chrono::{DateTime, Local};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    MIN_SCAN_SPACING_SECS, QrType,
+    api::{self, ScanAction, ScanSubmission},
+    config::Config,
+    confirm::{self, PendingSignOut},
+    db_worker::DbHandle,
+    greeting::{ENRICH_KEY, enriched_greeting, greet},
+    notify::{ChatObserver, batch_scan_notifications},
+    observer::{FileObserver, LOG_FILE_KEY, ObserverRegistry, ScanEvent},
+    payload::{QrRejection, parse_qr},
+    rules::{Presence, RuleAction, ScanRules},
+    sms::{SmsObserver, TwilioCredentials, send_sms_notifications},
+    snapshot,
+    sqlite::{BackingDatabase, CAMERA_SOURCE_PREFIX, DbError},
+    video::{CameraRead, PRIMARY_CAMERA},
+};
+
+/// Wait between attempts to load who is present.
+const DB_RELOAD_RETRY: Duration = Duration::from_secs(1);
+
+/// Who is present in one role, with when they signed in.
+pub type PresentRows = Vec<(String, DateTime<Local>)>;
+pub type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Where the scan loop shows what happened. Everything but [`Self::spawn`]
+/// does nothing by default, for headless mode.
+pub trait AttendanceView: Clone + 'static {
+    /// Runs `task` alongside the scan loop.
+    fn spawn(&self, task: Task);
+
+    /// Everyone present in `role`, with display names.
+    fn set_present(&self, _role: QrType, _rows: PresentRows) {}
+
+    /// Latest outcome, shown to whoever scanned.
+    fn show(&self, _message: String) {}
+
+    /// Replaces `shown` with `message`, unless something else has been
+    /// shown since.
+    fn replace(&self, _shown: &str, _message: String) {}
+
+    /// Latest database failure, `None` once a read or write succeeds.
+    fn set_db_error(&self, _error: Option<String>) {}
+
+    /// Whether students are present without any mentor.
+    fn set_unsupervised(&self, _unsupervised: bool) {}
+
+    /// Played for anyone near the kiosk.
+    fn feedback(&self, _action: &ScanAction) {}
+
+    /// A scan was recorded.
+    fn accepted(&self, _name: &str, _signed_in: bool, _time: DateTime<Local>) {}
+
+    /// Waits on someone at the kiosk to confirm `sign_out`. `false` when
+    /// there is no one to ask, recording it right away.
+    fn confirm_sign_out(&self, _sign_out: PendingSignOut) -> bool {
+        false
+    }
+}
+
+/// Everything the scan loop handles.
+pub struct ScanInputs {
+    /// Closed when there is no camera, submissions still work.
+    pub qr_reads_rx: async_channel::Receiver<CameraRead>,
+    pub submissions_rx: async_channel::Receiver<ScanSubmission>,
+    pub sign_out_confirmed_rx: async_channel::Receiver<PendingSignOut>,
+    /// Asks for rosters and present lists to be reloaded from the database.
+    pub refresh_rx: async_channel::Receiver<()>,
+}
+
+/// `entries` with registered guest identifiers replaced by the guest's name.
+pub fn display_names(
+    db: &BackingDatabase,
+    entries: &[(String, DateTime<Local>)],
+) -> Result<PresentRows, DbError> {
+    entries
+        .iter()
+        .map(|(name, time)| Ok((db.display_name(name)?, *time)))
+        .collect()
+}
+
+/// How a camera scan is taken, given when the badge was last read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanTiming {
+    /// Read again within the spacing, so already answered.
+    Duplicate,
+    /// Past the spacing, but signed in under the minimum dwell ago.
+    TooSoon,
+    /// Signs the person in or out.
+    Toggle,
+}
+
+/// Records the read in `last_read` and classifies it. Repeats keep
+/// extending the spacing, so a badge held up is never taken twice.
+fn scan_timing(
+    last_read: &mut HashMap<String, DateTime<Local>>,
+    name: &str,
+    time: DateTime<Local>,
+    signed_in_at: Option<DateTime<Local>>,
+    spacing_secs: i64,
+    min_dwell_secs: i64,
+) -> ScanTiming {
+    let previous_time = last_read.insert(name.to_string(), time);
+    if previous_time
+        .is_some_and(|previous_time| (time - previous_time).num_seconds() < spacing_secs)
+    {
+        ScanTiming::Duplicate
+    } else if signed_in_at
+        .is_some_and(|signed_in_at| (time - signed_in_at).num_seconds() < min_dwell_secs)
+    {
+        ScanTiming::TooSoon
+    } else {
+        ScanTiming::Toggle
+    }
+}
+
+/// Shows who is present in `role`, also kept for the HTTP API.
+fn show_present(view: &impl AttendanceView, role: QrType, rows: PresentRows) {
+    api::PRESENT.set(role, rows.clone());
+    view.set_present(role, rows);
+}
+
+/// Handles scans until every input closes.
+pub async fn run(
+    inputs: ScanInputs,
+    backing_db: DbHandle,
+    config: Config,
+    observers: ObserverRegistry,
+    view: impl AttendanceView,
+) {
+    let ScanInputs {
+        qr_reads_rx,
+        submissions_rx,
+        sign_out_confirmed_rx,
+        refresh_rx,
+    } = inputs;
+    let rules = config.rules_script.as_ref().and_then(|path| {
+        ScanRules::load(path)
+            .inspect_err(
+                |e| warn!(target: "rules", "Ignoring rules script {}: {e}", path.display()),
+            )
+            .ok()
+    });
+
+    let mut mentor_list = Vec::new();
+    let mut student_list = Vec::new();
+    let mut guest_list = Vec::new();
+    let mut total_list = HashMap::new();
+    let mut reload_present = true;
+
+    loop {
+        // Also picks up edits made outside of this loop, including roster
+        // changes.
+        if reload_present {
+            let loaded = async {
+                let rosters = backing_db
+                    .try_run(|db| Ok((db.get_mentors()?, db.get_students()?)))
+                    .await?;
+                Ok::<_, DbError>((rosters, backing_db.get_present().await?))
+            }
+            .await;
+
+            // Scans wait, rather than toggle against unknown lists.
+            let ((known_mentors, known_students), present) = match loaded {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    error!(target: "attendance", "Failed to load who is present: {e}");
+                    view.set_db_error(Some(format!("DATABASE ERROR: {e}")));
+                    tokio::time::sleep(DB_RELOAD_RETRY).await;
+                    continue;
+                }
+            };
+            reload_present = false;
+            view.set_db_error(None);
+
+            mentor_list = present
+                .iter()
+                .filter(|(name, _)| known_mentors.contains(name))
+                .map(|(name, time)| (name.clone(), *time))
+                .collect();
+            show_present(&view, QrType::Mentor, mentor_list.clone());
+
+            student_list = present
+                .iter()
+                .filter(|(name, _)| known_students.contains(name))
+                .map(|(name, time)| (name.clone(), *time))
+                .collect();
+            show_present(&view, QrType::Student, student_list.clone());
+
+            guest_list = present
+                .iter()
+                .filter(|(name, _)| name.starts_with("Guest"))
+                .map(|(name, time)| (name.clone(), *time))
+                .collect();
+            let guests = guest_list.clone();
+            show_present(
+                &view,
+                QrType::Guest,
+                backing_db
+                    .try_run(move |db| display_names(db, &guests))
+                    .await
+                    .unwrap_or_else(|_| guest_list.clone()),
+            );
+
+            total_list.extend(present);
+        }
+
+        view.set_unsupervised(mentor_list.is_empty() && !student_list.is_empty());
+
+        let (next_qr_read, submission, camera, confirmed) = tokio::select! {
+            Ok(read) = qr_reads_rx.recv() => (read.payload, None, read.camera, None),
+            Ok(submission) = submissions_rx.recv() => {
+                (submission.payload.clone(), Some(submission), PRIMARY_CAMERA, None)
+            }
+            Ok(sign_out) = sign_out_confirmed_rx.recv() => {
+                (sign_out.name.clone(), None, PRIMARY_CAMERA, Some(sign_out))
+            }
+            Ok(()) = refresh_rx.recv() => {
+                reload_present = true;
+                continue;
+            }
+            else => return,
+        };
+        // Main camera scans have no source, the second camera's are told
+        // apart for the attendance log.
+        let source = submission
+            .as_ref()
+            .map(|submission| submission.source.clone())
+            .or_else(|| {
+                (camera != PRIMARY_CAMERA).then(|| format!("{CAMERA_SOURCE_PREFIX}{camera}"))
+            })
+            .or_else(|| {
+                confirmed
+                    .as_ref()
+                    .and_then(|sign_out| sign_out.source.clone())
+            });
+        // Tells the submitting device what happened to its scan.
+        let reply_quietly = |action: ScanAction| {
+            if let Some(submission) = &submission {
+                let _ = submission.reply.try_send(action);
+            }
+        };
+        // Also tells anyone near the kiosk.
+        let reply = |action: ScanAction| {
+            view.feedback(&action);
+            reply_quietly(action);
+        };
+
+        // Confirmed sign-outs were checked when scanned.
+        let parsed = match &confirmed {
+            Some(sign_out) => Ok((sign_out.qr_type, sign_out.name.clone())),
+            None => {
+                let payload = next_qr_read.clone();
+                match backing_db.try_run(move |db| parse_qr(&payload, db)).await {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        error!(target: "attendance", source, "Failed to check badge: {e}");
+                        view.set_db_error(Some(format!("DATABASE ERROR: {e}")));
+                        reply(ScanAction::Rejected("database error".to_string()));
+                        continue;
+                    }
+                }
+            }
+        };
+        let (qr_type, next_qr_read) = match parsed {
+            Ok(parsed) => parsed,
+            Err(QrRejection::Malformed(e)) => {
+                warn!(target: "attendance", source, "Rejected badge: {e}");
+                view.show(format!("UNREADABLE BADGE: {e}"));
+                reply(ScanAction::Rejected(e.to_string()));
+                continue;
+            }
+            Err(QrRejection::UnknownPerson(name)) => {
+                warn!(target: "attendance", %name, source, "Rejected unknown QR code");
+                view.show(format!("NOT ON ROSTER {name}"));
+                reply(ScanAction::Rejected("unknown badge".to_string()));
+                continue;
+            }
+            Err(QrRejection::Inactive(name)) => {
+                warn!(target: "attendance", %name, source, "Rejected inactive member");
+                view.show(format!("INACTIVE {name} — see a mentor"));
+                reply(ScanAction::Rejected("inactive member".to_string()));
+                continue;
+            }
+        };
+        let is_guest = qr_type == QrType::Guest;
+        let time = confirmed
+            .as_ref()
+            .map_or_else(Local::now, |sign_out| sign_out.time);
+
+        // Prevent repeated QR scans. Re-read so admin panel changes apply on
+        // the next scan.
+        let spacing_config = config.clone();
+        let (min_scan_spacing_secs, min_dwell_secs) = backing_db
+            .try_run(move |db| Ok((spacing_config.scan_spacing_secs(db)?, db.min_dwell_secs()?)))
+            .await
+            .unwrap_or((config.scan_spacing_secs.unwrap_or(MIN_SCAN_SPACING_SECS), 0));
+        let signed_in_at = match qr_type {
+            QrType::Mentor => &mentor_list,
+            QrType::Student => &student_list,
+            QrType::Guest => &guest_list,
+        }
+        .iter()
+        .find(|(name, _)| *name == next_qr_read)
+        .map(|(_, signed_in_at)| *signed_in_at);
+        let previous_time = total_list.get(&next_qr_read).copied();
+        if confirmed.is_none() {
+            match scan_timing(
+                &mut total_list,
+                &next_qr_read,
+                time,
+                signed_in_at,
+                min_scan_spacing_secs,
+                min_dwell_secs,
+            ) {
+                ScanTiming::Duplicate => {
+                    debug!(target: "attendance", name = %next_qr_read, "Ignoring repeated scan");
+                    // A badge held up for a while repeats every frame, and
+                    // was already answered.
+                    reply_quietly(ScanAction::Rejected("repeated scan".to_string()));
+                    continue;
+                }
+                ScanTiming::TooSoon => {
+                    // More likely a badge held up too long than someone
+                    // leaving.
+                    debug!(target: "attendance", name = %next_qr_read, "Ignoring sign-out before minimum dwell");
+                    view.show(format!("TOO SOON TO SIGN OUT {next_qr_read}"));
+                    reply(ScanAction::Rejected("too soon to sign out".to_string()));
+                    continue;
+                }
+                ScanTiming::Toggle => (),
+            }
+        }
+
+        if confirmed.is_some() && signed_in_at.is_none() {
+            // Signed out some other way while the prompt was open.
+            continue;
+        }
+        // Camera sign-outs wait for the kiosk, other scans keep being
+        // handled meanwhile.
+        if confirmed.is_none()
+            && submission.is_none()
+            && signed_in_at.is_some()
+            && backing_db
+                .try_run(|db| confirm::enabled(db))
+                .await
+                .unwrap_or(false)
+            && view.confirm_sign_out(PendingSignOut {
+                name: next_qr_read.clone(),
+                qr_type,
+                time,
+                source: source.clone(),
+            })
+        {
+            debug!(target: "attendance", name = %next_qr_read, "Waiting on sign-out confirmation");
+            continue;
+        }
+
+        // Returns true when the scan signs the person in.
+        let list_update = |list: &mut Vec<(String, DateTime<Local>)>, qr_name: &String| {
+            if let Some(existing_idx) = list.iter().position(|(name, _)| name == qr_name) {
+                list.remove(existing_idx);
+                false
+            } else {
+                list.push((qr_name.clone(), time));
+                true
+            }
+        };
+
+        let presence = Presence {
+            mentors: mentor_list.len(),
+            students: student_list.len(),
+            guests: guest_list.len(),
+        };
+        match rules
+            .as_ref()
+            .map(|rules| rules.check(&next_qr_read, qr_type, time, presence))
+        {
+            Some(RuleAction::Reject) => {
+                info!(target: "rules", name = %next_qr_read, source, "Rejected by rules script");
+                view.show(format!("REJECTED {next_qr_read}"));
+                reply(ScanAction::Rejected("rejected by rules".to_string()));
+                continue;
+            }
+            Some(RuleAction::Flag) => {
+                let name = next_qr_read.clone();
+                let _ = backing_db
+                    .try_run(move |db| db.log_event("scan_flagged", &name, "rules script"))
+                    .await;
+            }
+            Some(RuleAction::Accept) | None => (),
+        }
+
+        // Recorded, or queued while the database is unavailable, before the
+        // lists change, so they never show a scan the database refused.
+        let recorded = backing_db
+            .add_scan(&next_qr_read, time, source.as_deref())
+            .await;
+        if let Err(e) = recorded {
+            error!(target: "attendance", name = %next_qr_read, source, "Failed to record scan: {e}");
+            view.set_db_error(Some(format!("DATABASE ERROR: {e}")));
+            view.show(format!("NOT RECORDED {next_qr_read}"));
+            reply(ScanAction::Rejected("database error".to_string()));
+            // Lets the badge be scanned again right away.
+            match previous_time {
+                Some(previous_time) => total_list.insert(next_qr_read.clone(), previous_time),
+                None => total_list.remove(&next_qr_read),
+            };
+            continue;
+        }
+        view.set_db_error(None);
+
+        let list = match qr_type {
+            QrType::Mentor => &mut mentor_list,
+            QrType::Student => &mut student_list,
+            QrType::Guest => &mut guest_list,
+        };
+        let signed_in = list_update(list, &next_qr_read);
+        let shown = list.clone();
+        show_present(
+            &view,
+            qr_type,
+            backing_db
+                .try_run({
+                    let shown = shown.clone();
+                    move |db| display_names(db, &shown)
+                })
+                .await
+                .unwrap_or(shown),
+        );
+
+        info!(
+            target: "attendance",
+            name = %next_qr_read,
+            %qr_type,
+            signed_in,
+            source,
+            "Scan"
+        );
+        let present = mentor_list.len() + student_list.len() + guest_list.len();
+        observers.scan(&ScanEvent {
+            name: next_qr_read.clone(),
+            role: qr_type,
+            signed_in,
+            time,
+            present,
+            source: source.clone(),
+        });
+
+        let greeting = greet(&next_qr_read, signed_in, is_guest);
+        view.show(greeting.clone());
+        view.accepted(&next_qr_read, signed_in, time);
+
+        // Only camera scans have a frame behind them, confirmed sign-outs
+        // would save the frame at confirmation.
+        if source.is_none()
+            && confirmed.is_none()
+            && backing_db
+                .try_run(|db| snapshot::enabled(db))
+                .await
+                .unwrap_or(false)
+            && let Some(path) = snapshot::save(&next_qr_read, time)
+        {
+            let name = next_qr_read.clone();
+            let _ = backing_db
+                .try_run(move |db| db.set_scan_snapshot(&name, time, &path))
+                .await;
+        }
+        reply(if signed_in {
+            ScanAction::Added(next_qr_read.clone())
+        } else {
+            ScanAction::Removed(next_qr_read.clone())
+        });
+
+        // Lookups run after this scan is handled, replacing the greeting
+        // unless another scan has already replaced it.
+        if !is_guest {
+            let backing_db = backing_db.clone();
+            let enrich_view = view.clone();
+            view.spawn(Box::pin(async move {
+                let enriched = backing_db
+                    .try_run(move |db| {
+                        if db.get_setting(ENRICH_KEY)?.unwrap_or(true) {
+                            enriched_greeting(db, &next_qr_read, signed_in, time).map(Some)
+                        } else {
+                            Ok(None)
+                        }
+                    })
+                    .await
+                    .ok()
+                    .flatten();
+
+                if let Some(enriched) = enriched {
+                    enrich_view.replace(&greeting, enriched);
+                }
+            }));
+        }
+    }
+}
+
+/// Observers notified of each scan, with their senders spawned on `view`.
+pub async fn observers(
+    backing_db: &DbHandle,
+    config: &Config,
+    view: &impl AttendanceView,
+) -> ObserverRegistry {
+    let (notify_tx, notify_rx) = async_channel::unbounded();
+    view.spawn(Box::pin(batch_scan_notifications(
+        backing_db.clone(),
+        notify_rx,
+    )));
+
+    let mut observers = ObserverRegistry::new();
+    observers.register(ChatObserver::new(notify_tx));
+    if let Ok(Some(path)) = backing_db.try_run(|db| db.get_setting(LOG_FILE_KEY)).await {
+        observers.register(FileObserver::new(path));
+    }
+    if let Some(credentials) = TwilioCredentials::from_config(config) {
+        let (sms_tx, sms_rx) = async_channel::unbounded();
+        view.spawn(Box::pin(send_sms_notifications(
+            backing_db.clone(),
+            credentials,
+            sms_rx,
+        )));
+        observers.register(SmsObserver::new(sms_tx));
+    }
+    observers
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    /// Runs `scans` of (badge, seconds in) the way the scan loop does,
+    /// toggling presence on every [`ScanTiming::Toggle`].
+    fn script(scans: &[(&str, i64)], spacing_secs: i64, min_dwell_secs: i64) -> Vec<ScanTiming> {
+        let start = Local.with_ymd_and_hms(2024, 9, 2, 18, 0, 0).unwrap();
+        let mut last_read = HashMap::new();
+        let mut present: HashMap<String, DateTime<Local>> = HashMap::new();
+        scans
+            .iter()
+            .map(|&(name, secs)| {
+                let time = start + chrono::Duration::seconds(secs);
+                let signed_in_at = present.get(name).copied();
+                let timing = scan_timing(
+                    &mut last_read,
+                    name,
+                    time,
+                    signed_in_at,
+                    spacing_secs,
+                    min_dwell_secs,
+                );
+                if timing == ScanTiming::Toggle && present.remove(name).is_none() {
+                    present.insert(name.to_string(), time);
+                }
+                timing
+            })
+            .collect()
+    }
+
+    #[test]
+    fn duplicates_ignored_before_dwell() {
+        use ScanTiming::*;
+        let scans = [
+            ("Ada", 0),
+            ("Ada", 3),
+            ("Bob", 5),
+            ("Ada", 10),
+            // Held up, so each read extends the spacing.
+            ("Ada", 25),
+            ("Ada", 50),
+            ("Ada", 75),
+            ("Ada", 80),
+            ("Bob", 90),
+            ("Ada", 100),
+        ];
+        assert_eq!(
+            script(&scans, 20, 60),
+            [
+                Toggle, Duplicate, Toggle, Duplicate, Duplicate, TooSoon, Toggle, Duplicate,
+                Toggle, Toggle,
+            ]
+        );
+    }
+
+    #[test]
+    fn no_dwell_toggles_past_spacing() {
+        use ScanTiming::*;
+        let scans = [
+            ("Ada", 0),
+            ("Ada", 19),
+            ("Ada", 40),
+            ("Ada", 45),
+            ("Ada", 70),
+        ];
+        assert_eq!(
+            script(&scans, 20, 0),
+            [Toggle, Duplicate, Toggle, Duplicate, Toggle]
+        );
+    }
+
+    #[test]
+    fn dwell_shorter_than_spacing_never_too_soon() {
+        use ScanTiming::*;
+        let scans = [("Ada", 0), ("Ada", 10), ("Ada", 31), ("Ada", 62)];
+        assert_eq!(script(&scans, 20, 5), [Toggle, Duplicate, Toggle, Toggle]);
+    }
+}
@@ -0,0 +1,92 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Read-only view of the audit log, to see who changed what on a shared
+//! kiosk.
+
+use dioxus::prelude::*;
+
+use crate::AttendanceContext;
+
+/// Entries shown per page.
+const PAGE_SIZE: usize = 200;
+
+/// The audit log newest first, a page at a time.
+#[component]
+pub fn AuditLog() -> Element {
+    let AttendanceContext {
+        backing_db,
+        db_error,
+        ..
+    } = use_context();
+    let mut page = use_signal(|| 0_usize);
+
+    let offset = page() * PAGE_SIZE;
+    let loaded = use_resource(move || {
+        let backing_db = backing_db.clone();
+        let offset = page() * PAGE_SIZE;
+        async move {
+            let loaded = backing_db
+                .try_run(move |db| {
+                    let rows: Vec<_> = db
+                        .audit_entries(offset, PAGE_SIZE)?
+                        .into_iter()
+                        .map(|entry| {
+                            (
+                                entry.at.format("%m-%d-%Y %H:%M:%S").to_string(),
+                                entry.action,
+                                entry.subject,
+                                entry.detail,
+                            )
+                        })
+                        .collect();
+                    Ok((db.audit_count()?, rows))
+                })
+                .await;
+            db_error.check(loaded)
+        }
+    });
+    let (total, rows) = loaded().flatten().unwrap_or_default();
+    let last_page = total.saturating_sub(1) / PAGE_SIZE;
+    let shown_end = offset + rows.len();
+
+    rsx! {
+        if rows.is_empty() {
+            p { "Nothing has been audited." }
+        } else {
+            div {
+                button {
+                    disabled: page() == 0,
+                    onclick: move |_| page -= 1,
+                    "Newer"
+                }
+                " {offset + 1}-{shown_end} of {total} "
+                button {
+                    disabled: page() >= last_page,
+                    onclick: move |_| page += 1,
+                    "Older"
+                }
+            }
+            table {
+                class: "present_list",
+                tr {
+                    th { "Time" }
+                    th { "Action" }
+                    th { "Subject" }
+                    th { "Detail" }
+                }
+                for (at, action, subject, detail) in rows {
+                    tr {
+                        td { "{at}" }
+                        td { "{action}" }
+                        td { "{subject}" }
+                        td { "{detail}" }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,558 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Backs up the database on a schedule, and copies backups off the kiosk, so
+//! a dead SD card or lost laptop does not lose attendance.
+//!
+//! Local backups are written by the database worker every
+//! [`BACKUP_INTERVAL_HOURS_KEY`] hours, keeping the newest
+//! [`BACKUP_KEEP_KEY`](crate::sqlite::BACKUP_KEEP_KEY).
+//!
+//! After each local backup the file is uploaded with HTTP PUT, or copied to a
+//! mounted directory, then read back and compared by size and SHA-256. A
+//! failed upload is not retried on its own, the next backup uploads a newer
+//! file instead. HTTP uploads require the `remote-backup` feature.
+
+use std::{
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, TimeDelta};
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+
+use crate::{
+    db_worker::DbHandle,
+    sqlite::{BACKUP_INTERVAL_HOURS_KEY, BackingDatabase, DEFAULT_BACKUP_INTERVAL_HOURS, DbError},
+};
+
+/// Setting holding the URL to PUT backups to. A trailing `/` appends the
+/// backup's file name.
+pub const REMOTE_URL_KEY: &str = "backup.remote_url";
+/// Bearer token sent with uploads to [`REMOTE_URL_KEY`].
+pub const REMOTE_TOKEN_KEY: &str = "backup.remote_token";
+/// Setting holding a mounted directory to copy backups into.
+pub const REMOTE_PATH_KEY: &str = "backup.remote_path";
+pub const LAST_REMOTE_KEY: &str = "backup.remote_last";
+
+/// Time between checks whether a backup is due.
+const CHECK_INTERVAL: Duration = Duration::from_mins(10);
+
+/// Where backups are sent. A URL takes precedence over a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteTarget {
+    Http { url: String, token: Option<String> },
+    Path(PathBuf),
+}
+
+impl Display for RemoteTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Http { url, .. } => write!(f, "{url}"),
+            Self::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Size and SHA-256 digest of a backup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checksum {
+    pub size: u64,
+    pub sha256: [u8; 32],
+}
+
+impl Checksum {
+    pub fn of(bytes: &[u8]) -> Self {
+        Self {
+            size: bytes.len() as u64,
+            sha256: Sha256::digest(bytes).into(),
+        }
+    }
+}
+
+impl Display for Checksum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sha256:")?;
+        for byte in self.sha256 {
+            write!(f, "{byte:02x}")?;
+        }
+        write!(f, " ({} bytes)", self.size)
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "remote-backup"), allow(dead_code))]
+pub enum RemoteBackupError {
+    /// Built without the `remote-backup` feature.
+    #[cfg(not(feature = "remote-backup"))]
+    Disabled,
+    Io(io::Error),
+    Request(String),
+    /// Reading the target, before anything was sent.
+    Db(DbError),
+    /// The copy read back differently than the local backup.
+    Mismatch {
+        expected: Checksum,
+        actual: Checksum,
+    },
+}
+
+impl Display for RemoteBackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(not(feature = "remote-backup"))]
+            Self::Disabled => write!(f, "built without the remote-backup feature"),
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Request(e) => write!(f, "upload failed: {e}"),
+            Self::Db(e) => write!(f, "{e}"),
+            Self::Mismatch { expected, actual } => {
+                write!(f, "copy is {actual}, expected {expected}")
+            }
+        }
+    }
+}
+
+/// The configured target, `None` when remote backups are off.
+pub fn remote_target(db: &BackingDatabase) -> Result<Option<RemoteTarget>, DbError> {
+    let url = db
+        .get_setting::<String>(REMOTE_URL_KEY)?
+        .filter(|url| !url.trim().is_empty());
+    let path = db
+        .get_setting::<String>(REMOTE_PATH_KEY)?
+        .filter(|path| !path.trim().is_empty());
+
+    Ok(match (url, path) {
+        (Some(url), _) => Some(RemoteTarget::Http {
+            url: url.trim().to_string(),
+            token: db
+                .get_setting::<String>(REMOTE_TOKEN_KEY)?
+                .filter(|token| !token.is_empty()),
+        }),
+        (None, Some(path)) => Some(RemoteTarget::Path(PathBuf::from(path.trim()))),
+        (None, None) => None,
+    })
+}
+
+pub fn last_remote_backup(db: &BackingDatabase) -> Result<Option<DateTime<Local>>, DbError> {
+    Ok(db
+        .get_setting(LAST_REMOTE_KEY)?
+        .and_then(DateTime::from_timestamp_secs)
+        .map(|timestamp| timestamp.into()))
+}
+
+/// Sends the local `backup` to the configured target, recording the outcome.
+///
+/// `None` when remote backups are off.
+pub async fn upload_backup(
+    backing_db: DbHandle,
+    backup: PathBuf,
+) -> Option<Result<Checksum, RemoteBackupError>> {
+    let target = match backing_db.try_run(|db| remote_target(db)).await {
+        Ok(target) => target?,
+        Err(e) => return Some(Err(RemoteBackupError::Db(e))),
+    };
+    let result = upload(&target, &backup).await;
+
+    let backup = backup.display().to_string();
+    match &result {
+        Ok(checksum) => {
+            info!(target: "backup", %target, %checksum, "Uploaded backup");
+            let detail = format!("{target} {checksum}");
+            let recorded = backing_db
+                .try_run(move |db| {
+                    db.set_setting(LAST_REMOTE_KEY, Local::now().timestamp())?;
+                    db.log_event("remote_backup", &backup, &detail)
+                })
+                .await;
+            if let Err(e) = recorded {
+                warn!(target: "backup", "Failed to record the remote backup: {e}");
+            }
+        }
+        Err(e) => {
+            warn!(target: "backup", %target, "Remote backup failed: {e}");
+            let detail = format!("{target}: {e}");
+            let _ = backing_db
+                .try_run(move |db| db.log_event("remote_backup_failed", &backup, &detail))
+                .await;
+        }
+    }
+
+    Some(result)
+}
+
+/// Backs up whenever the last backup is older than the configured interval,
+/// then uploads it. Failures are retried on the next check.
+pub async fn periodic_backup(backing_db: DbHandle) {
+    loop {
+        // Checked again later if the worker fails.
+        let (hours, last) = backing_db
+            .try_run(|db| {
+                Ok((
+                    db.get_setting(BACKUP_INTERVAL_HOURS_KEY)?
+                        .unwrap_or(DEFAULT_BACKUP_INTERVAL_HOURS),
+                    db.last_backup()?,
+                ))
+            })
+            .await
+            .unwrap_or((0, None));
+
+        if hours > 0 && last.is_none_or(|last| Local::now() - last >= TimeDelta::hours(hours)) {
+            match backing_db.backup().await {
+                Ok(path) => {
+                    upload_backup(backing_db.clone(), path).await;
+                }
+                Err(e) => error!(target: "backup", "Automatic backup failed: {e}"),
+            }
+        }
+
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+/// Copies `backup` to `target` and verifies the copy.
+pub async fn upload(target: &RemoteTarget, backup: &Path) -> Result<Checksum, RemoteBackupError> {
+    let bytes = fs::read(backup).map_err(RemoteBackupError::Io)?;
+    let expected = Checksum::of(&bytes);
+    let file_name = backup
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let actual = match target {
+        RemoteTarget::Http { url, token } => {
+            let url = if url.ends_with('/') {
+                format!("{url}{file_name}")
+            } else {
+                url.clone()
+            };
+            put(&url, token.as_deref(), bytes).await?
+        }
+        RemoteTarget::Path(dir) => {
+            copy_to(dir, &file_name, &bytes).map_err(RemoteBackupError::Io)?
+        }
+    };
+
+    if actual == expected {
+        Ok(expected)
+    } else {
+        Err(RemoteBackupError::Mismatch { expected, actual })
+    }
+}
+
+/// Writes through a temporary name, so an interrupted copy never looks
+/// complete, then reads the copy back.
+fn copy_to(dir: &Path, file_name: &str, bytes: &[u8]) -> io::Result<Checksum> {
+    fs::create_dir_all(dir)?;
+    let partial = dir.join(format!("{file_name}.partial"));
+    let path = dir.join(file_name);
+
+    let mut file = File::create(&partial)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(&partial, &path)?;
+
+    Ok(Checksum::of(&fs::read(&path)?))
+}
+
+#[cfg(not(feature = "remote-backup"))]
+async fn put(
+    _url: &str,
+    _token: Option<&str>,
+    _bytes: Vec<u8>,
+) -> Result<Checksum, RemoteBackupError> {
+    Err(RemoteBackupError::Disabled)
+}
+
+#[cfg(feature = "remote-backup")]
+use http::put;
+
+#[cfg(feature = "remote-backup")]
+mod http {
+    use std::time::Duration;
+
+    use super::{Checksum, RemoteBackupError};
+
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+    fn request_error(e: reqwest::Error) -> RemoteBackupError {
+        RemoteBackupError::Request(e.to_string())
+    }
+
+    fn authorized(
+        request: reqwest::RequestBuilder,
+        token: Option<&str>,
+    ) -> reqwest::RequestBuilder {
+        match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    /// Uploads `bytes`, then downloads them again to checksum what the server
+    /// stored.
+    pub async fn put(
+        url: &str,
+        token: Option<&str>,
+        bytes: Vec<u8>,
+    ) -> Result<Checksum, RemoteBackupError> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(request_error)?;
+
+        let response = authorized(client.put(url), token)
+            .body(bytes)
+            .send()
+            .await
+            .map_err(request_error)?;
+        if !response.status().is_success() {
+            return Err(RemoteBackupError::Request(format!(
+                "PUT responded {}",
+                response.status()
+            )));
+        }
+
+        let response = authorized(client.get(url), token)
+            .send()
+            .await
+            .map_err(request_error)?;
+        if !response.status().is_success() {
+            return Err(RemoteBackupError::Request(format!(
+                "reading the upload back responded {}",
+                response.status()
+            )));
+        }
+
+        let stored = response.bytes().await.map_err(request_error)?;
+        Ok(Checksum::of(&stored))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("backup-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn checksum_shown_as_hex() {
+        let checksum = Checksum::of(b"abc");
+        assert_eq!(
+            checksum.to_string(),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad (3 bytes)"
+        );
+        assert_ne!(Checksum::of(b"abd"), checksum);
+    }
+
+    #[test]
+    fn url_preferred_over_path() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        assert_eq!(remote_target(&db).unwrap(), None);
+
+        db.set_setting(REMOTE_PATH_KEY, " /mnt/usb ").unwrap();
+        db.set_setting(REMOTE_URL_KEY, " ").unwrap();
+        assert_eq!(
+            remote_target(&db).unwrap(),
+            Some(RemoteTarget::Path(PathBuf::from("/mnt/usb")))
+        );
+
+        db.set_setting(REMOTE_URL_KEY, "https://example.com/backups/")
+            .unwrap();
+        db.set_setting(REMOTE_TOKEN_KEY, "").unwrap();
+        assert_eq!(
+            remote_target(&db).unwrap(),
+            Some(RemoteTarget::Http {
+                url: "https://example.com/backups/".to_string(),
+                token: None,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn copy_verified_and_complete() {
+        let dir = temp_dir("copy");
+        let backup = dir.join("qr-tracker-backup.db");
+        fs::write(&backup, b"backup contents").unwrap();
+        let target = RemoteTarget::Path(dir.join("usb"));
+
+        let checksum = upload(&target, &backup).await.unwrap();
+        assert_eq!(checksum, Checksum::of(b"backup contents"));
+        assert_eq!(
+            fs::read(dir.join("usb/qr-tracker-backup.db")).unwrap(),
+            b"backup contents"
+        );
+        assert!(!dir.join("usb/qr-tracker-backup.db.partial").exists());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn outcome_recorded() {
+        let dir = temp_dir("record");
+        let backup = dir.join("qr-tracker-backup.db");
+        fs::write(&backup, b"backup contents").unwrap();
+        // A file where the directory should be.
+        fs::write(dir.join("blocked"), b"").unwrap();
+
+        let backing_db = DbHandle::spawn(|| BackingDatabase::new(None).unwrap());
+        assert!(
+            upload_backup(backing_db.clone(), backup.clone())
+                .await
+                .is_none()
+        );
+
+        let blocked = dir.join("blocked").to_string_lossy().to_string();
+        backing_db
+            .try_run(move |db| db.set_setting(REMOTE_PATH_KEY, blocked))
+            .await
+            .unwrap();
+        assert!(matches!(
+            upload_backup(backing_db.clone(), backup.clone()).await,
+            Some(Err(RemoteBackupError::Io(_)))
+        ));
+        assert_eq!(
+            backing_db
+                .try_run(|db| last_remote_backup(db))
+                .await
+                .unwrap(),
+            None
+        );
+
+        let usb = dir.join("usb").to_string_lossy().to_string();
+        backing_db
+            .try_run(move |db| db.set_setting(REMOTE_PATH_KEY, usb))
+            .await
+            .unwrap();
+        assert!(matches!(
+            upload_backup(backing_db.clone(), backup).await,
+            Some(Ok(_))
+        ));
+
+        let (last, actions) = backing_db
+            .try_run(|db| {
+                Ok((
+                    last_remote_backup(db)?,
+                    db.audit_entries(0, 10)?
+                        .into_iter()
+                        .map(|entry| entry.action)
+                        .collect::<Vec<_>>(),
+                ))
+            })
+            .await
+            .unwrap();
+        assert!(last.is_some());
+        assert!(actions.contains(&"remote_backup".to_string()));
+        assert!(actions.contains(&"remote_backup_failed".to_string()));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    /// Serves PUT and GET of a single file over HTTP/1.1, returning the
+    /// stored bytes with the last byte flipped when `corrupt`.
+    #[cfg(feature = "remote-backup")]
+    fn fake_server(token: &'static str, corrupt: bool) -> String {
+        use std::{
+            io::{BufRead, BufReader, Read},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/backups/", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            let mut stored = Vec::new();
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { break };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let (mut length, mut authorized) = (0, false);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap();
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    } else if name.eq_ignore_ascii_case("authorization") {
+                        authorized = value.trim() == format!("Bearer {token}");
+                    }
+                }
+
+                let body = if !authorized {
+                    Err("401 Unauthorized")
+                } else if request_line.starts_with("PUT /backups/qr-tracker-backup.db ") {
+                    stored = vec![0; length];
+                    reader.read_exact(&mut stored).unwrap();
+                    Ok(Vec::new())
+                } else if request_line.starts_with("GET /backups/qr-tracker-backup.db ") {
+                    let mut body = stored.clone();
+                    if corrupt && let Some(last) = body.last_mut() {
+                        *last ^= 1;
+                    }
+                    Ok(body)
+                } else {
+                    Err("404 Not Found")
+                };
+
+                let (status, body) = match body {
+                    Ok(body) => ("200 OK", body),
+                    Err(status) => (status, Vec::new()),
+                };
+                let _ = stream.write_all(
+                    format!(
+                        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .as_bytes(),
+                );
+                let _ = stream.write_all(&body);
+            }
+        });
+        url
+    }
+
+    #[cfg(feature = "remote-backup")]
+    #[tokio::test]
+    async fn http_upload_read_back() {
+        let dir = temp_dir("http");
+        let backup = dir.join("qr-tracker-backup.db");
+        fs::write(&backup, b"backup contents").unwrap();
+        let target = |url: &str, token: &str| RemoteTarget::Http {
+            url: url.to_string(),
+            token: Some(token.to_string()),
+        };
+
+        let url = fake_server("secret", false);
+        assert_eq!(
+            upload(&target(&url, "secret"), &backup).await.unwrap(),
+            Checksum::of(b"backup contents")
+        );
+        assert!(matches!(
+            upload(&target(&url, "wrong"), &backup).await,
+            Err(RemoteBackupError::Request(_))
+        ));
+
+        let url = fake_server("secret", true);
+        assert!(matches!(
+            upload(&target(&url, "secret"), &backup).await,
+            Err(RemoteBackupError::Mismatch { expected, .. })
+                if expected == Checksum::of(b"backup contents")
+        ));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,557 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Printable badge sheets.
+//!
+//! Each badge is a QR code with the name underneath, laid out for a label
+//! stock. People with a registered badge id get a structured payload, others
+//! get a plain name badge. PDF and PNG output require the `badges` feature.
+
+use std::{fmt::Display, io};
+
+use crate::{
+    QrType,
+    payload::Payload,
+    sqlite::{BackingDatabase, DbError},
+};
+
+/// Label stocks, all on US Letter paper.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LabelTemplate {
+    /// 2-1/3" x 3-3/8" name badges, 8 per sheet.
+    #[default]
+    Avery5395,
+    /// 3" x 4" name badges, 6 per sheet.
+    Avery5392,
+}
+
+/// Label placement, in millimeters from the top left of the page.
+#[cfg_attr(not(feature = "badges"), allow(dead_code))]
+struct Layout {
+    columns: usize,
+    rows: usize,
+    width: f32,
+    height: f32,
+    left: f32,
+    top: f32,
+    column_pitch: f32,
+    row_pitch: f32,
+}
+
+const INCH: f32 = 25.4;
+
+impl LabelTemplate {
+    pub fn per_sheet(self) -> usize {
+        let layout = self.layout();
+        layout.columns * layout.rows
+    }
+
+    fn layout(self) -> Layout {
+        match self {
+            Self::Avery5395 => Layout {
+                columns: 2,
+                rows: 4,
+                width: 3.375 * INCH,
+                height: 2.333 * INCH,
+                left: 0.6875 * INCH,
+                top: 0.59 * INCH,
+                column_pitch: 3.75 * INCH,
+                row_pitch: 2.5 * INCH,
+            },
+            Self::Avery5392 => Layout {
+                columns: 2,
+                rows: 3,
+                width: 4.0 * INCH,
+                height: 3.0 * INCH,
+                left: 0.25 * INCH,
+                top: 1.0 * INCH,
+                column_pitch: 4.0 * INCH,
+                row_pitch: 3.0 * INCH,
+            },
+        }
+    }
+}
+
+/// Options for [`write_pdf`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SheetOptions {
+    pub template: LabelTemplate,
+    /// Name size in points.
+    pub font_size: f32,
+    /// Labels to leave blank at the start of the first sheet, to reuse a
+    /// partially used sheet. Must be less than [`LabelTemplate::per_sheet`].
+    pub skip: usize,
+}
+
+/// Text and QR payload for one badge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Badge {
+    pub name: String,
+    pub payload: String,
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "badges"), allow(dead_code))]
+pub enum BadgeError {
+    /// Built without the `badges` feature.
+    #[cfg(not(feature = "badges"))]
+    Disabled,
+    /// The payload does not fit in a QR code.
+    Encode(String),
+    Pdf(String),
+    Image(String),
+    Io(io::Error),
+    Db(DbError),
+}
+
+impl Display for BadgeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(not(feature = "badges"))]
+            Self::Disabled => write!(f, "built without the badges feature"),
+            Self::Encode(name) => write!(f, "badge for {name} is too long for a QR code"),
+            Self::Pdf(e) => write!(f, "{e}"),
+            Self::Image(e) => write!(f, "{e}"),
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Db(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Badges for everyone on the `role` roster, sorted by name.
+pub fn roster_badges(db: &BackingDatabase, role: QrType) -> Result<Vec<Badge>, DbError> {
+    let mut names = match role {
+        QrType::Mentor => db.get_mentors()?,
+        QrType::Student => db.get_students()?,
+        QrType::Guest => Vec::new(),
+    };
+    names.sort();
+
+    names
+        .into_iter()
+        .map(|name| {
+            let payload = match db.alias_id(&name)? {
+                Some(id) => Payload::V1 {
+                    id,
+                    name: name.clone(),
+                    role: Some(role),
+                },
+                None => Payload::Legacy(name.clone()),
+            };
+            Ok(Badge {
+                payload: payload.encode(),
+                name,
+            })
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "badges"))]
+pub fn write_pdf(
+    _badges: &[Badge],
+    _options: SheetOptions,
+    _out: &std::path::Path,
+) -> Result<(), BadgeError> {
+    Err(BadgeError::Disabled)
+}
+
+#[cfg(feature = "badges")]
+pub use pdf::write_pdf;
+
+#[cfg(not(feature = "badges"))]
+pub fn generate_badges(
+    _db: &BackingDatabase,
+    _out_dir: &std::path::Path,
+) -> Result<Vec<std::path::PathBuf>, BadgeError> {
+    Err(BadgeError::Disabled)
+}
+
+#[cfg(feature = "badges")]
+pub use png::generate_badges;
+
+#[cfg(feature = "badges")]
+mod pdf {
+    use std::{fs::File, io::BufWriter, path::Path};
+
+    use printpdf::{BuiltinFont, Color, Greyscale, Mm, PdfDocument, PdfLayerReference, Rect};
+    use qrcode::{Color as Module, QrCode};
+
+    use super::{Badge, BadgeError, INCH, SheetOptions};
+
+    const PAGE_WIDTH: f32 = 8.5 * INCH;
+    const PAGE_HEIGHT: f32 = 11.0 * INCH;
+    /// Space kept clear inside each label edge.
+    const PADDING: f32 = 4.0;
+    /// Points to millimeters.
+    const PT: f32 = INCH / 72.0;
+    /// Helvetica's average glyph width, as a fraction of the font size.
+    const AVERAGE_GLYPH_WIDTH: f32 = 0.55;
+
+    /// `(left, bottom, right, top)` of each dark module, with the top left
+    /// of the code at `(x, y)` in page coordinates (origin bottom left).
+    pub(super) fn module_rects(
+        code: &QrCode,
+        x: f32,
+        y: f32,
+        size: f32,
+    ) -> impl Iterator<Item = (f32, f32, f32, f32)> {
+        let width = code.width();
+        let module = size / width as f32;
+
+        code.to_colors()
+            .into_iter()
+            .enumerate()
+            .filter(|(_, color)| *color == Module::Dark)
+            .map(move |(index, _)| {
+                let (column, row) = ((index % width) as f32, (index / width) as f32);
+                (
+                    x + column * module,
+                    y - (row + 1.0) * module,
+                    x + (column + 1.0) * module,
+                    y - row * module,
+                )
+            })
+    }
+
+    /// Draws dark modules as filled squares.
+    fn draw_qr(layer: &PdfLayerReference, code: &QrCode, x: f32, y: f32, size: f32) {
+        layer.set_fill_color(Color::Greyscale(Greyscale::new(0.0, None)));
+        for (left, bottom, right, top) in module_rects(code, x, y, size) {
+            layer.add_rect(Rect::new(Mm(left), Mm(bottom), Mm(right), Mm(top)));
+        }
+    }
+
+    /// Writes one badge per label, starting after `options.skip` labels.
+    pub fn write_pdf(
+        badges: &[Badge],
+        options: SheetOptions,
+        out: &Path,
+    ) -> Result<(), BadgeError> {
+        let layout = options.template.layout();
+        let per_page = options.template.per_sheet();
+        let pdf_error = |e: printpdf::Error| BadgeError::Pdf(e.to_string());
+
+        let (doc, first_page, first_layer) =
+            PdfDocument::new("Badges", Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Badges");
+        let font = doc
+            .add_builtin_font(BuiltinFont::Helvetica)
+            .map_err(pdf_error)?;
+
+        let text_height = options.font_size * PT;
+        let qr_size =
+            (layout.height - 3.0 * PADDING - text_height).min(layout.width - 2.0 * PADDING);
+
+        let mut layer = doc.get_page(first_page).get_layer(first_layer);
+        for (slot, badge) in (options.skip % per_page..).zip(badges) {
+            if slot % per_page == 0 && slot != 0 {
+                let (page, page_layer) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Badges");
+                layer = doc.get_page(page).get_layer(page_layer);
+            }
+
+            let slot = slot % per_page;
+            let (column, row) = (slot % layout.columns, slot / layout.columns);
+            let left = layout.left + column as f32 * layout.column_pitch;
+            let top = PAGE_HEIGHT - layout.top - row as f32 * layout.row_pitch;
+
+            let code = QrCode::new(badge.payload.as_bytes())
+                .map_err(|_| BadgeError::Encode(badge.name.clone()))?;
+            draw_qr(
+                &layer,
+                &code,
+                left + (layout.width - qr_size) / 2.0,
+                top - PADDING,
+                qr_size,
+            );
+
+            let text_width =
+                badge.name.chars().count() as f32 * options.font_size * AVERAGE_GLYPH_WIDTH * PT;
+            layer.use_text(
+                badge.name.as_str(),
+                options.font_size,
+                Mm(left + (layout.width - text_width).max(0.0) / 2.0),
+                Mm(top - layout.height + PADDING),
+                &font,
+            );
+        }
+
+        let file = File::create(out).map_err(BadgeError::Io)?;
+        doc.save(&mut BufWriter::new(file)).map_err(pdf_error)
+    }
+}
+
+#[cfg(feature = "badges")]
+mod png {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use opencv::{
+        core::{CV_8UC1, Mat, Point, Rect, Scalar},
+        imgcodecs::imwrite_def,
+        imgproc::{self, FILLED, FONT_HERSHEY_SIMPLEX, LINE_8, LINE_AA},
+    };
+    use qrcode::{Color as Module, QrCode};
+
+    use super::{BadgeError, INCH, LabelTemplate, roster_badges};
+    use crate::{QrType, sqlite::BackingDatabase};
+
+    /// Print resolution.
+    const DPI: f32 = 300.0;
+    /// Pixels kept clear inside each label edge.
+    const PADDING: i32 = 48;
+    /// Light modules required around the code by the QR spec.
+    const QUIET_ZONE: i32 = 4;
+    const FONT_SCALE: f64 = 2.0;
+    const FONT_THICKNESS: i32 = 4;
+
+    /// `<role>-<name>.png`, with anything but letters, digits, and `-`
+    /// replaced by `_`.
+    fn file_name(role: QrType, name: &str) -> String {
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("{role}-{name}.png")
+    }
+
+    /// White `width` x `height` image with the code centered at the top and
+    /// `caption` underneath. The caption font only covers ASCII.
+    fn render(code: &QrCode, caption: &str, width: i32, height: i32) -> opencv::Result<Mat> {
+        let mut image =
+            Mat::new_rows_cols_with_default(height, width, CV_8UC1, Scalar::all(255.0))?;
+
+        let mut baseline = 0;
+        let text = imgproc::get_text_size(
+            caption,
+            FONT_HERSHEY_SIMPLEX,
+            FONT_SCALE,
+            FONT_THICKNESS,
+            &mut baseline,
+        )?;
+
+        // Whole pixels per module keep the module edges sharp.
+        let modules = code.width() as i32;
+        let available = (height - 3 * PADDING - text.height - baseline).min(width - 2 * PADDING);
+        let module = (available / (modules + 2 * QUIET_ZONE)).max(1);
+        let left = (width - modules * module) / 2;
+        let top = PADDING + QUIET_ZONE * module;
+
+        for (index, color) in code.to_colors().into_iter().enumerate() {
+            if color == Module::Dark {
+                let (column, row) = (index as i32 % modules, index as i32 / modules);
+                imgproc::rectangle(
+                    &mut image,
+                    Rect::new(left + column * module, top + row * module, module, module),
+                    Scalar::all(0.0),
+                    FILLED,
+                    LINE_8,
+                    0,
+                )?;
+            }
+        }
+
+        imgproc::put_text(
+            &mut image,
+            caption,
+            Point::new(
+                ((width - text.width) / 2).max(0),
+                height - PADDING - baseline,
+            ),
+            FONT_HERSHEY_SIMPLEX,
+            FONT_SCALE,
+            Scalar::all(0.0),
+            FONT_THICKNESS,
+            LINE_AA,
+            false,
+        )?;
+
+        Ok(image)
+    }
+
+    /// Writes one PNG per mentor and student to `out_dir`, sized for an
+    /// [`LabelTemplate::Avery5395`] label, and returns the written paths.
+    pub fn generate_badges(
+        db: &BackingDatabase,
+        out_dir: &Path,
+    ) -> Result<Vec<PathBuf>, BadgeError> {
+        let layout = LabelTemplate::Avery5395.layout();
+        let width = (layout.width / INCH * DPI) as i32;
+        let height = (layout.height / INCH * DPI) as i32;
+        fs::create_dir_all(out_dir).map_err(BadgeError::Io)?;
+
+        let mut written = Vec::new();
+        for role in [QrType::Mentor, QrType::Student] {
+            for badge in roster_badges(db, role).map_err(BadgeError::Db)? {
+                let code = QrCode::new(badge.payload.as_bytes())
+                    .map_err(|_| BadgeError::Encode(badge.name.clone()))?;
+
+                let path = out_dir.join(file_name(role, &badge.name));
+                match render(&code, &badge.name, width, height)
+                    .and_then(|image| imwrite_def(&path.to_string_lossy(), &image))
+                {
+                    Ok(true) => written.push(path),
+                    Ok(false) => {
+                        return Err(BadgeError::Image(format!(
+                            "failed to write {}",
+                            path.display()
+                        )));
+                    }
+                    Err(e) => return Err(BadgeError::Image(e.to_string())),
+                }
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_badges_use_v1() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.add_student("Bob").unwrap();
+        db.add_student("Alice").unwrap();
+        db.resolve_alias("s-042", "Alice").unwrap();
+
+        let badges = roster_badges(&db, QrType::Student).unwrap();
+        let payloads: Vec<_> = badges
+            .iter()
+            .map(|badge| (badge.name.as_str(), Payload::parse(&badge.payload).unwrap()))
+            .collect();
+        assert_eq!(
+            payloads,
+            [
+                (
+                    "Alice",
+                    Payload::V1 {
+                        id: "s-042".to_string(),
+                        name: "Alice".to_string(),
+                        role: Some(QrType::Student),
+                    }
+                ),
+                ("Bob", Payload::Legacy("Bob".to_string())),
+            ]
+        );
+        assert!(roster_badges(&db, QrType::Guest).unwrap().is_empty());
+    }
+
+    /// Rasterizes the modules [`write_pdf`] draws for a badge and reads them
+    /// back with the scanner's decoder.
+    #[cfg(feature = "badges")]
+    #[test]
+    fn drawn_codes_scan_back() {
+        use image::{GrayImage, Luma};
+        use qrcode::QrCode;
+
+        use crate::video::decode_gray;
+
+        /// Pixels per millimeter, about 250 DPI.
+        const SCALE: f32 = 10.0;
+        const SIZE: f32 = 40.0;
+        const MARGIN: f32 = 8.0;
+
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.add_student("Zoë Ng").unwrap();
+        db.resolve_alias("s-007", "Zoë Ng").unwrap();
+        let badges = roster_badges(&db, QrType::Student).unwrap();
+
+        let side = ((SIZE + 2.0 * MARGIN) * SCALE) as u32;
+        let mut image = GrayImage::from_pixel(side, side, Luma([255]));
+        let code = QrCode::new(badges[0].payload.as_bytes()).unwrap();
+        for (left, bottom, right, top) in pdf::module_rects(&code, MARGIN, SIZE + MARGIN, SIZE) {
+            // Image rows run down from the top, page coordinates up.
+            let flip = |y: f32| ((SIZE + 2.0 * MARGIN - y) * SCALE).round() as u32;
+            for row in flip(top)..flip(bottom) {
+                for column in (left * SCALE).round() as u32..(right * SCALE).round() as u32 {
+                    image.put_pixel(column, row, Luma([0]));
+                }
+            }
+        }
+
+        let decoded = decode_gray(image);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(
+            Payload::parse(&decoded[0]).unwrap(),
+            Payload::V1 {
+                id: "s-007".to_string(),
+                name: "Zoë Ng".to_string(),
+                role: Some(QrType::Student),
+            }
+        );
+
+        let out =
+            std::env::temp_dir().join(format!("qr-tracker-badges-{}.pdf", std::process::id()));
+        let options = SheetOptions {
+            template: LabelTemplate::Avery5395,
+            font_size: 14.0,
+            skip: 7,
+        };
+        write_pdf(&badges, options, &out).unwrap();
+        let written = std::fs::read(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        assert!(written.starts_with(b"%PDF-"));
+    }
+
+    /// Reads each generated PNG back with the scanner's decoder.
+    #[cfg(feature = "badges")]
+    #[test]
+    fn generated_pngs_scan_back() {
+        use crate::video::decode_gray;
+
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.add_mentor("Bob").unwrap();
+        db.add_student("Ada Lovelace").unwrap();
+        db.add_student("Alice").unwrap();
+        db.resolve_alias("s-042", "Alice").unwrap();
+
+        let out_dir =
+            std::env::temp_dir().join(format!("qr-tracker-png-badges-{}", std::process::id()));
+        let written = generate_badges(&db, &out_dir).unwrap();
+        let decoded: Vec<_> = written
+            .iter()
+            .map(|path| {
+                let image = image::open(path).unwrap().to_luma8();
+                let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+                let texts = decode_gray(image);
+                assert_eq!(texts.len(), 1, "{file_name}");
+                (file_name, Payload::parse(&texts[0]).unwrap())
+            })
+            .collect();
+        std::fs::remove_dir_all(&out_dir).unwrap();
+
+        assert_eq!(
+            decoded,
+            [
+                (
+                    "mentor-Bob.png".to_string(),
+                    Payload::Legacy("Bob".to_string())
+                ),
+                (
+                    "student-Ada_Lovelace.png".to_string(),
+                    Payload::Legacy("Ada Lovelace".to_string())
+                ),
+                (
+                    "student-Alice.png".to_string(),
+                    Payload::V1 {
+                        id: "s-042".to_string(),
+                        name: "Alice".to_string(),
+                        role: Some(QrType::Student),
+                    }
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,769 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Headless subcommands that run against the database without the GUI or
+//! camera.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, NaiveDate, NaiveTime, Weekday};
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    QrType,
+    badges::{self, LabelTemplate, SheetOptions},
+    config::{self, Config},
+    control::{self, ControlCommand},
+    discovery,
+    frc::{self, MEETING_MIN_PEOPLE_KEY, MIN_HOURS_KEY},
+    payload::Payload,
+    payroll::{self, DEFAULT_ROUNDING_MINUTES, ROUNDING_KEY, ROUNDING_MINUTES_KEY, Rounding},
+    report::season_start,
+    schedule::{self, ScheduleError},
+    sheets::{self, SheetsTarget},
+    sqlite::{BackingDatabase, DbError, date_range, local_datetime},
+    version,
+    video::VideoSource,
+};
+
+#[derive(Debug, Parser)]
+#[command(version = version::LONG_VERSION, about)]
+pub struct Cli {
+    /// Config file to use instead of ~/.config/qr-tracker/config.toml.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Database file to use instead of the default.
+    #[arg(long, global = true)]
+    pub database: Option<PathBuf>,
+
+    /// Log filter, e.g. `info,video::analysis=debug`. Overrides
+    /// `QR_TRACKER_LOG`.
+    #[arg(long, global = true, value_name = "FILTER")]
+    pub log: Option<String>,
+
+    /// Monitor to show the kiosk on, by index from 0 or by name.
+    #[arg(long)]
+    pub monitor: Option<String>,
+
+    /// Keep the kiosk above other windows.
+    #[arg(long)]
+    pub always_on_top: bool,
+
+    /// Hide the mouse pointer over the kiosk.
+    #[arg(long)]
+    pub hide_cursor: bool,
+
+    /// Frame source: `camera`, `camera:<index>`, `file:<path>` to replay a
+    /// recorded video in a loop, or `dir:<path>` to loop a directory of JPEG
+    /// frames.
+    #[arg(long)]
+    pub source: Option<VideoSource>,
+
+    /// Address the camera preview stream listens on, e.g. `0.0.0.0:2343`
+    /// for other machines on the LAN.
+    #[arg(long, value_name = "ADDR:PORT")]
+    pub video_bind: Option<String>,
+
+    /// Copy the config file into the database settings again, replacing
+    /// changes made in the admin panel.
+    #[arg(long)]
+    pub force_config: bool,
+
+    /// Scan and record attendance without opening the kiosk window. The
+    /// preview stream and its `/health` endpoint are the only interfaces.
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Open the kiosk in a normal resizable window instead of fullscreen,
+    /// e.g. for development.
+    #[arg(long)]
+    pub windowed: bool,
+
+    /// Set a new admin PIN, typed on the terminal.
+    #[arg(long)]
+    pub reset_pin: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Export attendance scans as CSV.
+    #[command(args_conflicts_with_subcommands = true)]
+    Export {
+        #[command(subcommand)]
+        target: Option<ExportTarget>,
+        /// First day to export.
+        #[arg(long)]
+        from: Option<NaiveDate>,
+        /// Last day to export (inclusive).
+        #[arg(long)]
+        to: Option<NaiveDate>,
+        /// Output file, stdout if omitted.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Manage the mentor and student rosters.
+    Roster {
+        #[command(subcommand)]
+        command: RosterCommand,
+    },
+    /// Summarize attendance.
+    Report {
+        #[command(subcommand)]
+        command: ReportCommand,
+    },
+    /// Manage the meeting schedule.
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommand,
+    },
+    /// Print the QR code text for a badge.
+    Badge {
+        /// Name shown on the kiosk.
+        name: String,
+        /// Stable identifier, kept when the name changes, e.g. s-042.
+        #[arg(long)]
+        id: String,
+        #[arg(long, value_enum)]
+        role: Option<QrType>,
+    },
+    /// Print a roster's badges to a PDF of label sheets.
+    Badges {
+        #[arg(long, value_enum)]
+        role: QrType,
+        #[arg(long)]
+        out: PathBuf,
+        #[arg(long, value_enum, default_value_t)]
+        template: LabelTemplate,
+        /// Name size in points.
+        #[arg(long, default_value_t = 14.0)]
+        font_size: f32,
+        /// Labels already used on the first sheet.
+        #[arg(long, default_value_t = 0)]
+        skip: usize,
+    },
+    /// List kiosks advertising their HTTP API on the LAN.
+    Discover {
+        /// Seconds to wait for answers.
+        #[arg(long, default_value_t = 3)]
+        timeout: u64,
+    },
+    /// Send a command to the running kiosk over its control socket.
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
+    /// Manage the config file.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+impl Command {
+    /// Whether this changes the database, and so must not run next to a kiosk.
+    pub fn writes_database(&self) -> bool {
+        match self {
+            Self::Roster { .. } | Self::Schedule { .. } => true,
+            Self::Export { .. }
+            | Self::Report { .. }
+            | Self::Badge { .. }
+            | Self::Badges { .. }
+            | Self::Discover { .. }
+            | Self::Ctl { .. }
+            | Self::Config { .. } => false,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExportTarget {
+    /// Push a week's hours to the Google Sheets spreadsheet in the config.
+    Sheets {
+        /// Any day in the week to push, the current week if omitted.
+        #[arg(long)]
+        week: Option<NaiveDate>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ScheduleCommand {
+    /// Import upcoming meetings from the calendar in the config.
+    Sync,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CtlCommand {
+    /// Print who is present as JSON.
+    Present,
+    /// Sign out everyone present.
+    CheckoutAll,
+    /// Handle a badge payload as if scanned.
+    Scan { payload: String },
+    /// Write every scan as CSV to a file.
+    Export { path: PathBuf },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Write a commented config template.
+    Init {
+        /// Replace an existing config file.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum RosterCommand {
+    /// Add a single mentor or student.
+    Add(RosterName),
+    /// Deactivate a mentor or student, keeping them in reports.
+    Remove {
+        #[command(flatten)]
+        name: RosterName,
+        /// Delete them from the roster instead, e.g. a misspelled name.
+        #[arg(long)]
+        purge: bool,
+    },
+    /// Move a student to the mentor roster, keeping their attendance history.
+    Promote { name: String },
+    /// Refuse a member's badge, e.g. after graduating, keeping them in reports.
+    Deactivate { name: String },
+    /// Accept a deactivated member's badge again.
+    Reactivate { name: String },
+    /// Set the employee id used by `report payroll`.
+    PayrollId { name: String, employee_id: String },
+    /// Add everyone in a CSV file with a name column.
+    Import {
+        path: PathBuf,
+        /// Roster for rows without a type column.
+        #[arg(long, value_enum, default_value_t = QrType::Student)]
+        role: QrType,
+    },
+}
+
+#[derive(Debug, Args)]
+#[group(required = true, multiple = false)]
+pub struct RosterName {
+    #[arg(long)]
+    mentor: Option<String>,
+    #[arg(long)]
+    student: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ReportCommand {
+    /// Hours present per person, for the current week by default.
+    Hours {
+        /// Report since the start of the season.
+        #[arg(long, conflicts_with = "since")]
+        season: bool,
+        /// Report since this day.
+        #[arg(long)]
+        since: Option<NaiveDate>,
+    },
+    /// Student hours and meeting attendance as CSV, for FIRST award
+    /// submissions.
+    Frc {
+        /// First day of the season, the configured season start if omitted.
+        #[arg(long)]
+        season_start: Option<NaiveDate>,
+        /// Last day of the season (inclusive), today if omitted.
+        #[arg(long)]
+        season_end: Option<NaiveDate>,
+        /// Fewest people scanning on a day for it to count as a meeting.
+        #[arg(long)]
+        meeting_min_people: Option<usize>,
+        /// Flag students with fewer hours than this.
+        #[arg(long)]
+        min_hours: Option<f64>,
+        /// Output file, stdout if omitted.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Time clock sessions of people with an employee id as CSV.
+    Payroll {
+        /// First day of the pay period.
+        #[arg(long)]
+        from: NaiveDate,
+        /// Last day of the pay period (inclusive).
+        #[arg(long)]
+        to: NaiveDate,
+        /// Minutes clock times are rounded to, 15 if not configured.
+        #[arg(long)]
+        rounding_minutes: Option<i64>,
+        /// Rounding direction, nearest if not configured.
+        #[arg(long, value_enum)]
+        rounding: Option<Rounding>,
+        /// Output file, stdout if omitted.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+}
+
+fn start_of_day(date: NaiveDate) -> DateTime<Local> {
+    local_datetime(date, NaiveTime::MIN)
+}
+
+/// Writes the config template, without reading any existing config.
+pub fn init_config(path: &Path, force: bool) -> ExitCode {
+    match config::init(path, force) {
+        Ok(()) => {
+            eprintln!("Wrote {}", path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            eprintln!(
+                "{} already exists, use --force to replace it",
+                path.display()
+            );
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {e}", path.display());
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs a subcommand to completion, reporting failures on stderr.
+pub fn run(database: &str, config: &Config, command: Command) -> ExitCode {
+    let mut db = match BackingDatabase::new(Some(database)) {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("Failed to open {database}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match execute(&mut db, config, command) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Database error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn execute(
+    db: &mut BackingDatabase,
+    config: &Config,
+    command: Command,
+) -> Result<ExitCode, DbError> {
+    match command {
+        Command::Export {
+            target: Some(ExportTarget::Sheets { week }),
+            ..
+        } => {
+            let target = match SheetsTarget::from_config(config) {
+                Ok(target) => target,
+                Err(e) => {
+                    eprintln!("Sheets export failed: {e}");
+                    return Ok(ExitCode::FAILURE);
+                }
+            };
+            let week = sheets::week_start(week.unwrap_or_else(|| Local::now().date_naive()));
+            let hours = sheets::week_hours(db, week)?;
+
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            match runtime.block_on(sheets::push_week(&target, week, &hours)) {
+                Ok(()) => {
+                    eprintln!("Pushed hours for {} people, week of {week}", hours.len());
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(e) => {
+                    eprintln!("Sheets export failed: {e}");
+                    Ok(ExitCode::FAILURE)
+                }
+            }
+        }
+        Command::Export {
+            target: None,
+            from,
+            to,
+            out,
+        } => {
+            let range = date_range(from, to);
+
+            let writer: Box<dyn Write> = match &out {
+                Some(out) => match File::create(out) {
+                    Ok(file) => Box::new(BufWriter::new(file)),
+                    Err(e) => {
+                        eprintln!("Failed to create {}: {e}", out.display());
+                        return Ok(ExitCode::FAILURE);
+                    }
+                },
+                None => Box::new(io::stdout().lock()),
+            };
+
+            match db.export_attendance_csv(range, writer) {
+                Ok(count) => {
+                    if let Some(out) = out {
+                        eprintln!("Exported {count} scans to {}", out.display());
+                    }
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(e) => {
+                    eprintln!("Export failed: {e}");
+                    Ok(ExitCode::FAILURE)
+                }
+            }
+        }
+        Command::Roster {
+            command: RosterCommand::Add(RosterName { mentor, student }),
+        } => {
+            let added = match (mentor, student) {
+                (Some(mentor), _) => db.add_mentor(mentor.trim()),
+                (_, Some(student)) => db.add_student(student.trim()),
+                (None, None) => unreachable!("clap requires one role"),
+            };
+
+            match added {
+                Ok(true) => Ok(ExitCode::SUCCESS),
+                Ok(false) => {
+                    eprintln!("Already on the roster");
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(e) => {
+                    eprintln!("Not added: {e}");
+                    Ok(ExitCode::FAILURE)
+                }
+            }
+        }
+        Command::Roster {
+            command:
+                RosterCommand::Remove {
+                    name: RosterName { mentor, student },
+                    purge,
+                },
+        } => {
+            let (name, role) = match (mentor, student) {
+                (Some(mentor), _) => (mentor, QrType::Mentor),
+                (_, Some(student)) => (student, QrType::Student),
+                (None, None) => unreachable!("clap requires one role"),
+            };
+            let name = name.trim();
+
+            let removed = match (purge, db.roster_entry(name)?) {
+                (true, _) if role == QrType::Mentor => db.remove_mentor(name)?,
+                (true, _) => db.remove_student(name)?,
+                (false, Some((entry_role, true))) if entry_role == role => {
+                    db.set_member_active(name, false)?
+                }
+                (false, _) => false,
+            };
+
+            if !removed && purge {
+                eprintln!("Not a {role}");
+            } else if !removed {
+                eprintln!("Not an active {role}");
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Roster {
+            command: RosterCommand::Promote { name },
+        } => {
+            if db.move_student_to_mentor(name.trim())? {
+                Ok(ExitCode::SUCCESS)
+            } else {
+                eprintln!("{} is not a student", name.trim());
+                Ok(ExitCode::FAILURE)
+            }
+        }
+        Command::Roster {
+            command: RosterCommand::Deactivate { name },
+        } => {
+            if db.set_member_active(name.trim(), false)? {
+                Ok(ExitCode::SUCCESS)
+            } else {
+                eprintln!("{} is not an active member", name.trim());
+                Ok(ExitCode::FAILURE)
+            }
+        }
+        Command::Roster {
+            command: RosterCommand::Reactivate { name },
+        } => {
+            if db.set_member_active(name.trim(), true)? {
+                Ok(ExitCode::SUCCESS)
+            } else {
+                eprintln!("{} is not an inactive member", name.trim());
+                Ok(ExitCode::FAILURE)
+            }
+        }
+        Command::Roster {
+            command: RosterCommand::PayrollId { name, employee_id },
+        } => {
+            db.set_payroll_id(name.trim(), employee_id.trim())?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Roster {
+            command: RosterCommand::Import { path, role },
+        } => match db.import_roster_csv(&path, role) {
+            Ok(report) => {
+                println!("{report}");
+                Ok(ExitCode::SUCCESS)
+            }
+            Err(e) => {
+                eprintln!("Import of {} failed: {e}", path.display());
+                Ok(ExitCode::FAILURE)
+            }
+        },
+        Command::Report {
+            command: ReportCommand::Hours { season, since },
+        } => {
+            let since = match (season, since) {
+                (true, _) => season_start(db)?,
+                (false, Some(since)) => since,
+                (false, None) => Local::now().date_naive().week(Weekday::Mon).first_day(),
+            };
+
+            let mut stdout = io::stdout().lock();
+            for (name, duration) in db.hours_summary(start_of_day(since), None)? {
+                let hours = duration.num_minutes() as f64 / 60.0;
+                if writeln!(stdout, "{name}\t{hours:.2}").is_err() {
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Report {
+            command:
+                ReportCommand::Frc {
+                    season_start: start,
+                    season_end,
+                    meeting_min_people,
+                    min_hours,
+                    out,
+                },
+        } => {
+            let start = match start {
+                Some(start) => start,
+                None => season_start(db)?,
+            };
+            let range = date_range(Some(start), season_end).unwrap();
+            let meeting_min_people = match meeting_min_people {
+                Some(people) => people,
+                None => db.get_setting(MEETING_MIN_PEOPLE_KEY)?.unwrap_or(1),
+            };
+            let min_hours = match min_hours {
+                Some(hours) => Some(hours),
+                None => db.get_setting(MIN_HOURS_KEY)?,
+            };
+
+            let rows = match frc::frc_report(db, range, meeting_min_people, min_hours) {
+                Ok(rows) => rows,
+                Err(e) => {
+                    eprintln!("Report failed: {e}");
+                    return Ok(ExitCode::FAILURE);
+                }
+            };
+
+            let writer: Box<dyn Write> = match &out {
+                Some(out) => match File::create(out) {
+                    Ok(file) => Box::new(BufWriter::new(file)),
+                    Err(e) => {
+                        eprintln!("Failed to create {}: {e}", out.display());
+                        return Ok(ExitCode::FAILURE);
+                    }
+                },
+                None => Box::new(io::stdout().lock()),
+            };
+
+            match frc::write_csv(&rows, writer) {
+                Ok(count) => {
+                    if let Some(out) = out {
+                        eprintln!("Wrote {count} students to {}", out.display());
+                    }
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(e) => {
+                    eprintln!("Report failed: {e}");
+                    Ok(ExitCode::FAILURE)
+                }
+            }
+        }
+        Command::Report {
+            command:
+                ReportCommand::Payroll {
+                    from,
+                    to,
+                    rounding_minutes,
+                    rounding,
+                    out,
+                },
+        } => {
+            let range = date_range(Some(from), Some(to)).unwrap();
+            let rounding_minutes = match rounding_minutes {
+                Some(minutes) => minutes,
+                None => db
+                    .get_setting(ROUNDING_MINUTES_KEY)?
+                    .unwrap_or(DEFAULT_ROUNDING_MINUTES),
+            };
+            let rounding = match rounding {
+                Some(rounding) => rounding,
+                None => db.get_setting(ROUNDING_KEY)?.unwrap_or_default(),
+            };
+
+            let rows = payroll::payroll_report(db, range, rounding_minutes, rounding)?;
+
+            let writer: Box<dyn Write> = match &out {
+                Some(out) => match File::create(out) {
+                    Ok(file) => Box::new(BufWriter::new(file)),
+                    Err(e) => {
+                        eprintln!("Failed to create {}: {e}", out.display());
+                        return Ok(ExitCode::FAILURE);
+                    }
+                },
+                None => Box::new(io::stdout().lock()),
+            };
+
+            match payroll::write_csv(&rows, writer) {
+                Ok(count) => {
+                    if let Some(out) = out {
+                        eprintln!("Wrote {count} sessions to {}", out.display());
+                    }
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(e) => {
+                    eprintln!("Report failed: {e}");
+                    Ok(ExitCode::FAILURE)
+                }
+            }
+        }
+        Command::Schedule {
+            command: ScheduleCommand::Sync,
+        } => {
+            let Some(source) = config.calendar.as_deref() else {
+                eprintln!("Schedule sync failed: {}", ScheduleError::NotConfigured);
+                return Ok(ExitCode::FAILURE);
+            };
+
+            let now = Local::now();
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            match runtime.block_on(schedule::fetch_meetings(source, now)) {
+                Ok(meetings) => {
+                    schedule::store(db, now, &meetings)?;
+                    eprintln!("Imported {} upcoming meetings", meetings.len());
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(e) => {
+                    eprintln!("Schedule sync failed: {e}");
+                    Ok(ExitCode::FAILURE)
+                }
+            }
+        }
+        Command::Badge { name, id, role } => {
+            println!(
+                "{}",
+                Payload::V1 {
+                    id: id.trim().to_string(),
+                    name: name.trim().to_string(),
+                    role,
+                }
+                .encode()
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Badges {
+            role,
+            out,
+            template,
+            font_size,
+            skip,
+        } => {
+            if skip >= template.per_sheet() {
+                eprintln!("--skip must be less than {}", template.per_sheet());
+                return Ok(ExitCode::FAILURE);
+            }
+
+            let badges = badges::roster_badges(db, role)?;
+            let options = SheetOptions {
+                template,
+                font_size,
+                skip,
+            };
+            match badges::write_pdf(&badges, options, &out) {
+                Ok(()) => {
+                    eprintln!("Wrote {} badges to {}", badges.len(), out.display());
+                    Ok(ExitCode::SUCCESS)
+                }
+                Err(e) => {
+                    eprintln!("Badge sheet failed: {e}");
+                    Ok(ExitCode::FAILURE)
+                }
+            }
+        }
+        Command::Discover { timeout } => match discovery::browse(Duration::from_secs(timeout)) {
+            Ok(kiosks) => {
+                if kiosks.is_empty() {
+                    eprintln!("No kiosks found");
+                }
+
+                let mut stdout = io::stdout().lock();
+                for kiosk in kiosks {
+                    if writeln!(stdout, "{}\t{}", kiosk.name, kiosk.urls().join(" ")).is_err() {
+                        return Ok(ExitCode::FAILURE);
+                    }
+                }
+                Ok(ExitCode::SUCCESS)
+            }
+            Err(e) => {
+                eprintln!("Discovery failed: {e}");
+                Ok(ExitCode::FAILURE)
+            }
+        },
+        Command::Ctl { command } => {
+            let command = match command {
+                CtlCommand::Present => ControlCommand::Present,
+                CtlCommand::CheckoutAll => ControlCommand::CheckoutAll,
+                CtlCommand::Scan { payload } => ControlCommand::Scan { payload },
+                // The kiosk may run in another directory.
+                CtlCommand::Export { path } => ControlCommand::Export {
+                    path: std::path::absolute(&path).unwrap_or(path),
+                },
+            };
+
+            let response = match control::send(&serde_json::to_string(&command).unwrap()) {
+                Ok(response) => response,
+                Err(e) => {
+                    eprintln!(
+                        "Failed to reach the kiosk at {}: {e}",
+                        control::socket_path().display()
+                    );
+                    return Ok(ExitCode::FAILURE);
+                }
+            };
+            println!("{response}");
+
+            let succeeded = serde_json::from_str::<serde_json::Value>(&response)
+                .is_ok_and(|response| response.get("error").is_none());
+            if succeeded {
+                Ok(ExitCode::SUCCESS)
+            } else {
+                Ok(ExitCode::FAILURE)
+            }
+        }
+        Command::Config {
+            command: ConfigCommand::Init { .. },
+        } => unreachable!("handled before loading the config"),
+    }
+}
@@ -0,0 +1,244 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Detects system clock drift with a minimal SNTP client.
+//!
+//! Drift is only reported, stored timestamps are never adjusted. Without
+//! network access the check is skipped.
+
+use std::{fmt::Display, io, time::Duration};
+
+use chrono::Utc;
+use dioxus::prelude::*;
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+use crate::{config::Config, db_worker::DbHandle};
+
+/// Setting holding the largest tolerated offset from NTP time.
+pub const MAX_OFFSET_KEY: &str = "clock.max_offset_secs";
+
+const DEFAULT_SERVER: &str = "pool.ntp.org";
+const DEFAULT_MAX_OFFSET_SECS: f64 = 60.0;
+const CHECK_INTERVAL: Duration = Duration::from_secs(4 * 60 * 60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch (1970).
+const NTP_UNIX_OFFSET: f64 = 2_208_988_800.0;
+const PACKET_LEN: usize = 48;
+
+#[derive(Debug)]
+pub enum NtpError {
+    Io(io::Error),
+    Timeout,
+    /// Not a usable server reply, e.g. a kiss-of-death packet.
+    InvalidReply,
+}
+
+impl Display for NtpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Timeout => write!(f, "timed out"),
+            Self::InvalidReply => write!(f, "invalid reply"),
+        }
+    }
+}
+
+/// Current time as seconds since the Unix epoch.
+fn now_secs() -> f64 {
+    Utc::now().timestamp_micros() as f64 / 1_000_000.0
+}
+
+/// Reads a 64 bit NTP timestamp as seconds since the Unix epoch.
+fn read_timestamp(bytes: &[u8]) -> f64 {
+    let seconds = u32::from_be_bytes(bytes[..4].try_into().unwrap());
+    let fraction = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+    seconds as f64 + fraction as f64 / 2_f64.powi(32) - NTP_UNIX_OFFSET
+}
+
+fn write_timestamp(bytes: &mut [u8], unix_secs: f64) {
+    let ntp = unix_secs + NTP_UNIX_OFFSET;
+    bytes[..4].copy_from_slice(&(ntp.trunc() as u32).to_be_bytes());
+    bytes[4..8].copy_from_slice(&((ntp.fract() * 2_f64.powi(32)) as u32).to_be_bytes());
+}
+
+/// Local clock offset from the server, positive when the local clock is
+/// behind: `((receive - originate) + (transmit - destination)) / 2`.
+pub fn clock_offset(originate: f64, receive: f64, transmit: f64, destination: f64) -> f64 {
+    ((receive - originate) + (transmit - destination)) / 2.0
+}
+
+/// Queries `server` (`host` or `host:port`) once.
+pub async fn query_offset(server: &str) -> Result<f64, NtpError> {
+    let address = if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{server}:123")
+    };
+
+    let request = async {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        socket.connect(&address).await?;
+
+        let mut packet = [0; PACKET_LEN];
+        // Leap indicator 0, version 3, client mode.
+        packet[0] = 0x1B;
+        let originate = now_secs();
+        write_timestamp(&mut packet[40..], originate);
+        socket.send(&packet).await?;
+
+        let mut reply = [0; PACKET_LEN];
+        let len = socket.recv(&mut reply).await?;
+        io::Result::Ok((originate, packet, reply, len, now_secs()))
+    };
+
+    let (originate, packet, reply, len, destination) =
+        match tokio::time::timeout(REQUEST_TIMEOUT, request).await {
+            Ok(Ok(exchange)) => exchange,
+            Ok(Err(e)) => return Err(NtpError::Io(e)),
+            Err(_) => return Err(NtpError::Timeout),
+        };
+
+    // Server mode, nonzero stratum, and echoing our transmit time.
+    if len < PACKET_LEN || reply[0] & 0x7 != 4 || reply[1] == 0 || reply[24..32] != packet[40..48] {
+        return Err(NtpError::InvalidReply);
+    }
+
+    Ok(clock_offset(
+        originate,
+        read_timestamp(&reply[32..40]),
+        read_timestamp(&reply[40..48]),
+        destination,
+    ))
+}
+
+/// Checks the clock on startup and every few hours, setting `drift` to the
+/// offset in seconds while it exceeds the configured maximum.
+pub async fn monitor_clock(backing_db: DbHandle, config: Config, mut drift: Signal<Option<f64>>) {
+    let servers = config
+        .ntp_server
+        .clone()
+        .unwrap_or_else(|| DEFAULT_SERVER.to_string());
+
+    loop {
+        let max_offset = backing_db
+            .try_run(|db| {
+                Ok(db
+                    .get_setting(MAX_OFFSET_KEY)?
+                    .unwrap_or(DEFAULT_MAX_OFFSET_SECS))
+            })
+            .await
+            .unwrap_or(DEFAULT_MAX_OFFSET_SECS);
+
+        let mut checked = false;
+        for server in servers.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match query_offset(server).await {
+                Ok(offset) => {
+                    checked = true;
+                    if offset.abs() > max_offset {
+                        warn!(target: "clock", server, offset, "System clock is off");
+                        let server = server.to_string();
+                        let _ = backing_db
+                            .try_run(move |db| {
+                                db.log_event("clock_drift", &server, &format!("{offset:.3}s"))
+                            })
+                            .await;
+                        drift.set(Some(offset));
+                    } else {
+                        info!(target: "clock", server, offset, "System clock checked");
+                        drift.set(None);
+                    }
+                    break;
+                }
+                Err(e) => debug!(target: "clock", server, "NTP query failed: {e}"),
+            }
+        }
+        if !checked {
+            debug!(target: "clock", "No NTP server reachable, skipping clock check");
+        }
+
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers one request with its clock `shift` seconds ahead, after
+    /// letting `tamper` edit the reply.
+    async fn mock_server(shift: f64, tamper: fn(&mut [u8; PACKET_LEN])) -> String {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut request = [0; PACKET_LEN];
+            let (_, client) = socket.recv_from(&mut request).await.unwrap();
+
+            let mut reply = [0; PACKET_LEN];
+            // Version 3, server mode, stratum 2.
+            reply[0] = 0x1C;
+            reply[1] = 2;
+            reply[24..32].copy_from_slice(&request[40..48]);
+            write_timestamp(&mut reply[32..40], now_secs() + shift);
+            write_timestamp(&mut reply[40..48], now_secs() + shift);
+            tamper(&mut reply);
+            socket.send_to(&reply, client).await.unwrap();
+        });
+        address
+    }
+
+    #[test]
+    fn offset_splits_round_trip() {
+        // One second each way, server an hour ahead.
+        assert_eq!(clock_offset(100.0, 3701.0, 3701.5, 102.5), 3600.0);
+        assert_eq!(clock_offset(3701.0, 102.0, 102.5, 3703.5), -3600.0);
+        assert_eq!(clock_offset(100.0, 100.5, 100.5, 101.0), 0.0);
+    }
+
+    #[test]
+    fn timestamps_round_trip() {
+        let mut bytes = [0; 8];
+        write_timestamp(&mut bytes, 1_700_000_000.25);
+        assert_eq!(read_timestamp(&bytes), 1_700_000_000.25);
+        // Counted from 1900, not 1970.
+        assert_eq!(bytes[..4], 3_908_988_800_u32.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn measures_mock_offset() {
+        let ahead = query_offset(&mock_server(3600.0, |_| ()).await)
+            .await
+            .unwrap();
+        assert!((ahead - 3600.0).abs() < 1.0, "{ahead}");
+
+        let behind = query_offset(&mock_server(-90.0, |_| ()).await)
+            .await
+            .unwrap();
+        assert!((behind + 90.0).abs() < 1.0, "{behind}");
+    }
+
+    #[tokio::test]
+    async fn bad_replies_refused() {
+        let kiss_of_death = mock_server(0.0, |reply| reply[1] = 0).await;
+        assert!(matches!(
+            query_offset(&kiss_of_death).await,
+            Err(NtpError::InvalidReply)
+        ));
+
+        let wrong_request = mock_server(0.0, |reply| reply[31] ^= 1).await;
+        assert!(matches!(
+            query_offset(&wrong_request).await,
+            Err(NtpError::InvalidReply)
+        ));
+
+        let client_mode = mock_server(0.0, |reply| reply[0] = 0x1B).await;
+        assert!(matches!(
+            query_offset(&client_mode).await,
+            Err(NtpError::InvalidReply)
+        ));
+    }
+}
@@ -0,0 +1,564 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Startup configuration from a TOML file and environment variables.
+//!
+//! Precedence, highest first: command line flag, environment variable,
+//! config file, built-in default. Every field is `None` when none of them
+//! set it.
+//!
+//! Values with a matching database setting are copied into it on the first
+//! launch, see [`Config::seed`]. From then on the setting, changed in the
+//! admin panel, comes before the environment and file, which only act as
+//! defaults, unless `--force-config` copies them in again. Command line flags
+//! still come first.
+
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Deserializer};
+use tracing::{error, info};
+
+use crate::{
+    MIN_SCAN_SPACING_SECS, QrType,
+    cli::Cli,
+    signout,
+    sqlite::{BackingDatabase, CameraResolution, DbError},
+    video::VIDEO_BIND_KEY,
+};
+
+/// Commented example written by `qr-tracker config init`.
+pub const TEMPLATE: &str = r#"# qr-tracker configuration.
+#
+# Values here are copied into the database settings on the first launch, and
+# are only defaults afterwards: changes made in the admin panel win, unless
+# qr-tracker runs with --force-config to copy this file in again. Environment
+# variables (QR_TRACKER_<KEY IN CAPS>) and command line flags override this
+# file.
+
+# Database file, qr-tracker.db in the data directory by default.
+# database = "/srv/qr-tracker/qr-tracker.db"
+
+# Port serving the camera preview stream.
+# video_port = 2343
+
+# Address and port serving the camera preview stream, localhost and video_port
+# by default. Use 0.0.0.0 to serve other machines on the LAN.
+# video_bind = "0.0.0.0:2343"
+
+# Camera index to try before probing every camera.
+# camera_index = 0
+
+# Camera resolution, <width>x<height>.
+# resolution = "1280x720"
+
+# Failed camera reads in a row, each followed by reopening the camera, before
+# giving up until restart.
+# camera_max_failures = 10
+
+# Second camera scanned alongside the first, e.g. at a back door. Its scans
+# are logged with source "camera-1", and it is not shown on the preview.
+# second_camera_index = 1
+
+# Retry codes OpenCV finds but cannot read with a second decoder, at some CPU
+# cost.
+# qr_fallback = true
+
+# Minimum seconds between two scans of the same badge.
+# scan_spacing_secs = 20
+
+# Local time everyone still present is signed out each night.
+# sign_out_time = "23:59"
+
+# Roster CSV imported into a new database, as with qr-tracker roster import.
+# roster = "roster.csv"
+
+# Google service account JSON key and spreadsheet ID for pushing hours
+# (qr-tracker export sheets). Requires the sheets feature.
+# sheets_key = "service-account.json"
+# sheets_spreadsheet = "1AbC..."
+
+# iCal feed URL or file with the meeting schedule, synced daily.
+# Requires the calendar feature.
+# calendar = "https://calendar.google.com/calendar/ical/.../basic.ics"
+
+# NTP servers checked for clock drift, comma separated, tried in order.
+# ntp_server = "pool.ntp.org"
+
+# Rhai script deciding whether to accept each scan. Requires the scripting
+# feature.
+# rules_script = "rules.rhai"
+
+# Twilio account for texting contacts on check-in and check-out. Keep the
+# auth token here or in QR_TRACKER_TWILIO_AUTH_TOKEN, never in the database.
+# Requires the sms feature.
+# twilio_account_sid = "AC..."
+# twilio_auth_token = "..."
+# twilio_from = "+15555550100"
+
+# Kiosk window: monitor index (from 0) or name, staying above other windows,
+# and hiding the mouse pointer.
+# monitor = "1"
+# always_on_top = false
+# hide_cursor = false
+
+# JSON file with the latest released version, e.g. {"version": "0.2.0"},
+# checked once a day. Requires the update-check feature.
+# update_url = "https://example.com/qr-tracker/latest.json"
+"#;
+
+/// Setting marking a database as seeded from the config file.
+const SEEDED_KEY: &str = "config.seeded";
+
+const KNOWN_KEYS: [&str; 23] = [
+    "database",
+    "video_port",
+    "video_bind",
+    "camera_index",
+    "resolution",
+    "camera_max_failures",
+    "second_camera_index",
+    "qr_fallback",
+    "scan_spacing_secs",
+    "sign_out_time",
+    "roster",
+    "sheets_key",
+    "sheets_spreadsheet",
+    "calendar",
+    "ntp_server",
+    "rules_script",
+    "twilio_account_sid",
+    "twilio_auth_token",
+    "twilio_from",
+    "monitor",
+    "always_on_top",
+    "hide_cursor",
+    "update_url",
+];
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub database: Option<PathBuf>,
+    pub video_port: Option<u16>,
+    pub video_bind: Option<String>,
+    pub camera_index: Option<u32>,
+    #[serde(deserialize_with = "parse_str")]
+    pub resolution: Option<CameraResolution>,
+    pub camera_max_failures: Option<u32>,
+    pub second_camera_index: Option<u32>,
+    pub qr_fallback: Option<bool>,
+    pub scan_spacing_secs: Option<i64>,
+    #[serde(deserialize_with = "parse_str")]
+    pub sign_out_time: Option<NaiveTime>,
+    pub roster: Option<PathBuf>,
+    pub sheets_key: Option<PathBuf>,
+    pub sheets_spreadsheet: Option<String>,
+    pub calendar: Option<String>,
+    pub ntp_server: Option<String>,
+    pub rules_script: Option<PathBuf>,
+    pub twilio_account_sid: Option<String>,
+    pub twilio_auth_token: Option<String>,
+    pub twilio_from: Option<String>,
+    pub monitor: Option<String>,
+    pub always_on_top: Option<bool>,
+    pub hide_cursor: Option<bool>,
+    pub update_url: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            Self::Parse(path, e) => write!(f, "{}: {e}", path.display()),
+        }
+    }
+}
+
+/// Parses a string value with [`FromStr`], so bad values are reported with
+/// their line.
+fn parse_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err: Display>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// `qr-tracker.toml` next to the executable when there is one, e.g. on a
+/// provisioning USB drive. Otherwise `~/.config/qr-tracker/config.toml`, or
+/// the platform equivalent.
+pub fn default_path() -> PathBuf {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.parent()?.join("qr-tracker.toml")))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| {
+            dirs::config_dir()
+                .unwrap_or_default()
+                .join("qr-tracker")
+                .join("config.toml")
+        })
+}
+
+/// Overrides the data directory, e.g. for a portable install on a USB drive.
+const DATA_DIR_ENV: &str = "QR_TRACKER_DATA_DIR";
+
+/// Files and folders kept in the data directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataPath {
+    Root,
+    Database,
+    Logs,
+    Backups,
+    Exports,
+    Badges,
+    Snapshots,
+    Reports,
+}
+
+/// Where `path` lives in the data directory: `$QR_TRACKER_DATA_DIR`, else
+/// `~/.local/share/qr-tracker` or the platform equivalent.
+///
+/// Every default file location is resolved here.
+pub fn data_path(path: DataPath) -> PathBuf {
+    let root = std::env::var_os(DATA_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dirs::data_dir().unwrap_or_default().join("qr-tracker"));
+
+    match path {
+        DataPath::Root => root,
+        DataPath::Database => root.join("qr-tracker.db"),
+        DataPath::Logs => root.join("logs"),
+        DataPath::Backups => root.join("backups"),
+        DataPath::Exports => root.join("exports"),
+        DataPath::Badges => root.join("badges"),
+        DataPath::Snapshots => root.join("snapshots"),
+        DataPath::Reports => root.join("reports"),
+    }
+}
+
+impl Config {
+    /// Reads the config file and applies environment overrides.
+    ///
+    /// A missing file is only an error when `required`. Unknown keys are
+    /// reported and otherwise ignored.
+    pub fn load(path: &Path, required: bool) -> Result<Self, ConfigError> {
+        let mut config = match fs::read_to_string(path) {
+            Ok(contents) => {
+                Self::parse(&contents).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Self::default(),
+            Err(e) => return Err(ConfigError::Io(path.to_path_buf(), e)),
+        };
+
+        config.apply_env(|var| std::env::var(var).ok());
+        Ok(config)
+    }
+
+    /// Overrides fields from command line flags. `--video-bind` and
+    /// `--source` are kept apart, as they also come before the database
+    /// settings.
+    pub fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(database) = &cli.database {
+            self.database = Some(database.clone());
+        }
+        if let Some(monitor) = &cli.monitor {
+            self.monitor = Some(monitor.clone());
+        }
+        if cli.always_on_top {
+            self.always_on_top = Some(true);
+        }
+        if cli.hide_cursor {
+            self.hide_cursor = Some(true);
+        }
+    }
+
+    /// Seconds between two scans of one badge: the database setting, else
+    /// the file's, else the default.
+    pub fn scan_spacing_secs(&self, db: &BackingDatabase) -> Result<i64, DbError> {
+        Ok(db
+            .scan_spacing_secs()?
+            .or(self.scan_spacing_secs)
+            .unwrap_or(MIN_SCAN_SPACING_SECS))
+    }
+
+    fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        let table: toml::Table = toml::from_str(contents)?;
+        for key in table.keys() {
+            if !KNOWN_KEYS.contains(&key.as_str()) {
+                tracing::warn!(target: "config", "Ignoring unknown config key: {key}");
+            }
+        }
+
+        // From the text rather than the table, so errors keep their line.
+        toml::from_str(contents)
+    }
+
+    /// Copies file values with a matching setting into `db` the first time
+    /// it is opened, and imports `roster`. With `force`, the settings are
+    /// copied again on every start, replacing admin panel changes.
+    pub fn seed(&self, db: &mut BackingDatabase, force: bool) -> Result<(), DbError> {
+        let first = !db.get_setting(SEEDED_KEY)?.unwrap_or(false);
+        if !first && !force {
+            return Ok(());
+        }
+
+        if let Some(bind) = &self.video_bind {
+            db.set_setting(VIDEO_BIND_KEY, bind)?;
+        }
+        if let Some(index) = self.camera_index {
+            db.set_camera_index(index)?;
+        }
+        if let Some(CameraResolution(resolution)) = self.resolution {
+            db.set_resolution(resolution)?;
+        }
+        if let Some(secs) = self.scan_spacing_secs {
+            db.set_scan_spacing_secs(secs)?;
+        }
+        if let Some(time) = self.sign_out_time {
+            db.set_setting(signout::TIME_KEY, time)?;
+        }
+
+        if first && let Some(roster) = &self.roster {
+            match db.import_roster_csv(roster, QrType::Student) {
+                Ok(report) => info!(target: "config", "Imported {}: {report}", roster.display()),
+                Err(e) => error!(target: "config", "Import of {} failed: {e}", roster.display()),
+            }
+        }
+        db.set_setting(SEEDED_KEY, true)?;
+        info!(target: "config", "Copied config file values into the settings");
+        Ok(())
+    }
+
+    /// Overrides fields from `QR_TRACKER_<KEY>` variables, looked up with
+    /// `env`.
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) {
+        fn env_override<T: FromStr>(
+            env: &dyn Fn(&str) -> Option<String>,
+            field: &mut Option<T>,
+            key: &str,
+        ) {
+            let var = format!("QR_TRACKER_{}", key.to_ascii_uppercase());
+            if let Some(value) = env(&var) {
+                match value.parse() {
+                    Ok(value) => *field = Some(value),
+                    Err(_) => {
+                        tracing::warn!(target: "config", "Ignoring unparsable {var}: {value}")
+                    }
+                }
+            }
+        }
+
+        env_override(&env, &mut self.database, "database");
+        env_override(&env, &mut self.video_port, "video_port");
+        env_override(&env, &mut self.video_bind, "video_bind");
+        env_override(&env, &mut self.camera_index, "camera_index");
+        env_override(&env, &mut self.resolution, "resolution");
+        env_override(&env, &mut self.camera_max_failures, "camera_max_failures");
+        env_override(&env, &mut self.second_camera_index, "second_camera_index");
+        env_override(&env, &mut self.qr_fallback, "qr_fallback");
+        env_override(&env, &mut self.scan_spacing_secs, "scan_spacing_secs");
+        env_override(&env, &mut self.sign_out_time, "sign_out_time");
+        env_override(&env, &mut self.roster, "roster");
+        env_override(&env, &mut self.sheets_key, "sheets_key");
+        env_override(&env, &mut self.sheets_spreadsheet, "sheets_spreadsheet");
+        env_override(&env, &mut self.calendar, "calendar");
+        env_override(&env, &mut self.ntp_server, "ntp_server");
+        env_override(&env, &mut self.rules_script, "rules_script");
+        env_override(&env, &mut self.twilio_account_sid, "twilio_account_sid");
+        env_override(&env, &mut self.twilio_auth_token, "twilio_auth_token");
+        env_override(&env, &mut self.twilio_from, "twilio_from");
+        env_override(&env, &mut self.monitor, "monitor");
+        env_override(&env, &mut self.always_on_top, "always_on_top");
+        env_override(&env, &mut self.hide_cursor, "hide_cursor");
+        env_override(&env, &mut self.update_url, "update_url");
+    }
+}
+
+/// Writes [`TEMPLATE`] to `path`, refusing to replace an existing file unless
+/// `force` is set.
+pub fn init(path: &Path, force: bool) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if force {
+        fs::write(path, TEMPLATE)
+    } else {
+        use io::Write;
+        fs::File::create_new(path)?.write_all(TEMPLATE.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    fn vars<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        move |var| {
+            pairs
+                .iter()
+                .find(|(name, _)| *name == var)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn partial_file_leaves_rest_unset() {
+        let config = Config::parse("camera_index = 2\nsign_out_time = \"22:30\"\n").unwrap();
+        assert_eq!(
+            config,
+            Config {
+                camera_index: Some(2),
+                sign_out_time: NaiveTime::from_hms_opt(22, 30, 0),
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_keys_ignored() {
+        let config = Config::parse("camera_index = 1\ntheme = \"dark\"\n[extra]\nx = 1\n").unwrap();
+        assert_eq!(config.camera_index, Some(1));
+    }
+
+    #[test]
+    fn bad_values_rejected() {
+        assert!(Config::parse("resolution = \"big\"").is_err());
+        assert!(Config::parse("video_port = 70000").is_err());
+        assert!(Config::parse("camera_index = ").is_err());
+    }
+
+    #[test]
+    fn template_is_all_defaults() {
+        assert_eq!(Config::parse(TEMPLATE).unwrap(), Config::default());
+        for key in KNOWN_KEYS {
+            assert!(TEMPLATE.contains(&format!("# {key} = ")), "{key}");
+        }
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let mut config = Config::parse("camera_index = 1\nvideo_port = 2343\n").unwrap();
+        config.apply_env(vars(&[
+            ("QR_TRACKER_CAMERA_INDEX", "3"),
+            ("QR_TRACKER_VIDEO_PORT", "not a port"),
+            ("QR_TRACKER_MONITOR", "HDMI-1"),
+        ]));
+
+        assert_eq!(config.camera_index, Some(3));
+        // Unparsable values leave the file's.
+        assert_eq!(config.video_port, Some(2343));
+        assert_eq!(config.monitor.as_deref(), Some("HDMI-1"));
+    }
+
+    #[test]
+    fn cli_overrides_env() {
+        let mut config = Config::parse("database = \"file.db\"\nmonitor = \"0\"\n").unwrap();
+        config.apply_env(vars(&[
+            ("QR_TRACKER_DATABASE", "env.db"),
+            ("QR_TRACKER_MONITOR", "1"),
+            ("QR_TRACKER_HIDE_CURSOR", "false"),
+        ]));
+        let cli =
+            Cli::try_parse_from(["qr-tracker", "--database", "cli.db", "--hide-cursor"]).unwrap();
+        config.apply_cli(&cli);
+
+        assert_eq!(config.database, Some(PathBuf::from("cli.db")));
+        // Unset flags leave the environment's.
+        assert_eq!(config.monitor.as_deref(), Some("1"));
+        assert_eq!(config.hide_cursor, Some(true));
+        assert_eq!(config.always_on_top, None);
+    }
+
+    #[test]
+    fn file_fills_unset_settings() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        assert_eq!(
+            Config::default().scan_spacing_secs(&db).unwrap(),
+            MIN_SCAN_SPACING_SECS
+        );
+
+        let config = Config::parse("scan_spacing_secs = 30\n").unwrap();
+        assert_eq!(config.scan_spacing_secs(&db).unwrap(), 30);
+
+        // The database comes before the file.
+        db.set_scan_spacing_secs(45).unwrap();
+        assert_eq!(config.scan_spacing_secs(&db).unwrap(), 45);
+        assert_eq!(Config::default().scan_spacing_secs(&db).unwrap(), 45);
+    }
+
+    #[test]
+    fn missing_file_only_fails_when_required() {
+        let path = std::env::temp_dir().join(format!("no-config-{}.toml", std::process::id()));
+        assert!(matches!(Config::load(&path, false), Ok(config) if config.database.is_none()));
+        assert!(matches!(
+            Config::load(&path, true),
+            Err(ConfigError::Io(..))
+        ));
+    }
+
+    #[test]
+    fn file_seeds_settings_once() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        let config = Config {
+            camera_index: Some(2),
+            scan_spacing_secs: Some(30),
+            ..Config::default()
+        };
+        config.seed(&mut db, false).unwrap();
+        assert_eq!(db.get_camera_index().unwrap(), Some(2));
+        assert_eq!(db.scan_spacing_secs().unwrap(), Some(30));
+
+        // Changed in the admin panel, kept over the file and used.
+        db.set_camera_index(0).unwrap();
+        db.set_scan_spacing_secs(45).unwrap();
+        config.seed(&mut db, false).unwrap();
+        assert_eq!(db.get_camera_index().unwrap(), Some(0));
+        assert_eq!(config.scan_spacing_secs(&db).unwrap(), 45);
+
+        config.seed(&mut db, true).unwrap();
+        assert_eq!(db.get_camera_index().unwrap(), Some(2));
+        assert_eq!(config.scan_spacing_secs(&db).unwrap(), 30);
+    }
+
+    #[test]
+    fn init_keeps_existing_file() {
+        let path = std::env::temp_dir()
+            .join(format!("config-init-{}", std::process::id()))
+            .join("config.toml");
+        let _ = fs::remove_file(&path);
+
+        init(&path, false).unwrap();
+        fs::write(&path, "camera_index = 1\n").unwrap();
+        assert_eq!(
+            init(&path, false).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "camera_index = 1\n");
+
+        init(&path, true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), TEMPLATE);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
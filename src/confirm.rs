@@ -0,0 +1,145 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Asks before a camera scan signs someone out, so a badge scanned twice in
+//! one visit does not end it.
+//!
+//! The scan loop queues the sign-out instead of recording it. It is recorded
+//! at the time of the scan once confirmed, or when the countdown runs out.
+//! Sign-ins and scans from other devices are never held.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use dioxus::prelude::*;
+
+use crate::{
+    QrType,
+    sqlite::{BackingDatabase, DbError},
+};
+
+/// Setting controlling whether camera sign-outs are confirmed.
+pub const ENABLED_KEY: &str = "scan.confirm_sign_out";
+
+/// Sign-outs nobody answers are recorded after this long.
+const AUTO_CONFIRM: Duration = Duration::from_secs(10);
+
+pub fn enabled(db: &BackingDatabase) -> Result<bool, DbError> {
+    Ok(db.get_setting(ENABLED_KEY)?.unwrap_or(true))
+}
+
+/// A scan that would sign `name` out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingSignOut {
+    pub name: String,
+    pub qr_type: QrType,
+    pub time: DateTime<Local>,
+    /// Of the scan, e.g. the second camera.
+    pub source: Option<String>,
+}
+
+/// Sign-outs waiting on the kiosk, and the scan loop's queue of confirmed
+/// ones.
+#[derive(Clone)]
+pub struct SignOutQueue {
+    /// Oldest first, the prompt shows the first.
+    pub pending: Signal<Vec<PendingSignOut>>,
+    confirmed_tx: async_channel::Sender<PendingSignOut>,
+}
+
+impl SignOutQueue {
+    pub fn new(
+        pending: Signal<Vec<PendingSignOut>>,
+        confirmed_tx: async_channel::Sender<PendingSignOut>,
+    ) -> Self {
+        Self {
+            pending,
+            confirmed_tx,
+        }
+    }
+
+    /// Queues `sign_out`, unless that person is already waiting, and
+    /// confirms it once the countdown runs out.
+    pub fn push(&self, sign_out: PendingSignOut) {
+        let mut pending = self.pending;
+        if pending
+            .peek()
+            .iter()
+            .any(|queued| queued.name == sign_out.name)
+        {
+            return;
+        }
+        pending.write().push(sign_out.clone());
+
+        let queue = self.clone();
+        spawn(async move {
+            tokio::time::sleep(AUTO_CONFIRM).await;
+            queue.confirm(&sign_out);
+        });
+    }
+
+    /// Hands `sign_out` to the scan loop, unless it was already answered.
+    pub fn confirm(&self, sign_out: &PendingSignOut) {
+        if self.take(sign_out) {
+            let _ = self.confirmed_tx.try_send(sign_out.clone());
+        }
+    }
+
+    /// Drops `sign_out`, leaving the person signed in.
+    pub fn cancel(&self, sign_out: &PendingSignOut) {
+        self.take(sign_out);
+    }
+
+    /// Removes `sign_out` from the queue, false when it was not queued.
+    fn take(&self, sign_out: &PendingSignOut) -> bool {
+        let mut pending = self.pending;
+        let idx = pending.peek().iter().position(|queued| queued == sign_out);
+        idx.map(|idx| pending.write().remove(idx)).is_some()
+    }
+}
+
+/// Covers the kiosk while a sign-out waits, with a countdown to when it is
+/// recorded anyway.
+#[component]
+pub fn SignOutPrompt() -> Element {
+    let queue: SignOutQueue = use_context();
+    let queue_cancel = queue.clone();
+
+    // Redraws the countdown.
+    let mut now = use_signal(Local::now);
+    use_future(move || async move {
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            now.set(Local::now());
+        }
+    });
+
+    let pending = queue.pending.read();
+    let Some(sign_out) = pending.first().cloned() else {
+        return rsx! {};
+    };
+    let waiting = pending.len() - 1;
+    drop(pending);
+
+    let elapsed = (now() - sign_out.time).num_seconds().max(0) as u64;
+    let remaining = AUTO_CONFIRM.as_secs().saturating_sub(elapsed);
+    let sign_out_cancel = sign_out.clone();
+
+    rsx! {
+        div {
+            class: "confirm_overlay",
+            h1 { "Sign out {sign_out.name}?" }
+            h3 { "Signing out in {remaining}s" }
+            if waiting > 0 {
+                p { "{waiting} more waiting" }
+            }
+            div {
+                button { onclick: move |_| queue.confirm(&sign_out), "Confirm" }
+                button { onclick: move |_| queue_cancel.cancel(&sign_out_cancel), "Cancel" }
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Local control socket for shell scripts, without enabling the HTTP API.
+//!
+//! Each line sent is a JSON command, answered by one line of JSON:
+//! `{"cmd": "present"}`, `{"cmd": "checkout_all"}`,
+//! `{"cmd": "scan", "payload": "..."}`, and `{"cmd": "export", "path": "..."}`.
+//! Failures are answered with `{"error": "..."}`. The socket is only
+//! accessible to the user running the kiosk. Unix only.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{self, DataPath};
+
+/// Largest accepted command line.
+#[cfg_attr(not(unix), allow(dead_code))]
+const MAX_LINE_LEN: u64 = 4 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ControlCommand {
+    /// Who is present, as `GET /api/present`.
+    Present,
+    /// Signs out everyone present.
+    CheckoutAll,
+    /// Handled as if scanned by the camera.
+    Scan { payload: String },
+    /// Writes every scan as CSV to `path` on the kiosk.
+    Export { path: PathBuf },
+}
+
+/// `$XDG_RUNTIME_DIR/qr-tracker.sock`, or in the data directory when unset.
+pub fn socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| config::data_path(DataPath::Root))
+        .join("qr-tracker.sock")
+}
+
+#[cfg(unix)]
+pub use unix::{send, serve};
+
+/// Windows has no control socket.
+#[cfg(not(unix))]
+pub async fn serve(
+    _backing_db: crate::db_worker::DbHandle,
+    _refresh_tx: async_channel::Sender<()>,
+    _submissions: async_channel::Sender<crate::api::ScanSubmission>,
+    _view: impl crate::attendance::AttendanceView,
+) {
+}
+
+#[cfg(not(unix))]
+pub fn send(_line: &str) -> std::io::Result<String> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "the control socket is unix only",
+    ))
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::{self, Permissions},
+        io::{self, BufRead, BufReader, Write},
+        os::unix::fs::PermissionsExt,
+    };
+
+    use chrono::Local;
+    use serde_json::{Value, json};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt},
+        net::{UnixListener, UnixStream},
+    };
+    use tracing::{debug, error, info, warn};
+
+    use super::{ControlCommand, MAX_LINE_LEN, socket_path};
+    use crate::{
+        api::{self, SCAN_REPLY_TIMEOUT, ScanSubmission},
+        attendance::AttendanceView,
+        db_worker::DbHandle,
+        sqlite::date_range,
+    };
+
+    /// Source recorded for scans submitted over the socket.
+    const SOURCE: &str = "ctl";
+
+    /// Serves the control socket until the app exits.
+    ///
+    /// Scans go to the scan loop through `submissions`, other changes are
+    /// written to `backing_db` followed by a `refresh_tx` reload, the same
+    /// as admin panel edits. Connections run on `view`.
+    pub async fn serve(
+        backing_db: DbHandle,
+        refresh_tx: async_channel::Sender<()>,
+        submissions: async_channel::Sender<ScanSubmission>,
+        view: impl AttendanceView,
+    ) {
+        let path = socket_path();
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        // Left behind by an unclean exit, the instance lock guarantees it is
+        // not in use.
+        let _ = fs::remove_file(&path);
+
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!(target: "control", "Failed to bind {}: {e}", path.display());
+                return;
+            }
+        };
+        if let Err(e) = fs::set_permissions(&path, Permissions::from_mode(0o600)) {
+            error!(target: "control", "Failed to restrict {}: {e}", path.display());
+            let _ = fs::remove_file(&path);
+            return;
+        }
+        info!(target: "control", "Control socket listening on {}", path.display());
+
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!(target: "control", "Control socket accept failed: {e}");
+                    continue;
+                }
+            };
+
+            let backing_db = backing_db.clone();
+            let refresh_tx = refresh_tx.clone();
+            let submissions = submissions.clone();
+            view.spawn(Box::pin(async move {
+                if let Err(e) = handle(stream, &backing_db, &refresh_tx, &submissions).await {
+                    debug!(target: "control", "Control connection failed: {e}");
+                }
+            }));
+        }
+    }
+
+    async fn handle(
+        mut stream: UnixStream,
+        backing_db: &DbHandle,
+        refresh_tx: &async_channel::Sender<()>,
+        submissions: &async_channel::Sender<ScanSubmission>,
+    ) -> io::Result<()> {
+        let (read, mut write) = stream.split();
+        let mut reader = tokio::io::BufReader::new(read);
+
+        loop {
+            let mut line = String::new();
+            let read = (&mut reader)
+                .take(MAX_LINE_LEN)
+                .read_line(&mut line)
+                .await?;
+            if read == 0 {
+                return Ok(());
+            }
+            if !line.ends_with('\n') && read as u64 == MAX_LINE_LEN {
+                let response = json!({ "error": "command too long" });
+                write.write_all(format!("{response}\n").as_bytes()).await?;
+                return Ok(());
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let response = match serde_json::from_str(&line) {
+                Ok(command) => run(command, backing_db, refresh_tx, submissions).await,
+                Err(e) => json!({ "error": format!("invalid command: {e}") }),
+            };
+            write.write_all(format!("{response}\n").as_bytes()).await?;
+        }
+    }
+
+    async fn run(
+        command: ControlCommand,
+        backing_db: &DbHandle,
+        refresh_tx: &async_channel::Sender<()>,
+        submissions: &async_channel::Sender<ScanSubmission>,
+    ) -> Value {
+        match command {
+            ControlCommand::Present => match backing_db.try_run(|db| api::present(db)).await {
+                Ok(present) => present,
+                Err(e) => json!({ "error": e.to_string() }),
+            },
+            ControlCommand::CheckoutAll => {
+                let now = Local::now();
+                let checked_out = backing_db
+                    .try_run(move |db| {
+                        db.get_present().and_then(|present| {
+                            present
+                                .iter()
+                                .try_for_each(|(name, _)| db.add_scan(name, now, Some(SOURCE)))
+                                .map(|()| present.len())
+                        })
+                    })
+                    .await;
+
+                // Some may have been checked out before a failure.
+                let _ = refresh_tx.try_send(());
+                match checked_out {
+                    Ok(count) => {
+                        info!(target: "control", count, "Checked out everyone");
+                        json!({ "checked_out": count })
+                    }
+                    Err(e) => json!({ "error": e.to_string() }),
+                }
+            }
+            ControlCommand::Scan { payload } => {
+                let (reply, outcome) = async_channel::bounded(1);
+                let submission = ScanSubmission {
+                    payload,
+                    source: SOURCE.to_string(),
+                    reply,
+                };
+                if submissions.send(submission).await.is_err() {
+                    return json!({ "error": "kiosk is not accepting scans" });
+                }
+
+                match tokio::time::timeout(SCAN_REPLY_TIMEOUT, outcome.recv()).await {
+                    Ok(Ok(action)) => action.to_json(),
+                    _ => json!({ "error": "kiosk did not handle the scan in time" }),
+                }
+            }
+            ControlCommand::Export { path } => {
+                let file = match fs::File::create(&path) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        return json!({ "error": format!("{}: {e}", path.display()) });
+                    }
+                };
+
+                let exported = backing_db
+                    .run(move |db| {
+                        db.export_attendance_csv(date_range(None, None), io::BufWriter::new(file))
+                    })
+                    .await;
+                match exported {
+                    Ok(Ok(count)) => json!({ "exported": count, "path": path }),
+                    Ok(Err(e)) => json!({ "error": e.to_string() }),
+                    Err(e) => json!({ "error": e.to_string() }),
+                }
+            }
+        }
+    }
+
+    /// Sends one command line to a running kiosk and returns its response.
+    pub fn send(line: &str) -> io::Result<String> {
+        let mut stream = std::os::unix::net::UnixStream::connect(socket_path())?;
+        stream.write_all(format!("{}\n", line.trim()).as_bytes())?;
+        stream.shutdown(std::net::Shutdown::Write)?;
+
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response)?;
+        Ok(response.trim().to_string())
+    }
+}
@@ -0,0 +1,430 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Runs database access on a dedicated thread, so slow writes and WAL
+//! checkpoints never stall the UI.
+//!
+//! The worker owns the [`BackingDatabase`] and handles [`DbCommand`]s in the
+//! order sent. [`DbHandle`] is the cloneable sender used by the app. Debug
+//! builds assert that no query runs on the thread that started a worker.
+//!
+//! Scans the database cannot take right now wait in the worker's
+//! [`ScanQueue`], retried between commands.
+
+use std::{
+    convert::Infallible,
+    ffi::c_int,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::{
+        Arc, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread::{self, ThreadId},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use nokhwa::utils::Resolution;
+use rusqlite::{
+    Connection,
+    backup::{Backup, StepResult},
+};
+use tracing::error;
+
+use crate::{
+    scan_queue::ScanQueue,
+    sqlite::{BackingDatabase, BackupError, DbError},
+};
+
+const WORKER_STOPPED: &str = "database worker stopped";
+/// Pages copied per backup step, 1 MiB at the default page size.
+const BACKUP_PAGES_PER_STEP: c_int = 256;
+/// Pause between backup steps.
+const BACKUP_STEP_PAUSE: Duration = Duration::from_millis(50);
+/// How often commands are checked for while waiting to retry queued scans.
+const RETRY_POLL: Duration = Duration::from_millis(50);
+
+/// Thread running the UI, which must not touch the database.
+static UI_THREAD: OnceLock<ThreadId> = OnceLock::new();
+
+/// Whether this is the UI thread, once any worker is running.
+pub fn on_ui_thread() -> bool {
+    UI_THREAD.get() == Some(&thread::current().id())
+}
+
+type Reply<T> = async_channel::Sender<T>;
+type Job = Box<dyn FnOnce(&mut BackingDatabase) + Send>;
+/// Everyone present, with when they signed in.
+type Present = Vec<(String, DateTime<Local>)>;
+
+/// One request to the worker. Each carries where to send its result.
+pub enum DbCommand {
+    AddScan {
+        name: String,
+        time: DateTime<Local>,
+        source: Option<String>,
+        reply: Reply<Result<(), DbError>>,
+    },
+    RemoveScan {
+        name: String,
+        time: DateTime<Local>,
+        reply: Reply<Result<(), DbError>>,
+    },
+    GetPresent {
+        reply: Reply<Result<Present, DbError>>,
+    },
+    SignOutAll {
+        cutoff: DateTime<Local>,
+        source: String,
+        reply: Reply<Result<Vec<String>, DbError>>,
+    },
+    GetResolution {
+        reply: Reply<Result<Option<Resolution>, DbError>>,
+    },
+    SetResolution {
+        resolution: Resolution,
+        reply: Reply<Result<(), DbError>>,
+    },
+    /// Passive WAL checkpoint, nothing is replied.
+    Checkpoint,
+    /// Copies the database into a new backup, see [`backup`].
+    Backup {
+        reply: Reply<Result<PathBuf, BackupError>>,
+    },
+    /// Anything else, see [`DbHandle::run`].
+    Run(Job),
+}
+
+impl DbCommand {
+    /// Handles the command, keeping the worker running if it panics. The
+    /// reply is then dropped, failing the request with
+    /// [`DbError::WorkerFailed`].
+    fn handle(self, db: &mut BackingDatabase, queue: &mut ScanQueue) {
+        if panic::catch_unwind(AssertUnwindSafe(|| self.handle_inner(db, queue))).is_err() {
+            error!(target: "database", "A database request panicked");
+        }
+    }
+
+    fn handle_inner(self, db: &mut BackingDatabase, queue: &mut ScanQueue) {
+        // A dropped reply means the caller stopped waiting.
+        match self {
+            Self::AddScan {
+                name,
+                time,
+                source,
+                reply,
+            } => {
+                let _ = reply.try_send(queue.add(db, &name, time, source.as_deref()));
+            }
+            Self::RemoveScan { name, time, reply } => {
+                let _ = reply.try_send(db.remove_scan(&name, time));
+            }
+            Self::GetPresent { reply } => {
+                let _ = reply.try_send(db.get_present());
+            }
+            Self::SignOutAll {
+                cutoff,
+                source,
+                reply,
+            } => {
+                let _ = reply.try_send(db.sign_out_all(cutoff, &source));
+            }
+            Self::GetResolution { reply } => {
+                let _ = reply.try_send(db.get_resolution());
+            }
+            Self::SetResolution { resolution, reply } => {
+                let _ = reply.try_send(db.set_resolution(resolution));
+            }
+            Self::Checkpoint => {
+                if let Err(e) = db.checkpoint() {
+                    error!(target: "db", "Checkpoint failed: {e}");
+                }
+            }
+            // Started by the worker loop, so only reached mid backup.
+            Self::Backup { reply } => {
+                let _ = reply.try_send(Err(BackupError::InProgress));
+            }
+            Self::Run(job) => job(db),
+        }
+    }
+}
+
+/// Sends commands to a database worker. The worker stops once every handle
+/// is dropped.
+#[derive(Clone)]
+pub struct DbHandle {
+    commands: async_channel::Sender<DbCommand>,
+    pending_scans: Arc<AtomicUsize>,
+}
+
+impl DbHandle {
+    /// Starts a worker with the database from `open`, which runs on the
+    /// worker. The calling thread is taken as the UI thread.
+    pub fn spawn(open: impl FnOnce() -> BackingDatabase + Send + 'static) -> Self {
+        match Self::try_spawn(move || Ok::<_, Infallible>(open())) {
+            Ok(handle) => handle,
+        }
+    }
+
+    /// [`Self::spawn`], waiting for `open` to succeed.
+    pub fn try_spawn<E: Send + 'static>(
+        open: impl FnOnce() -> Result<BackingDatabase, E> + Send + 'static,
+    ) -> Result<Self, E> {
+        UI_THREAD.get_or_init(|| thread::current().id());
+
+        let (opened_tx, opened) = async_channel::bounded(1);
+        let (commands, commands_rx) = async_channel::unbounded::<DbCommand>();
+        let pending_scans = Arc::new(AtomicUsize::new(0));
+        let queue_len = pending_scans.clone();
+        thread::Builder::new()
+            .name("db_worker".to_string())
+            .spawn(move || {
+                let mut db = match open() {
+                    Ok(db) => db,
+                    Err(e) => {
+                        let _ = opened_tx.try_send(Err(e));
+                        return;
+                    }
+                };
+                let mut queue = ScanQueue::load(&db, queue_len);
+                let _ = opened_tx.try_send(Ok(()));
+                loop {
+                    let command = match queue.retry_in() {
+                        Some(wait) => match recv_timeout(&commands_rx, wait) {
+                            Ok(Some(command)) => command,
+                            Ok(None) => {
+                                queue.flush(&mut db);
+                                continue;
+                            }
+                            Err(_) => break,
+                        },
+                        None => match commands_rx.recv_blocking() {
+                            Ok(command) => command,
+                            Err(_) => break,
+                        },
+                    };
+                    match command {
+                        DbCommand::Backup { reply } => {
+                            let result = backup(&mut db, &mut queue, &commands_rx);
+                            if let Err(e) = db.finish_backup(&result) {
+                                error!(target: "backup", "Failed to record the backup: {e}");
+                            }
+                            let _ = reply.try_send(result);
+                        }
+                        command => command.handle(&mut db, &mut queue),
+                    }
+                }
+            })
+            .expect("failed to start the database worker");
+
+        opened.recv_blocking().expect(WORKER_STOPPED)?;
+        Ok(Self {
+            commands,
+            pending_scans,
+        })
+    }
+
+    fn send(&self, command: DbCommand) {
+        if self.commands.try_send(command).is_err() {
+            error!(target: "database", "{WORKER_STOPPED}");
+        }
+    }
+
+    /// Fails once the worker stopped, or if the request panicked. Both are
+    /// logged already, so callers only waiting for a write can drop the
+    /// error.
+    async fn request<T>(&self, command: impl FnOnce(Reply<T>) -> DbCommand) -> Result<T, DbError> {
+        let (reply, response) = async_channel::bounded(1);
+        self.send(command(reply));
+        response.recv().await.map_err(|_| DbError::WorkerFailed)
+    }
+
+    pub async fn add_scan(
+        &self,
+        name: &str,
+        time: DateTime<Local>,
+        source: Option<&str>,
+    ) -> Result<(), DbError> {
+        self.request(|reply| DbCommand::AddScan {
+            name: name.to_string(),
+            time,
+            source: source.map(str::to_string),
+            reply,
+        })
+        .await?
+    }
+
+    pub async fn remove_scan(&self, name: &str, time: DateTime<Local>) -> Result<(), DbError> {
+        self.request(|reply| DbCommand::RemoveScan {
+            name: name.to_string(),
+            time,
+            reply,
+        })
+        .await?
+    }
+
+    pub async fn get_present(&self) -> Result<Present, DbError> {
+        self.request(|reply| DbCommand::GetPresent { reply })
+            .await?
+    }
+
+    pub async fn sign_out_all(
+        &self,
+        cutoff: DateTime<Local>,
+        source: &str,
+    ) -> Result<Vec<String>, DbError> {
+        self.request(|reply| DbCommand::SignOutAll {
+            cutoff,
+            source: source.to_string(),
+            reply,
+        })
+        .await?
+    }
+
+    pub async fn get_resolution(&self) -> Result<Option<Resolution>, DbError> {
+        self.request(|reply| DbCommand::GetResolution { reply })
+            .await?
+    }
+
+    pub async fn set_resolution(&self, resolution: Resolution) -> Result<(), DbError> {
+        self.request(|reply| DbCommand::SetResolution { resolution, reply })
+            .await?
+    }
+
+    /// Writes a new backup, returning where. Scans keep being recorded
+    /// meanwhile.
+    pub async fn backup(&self) -> Result<PathBuf, BackupError> {
+        self.request(|reply| DbCommand::Backup { reply })
+            .await
+            .map_err(BackupError::Db)?
+    }
+
+    /// Scans accepted but not yet recorded, see [`ScanQueue`].
+    pub fn pending_scans(&self) -> usize {
+        self.pending_scans.load(Ordering::Relaxed)
+    }
+
+    /// Queues a checkpoint without waiting for it.
+    pub fn checkpoint(&self) {
+        self.send(DbCommand::Checkpoint);
+    }
+
+    /// Runs `f` on the worker, after every command sent before it.
+    pub async fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut BackingDatabase) -> T + Send + 'static,
+    ) -> Result<T, DbError> {
+        self.request(|reply| {
+            DbCommand::Run(Box::new(move |db| {
+                let _ = reply.try_send(f(db));
+            }))
+        })
+        .await
+    }
+
+    /// [`Self::run`] for a fallible `f`, e.g. a query.
+    pub async fn try_run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut BackingDatabase) -> Result<T, DbError> + Send + 'static,
+    ) -> Result<T, DbError> {
+        self.run(f).await?
+    }
+}
+
+/// Copies `db` a few pages at a time from a second connection, handling
+/// commands sent meanwhile between steps so scans are not held up.
+///
+/// Written next to its final path and renamed once complete, so a backup
+/// file is never partial.
+fn backup(
+    db: &mut BackingDatabase,
+    queue: &mut ScanQueue,
+    commands_rx: &async_channel::Receiver<DbCommand>,
+) -> Result<PathBuf, BackupError> {
+    let path = db.next_backup_path().map_err(BackupError::Db)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(BackupError::Io)?;
+    }
+    let partial = path.with_extension("db.partial");
+
+    let source = db.backup_source()?;
+    let copied = Connection::open(&partial).and_then(|mut dest| {
+        let backup = Backup::new(&source, &mut dest)?;
+        while backup.step(BACKUP_PAGES_PER_STEP)? != StepResult::Done {
+            while let Ok(command) = commands_rx.try_recv() {
+                command.handle(db, queue);
+            }
+            thread::sleep(BACKUP_STEP_PAUSE);
+        }
+        Ok(())
+    });
+
+    match copied
+        .map_err(BackupError::Sqlite)
+        .and_then(|()| fs::rename(&partial, &path).map_err(BackupError::Io))
+    {
+        Ok(()) => Ok(path),
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// Next command, or `None` once `timeout` passes without one. `Err` once
+/// every handle is dropped.
+fn recv_timeout(
+    commands_rx: &async_channel::Receiver<DbCommand>,
+    timeout: Duration,
+) -> Result<Option<DbCommand>, async_channel::TryRecvError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match commands_rx.try_recv() {
+            Ok(command) => return Ok(Some(command)),
+            Err(async_channel::TryRecvError::Empty) if Instant::now() < deadline => {
+                thread::sleep(RETRY_POLL);
+            }
+            Err(async_channel::TryRecvError::Empty) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn panicking_job_fails_only_itself() {
+        let handle = DbHandle::spawn(|| BackingDatabase::new(None).unwrap());
+
+        let panicked = handle.run(|_| -> () { panic!("job panicked") }).await;
+        assert!(matches!(panicked, Err(DbError::WorkerFailed)));
+
+        handle
+            .run(|db| db.add_mentor("Ada"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            handle.try_run(|db| db.get_mentors()).await.unwrap(),
+            ["Ada"]
+        );
+    }
+
+    #[tokio::test]
+    async fn try_run_flattens_errors() {
+        let handle = DbHandle::spawn(|| BackingDatabase::new(None).unwrap());
+
+        let failed = handle
+            .try_run(|_| Err::<(), _>(DbError::WorkerFailed))
+            .await;
+        assert!(matches!(failed, Err(DbError::WorkerFailed)));
+        assert_eq!(handle.try_run(|_| Ok(1)).await.unwrap(), 1);
+    }
+}
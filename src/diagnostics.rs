@@ -0,0 +1,103 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Collapsible readout of the video pipeline under the preview, to tell
+//! whether the camera, the decoders, or the scan loop is falling behind.
+
+use std::time::{Duration, Instant};
+
+use dioxus::prelude::*;
+
+use crate::{
+    PIPELINE_METRICS,
+    video::{ANALYSIS_SCALES, MetricsSample},
+};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+const SCALE_LABELS: [&str; ANALYSIS_SCALES] = ["full", "1/2", "1/4", "1/8"];
+
+/// Per second rates between two samples.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Rates {
+    camera_fps: f64,
+    analyzed_fps: [f64; ANALYSIS_SCALES],
+    detections: f64,
+    decodes: f64,
+}
+
+impl Rates {
+    /// Counters reset between the samples read as 0.
+    fn between(earlier: &MetricsSample, later: &MetricsSample, elapsed: Duration) -> Self {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let rate = |earlier: u64, later: u64| later.saturating_sub(earlier) as f64 / secs;
+        Self {
+            camera_fps: rate(earlier.frames_captured, later.frames_captured),
+            analyzed_fps: std::array::from_fn(|idx| {
+                rate(earlier.frames_analyzed[idx], later.frames_analyzed[idx])
+            }),
+            detections: rate(earlier.detections, later.detections),
+            decodes: rate(earlier.decodes, later.decodes),
+        }
+    }
+}
+
+#[component]
+pub fn PipelineDiagnostics() -> Element {
+    let mut sample = use_signal(MetricsSample::default);
+    let mut rates = use_signal(Rates::default);
+
+    use_future(move || async move {
+        let mut last = (PIPELINE_METRICS.sample(), Instant::now());
+        loop {
+            tokio::time::sleep(SAMPLE_INTERVAL).await;
+            let next = (PIPELINE_METRICS.sample(), Instant::now());
+            rates.set(Rates::between(&last.0, &next.0, next.1 - last.1));
+            sample.set(next.0);
+            last = next;
+        }
+    });
+
+    let sample = sample();
+    let rates = rates();
+    let (stream_queued, analysis_queued) = sample.buffer_queued.split_first().unwrap();
+    rsx! {
+        details {
+            summary { "Diagnostics" }
+            table {
+                tr {
+                    td { "Camera" }
+                    td { "{rates.camera_fps:.1} fps" }
+                }
+                for (label, fps) in SCALE_LABELS.iter().zip(rates.analyzed_fps) {
+                    tr {
+                        td { "Analysis {label}" }
+                        td { "{fps:.1} fps" }
+                    }
+                }
+                tr {
+                    td { "Detections" }
+                    td { "{rates.detections:.1}/s" }
+                }
+                tr {
+                    td { "Decodes" }
+                    td { "{rates.decodes:.1}/s" }
+                }
+                tr {
+                    td { "Dropped frames" }
+                    td { "{sample.dropped_writes}" }
+                }
+                tr {
+                    td { "Frames queued" }
+                    td { "stream {stream_queued}, analysis {analysis_queued:?}" }
+                }
+                tr {
+                    td { "Reads queued" }
+                    td { "{sample.qr_queued}" }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Finds kiosks on the LAN over mDNS.
+//!
+//! A kiosk serving the HTTP API advertises `_qrtracker._tcp.local.`, with its
+//! API port and name as TXT records, so peers and dashboards can be pointed
+//! at it without looking up addresses.
+
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use tracing::{debug, info};
+
+use crate::sqlite::{BackingDatabase, DbError};
+
+pub const SERVICE_TYPE: &str = "_qrtracker._tcp.local.";
+/// Setting holding the name advertised for this kiosk.
+pub const NAME_KEY: &str = "kiosk.name";
+
+/// How long a goodbye announcement may take on shutdown.
+const UNREGISTER_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The configured kiosk name, or one derived from the kiosk id.
+pub fn kiosk_name(db: &BackingDatabase) -> Result<String, DbError> {
+    Ok(db
+        .get_setting::<String>(NAME_KEY)?
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| {
+            let id: String = db.kiosk_id().chars().take(6).collect();
+            format!("qr-tracker-{id}")
+        }))
+}
+
+/// Advertises this kiosk until dropped.
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Advertisement {
+    /// Announces on every interface, following addresses as interfaces come
+    /// and go.
+    pub fn start(name: &str, kiosk_id: &str, port: u16) -> Result<Self, mdns_sd::Error> {
+        let daemon = ServiceDaemon::new()?;
+        let port_text = port.to_string();
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            name,
+            &format!("{kiosk_id}.local."),
+            "",
+            port,
+            &[("port", port_text.as_str()), ("name", name)][..],
+        )?
+        .enable_addr_auto();
+
+        let fullname = info.get_fullname().to_string();
+        daemon.register(info)?;
+        info!(target: "discovery", name, "Advertising on mDNS");
+
+        Ok(Self { daemon, fullname })
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        // Waits for the goodbye, so peers drop this kiosk right away.
+        if let Ok(status) = self.daemon.unregister(&self.fullname) {
+            let _ = status.recv_timeout(UNREGISTER_TIMEOUT);
+        }
+        let _ = self.daemon.shutdown();
+        debug!(target: "discovery", "Stopped mDNS advertisement");
+    }
+}
+
+/// A kiosk that answered a browse.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Kiosk {
+    pub name: String,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+}
+
+impl Kiosk {
+    /// API base URLs, usable as `sync.peers` entries.
+    pub fn urls(&self) -> Vec<String> {
+        self.addresses
+            .iter()
+            .map(|address| format!("http://{}", SocketAddr::new(*address, self.port)))
+            .collect()
+    }
+}
+
+/// Kiosks resolved within `timeout`, sorted by name.
+pub fn browse(timeout: Duration) -> Result<Vec<Kiosk>, mdns_sd::Error> {
+    let daemon = ServiceDaemon::new()?;
+    let events = daemon.browse(SERVICE_TYPE)?;
+    let deadline = Instant::now() + timeout;
+
+    let mut kiosks = BTreeMap::new();
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let Ok(event) = events.recv_timeout(remaining) else {
+            break;
+        };
+
+        if let ServiceEvent::ServiceResolved(info) = event {
+            let name = info
+                .get_property_val_str("name")
+                .unwrap_or(info.get_fullname())
+                .to_string();
+            let mut addresses: Vec<_> = info.get_addresses().iter().copied().collect();
+            addresses.sort();
+
+            kiosks.insert(
+                info.get_fullname().to_string(),
+                Kiosk {
+                    name,
+                    addresses,
+                    port: info.get_port(),
+                },
+            );
+        }
+    }
+
+    let _ = daemon.stop_browse(SERVICE_TYPE);
+    let _ = daemon.shutdown();
+
+    let mut kiosks: Vec<_> = kiosks.into_values().collect();
+    kiosks.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(kiosks)
+}
@@ -0,0 +1,115 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Escalates when students are present without any mentor.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use dioxus::prelude::*;
+
+use crate::{
+    AttendanceContext,
+    notify::{KIND_KEY, NotifyKind, WEBHOOK_URL_KEY, post_webhook},
+};
+
+static ALARM_WAV: Asset = asset!("/assets/alarm.wav");
+
+pub const ENABLED_KEY: &str = "escalation.enabled";
+pub const MINUTES_KEY: &str = "escalation.minutes";
+
+const DEFAULT_MINUTES: i64 = 10;
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shows a full screen alert once students have been unsupervised for longer
+/// than the configured threshold.
+///
+/// `unsupervised_since` is maintained by the scan loop, so the overlay clears
+/// as soon as a mentor scans in.
+#[component]
+pub fn EscalationMonitor(unsupervised_since: ReadSignal<Option<DateTime<Local>>>) -> Element {
+    let AttendanceContext { backing_db, .. } = use_context();
+
+    // (unsupervised_since, escalation start) of the active escalation.
+    let mut escalation = use_signal(|| None::<(DateTime<Local>, DateTime<Local>)>);
+
+    use_future(move || {
+        let backing_db = backing_db.clone();
+        async move {
+            loop {
+                let since = *unsupervised_since.peek();
+                let active = *escalation.peek();
+
+                if let Some((escalated_since, start)) = active
+                    && since != Some(escalated_since)
+                {
+                    let end = Local::now();
+                    let detail = format!("start={} end={}", start.to_rfc3339(), end.to_rfc3339());
+                    let _ = backing_db
+                        .try_run(move |db| db.log_event("escalation_end", "no_mentor", &detail))
+                        .await;
+                    escalation.set(None);
+                } else if let Some(since) = since
+                    && active.is_none()
+                {
+                    let (enabled, minutes, webhook) = backing_db
+                        .try_run(|db| {
+                            let webhook = match db.get_setting::<String>(WEBHOOK_URL_KEY)? {
+                                Some(url) => Some((
+                                    url,
+                                    db.get_setting::<NotifyKind>(KIND_KEY)?.unwrap_or_default(),
+                                )),
+                                None => None,
+                            };
+                            Ok((
+                                db.get_setting(ENABLED_KEY)?.unwrap_or(false),
+                                db.get_setting(MINUTES_KEY)?.unwrap_or(DEFAULT_MINUTES),
+                                webhook,
+                            ))
+                        })
+                        .await
+                        .unwrap_or((false, DEFAULT_MINUTES, None));
+                    let start = Local::now();
+
+                    if enabled && (start - since).num_minutes() >= minutes {
+                        let detail =
+                            format!("start={} since={}", start.to_rfc3339(), since.to_rfc3339());
+                        let _ = backing_db
+                            .try_run(move |db| {
+                                db.log_event("escalation_start", "no_mentor", &detail)
+                            })
+                            .await;
+                        escalation.set(Some((since, start)));
+
+                        // Sent right away, unlike batched scan notifications.
+                        if let Some((url, kind)) = webhook {
+                            let body = kind.no_mentor(since);
+                            spawn(async move { post_webhook(&url, &body).await });
+                        }
+                    }
+                }
+
+                tokio::time::sleep(CHECK_INTERVAL).await;
+            }
+        }
+    });
+
+    let since = unsupervised_since();
+    match (escalation(), since) {
+        (Some(_), Some(since)) => {
+            let since = since.format("%H:%M");
+            rsx! {
+                div {
+                    class: "escalation_overlay",
+                    h1 { "NO MENTOR PRESENT" }
+                    h2 { "Students have been unsupervised since {since}" }
+                    audio { src: ALARM_WAV, autoplay: true, r#loop: true }
+                }
+            }
+        }
+        _ => rsx! {},
+    }
+}
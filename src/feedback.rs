@@ -0,0 +1,103 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Sound and a full screen flash for each handled scan, noticeable without
+//! looking at the kiosk.
+
+use std::time::Duration;
+
+use dioxus::prelude::*;
+
+use crate::{AttendanceContext, api::ScanAction};
+
+static ADDED_WAV: Asset = asset!("/assets/scan_added.wav");
+static REMOVED_WAV: Asset = asset!("/assets/scan_removed.wav");
+static REJECTED_WAV: Asset = asset!("/assets/scan_rejected.wav");
+
+/// Setting silencing scan sounds, the flash still shows.
+pub const MUTED_KEY: &str = "feedback.muted";
+
+const FLASH: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feedback {
+    Added,
+    Removed,
+    Rejected,
+}
+
+impl From<&ScanAction> for Feedback {
+    fn from(action: &ScanAction) -> Self {
+        match action {
+            ScanAction::Added(_) => Self::Added,
+            ScanAction::Removed(_) => Self::Removed,
+            ScanAction::Rejected(_) => Self::Rejected,
+        }
+    }
+}
+
+/// Latest scan outcome, numbered so repeated outcomes play again.
+pub type LatestFeedback = Option<(u64, Feedback)>;
+
+/// Sets `latest` to `feedback`, replaying it even if unchanged.
+pub fn play(mut latest: Signal<LatestFeedback>, feedback: Feedback) {
+    let seq = latest.peek().map_or(0, |(seq, _)| seq + 1);
+    latest.set(Some((seq, feedback)));
+}
+
+/// Plays and flashes each outcome set by the scan loop.
+///
+/// Playback runs in the webview, the scan loop only sets `latest`.
+#[component]
+pub fn ScanFeedback(latest: ReadSignal<LatestFeedback>) -> Element {
+    let AttendanceContext { backing_db, .. } = use_context();
+    let mut flashing = use_signal(|| None::<u64>);
+    // Re-read on each scan, so muting from the admin panel applies next scan.
+    let muted = use_resource(move || {
+        let backing_db = backing_db.clone();
+        latest.read();
+        async move {
+            backing_db
+                .try_run(|db| Ok(db.get_setting(MUTED_KEY)?.unwrap_or(false)))
+                .await
+                .ok()
+        }
+    });
+
+    use_effect(move || {
+        if let Some((seq, _)) = latest() {
+            flashing.set(Some(seq));
+            spawn(async move {
+                tokio::time::sleep(FLASH).await;
+                // A later scan restarted the flash.
+                if *flashing.peek() == Some(seq) {
+                    flashing.set(None);
+                }
+            });
+        }
+    });
+
+    let Some((seq, feedback)) = latest() else {
+        return rsx! {};
+    };
+    // Silent until the setting is read, rather than playing against it.
+    let muted = muted().is_none_or(|muted| muted.unwrap_or(false));
+    let (class, sound) = match feedback {
+        Feedback::Added => ("feedback_flash accepted", ADDED_WAV),
+        Feedback::Removed => ("feedback_flash accepted", REMOVED_WAV),
+        Feedback::Rejected => ("feedback_flash rejected", REJECTED_WAV),
+    };
+
+    rsx! {
+        if flashing() == Some(seq) {
+            div { class }
+        }
+        // Keyed, so each scan starts a new element that plays again.
+        if !muted {
+            audio { key: "{seq}", src: sound, autoplay: true }
+        }
+    }
+}
@@ -0,0 +1,210 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Season hours in the layout FIRST award submissions ask for.
+//!
+//! A meeting is any day on which enough people scanned, by default anyone.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
+
+use chrono::{DateTime, Local, NaiveDate, TimeDelta};
+
+use crate::{
+    QrType,
+    sqlite::{BackingDatabase, DbError, ExportError},
+};
+
+/// Setting holding the fewest people that make a day count as a meeting.
+pub const MEETING_MIN_PEOPLE_KEY: &str = "frc.meeting_min_people";
+/// Setting holding the hours below which students are flagged.
+pub const MIN_HOURS_KEY: &str = "frc.min_hours";
+
+/// Column layout of the export. Changing it breaks award submissions.
+const HEADER: [&str; 5] = [
+    "Student",
+    "Total Hours",
+    "Meetings Attended",
+    "Meeting Percentage",
+    "Below Minimum",
+];
+
+/// One student's season.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrcRow {
+    pub name: String,
+    pub hours: TimeDelta,
+    pub meetings_attended: usize,
+    /// Out of every meeting in the range.
+    pub meeting_percentage: f64,
+    /// Below the minimum hours, when one is set.
+    pub below_minimum: bool,
+}
+
+/// Every rostered student's hours and meetings between `since` and `until`.
+pub fn frc_report(
+    db: &BackingDatabase,
+    (since, until): (DateTime<Local>, DateTime<Local>),
+    meeting_min_people: usize,
+    min_hours: Option<f64>,
+) -> Result<Vec<FrcRow>, DbError> {
+    let mut people_by_day: HashMap<NaiveDate, HashSet<String>> = HashMap::new();
+    for row in db
+        .attendance(Some((since, until)))
+        .map_err(DbError::Sqlite)?
+    {
+        people_by_day.entry(row.day).or_default().insert(row.name);
+    }
+    people_by_day.retain(|_, people| people.len() >= meeting_min_people.max(1));
+    let meetings = people_by_day.len();
+
+    let hours: HashMap<_, _> = db.hours_summary(since, Some(until))?.into_iter().collect();
+
+    // Sorted, and including graduated students.
+    let students = db
+        .get_all_members(true)?
+        .into_iter()
+        .filter(|member| member.role == QrType::Student)
+        .map(|member| member.name);
+    Ok(students
+        .map(|name| {
+            let hours = hours.get(&name).copied().unwrap_or_default();
+            let meetings_attended = people_by_day
+                .values()
+                .filter(|people| people.contains(&name))
+                .count();
+            let meeting_percentage = if meetings == 0 {
+                0.0
+            } else {
+                meetings_attended as f64 / meetings as f64 * 100.0
+            };
+
+            FrcRow {
+                below_minimum: min_hours.is_some_and(|min_hours| hours_of(hours) < min_hours),
+                name,
+                hours,
+                meetings_attended,
+                meeting_percentage,
+            }
+        })
+        .collect())
+}
+
+fn hours_of(duration: TimeDelta) -> f64 {
+    duration.num_minutes() as f64 / 60.0
+}
+
+/// Writes `rows` as CSV, returning how many were written.
+pub fn write_csv(rows: &[FrcRow], writer: impl Write) -> Result<usize, ExportError> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(HEADER).map_err(ExportError::Csv)?;
+
+    for row in rows {
+        writer
+            .write_record([
+                row.name.clone(),
+                format!("{:.2}", hours_of(row.hours)),
+                row.meetings_attended.to_string(),
+                format!("{:.1}", row.meeting_percentage),
+                if row.below_minimum { "yes" } else { "no" }.to_string(),
+            ])
+            .map_err(ExportError::Csv)?;
+    }
+
+    writer.flush().map_err(|e| ExportError::Csv(e.into()))?;
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use super::*;
+    use crate::sqlite::local_datetime;
+
+    fn at(day: u32, time: &str) -> DateTime<Local> {
+        local_datetime(
+            NaiveDate::from_ymd_opt(2024, 9, day).unwrap(),
+            NaiveTime::parse_from_str(time, "%H:%M").unwrap(),
+        )
+    }
+
+    /// Two evening meetings, then a Saturday only Cy came in for.
+    fn seeded() -> BackingDatabase {
+        let mut db = BackingDatabase::new(None).unwrap();
+        for student in ["Ada", "Bob", "Cy"] {
+            db.add_student(student).unwrap();
+        }
+        db.add_mentor("Mo").unwrap();
+
+        for (name, day, times) in [
+            ("Ada", 2, ["18:00", "21:00"]),
+            ("Bob", 2, ["18:00", "19:30"]),
+            ("Mo", 2, ["18:00", "21:00"]),
+            ("Ada", 4, ["18:00", "20:00"]),
+            ("Mo", 4, ["18:00", "20:00"]),
+            ("Cy", 7, ["10:00", "10:45"]),
+        ] {
+            for time in times {
+                db.add_scan(name, at(day, time), None).unwrap();
+            }
+        }
+        // Graduated students still count for the season.
+        db.set_member_active("Cy", false).unwrap();
+        db
+    }
+
+    fn season() -> (DateTime<Local>, DateTime<Local>) {
+        (at(1, "00:00"), at(30, "23:59"))
+    }
+
+    #[test]
+    fn matches_golden_file() {
+        let rows = frc_report(&seeded(), season(), 1, Some(2.0)).unwrap();
+        let mut out = Vec::new();
+        assert_eq!(write_csv(&rows, &mut out).unwrap(), 3);
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            include_str!("../tests/golden/frc_hours.csv")
+        );
+    }
+
+    #[test]
+    fn small_days_not_meetings() {
+        let rows = frc_report(&seeded(), season(), 2, None).unwrap();
+        let meetings: Vec<_> = rows
+            .iter()
+            .map(|row| {
+                (
+                    row.name.as_str(),
+                    row.meetings_attended,
+                    row.meeting_percentage,
+                    row.below_minimum,
+                )
+            })
+            .collect();
+        assert_eq!(
+            meetings,
+            [
+                ("Ada", 2, 100.0, false),
+                ("Bob", 1, 50.0, false),
+                ("Cy", 0, 0.0, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_meetings_is_zero_percent() {
+        let rows = frc_report(&seeded(), (at(20, "00:00"), at(30, "23:59")), 1, None).unwrap();
+        assert!(
+            rows.iter()
+                .all(|row| row.hours.is_zero() && row.meeting_percentage == 0.0)
+        );
+    }
+}
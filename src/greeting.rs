@@ -0,0 +1,63 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Banner messages shown after a scan.
+
+use chrono::{DateTime, Local, TimeDelta, Weekday};
+
+use crate::sqlite::{BackingDatabase, DbError};
+
+/// Setting controlling whether greetings include attendance numbers.
+pub const ENRICH_KEY: &str = "greeting.enrich";
+
+/// Immediate banner for a scan, before any database lookups.
+pub fn greet(name: &str, signed_in: bool, guest: bool) -> String {
+    match (signed_in, guest) {
+        (true, true) => "Welcome, guest!".to_string(),
+        (false, true) => "Thanks for visiting!".to_string(),
+        (true, false) => format!("Welcome, {name}!"),
+        (false, false) => format!("See you, {name}!"),
+    }
+}
+
+/// Banner including attendance numbers for the scan at `time`.
+pub fn enriched_greeting(
+    db: &BackingDatabase,
+    name: &str,
+    signed_in: bool,
+    time: DateTime<Local>,
+) -> Result<String, DbError> {
+    let today = time.date_naive();
+
+    if signed_in {
+        let week_start = today.week(Weekday::Mon).first_day();
+        let meetings = db.days_attended(name, week_start)?;
+        Ok(format!(
+            "Welcome back, {name}! {} meeting this week",
+            ordinal(meetings)
+        ))
+    } else {
+        let duration = format_duration(db.time_on(name, today)?);
+        Ok(format!("See you, {name} — {duration} today"))
+    }
+}
+
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{n}{suffix}")
+}
+
+/// Formats as hours and minutes, e.g. "2h 40m".
+pub fn format_duration(duration: TimeDelta) -> String {
+    let minutes = duration.num_minutes().max(0);
+    format!("{}h {}m", minutes / 60, minutes % 60)
+}
@@ -0,0 +1,153 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Runs the scan loop without the kiosk window, e.g. on a Raspberry Pi with
+//! no display attached.
+//!
+//! The camera preview stream, its `/health` endpoint, and the
+//! [`control`](crate::control) socket are the only interfaces. Ctrl-C and
+//! SIGTERM go through [`shutdown`](crate::shutdown), which checkpoints the
+//! database before exiting.
+
+use std::process::ExitCode;
+
+use tokio::task::LocalSet;
+use tracing::{error, info};
+
+use crate::{
+    VideoChannels,
+    attendance::{self, AttendanceView, ScanInputs, Task},
+    backup::periodic_backup,
+    config::Config,
+    control,
+    db_worker::DbHandle,
+    restore_video_settings,
+    schedule::daily_sync,
+    setup,
+    sheets::weekly_push,
+    signout::nightly_sign_out,
+    snapshot,
+    sqlite::{BackingDatabase, DbError},
+};
+
+/// Nothing to show, results are only logged.
+#[derive(Clone)]
+struct HeadlessView;
+
+impl AttendanceView for HeadlessView {
+    fn spawn(&self, task: Task) {
+        tokio::task::spawn_local(task);
+    }
+}
+
+/// Handles scans until shut down. `bind_fixed` and `source_fixed` are
+/// whether the command line sets the stream address and frame source, see
+/// [`restore_video_settings`], `force_config` is `--force-config`.
+pub fn run(
+    database: String,
+    config: Config,
+    video_channels: VideoChannels,
+    bind_fixed: bool,
+    source_fixed: bool,
+    force_config: bool,
+) -> ExitCode {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            error!("Failed to start the async runtime: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if setup::needs_setup(&database) {
+        info!("Creating {database}, first-run setup is skipped without a window");
+    }
+    let opened = DbHandle::try_spawn({
+        let database = database.clone();
+        let config = config.clone();
+        move || {
+            let mut db = BackingDatabase::new(Some(&database))?;
+            if let Err(e) = config.seed(&mut db, force_config) {
+                error!(target: "config", "Failed to copy config file values: {e}");
+            }
+            if let Err(e) = db.warn_integrity_issues() {
+                error!(target: "db", "Integrity check failed: {e}");
+            }
+            if let Err(e) = db.prune_audit() {
+                error!(target: "db", "Failed to prune the audit log: {e}");
+            }
+            Ok::<_, DbError>(db)
+        }
+    });
+    let backing_db = match opened {
+        Ok(backing_db) => backing_db,
+        Err(e) => {
+            error!("Failed to open {database}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let VideoChannels {
+        qr_reads_rx,
+        camera_resolution_select_tx,
+        camera_select_tx,
+        video_bind_tx,
+    } = video_channels;
+
+    // Nothing asks for sign-out confirmation, the sender is held so the scan
+    // loop keeps waiting on the camera.
+    let (submissions_tx, submissions_rx) = async_channel::unbounded();
+    let (_sign_out_confirmed_tx, sign_out_confirmed_rx) = async_channel::unbounded();
+    let (refresh_tx, refresh_rx) = async_channel::unbounded();
+
+    info!("Running headless on {database}");
+    LocalSet::new().block_on(&runtime, async move {
+        // The senders stay open until the scan loop ends.
+        restore_video_settings(
+            backing_db.clone(),
+            video_bind_tx.clone(),
+            camera_select_tx.clone(),
+            camera_resolution_select_tx.clone(),
+            bind_fixed,
+            source_fixed,
+        )
+        .await;
+        let view = HeadlessView;
+        let prune_db = backing_db.clone();
+        view.spawn(Box::pin(async move {
+            if let Err(e) = prune_db.try_run(snapshot::prune).await {
+                error!(target: "snapshot", "Failed to prune snapshots: {e}");
+            }
+        }));
+        view.spawn(Box::pin(nightly_sign_out(
+            backing_db.clone(),
+            refresh_tx.clone(),
+        )));
+        view.spawn(Box::pin(weekly_push(backing_db.clone(), config.clone())));
+        view.spawn(Box::pin(daily_sync(backing_db.clone(), config.clone())));
+        view.spawn(Box::pin(periodic_backup(backing_db.clone())));
+        view.spawn(Box::pin(control::serve(
+            backing_db.clone(),
+            refresh_tx.clone(),
+            submissions_tx,
+            view.clone(),
+        )));
+
+        let observers = attendance::observers(&backing_db, &config, &view).await;
+        let inputs = ScanInputs {
+            qr_reads_rx,
+            submissions_rx,
+            sign_out_confirmed_rx,
+            refresh_rx,
+        };
+        attendance::run(inputs, backing_db, config, observers, view).await;
+    });
+
+    ExitCode::SUCCESS
+}
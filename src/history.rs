@@ -0,0 +1,106 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Who attended on a past day.
+
+use chrono::{Local, NaiveDate, TimeDelta};
+use dioxus::prelude::*;
+
+use crate::{AttendanceContext, sqlite::DbError};
+
+/// One row per person who scanned on the picked day.
+///
+/// Scans within the day pair into sessions the same way as
+/// [`BackingDatabase::time_on`](crate::sqlite::BackingDatabase::time_on), a
+/// trailing unpaired scan has no sign-out.
+#[component]
+pub fn History() -> Element {
+    let AttendanceContext {
+        backing_db,
+        db_error,
+        ..
+    } = use_context();
+    let mut date = use_signal(|| Local::now().date_naive());
+
+    let day = date();
+    let loaded = use_resource(move || {
+        let backing_db = backing_db.clone();
+        let day = date();
+        async move {
+            let loaded = backing_db
+                .try_run(move |db| {
+                    db.attendance_on(day)?
+                        .into_iter()
+                        .map(|(name, scans)| {
+                            let name = db.display_name(&name)?;
+                            let first_in = scans[0].format("%H:%M").to_string();
+                            let last_out = if scans.len() % 2 == 0 {
+                                scans[scans.len() - 1].format("%H:%M").to_string()
+                            } else {
+                                "-".to_string()
+                            };
+                            let total: TimeDelta = scans
+                                .chunks_exact(2)
+                                .map(|session| session[1] - session[0])
+                                .sum();
+                            let duration =
+                                format!("{}h {:02}m", total.num_hours(), total.num_minutes() % 60);
+                            Ok((name, first_in, last_out, duration))
+                        })
+                        .collect::<Result<Vec<_>, DbError>>()
+                })
+                .await;
+            db_error.check(loaded)
+        }
+    });
+    let rows = loaded().flatten().unwrap_or_default();
+    let day_value = day.format("%Y-%m-%d").to_string();
+    let day_label = day.format("%A, %B %-d %Y").to_string();
+
+    rsx! {
+        div {
+            button {
+                onclick: move |_| date.set(day.pred_opt().unwrap_or(day)),
+                "Previous"
+            }
+            input {
+                r#type: "date",
+                value: "{day_value}",
+                onchange: move |e| {
+                    if let Ok(picked) = NaiveDate::parse_from_str(e.value().trim(), "%Y-%m-%d") {
+                        date.set(picked);
+                    }
+                },
+            }
+            button {
+                onclick: move |_| date.set(day.succ_opt().unwrap_or(day)),
+                "Next"
+            }
+        }
+        h3 { "{day_label}" }
+        if rows.is_empty() {
+            p { "Nobody scanned on this day." }
+        } else {
+            table {
+                class: "present_list",
+                tr {
+                    th { "Name" }
+                    th { "First In" }
+                    th { "Last Out" }
+                    th { "Time" }
+                }
+                for (name, first_in, last_out, duration) in rows {
+                    tr {
+                        td { "{name}" }
+                        td { "{first_in}" }
+                        td { "{last_out}" }
+                        td { "{duration}" }
+                    }
+                }
+            }
+        }
+    }
+}
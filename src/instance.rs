@@ -0,0 +1,78 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Keeps two kiosks from running against the same database.
+//!
+//! Uses an advisory lock on `<database>.lock`, which the OS releases when the
+//! holder exits for any reason, so a crash never blocks the next launch.
+
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions, TryLockError},
+    io::{self, Read, Seek, Write},
+    path::PathBuf,
+    process,
+};
+
+/// Held for as long as this process uses the database.
+#[derive(Debug)]
+pub struct InstanceLock {
+    _file: File,
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    /// Another process holds the lock, with its pid when known.
+    Held(Option<u32>),
+    Io(PathBuf, io::Error),
+}
+
+impl Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Held(Some(pid)) => write!(f, "qr-tracker is already running (pid {pid})"),
+            Self::Held(None) => write!(f, "qr-tracker is already running"),
+            Self::Io(path, e) => write!(f, "failed to lock {}: {e}", path.display()),
+        }
+    }
+}
+
+fn lock_path(database: &str) -> PathBuf {
+    PathBuf::from(format!("{database}.lock"))
+}
+
+impl InstanceLock {
+    /// Takes the lock without waiting.
+    pub fn acquire(database: &str) -> Result<Self, LockError> {
+        let path = lock_path(database);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| LockError::Io(path.clone(), e))?;
+
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                let mut pid = String::new();
+                let _ = file.read_to_string(&mut pid);
+                return Err(LockError::Held(pid.trim().parse().ok()));
+            }
+            Err(TryLockError::Error(e)) => return Err(LockError::Io(path, e)),
+        }
+
+        // Only informational, the lock itself is what counts.
+        let _ = file
+            .set_len(0)
+            .and_then(|()| file.rewind())
+            .and_then(|()| write!(file, "{}", process::id()))
+            .and_then(|()| file.flush());
+
+        Ok(Self { _file: file })
+    }
+}
@@ -0,0 +1,10 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Parts of the kiosk that stand on their own, split out of the binary so
+//! they can be used and checked without the GUI.
+
+pub mod atomic_buf;
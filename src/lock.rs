@@ -0,0 +1,389 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Admin PIN guarding the admin and audit panes, undo, manual presence
+//! toggles, and camera changes. Badge scans never ask for it.
+//!
+//! The PIN is stored as an argon2 hash. A correct PIN unlocks the kiosk
+//! until [`UNLOCK_IDLE`] passes without a guarded action or a click in an
+//! unlocked pane. Wrong PINs are audited and wait twice as long after each
+//! failure. Without a PIN everything stays unlocked.
+
+use std::{
+    io::{self, BufRead, IsTerminal, Write},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    password_hash::{SaltString, rand_core::OsRng},
+};
+use dioxus::prelude::*;
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+
+use crate::{
+    AttendanceContext,
+    db_worker::DbHandle,
+    sqlite::{BackingDatabase, DbError},
+};
+
+/// Setting holding the argon2 hash of the admin PIN.
+pub const PIN_HASH_KEY: &str = "admin.pin_argon2";
+/// Hex SHA-256 of the PIN, written by older versions' first-run setup.
+/// Replaced with [`PIN_HASH_KEY`] once the PIN is entered.
+const LEGACY_PIN_KEY: &str = "admin.pin_sha256";
+
+/// Time without activity before the kiosk locks again.
+pub const UNLOCK_IDLE: Duration = Duration::from_secs(5 * 60);
+/// How often an idle unlock is checked for.
+const IDLE_CHECK: Duration = Duration::from_secs(10);
+/// Wait after the first wrong PIN, doubled after each one after.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(5 * 60);
+
+/// 4 to 8 digits.
+pub fn valid_pin(pin: &str) -> bool {
+    (4..=8).contains(&pin.len()) && pin.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+fn hash_pin(pin: &str) -> String {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(pin.as_bytes(), &salt)
+        .expect("argon2 takes any PIN")
+        .to_string()
+}
+
+/// Whether a PIN has been set.
+pub fn pin_set(db: &BackingDatabase) -> Result<bool, DbError> {
+    Ok(db.get_setting::<String>(PIN_HASH_KEY)?.is_some()
+        || db.get_setting::<String>(LEGACY_PIN_KEY)?.is_some())
+}
+
+/// Replaces the PIN, or removes it with `None`.
+pub fn set_pin(db: &mut BackingDatabase, pin: Option<&str>) -> Result<(), DbError> {
+    // Blank settings are unset.
+    db.set_setting(PIN_HASH_KEY, pin.map(hash_pin).unwrap_or_default())?;
+    db.set_setting(LEGACY_PIN_KEY, "")?;
+    let action = if pin.is_some() {
+        "pin_changed"
+    } else {
+        "pin_removed"
+    };
+    db.log_event(action, "", "")
+}
+
+/// Whether `pin` is the admin PIN. A correct legacy PIN is rehashed.
+pub fn verify_pin(db: &mut BackingDatabase, pin: &str) -> Result<bool, DbError> {
+    if let Some(hash) = db.get_setting::<String>(PIN_HASH_KEY)? {
+        return Ok(PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(pin.as_bytes(), &hash)
+                .is_ok()
+        }));
+    }
+
+    let Some(legacy) = db.get_setting::<String>(LEGACY_PIN_KEY)? else {
+        return Ok(false);
+    };
+    let digest: String = Sha256::digest(pin.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let correct = digest == legacy;
+    if correct {
+        info!(target: "lock", "Upgrading the admin PIN hash");
+        db.set_setting(PIN_HASH_KEY, hash_pin(pin))?;
+        db.set_setting(LEGACY_PIN_KEY, "")?;
+    }
+    Ok(correct)
+}
+
+/// `--reset-pin`, setting a new PIN typed on the terminal. Refused when not
+/// run from a terminal, so it cannot be triggered from the kiosk itself.
+pub fn reset_pin(database: &str) -> ExitCode {
+    if !io::stdin().is_terminal() {
+        error!("--reset-pin must be run from a terminal");
+        return ExitCode::FAILURE;
+    }
+
+    print!("New admin PIN (4 to 8 digits, blank to remove): ");
+    let _ = io::stdout().flush();
+    let mut pin = String::new();
+    if let Err(e) = io::stdin().lock().read_line(&mut pin) {
+        error!("Failed to read the PIN: {e}");
+        return ExitCode::FAILURE;
+    }
+    let pin = pin.trim();
+    if !pin.is_empty() && !valid_pin(pin) {
+        eprintln!("The PIN must be 4 to 8 digits");
+        return ExitCode::FAILURE;
+    }
+
+    let reset = BackingDatabase::new(Some(database)).and_then(|mut db| {
+        set_pin(&mut db, (!pin.is_empty()).then_some(pin))?;
+        db.log_event("pin_reset", "", "from the terminal")
+    });
+    if let Err(e) = reset {
+        error!("Failed to reset the PIN: {e}");
+        return ExitCode::FAILURE;
+    }
+    println!(
+        "{}",
+        if pin.is_empty() {
+            "PIN removed"
+        } else {
+            "PIN reset"
+        }
+    );
+    ExitCode::SUCCESS
+}
+
+/// Unlock state of the kiosk window, provided as context.
+#[derive(Clone, Copy)]
+pub struct KioskLock {
+    unlocked: Signal<bool>,
+    /// Last guarded action or click while unlocked, not rendered.
+    last_activity: Signal<Instant>,
+    /// Runs once the PIN is entered, shown as a prompt while set.
+    pending: Signal<Option<Callback>>,
+    failures: Signal<u32>,
+    retry_at: Signal<Option<Instant>>,
+}
+
+/// Creates the [`KioskLock`], locking again after [`UNLOCK_IDLE`].
+pub fn use_kiosk_lock(backing_db: &DbHandle) -> KioskLock {
+    let backing_db = backing_db.clone();
+    // Locked until the PIN is known to be unset.
+    let unlocked = use_signal(|| false);
+    let last_activity = use_signal(Instant::now);
+    let lock = KioskLock {
+        unlocked,
+        last_activity,
+        pending: use_signal(|| None),
+        failures: use_signal(|| 0),
+        retry_at: use_signal(|| None),
+    };
+
+    use_future(move || {
+        let backing_db = backing_db.clone();
+        let mut unlocked = unlocked;
+        async move {
+            if !backing_db.try_run(|db| pin_set(db)).await.unwrap_or(true) {
+                unlocked.set(true);
+            }
+            loop {
+                tokio::time::sleep(IDLE_CHECK).await;
+                if *unlocked.peek()
+                    && last_activity.peek().elapsed() >= UNLOCK_IDLE
+                    && backing_db.try_run(|db| pin_set(db)).await.unwrap_or(true)
+                {
+                    info!(target: "lock", "Locked after inactivity");
+                    unlocked.set(false);
+                }
+            }
+        }
+    });
+    lock
+}
+
+impl KioskLock {
+    pub fn is_unlocked(&self) -> bool {
+        (self.unlocked)()
+    }
+
+    /// Keeps an unlocked kiosk unlocked.
+    pub fn touch(&self) {
+        let mut last_activity = self.last_activity;
+        *last_activity.write() = Instant::now();
+    }
+
+    /// Runs `action` now when unlocked, otherwise once the PIN is entered.
+    pub fn guard(&self, action: Callback) {
+        if *self.unlocked.peek() {
+            self.touch();
+            action(());
+        } else {
+            let mut pending = self.pending;
+            pending.set(Some(action));
+        }
+    }
+
+    /// Whether the PIN prompt is showing.
+    pub fn prompting(&self) -> bool {
+        self.pending.read().is_some()
+    }
+
+    /// Unlocks after setting the first PIN, without asking for it.
+    pub fn unlock(&self) {
+        let mut unlocked = self.unlocked;
+        unlocked.set(true);
+        self.touch();
+    }
+}
+
+/// Asks for the PIN while a guarded action waits.
+#[component]
+pub fn PinPrompt() -> Element {
+    let AttendanceContext { backing_db, .. } = use_context();
+    let lock: KioskLock = use_context();
+    let KioskLock {
+        mut pending,
+        mut failures,
+        mut retry_at,
+        ..
+    } = lock;
+    let mut pin = use_signal(String::new);
+    let mut message = use_signal(String::new);
+    // Ignores Enter while the last PIN is still being hashed.
+    let mut checking = use_signal(|| false);
+
+    let submit = use_callback(move |()| {
+        if checking() {
+            return;
+        }
+        let entered = pin.take();
+        if let Some(wait) = retry_at().map(|at| at.saturating_duration_since(Instant::now()))
+            && !wait.is_zero()
+        {
+            message.set(format!("Try again in {}s", wait.as_secs() + 1));
+            return;
+        }
+
+        checking.set(true);
+        let backing_db = backing_db.clone();
+        spawn(async move {
+            match backing_db.try_run(move |db| verify_pin(db, &entered)).await {
+                Ok(true) => {
+                    failures.set(0);
+                    retry_at.set(None);
+                    message.set(String::new());
+                    lock.unlock();
+                    if let Some(action) = pending.take() {
+                        action(());
+                    }
+                }
+                Ok(false) => {
+                    failures += 1;
+                    let failed = failures();
+                    let backoff = FIRST_BACKOFF
+                        .saturating_mul(2_u32.saturating_pow(failed - 1))
+                        .min(MAX_BACKOFF);
+                    retry_at.set(Some(Instant::now() + backoff));
+                    warn!(target: "lock", failed, "Wrong admin PIN");
+                    let _ = backing_db
+                        .try_run(move |db| {
+                            db.log_event("pin_failed", "", &format!("attempt {failed}"))
+                        })
+                        .await;
+                    message.set(format!("Wrong PIN, wait {}s", backoff.as_secs()));
+                }
+                Err(e) => message.set(format!("Could not check the PIN: {e}")),
+            }
+            checking.set(false);
+        });
+    });
+
+    rsx! {
+        div {
+            class: "pin_prompt",
+            h2 { "Admin PIN" }
+            input {
+                r#type: "password",
+                inputmode: "numeric",
+                autofocus: true,
+                value: "{pin}",
+                oninput: move |e| pin.set(e.value()),
+                onkeydown: move |e| {
+                    if e.key() == Key::Enter {
+                        submit(());
+                    }
+                },
+            }
+            button { onclick: move |_| submit(()), "Unlock" }
+            button {
+                onclick: move |_| {
+                    pin.set(String::new());
+                    message.set(String::new());
+                    pending.set(None);
+                },
+                "Cancel"
+            }
+            if !message.read().is_empty() {
+                p { color: "red", "{message}" }
+            }
+        }
+    }
+}
+
+/// Sets, changes, or removes the admin PIN. Shown in the admin pane, so
+/// already unlocked.
+#[component]
+pub fn PinSettings() -> Element {
+    let AttendanceContext {
+        backing_db,
+        mut process_change,
+        ..
+    } = use_context();
+    let backing_db_set = backing_db.clone();
+    let lock: KioskLock = use_context();
+
+    let mut pin = use_signal(String::new);
+    // Bumped to re-read whether a PIN is set.
+    let mut revision = use_signal(|| 0_u64);
+
+    let has_pin = use_resource(move || {
+        let backing_db = backing_db.clone();
+        revision.read();
+        async move { backing_db.try_run(|db| pin_set(db)).await.ok() }
+    });
+    // Assumed set until read, so removing is offered rather than hidden.
+    let has_pin = has_pin().is_none_or(|has_pin| has_pin.unwrap_or(true));
+
+    let set = use_callback(move |new_pin: Option<String>| {
+        if let Some(new_pin) = &new_pin
+            && !valid_pin(new_pin)
+        {
+            process_change.set("PIN MUST BE 4 TO 8 DIGITS".to_string());
+            return;
+        }
+        let removed = new_pin.is_none();
+        let backing_db = backing_db_set.clone();
+        spawn(async move {
+            match backing_db
+                .try_run(move |db| set_pin(db, new_pin.as_deref()))
+                .await
+            {
+                Ok(()) => {
+                    process_change.set(if removed { "PIN REMOVED" } else { "PIN SET" }.to_string());
+                    lock.unlock();
+                }
+                Err(e) => process_change.set(format!("PIN NOT CHANGED: {e}")),
+            }
+            *revision.write() += 1;
+        });
+    });
+
+    rsx! {
+        p { if has_pin { "A PIN is required for admin actions." } else { "No PIN set." } }
+        input {
+            r#type: "password",
+            inputmode: "numeric",
+            placeholder: "New PIN",
+            value: "{pin}",
+            oninput: move |e| pin.set(e.value()),
+        }
+        button {
+            onclick: move |_| set(Some(pin.take().trim().to_string())),
+            if has_pin { "Change PIN" } else { "Set PIN" }
+        }
+        if has_pin {
+            button { onclick: move |_| set(None), "Remove PIN" }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Diagnostics to stderr and a log file in the data directory, rotated daily
+//! and when it grows past a size cap.
+//!
+//! The level is controlled by `--log` or `QR_TRACKER_LOG`, using `tracing`
+//! env-filter syntax, e.g. `QR_TRACKER_LOG=info,video::camera=debug`.
+//! Subsystems log to the `video::camera`, `video::stream`, `video::analysis`,
+//! `attendance`, and `db` targets.
+
+use std::{
+    backtrace::Backtrace,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Local, NaiveDate};
+use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::config::{self, DataPath};
+
+const FILTER_ENV: &str = "QR_TRACKER_LOG";
+const DEFAULT_FILTER: &str = "info";
+
+/// Rotated files kept, named `qr-tracker.log.<date>` for the day logged, and
+/// `qr-tracker.log.<date>.<n>` for further files that day.
+const KEPT_LOGS: usize = 7;
+/// A log file is also rotated once it would grow past this size, so a
+/// runaway log cannot fill the disk within a day.
+const MAX_LOG_SIZE: u64 = 5 * 1024 * 1024;
+
+/// `logs/qr-tracker.log` in the data directory.
+pub fn log_path() -> PathBuf {
+    config::data_path(DataPath::Logs).join("qr-tracker.log")
+}
+
+/// Installs the global subscriber and panic hook. `filter` takes precedence
+/// over `QR_TRACKER_LOG`.
+///
+/// Falls back to only logging on stderr when the log file cannot be opened.
+pub fn init(filter: Option<&str>) {
+    let filter = match filter {
+        Some(filter) => EnvFilter::try_new(filter).map_err(|e| ("--log", e.to_string())),
+        None => EnvFilter::try_from_env(FILTER_ENV).map_err(|e| (FILTER_ENV, e.to_string())),
+    }
+    .unwrap_or_else(|(source, e)| {
+        if filter.is_some() || std::env::var_os(FILTER_ENV).is_some() {
+            eprintln!("Ignoring invalid {source}: {e}");
+        }
+        EnvFilter::new(DEFAULT_FILTER)
+    });
+
+    let path = log_path();
+    let (file_layer, file_error) = match RollingFile::open(&path) {
+        Ok(file) => (Some(fmt::layer().with_writer(Mutex::new(file))), None),
+        Err(e) => (None, Some(e)),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer().with_writer(io::stderr))
+        .with(file_layer)
+        .init();
+
+    if let Some(e) = file_error {
+        tracing::warn!("Not logging to {}: {e}", path.display());
+    }
+
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let thread = std::thread::current();
+        tracing::error!(
+            target: "panic",
+            "thread '{}' {info}\n{}",
+            thread.name().unwrap_or("<unnamed>"),
+            Backtrace::force_capture()
+        );
+        default_hook(info);
+    }));
+}
+
+/// Appends to a file, moving it aside at the first write of each day or
+/// once it reaches `max_size`.
+struct RollingFile {
+    path: PathBuf,
+    file: File,
+    /// Day of the entries in `file`.
+    day: NaiveDate,
+    size: u64,
+    max_size: u64,
+}
+
+impl RollingFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        Self::open_capped(path, MAX_LOG_SIZE)
+    }
+
+    fn open_capped(path: &Path, max_size: u64) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = File::options().create(true).append(true).open(path)?;
+        // A file left from an earlier day is rotated on the first write.
+        let metadata = file.metadata()?;
+        let day = metadata
+            .modified()
+            .map(|modified| DateTime::<Local>::from(modified).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive());
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            day,
+            size: metadata.len(),
+            max_size,
+        })
+    }
+
+    /// Rotated files with their day and number within it, oldest first.
+    fn rotated_files(&self) -> io::Result<Vec<((String, u32), PathBuf)>> {
+        let (Some(dir), Some(name)) = (self.path.parent(), self.path.file_name()) else {
+            return Ok(Vec::new());
+        };
+        let prefix = format!("{}.", name.to_string_lossy());
+
+        let mut rotated: Vec<_> = fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let suffix = name.strip_prefix(&prefix)?;
+                let (day, n) = suffix.split_once('.').unwrap_or((suffix, "0"));
+                Some(((day.to_string(), n.parse().ok()?), entry.path()))
+            })
+            .collect();
+        rotated.sort();
+        Ok(rotated)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let day = self.day.to_string();
+        // Numbered after the newest file of the day, even once older ones
+        // are pruned.
+        let n = self
+            .rotated_files()?
+            .iter()
+            .filter(|((rotated_day, _), _)| *rotated_day == day)
+            .map(|((_, n), _)| n + 1)
+            .max();
+        let mut rotated = self.path.clone().into_os_string();
+        match n {
+            Some(n) => rotated.push(format!(".{day}.{n}")),
+            None => rotated.push(format!(".{day}")),
+        }
+
+        fs::rename(&self.path, rotated)?;
+        *self = Self::open_capped(&self.path, self.max_size)?;
+        self.prune()
+    }
+
+    /// Removes all but the newest [`KEPT_LOGS`] rotated files.
+    fn prune(&self) -> io::Result<()> {
+        for (_, old) in self.rotated_files()?.iter().rev().skip(KEPT_LOGS) {
+            fs::remove_file(old)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A single oversized write still goes into an empty file.
+        if Local::now().date_naive() != self.day
+            || (self.size > 0 && self.size + buf.len() as u64 > self.max_size)
+        {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotated_at_size_cap() {
+        let dir = std::env::temp_dir().join(format!("qr-tracker-logs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("qr-tracker.log");
+        let mut file = RollingFile::open_capped(&path, 10).unwrap();
+        for _ in 0..(KEPT_LOGS + 3) {
+            file.write_all(b"0123456789").unwrap();
+        }
+        file.flush().unwrap();
+
+        let today = Local::now().date_naive();
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        let mut expected: Vec<_> = (2..=KEPT_LOGS + 1)
+            .map(|n| format!("qr-tracker.log.{today}.{n}"))
+            .collect();
+        expected.push("qr-tracker.log".to_string());
+        expected.sort();
+        assert_eq!(names, expected);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 10);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
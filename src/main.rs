@@ -5,131 +5,891 @@
  */
 
 use std::{
-    collections::HashMap,
-    fmt::Write,
-    path::PathBuf,
-    rc::Rc,
-    sync::{LazyLock, OnceLock, RwLock},
+    fmt::Display,
+    fs,
+    net::SocketAddr,
+    path::Path,
+    process::ExitCode,
+    str::FromStr,
+    sync::{
+        OnceLock, RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
     thread,
     time::Duration,
 };
 
 use chrono::{DateTime, Local};
-use dioxus::{
-    desktop::{WindowBuilder, tao::platform::unix::WindowBuilderExtUnix},
-    prelude::*,
+use clap::Parser;
+use dioxus::prelude::*;
+use dioxus_desktop::{
+    tao::event::{Event, WindowEvent},
+    use_wry_event_handler, window,
 };
-use dioxus_desktop::window;
 use nokhwa::utils::Resolution;
 
-use crate::{sqlite::BackingDatabase, video::video_routine};
+use crate::{
+    admin::{AdminPanel, ExportButton, ManualScan, RegisterGuest, ReportButton},
+    api::{ApiSettings, ApiWriter, ScanAction, ScanSubmission},
+    attendance::{AttendanceView, PresentRows, ScanInputs},
+    audit::AuditLog,
+    backup::periodic_backup,
+    cli::{Cli, Command, ConfigCommand},
+    clock::monitor_clock,
+    config::{Config, DataPath},
+    confirm::{PendingSignOut, SignOutPrompt, SignOutQueue},
+    db_worker::DbHandle,
+    diagnostics::PipelineDiagnostics,
+    escalation::EscalationMonitor,
+    feedback::{Feedback, LatestFeedback, ScanFeedback},
+    history::History,
+    instance::InstanceLock,
+    lock::{PinPrompt, use_kiosk_lock},
+    observer::{CameraStatus, ObserverRegistry},
+    platform::{FULLSCREEN_KEY, KioskOptions},
+    present_list::{ListSort, PresentList, SORT_KEY},
+    schedule::daily_sync,
+    setup::Setup,
+    sheets::weekly_push,
+    signout::nightly_sign_out,
+    sqlite::{BackingDatabase, DbError},
+    sync::sync_peers,
+    version::check_updates,
+    video::{
+        CameraRead, DEFAULT_MAX_FRAME_FAILURES, PipelineMetrics, SCAN_MODE_KEY, ScanMode,
+        StreamClients, VIDEO_BIND_KEY, VideoOptions, VideoSource, list_cameras, video_routine,
+    },
+};
 
 /// Arbitrary buffer length to allow QR processing to catch up with QR input.
 const QR_BUFFER_SIZE: usize = 1024;
 const MIN_SCAN_SPACING_SECS: i64 = 20;
+/// Time between checks of how many scans wait on the database.
+const PENDING_SCANS_POLL: Duration = Duration::from_secs(1);
+/// Accepted scans kept for the undo button.
+const UNDO_DEPTH: usize = 10;
 
-pub const VIDEO_SOCKET: &str = "localhost:2343";
-pub const VIDEO_SOCKET_HTTP: &str = const_str::concat!("http://", VIDEO_SOCKET);
-
-static BACKING_DATABASE_FILE: LazyLock<String> = LazyLock::new(|| {
-    let mut path = "qr-tracker.db".to_string();
-
-    // Appimage compatibility.
-    // By default AppImage changes to a /usr directory.
-    // This changes back to the program launch directory.
-    if let Ok(owd) = std::env::var("OWD") {
-        let mut new_path = PathBuf::from(owd);
-        new_path.push(path);
-        println!("Extended path for appimage: {}", new_path.to_string_lossy());
-        path = new_path.to_string_lossy().to_string();
+const DEFAULT_VIDEO_PORT: u16 = 2343;
+/// Time between checks for a camera being plugged in or unplugged.
+const CAMERA_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const NO_CAMERA: &str = "No camera detected";
+/// Sends to Rust on Ctrl+Shift+Q anywhere in the window, focused input or
+/// not.
+const EXIT_SHORTCUT_LISTENER: &str = r#"
+window.addEventListener("keydown", (e) => {
+    if (e.ctrlKey && e.shiftKey && e.code === "KeyQ") {
+        e.preventDefault();
+        dioxus.send(true);
     }
-
-    path
 });
+"#;
 
 static MAIN_CSS: Asset = asset!("/assets/main.css");
 
-mod atomic_buf;
+mod admin;
+mod api;
+mod attendance;
+mod audit;
+mod backup;
+mod badges;
+mod cli;
+mod clock;
+mod config;
+mod confirm;
+mod control;
+mod db_worker;
+mod diagnostics;
+mod discovery;
+mod escalation;
+mod feedback;
+mod frc;
+mod greeting;
+mod headless;
+mod history;
+mod instance;
+mod lock;
+mod logging;
+mod migrations;
+mod notify;
+mod observer;
+mod payload;
+mod payroll;
+mod person_detail;
+mod platform;
+mod present_list;
+mod report;
+mod rules;
+mod scan_queue;
+mod schedule;
+mod setup;
+mod sheets;
+mod shutdown;
+mod signout;
+mod sms;
+mod snapshot;
 mod sqlite;
+mod sync;
+mod version;
 mod video;
+mod web;
+
+// Resolutions of the open camera, empty while there is none.
+pub static CAMERA_RESOLUTION_LIST: RwLock<Vec<Resolution>> = RwLock::new(Vec::new());
+/// Address the preview stream is listening on, `None` until bound.
+pub static VIDEO_ADDR: RwLock<Option<SocketAddr>> = RwLock::new(None);
+/// Latest full resolution JPEG from the video source, empty before the first
+/// frame.
+pub static LATEST_FRAME: RwLock<Vec<u8>> = RwLock::new(Vec::new());
+/// Codes the analysis threads look for, loaded from the database.
+pub static SCAN_MODE: RwLock<ScanMode> = RwLock::new(ScanMode::QrOnly);
+/// Set while frames are coming from the video source.
+pub static CAMERA_CONNECTED: AtomicBool = AtomicBool::new(false);
+/// Set when no camera can be used at all, e.g. access was denied.
+pub static CAMERA_ERROR: OnceLock<String> = OnceLock::new();
+/// Viewers of the preview through the HTTP API, see [`api`].
+pub static API_STREAM_CLIENTS: StreamClients = StreamClients::new();
+/// Counters from the video threads, shown by [`PipelineDiagnostics`].
+pub static PIPELINE_METRICS: PipelineMetrics = PipelineMetrics::new();
+
+/// Camera as seen by the video pane, polled from the video thread.
+#[derive(Debug, Clone, PartialEq)]
+enum CameraState {
+    /// No frames yet, or the camera was unplugged.
+    Searching,
+    /// With the resolutions the camera offers.
+    Ready(Box<[Resolution]>),
+    /// No camera can be used until restart.
+    Failed(String),
+}
+
+impl CameraState {
+    fn poll() -> Self {
+        if CAMERA_CONNECTED.load(Ordering::Relaxed) {
+            Self::Ready(CAMERA_RESOLUTION_LIST.read().unwrap().as_slice().into())
+        } else if let Some(error) = CAMERA_ERROR.get() {
+            Self::Failed(error.clone())
+        } else {
+            Self::Searching
+        }
+    }
+
+    /// Empty unless ready.
+    fn resolutions(&self) -> &[Resolution] {
+        match self {
+            Self::Ready(resolutions) => resolutions,
+            Self::Searching | Self::Failed(_) => &[],
+        }
+    }
+
+    fn status(&self) -> CameraStatus {
+        match self {
+            Self::Ready(_) => CameraStatus::Ready,
+            Self::Searching => CameraStatus::Unavailable(NO_CAMERA.to_string()),
+            Self::Failed(error) => CameraStatus::Unavailable(error.clone()),
+        }
+    }
+}
+
+/// What the right half of the kiosk shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    Attendance,
+    Admin,
+    History,
+    Audit,
+}
+
+impl Pane {
+    /// Needs the admin PIN to open, see [`KioskLock`].
+    fn locked(self) -> bool {
+        matches!(self, Self::Admin | Self::Audit)
+    }
+}
+
+/// An accepted scan, kept so it can be undone.
+#[derive(Debug, Clone, PartialEq)]
+struct UndoEntry {
+    name: String,
+    signed_in: bool,
+    time: DateTime<Local>,
+}
+
+/// Shows the scan loop's results in the kiosk window.
+#[derive(Clone)]
+struct KioskView {
+    mentor_rows: Signal<PresentRows>,
+    student_rows: Signal<PresentRows>,
+    guest_rows: Signal<PresentRows>,
+    process_change: Signal<String>,
+    db_error: Signal<Option<String>>,
+    scan_feedback: Signal<LatestFeedback>,
+    unsupervised_since: Signal<Option<DateTime<Local>>>,
+    undo_stack: Signal<Vec<UndoEntry>>,
+    sign_out_queue: SignOutQueue,
+}
+
+impl AttendanceView for KioskView {
+    fn spawn(&self, task: attendance::Task) {
+        spawn(task);
+    }
+
+    fn set_present(&self, role: QrType, rows: PresentRows) {
+        let mut dest = match role {
+            QrType::Mentor => self.mentor_rows,
+            QrType::Student => self.student_rows,
+            QrType::Guest => self.guest_rows,
+        };
+        dest.set(rows);
+    }
+
+    fn show(&self, message: String) {
+        let mut process_change = self.process_change;
+        process_change.set(message);
+    }
+
+    fn replace(&self, shown: &str, message: String) {
+        let mut process_change = self.process_change;
+        if *process_change.peek() == shown {
+            process_change.set(message);
+        }
+    }
+
+    fn set_db_error(&self, error: Option<String>) {
+        let mut db_error = self.db_error;
+        if *db_error.peek() != error {
+            db_error.set(error);
+        }
+    }
+
+    fn set_unsupervised(&self, unsupervised: bool) {
+        let mut unsupervised_since = self.unsupervised_since;
+        if unsupervised != unsupervised_since.peek().is_some() {
+            unsupervised_since.set(unsupervised.then(Local::now));
+        }
+    }
+
+    fn feedback(&self, action: &ScanAction) {
+        feedback::play(self.scan_feedback, Feedback::from(action));
+    }
+
+    fn accepted(&self, name: &str, signed_in: bool, time: DateTime<Local>) {
+        let mut undo_stack = self.undo_stack;
+        let mut undo_stack = undo_stack.write();
+        undo_stack.push(UndoEntry {
+            name: name.to_string(),
+            signed_in,
+            time,
+        });
+        if undo_stack.len() > UNDO_DEPTH {
+            undo_stack.remove(0);
+        }
+    }
+
+    fn confirm_sign_out(&self, sign_out: PendingSignOut) -> bool {
+        self.sign_out_queue.push(sign_out);
+        true
+    }
+}
+
+/// Runs tasks on the app, before the kiosk's signals exist.
+#[derive(Clone)]
+struct SpawnView;
+
+impl AttendanceView for SpawnView {
+    fn spawn(&self, task: attendance::Task) {
+        spawn(task);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum QrType {
+    Mentor,
+    Student,
+    Guest,
+}
+
+impl Display for QrType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mentor => write!(f, "mentor"),
+            Self::Student => write!(f, "student"),
+            Self::Guest => write!(f, "guest"),
+        }
+    }
+}
+
+impl FromStr for QrType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mentor" => Ok(Self::Mentor),
+            "student" => Ok(Self::Student),
+            "guest" => Ok(Self::Guest),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Database file used by [`Kiosk`].
+#[derive(Clone)]
+struct DatabaseFile(String);
+
+/// Whether `--force-config` was passed, see [`Config::seed`].
+#[derive(Clone)]
+struct ForceConfig(bool);
+
+/// Stream address from the command line, which replaces the saved setting.
+#[derive(Clone)]
+struct VideoBind(Option<String>);
+
+/// Whether the command line sets the frame source, which replaces the saved
+/// camera.
+#[derive(Clone, Copy)]
+struct SourceFixed(bool);
+
+/// Attendance state shared with components outside of [`app`].
+#[derive(Clone)]
+pub struct AttendanceContext {
+    pub backing_db: DbHandle,
+    /// Asks the scan loop to reload rosters and present lists from the
+    /// database.
+    pub refresh_tx: async_channel::Sender<()>,
+    /// Handled by the scan loop the same as camera reads.
+    pub scan_submissions_tx: async_channel::Sender<ScanSubmission>,
+    pub process_change: Signal<String>,
+    pub db_error: DbErrorBanner,
+}
+
+/// The red database error shown above every pane.
+#[derive(Clone, Copy)]
+pub struct DbErrorBanner(Signal<Option<String>>);
 
-// Populated with all available camera resolutions on startup.
-pub static CAMERA_RESOLUTION_LIST: OnceLock<Box<[Resolution]>> = OnceLock::new();
+impl DbErrorBanner {
+    /// `result`'s value, or `None` after showing its error. Any success
+    /// clears the banner, the same as in the scan loop.
+    pub fn check<T>(self, result: Result<T, DbError>) -> Option<T> {
+        let Self(mut db_error) = self;
+        match result {
+            Ok(value) => {
+                if db_error.peek().is_some() {
+                    db_error.set(None);
+                }
+                Some(value)
+            }
+            Err(e) => {
+                tracing::error!(target: "db", "{e}");
+                db_error.set(Some(format!("DATABASE ERROR: {e}")));
+                None
+            }
+        }
+    }
+}
 
 #[derive(Clone)]
-struct VideoChannels {
-    pub qr_reads_rx: async_channel::Receiver<String>,
+pub struct VideoChannels {
+    pub qr_reads_rx: async_channel::Receiver<CameraRead>,
     pub camera_resolution_select_tx: async_channel::Sender<Resolution>,
+    pub camera_select_tx: async_channel::Sender<u32>,
+    /// Moves the preview stream to a new address, without touching the
+    /// camera.
+    pub video_bind_tx: async_channel::Sender<String>,
 }
 
-fn main() {
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    logging::init(cli.log.as_deref());
+    let config_path = cli.config.clone().unwrap_or_else(config::default_path);
+
+    if let Some(Command::Config {
+        command: ConfigCommand::Init { force },
+    }) = cli.command
+    {
+        return cli::init_config(&config_path, force);
+    }
+
+    let mut config = match Config::load(&config_path, cli.config.is_some()) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Failed to load config {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    config.apply_cli(&cli);
+
+    let data_dir = config::data_path(DataPath::Root);
+    if let Err(e) = fs::create_dir_all(&data_dir) {
+        tracing::error!("Failed to create {}: {e}", data_dir.display());
+        return ExitCode::FAILURE;
+    }
+
+    let database = config
+        .database
+        .clone()
+        .unwrap_or_else(|| config::data_path(DataPath::Database))
+        .to_string_lossy()
+        .to_string();
+
+    // Before anything opens it, so a failed step exits with the step named.
+    // New databases are created up to date.
+    if Path::new(&database).exists()
+        && let Err(e) = migrations::migrate_file(&database)
+    {
+        tracing::error!("Failed to migrate {database}: {e}");
+        return ExitCode::FAILURE;
+    }
+
+    if cli.reset_pin {
+        let _lock = match InstanceLock::acquire(&database) {
+            Ok(lock) => lock,
+            Err(e) => {
+                tracing::error!("{e}, close it before resetting the PIN");
+                return ExitCode::FAILURE;
+            }
+        };
+        return lock::reset_pin(&database);
+    }
+
+    if let Some(command) = cli.command {
+        // Read-only subcommands can run next to a kiosk.
+        let _lock = if command.writes_database() {
+            match InstanceLock::acquire(&database) {
+                Ok(lock) => Some(lock),
+                Err(e) => {
+                    tracing::error!("{e}, close it before changing the database");
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else {
+            None
+        };
+
+        shutdown::handle_signals(database.clone(), false);
+        return cli::run(&database, &config, command);
+    }
+
+    // Held until exit, before the camera is claimed.
+    let _lock = match InstanceLock::acquire(&database) {
+        Ok(lock) => lock,
+        Err(e) => {
+            tracing::error!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // The config file's address is only a default, the saved one replaces it
+    // once restored.
+    let initial_video_bind = cli
+        .video_bind
+        .clone()
+        .or(config.video_bind.clone())
+        .unwrap_or_else(|| {
+            format!(
+                "localhost:{}",
+                config.video_port.unwrap_or(DEFAULT_VIDEO_PORT)
+            )
+        });
+    let max_frame_failures = config
+        .camera_max_failures
+        .unwrap_or(DEFAULT_MAX_FRAME_FAILURES);
+    let video_options = VideoOptions {
+        max_frame_failures,
+        qr_fallback: config.qr_fallback.unwrap_or(true),
+        second_camera: config.second_camera_index,
+    };
+    let source_fixed = cli.source.is_some();
+    let video_source = cli
+        .source
+        .clone()
+        .unwrap_or(VideoSource::Camera(config.camera_index));
+
     let (qr_reads_tx, qr_reads_rx) = async_channel::bounded(QR_BUFFER_SIZE);
     let (camera_resolution_select_tx, camera_resolution_select_rx) = async_channel::bounded(1);
-    thread::spawn(move || video_routine(qr_reads_tx, camera_resolution_select_rx));
+    let (camera_select_tx, camera_select_rx) = async_channel::bounded(1);
+    let (video_bind_tx, video_bind_rx) = async_channel::bounded(1);
+    // The listener starts on the first address it receives.
+    video_bind_tx.try_send(initial_video_bind).unwrap();
+    thread::spawn(move || {
+        video_routine(
+            qr_reads_tx,
+            camera_resolution_select_rx,
+            camera_select_rx,
+            video_bind_rx,
+            video_source,
+            video_options,
+        );
+        shutdown::video_stopped();
+    });
+
+    shutdown::handle_signals(database.clone(), true);
+
+    // Read before the window opens, without setting the database up. New
+    // databases, and ones that cannot be read, start fullscreen.
+    let saved_windowed = Path::new(&database).exists()
+        && matches!(
+            BackingDatabase::open_read_only(&database)
+                .map_err(DbError::Sqlite)
+                .and_then(|db| db.get_setting::<bool>(FULLSCREEN_KEY)),
+            Ok(Some(false))
+        );
+    let kiosk_options = KioskOptions {
+        monitor: config.monitor.clone(),
+        always_on_top: config.always_on_top.unwrap_or(false),
+        hide_cursor: config.hide_cursor.unwrap_or(false),
+        windowed: cli.windowed || saved_windowed,
+    };
 
     let video_channels = VideoChannels {
         qr_reads_rx,
         camera_resolution_select_tx,
+        camera_select_tx,
+        video_bind_tx,
     };
 
+    if cli.headless {
+        return headless::run(
+            database,
+            config,
+            video_channels,
+            cli.video_bind.is_some(),
+            source_fixed,
+            cli.force_config,
+        );
+    }
+
     dioxus::LaunchBuilder::new()
         .with_cfg(desktop! {
             dioxus_desktop::Config::default()
                 .with_menu(None)
-                .with_window(
-                WindowBuilder::new()
-                    .with_fullscreen(Some(dioxus_desktop::tao::window::Fullscreen::Borderless(
-                        None,
-                    )))
-                    .with_skip_taskbar(true)
-                    ,
-            )
+                .with_window(platform::window(&kiosk_options))
         })
         .with_context(video_channels)
+        .with_context(DatabaseFile(database))
+        .with_context(VideoBind(cli.video_bind))
+        .with_context(SourceFixed(source_fixed))
+        .with_context(ForceConfig(cli.force_config))
+        .with_context(config)
+        .with_context(kiosk_options)
         .launch(app);
+
+    ExitCode::SUCCESS
 }
 
-fn format_evenly(entries: &[(String, DateTime<Local>)]) -> String {
-    let longest_name = entries
-        .iter()
-        .map(|(name, _)| name.len())
-        .max()
-        .unwrap_or(0);
+/// Applies the stream address, scan mode, camera, and resolution saved from
+/// the admin panel, which replace the config file's. `bind_fixed` and
+/// `source_fixed` are whether the command line sets the stream address or
+/// frame source, which then win.
+async fn restore_video_settings(
+    backing_db: DbHandle,
+    video_bind_tx: async_channel::Sender<String>,
+    camera_select_tx: async_channel::Sender<u32>,
+    camera_resolution_select_tx: async_channel::Sender<Resolution>,
+    bind_fixed: bool,
+    source_fixed: bool,
+) {
+    let saved = backing_db
+        .try_run(|db| {
+            Ok((
+                db.get_setting::<String>(VIDEO_BIND_KEY)?,
+                db.get_setting(SCAN_MODE_KEY)?,
+                db.get_camera_index()?,
+            ))
+        })
+        .await;
+    let (bind, mode, index) = match saved {
+        Ok(saved) => saved,
+        Err(e) => {
+            tracing::warn!("Failed to load the video settings: {e}");
+            return;
+        }
+    };
+    if !bind_fixed && let Some(bind) = bind {
+        video_bind_tx.send(bind).await.unwrap();
+    }
+    if let Some(mode) = mode {
+        *SCAN_MODE.write().unwrap() = mode;
+    }
+    if !source_fixed && let Some(index) = index {
+        camera_select_tx.send(index).await.unwrap();
+    }
 
-    let mut out = String::new();
+    let resolution = backing_db
+        .get_resolution()
+        .await
+        .inspect_err(|e| tracing::warn!("Failed to load the camera resolution: {e}"));
+    if let Ok(Some(resolution)) = resolution {
+        camera_resolution_select_tx.send(resolution).await.unwrap();
+    }
+}
 
-    for (name, time) in entries {
-        out.push_str(name);
-        for _ in 0..(longest_name - name.len()) {
-            out.push(' ');
+/// Reports camera changes to `observers`, and keeps `camera_state` and
+/// `video_url` current.
+async fn poll_cameras(
+    observers: ObserverRegistry,
+    mut camera_state: Signal<CameraState>,
+    mut video_url: Signal<Option<String>>,
+) {
+    // Reported on every change, starting with the first poll.
+    let mut last_status = None;
+    loop {
+        let state = CameraState::poll();
+        let status = state.status();
+        if last_status.as_ref() != Some(&status) {
+            observers.status(&status);
+            last_status = Some(status);
+        }
+        if *camera_state.peek() != state {
+            camera_state.set(state);
         }
 
-        writeln!(out, "\t{}", time.format("%m-%d-%Y %H:%M:%S %p")).unwrap();
-    }
+        let url = VIDEO_ADDR.read().unwrap().map(stream_url);
+        if *video_url.peek() != url {
+            video_url.set(url);
+        }
 
-    // Remove trailing newline
-    out.pop();
+        tokio::time::sleep(CAMERA_POLL_INTERVAL).await;
+    }
+}
 
-    out
+/// Preview stream URL for a listener on `addr`, through localhost when it
+/// listens on every address.
+fn stream_url(addr: SocketAddr) -> String {
+    let host = if addr.ip().is_unspecified() {
+        "localhost".to_string()
+    } else {
+        match addr {
+            SocketAddr::V4(addr) => addr.ip().to_string(),
+            SocketAddr::V6(addr) => format!("[{}]", addr.ip()),
+        }
+    };
+    format!("http://{host}:{}/stream", addr.port())
 }
 
+/// Runs first-run setup before the kiosk while there is no database.
 #[component]
 fn app() -> Element {
-    let backing_db = use_hook(|| {
-        Rc::new(RwLock::new(BackingDatabase::new(Some(
-            &BACKING_DATABASE_FILE,
-        ))))
+    let DatabaseFile(database) = use_context();
+    let setup_done = use_signal(|| !setup::needs_setup(&database));
+
+    if setup_done() {
+        rsx! { Kiosk {} }
+    } else {
+        rsx! {
+            document::Stylesheet { href: MAIN_CSS }
+            Setup { done: setup_done }
+        }
+    }
+}
+
+#[component]
+fn Kiosk() -> Element {
+    let DatabaseFile(database) = use_context();
+    let VideoBind(video_bind) = use_context();
+    let SourceFixed(source_fixed) = use_context();
+    let config: Config = use_context();
+    let ForceConfig(force_config) = use_context();
+    let opened = use_hook(|| {
+        let path = database.clone();
+        let config = config.clone();
+        DbHandle::try_spawn(move || {
+            let mut db = BackingDatabase::new(Some(&path))?;
+            if let Err(e) = config.seed(&mut db, force_config) {
+                tracing::error!(target: "config", "Failed to copy config file values: {e}");
+            }
+            if let Err(e) = db.warn_integrity_issues() {
+                tracing::error!(target: "db", "Integrity check failed: {e}");
+            }
+            if let Err(e) = db.prune_audit() {
+                tracing::error!(target: "db", "Failed to prune the audit log: {e}");
+            }
+            Ok::<_, DbError>(db)
+        })
+        .map_err(|e| {
+            tracing::error!(target: "db", "Failed to open {database}: {e}");
+            e.to_string()
+        })
     });
+    // Never changes, so the hooks below are either always or never run.
+    let backing_db = match opened {
+        Ok(backing_db) => backing_db,
+        Err(e) => {
+            return rsx! {
+                document::Stylesheet { href: MAIN_CSS }
+                h3 { color: "red", "DATABASE ERROR: {e}" }
+            };
+        }
+    };
     let backing_db_process_change = backing_db.clone();
     let backing_db_select = backing_db.clone();
+    let backing_db_camera_select = backing_db.clone();
     let backing_db_select_reset = backing_db.clone();
+    let backing_db_occupancy = backing_db.clone();
 
-    let mut mentor_string = use_signal(|| "".to_string());
-    let mut student_string = use_signal(|| "".to_string());
-    let mut guest_string = use_signal(|| "".to_string());
+    // Shown names, updated by the scan loop.
+    let mentor_rows = use_signal(Vec::new);
+    let student_rows = use_signal(Vec::new);
+    let guest_rows = use_signal(Vec::new);
+    // Narrows all three lists.
+    let mut list_filter = use_signal(String::new);
+    let mut list_sort = use_signal(ListSort::default);
+    use_hook(|| {
+        let backing_db = backing_db.clone();
+        spawn(async move {
+            if let Ok(Some(sort)) = backing_db.try_run(|db| db.get_setting(SORT_KEY)).await {
+                list_sort.set(sort);
+            }
+        })
+    });
     let mut process_change = use_signal(|| "".to_string());
+    let mut pane = use_signal(|| Pane::Attendance);
+    // Most recent last, undone in reverse order.
+    let mut undo_stack = use_signal(Vec::<UndoEntry>::new);
+    // Latest database failure, until a later read or write succeeds.
+    let db_error = use_signal(|| None::<String>);
+    // Scans accepted while the database could not be written.
+    let mut pending_scans = use_signal(|| 0);
+    // Outcome of the latest scan, played and flashed by `ScanFeedback`.
+    let scan_feedback = use_signal(|| None);
+    // Set while students are present without any mentor.
+    let unsupervised_since = use_signal(|| None::<DateTime<Local>>);
+    // System clock offset from NTP in seconds, while over the limit.
+    let clock_drift = use_signal(|| None::<f64>);
+    // Latest released version, when newer than this build.
+    let update_available = use_signal(|| None::<String>);
+
+    let kiosk_options: KioskOptions = use_context();
+    use_hook(|| platform::apply_kiosk_options(&window().window, &kiosk_options));
 
-    let camera_resolution_list = use_hook(|| CAMERA_RESOLUTION_LIST.wait());
+    // Runs the same shutdown as SIGTERM, instead of exiting mid-write.
+    let database_close = database.clone();
+    use_wry_event_handler(move |event, _| {
+        if let Event::WindowEvent {
+            event: WindowEvent::CloseRequested,
+            ..
+        } = event
+        {
+            shutdown::shutdown(&database_close, true);
+        }
+    });
+
+    // Scans submitted over the HTTP API, handled alongside camera reads.
+    let (scan_submissions_tx, scan_submissions_rx) =
+        use_hook(|| async_channel::bounded::<ScanSubmission>(QR_BUFFER_SIZE));
+
+    let (refresh_tx, refresh_rx) = use_hook(async_channel::unbounded);
+
+    // Stops with the app, as it runs on the app's runtime.
+    use_hook(|| {
+        let database = database.clone();
+        let submissions = scan_submissions_tx.clone();
+        let writer = ApiWriter {
+            backing_db: backing_db.clone(),
+            refresh_tx: refresh_tx.clone(),
+        };
+        spawn(async move {
+            match writer.backing_db.try_run(|db| ApiSettings::load(db)).await {
+                Ok(Some(settings)) => api::serve(database, settings, submissions, writer).await,
+                Ok(None) => (),
+                Err(e) => {
+                    tracing::error!(target: "api", "Failed to load the HTTP API settings: {e}")
+                }
+            }
+        })
+    });
+    use_hook(|| {
+        spawn(control::serve(
+            backing_db.clone(),
+            refresh_tx.clone(),
+            scan_submissions_tx.clone(),
+            SpawnView,
+        ))
+    });
+    use_hook(|| spawn(sync_peers(backing_db.clone(), refresh_tx.clone())));
+    use_hook(|| {
+        let backing_db = backing_db.clone();
+        spawn(async move {
+            if let Err(e) = backing_db.try_run(snapshot::prune).await {
+                tracing::error!(target: "snapshot", "Failed to prune snapshots: {e}");
+            }
+        })
+    });
+    use_hook(|| spawn(nightly_sign_out(backing_db.clone(), refresh_tx.clone())));
+    use_hook(|| spawn(weekly_push(backing_db.clone(), config.clone())));
+    use_hook(|| spawn(daily_sync(backing_db.clone(), config.clone())));
+    use_hook(|| spawn(periodic_backup(backing_db.clone())));
+    use_hook(|| {
+        spawn(monitor_clock(
+            backing_db.clone(),
+            config.clone(),
+            clock_drift,
+        ))
+    });
+    use_hook(|| spawn(check_updates(config.clone(), update_available)));
+    // Camera sign-outs waiting on the kiosk, see `confirm`.
+    let (sign_out_confirmed_tx, sign_out_confirmed_rx) = use_hook(async_channel::unbounded);
+    let pending_sign_outs = use_signal(Vec::new);
+    let sign_out_queue = use_context_provider(|| {
+        SignOutQueue::new(pending_sign_outs, sign_out_confirmed_tx.clone())
+    });
+    let backing_db_undo = backing_db.clone();
+    let refresh_undo_tx = refresh_tx.clone();
+    use_context_provider(|| AttendanceContext {
+        backing_db: backing_db.clone(),
+        refresh_tx,
+        scan_submissions_tx: scan_submissions_tx.clone(),
+        process_change,
+        db_error: DbErrorBanner(db_error),
+    });
+    let lock = use_kiosk_lock(&backing_db);
+    use_context_provider(|| lock);
+    // Back to attendance once the kiosk locks again.
+    use_effect(move || {
+        if !lock.is_unlocked() && pane.peek().locked() {
+            pane.set(Pane::Attendance);
+        }
+    });
+
+    // The way out of a fullscreen kiosk with no window controls, shutting
+    // down the same as closing the window.
+    let exit_kiosk = use_callback({
+        let database = database.clone();
+        let backing_db = backing_db.clone();
+        move |()| {
+            let database = database.clone();
+            let backing_db = backing_db.clone();
+            spawn(async move {
+                let _ = backing_db
+                    .try_run(|db| db.log_event("kiosk_exited", "", "Ctrl+Shift+Q"))
+                    .await;
+                shutdown::shutdown(&database, true)
+            });
+        }
+    });
+    use_future(move || async move {
+        let mut shortcut = document::eval(EXIT_SHORTCUT_LISTENER);
+        while shortcut.recv::<bool>().await.is_ok() {
+            lock.guard(exit_kiosk);
+        }
+    });
+
+    // Polled, cameras can be plugged in or unplugged at any time.
+    let camera_state = use_signal(|| CameraState::Searching);
+    // Moves when the stream is rebound.
+    let video_url = use_signal(|| None::<String>);
+
+    let backing_db_pending = backing_db.clone();
+    use_future(move || {
+        let backing_db = backing_db_pending.clone();
+        async move {
+            loop {
+                let pending = backing_db.pending_scans();
+                if *pending_scans.peek() != pending {
+                    pending_scans.set(pending);
+                }
+                tokio::time::sleep(PENDING_SCANS_POLL).await;
+            }
+        }
+    });
 
     let img_dims = use_hook(|| {
         let size = window().window.inner_size();
@@ -141,90 +901,49 @@ fn app() -> Element {
     let VideoChannels {
         qr_reads_rx,
         camera_resolution_select_tx,
+        camera_select_tx,
+        video_bind_tx,
     } = use_context();
-    let camera_resolution_select_tx_reset = camera_resolution_select_tx.clone();
 
-    // Set camera resolution with any existing selection.
+    let camera_resolution_select_tx_reset = camera_resolution_select_tx.clone();
     use_hook(|| {
-        if let Some(resolution) = backing_db.read().unwrap().get_resolution() {
-            camera_resolution_select_tx
-                .send_blocking(resolution)
-                .unwrap();
-        }
+        spawn(restore_video_settings(
+            backing_db.clone(),
+            video_bind_tx.clone(),
+            camera_select_tx.clone(),
+            camera_resolution_select_tx.clone(),
+            video_bind.is_some(),
+            source_fixed,
+        ))
     });
+    let camera_list = use_hook(list_cameras);
 
-    // Updates attendance lists.
+    // Updates attendance lists and reports camera changes, once the
+    // observers have loaded their settings.
     use_hook(|| {
+        let inputs = ScanInputs {
+            qr_reads_rx: qr_reads_rx.clone(),
+            submissions_rx: scan_submissions_rx.clone(),
+            sign_out_confirmed_rx: sign_out_confirmed_rx.clone(),
+            refresh_rx: refresh_rx.clone(),
+        };
+        let view = KioskView {
+            mentor_rows,
+            student_rows,
+            guest_rows,
+            process_change,
+            db_error,
+            scan_feedback,
+            unsupervised_since,
+            undo_stack,
+            sign_out_queue: sign_out_queue.clone(),
+        };
+        let backing_db = backing_db.clone();
+        let config = config.clone();
         spawn(async move {
-            let carryover_present = backing_db.read().unwrap().get_present();
-            let known_mentors = backing_db.read().unwrap().get_mentors().into_boxed_slice();
-            let known_students = backing_db.read().unwrap().get_students().into_boxed_slice();
-
-            let mut mentor_list: Vec<_> = carryover_present
-                .iter()
-                .filter(|(name, _)| known_mentors.contains(name))
-                .map(|(name, time)| (name.clone(), *time))
-                .collect();
-            mentor_string.set(format_evenly(&mentor_list));
-
-            let mut student_list: Vec<_> = carryover_present
-                .iter()
-                .filter(|(name, _)| known_students.contains(name))
-                .map(|(name, time)| (name.clone(), *time))
-                .collect();
-            student_string.set(format_evenly(&student_list));
-
-            let mut guest_list: Vec<_> = carryover_present
-                .iter()
-                .filter(|(name, _)| name.starts_with("Guest"))
-                .map(|(name, time)| (name.clone(), *time))
-                .collect();
-            guest_string.set(format_evenly(&guest_list));
-
-            let mut total_list: HashMap<_, _> = carryover_present.into_iter().collect();
-
-            loop {
-                let next_qr_read = qr_reads_rx.recv().await.unwrap();
-                let time = Local::now();
-
-                // Prevent repeated QR scans.
-                let previous_time = total_list.get(&next_qr_read).copied();
-                total_list.insert(next_qr_read.clone(), time);
-                if let Some(previous_time) = previous_time
-                    && ((time - previous_time).num_seconds() < MIN_SCAN_SPACING_SECS)
-                {
-                    continue;
-                }
-
-                let mut list_update = |list: &mut Vec<(String, DateTime<Local>)>,
-                                       mut dest: Signal<String>,
-                                       qr_name: &String| {
-                    if let Some(existing_idx) = list.iter().position(|(name, _)| name == qr_name) {
-                        process_change.set(format!("REMOVED {qr_name}"));
-                        list.remove(existing_idx);
-                    } else {
-                        process_change.set(format!("ADDED {qr_name}"));
-                        list.push((qr_name.clone(), time));
-                    }
-                    dest.set(format_evenly(list))
-                };
-
-                if known_mentors.contains(&next_qr_read) {
-                    list_update(&mut mentor_list, mentor_string, &next_qr_read);
-                } else if known_students.contains(&next_qr_read) {
-                    list_update(&mut student_list, student_string, &next_qr_read);
-                } else if next_qr_read.starts_with("Guest") {
-                    list_update(&mut guest_list, guest_string, &next_qr_read);
-                } else {
-                    process_change.set(format!("REJECTED {next_qr_read}"));
-                    continue;
-                };
-
-                backing_db
-                    .write()
-                    .unwrap()
-                    .add_scan(next_qr_read.as_str(), time);
-            }
+            let observers = attendance::observers(&backing_db, &config, &SpawnView).await;
+            spawn(poll_cameras(observers.clone(), camera_state, video_url));
+            attendance::run(inputs, backing_db, config, observers, view).await;
         })
     });
 
@@ -234,100 +953,293 @@ fn app() -> Element {
             if !process_change.is_empty() {
                 tokio::time::sleep(Duration::from_mins(1)).await;
                 process_change.set("".to_string());
-                backing_db_process_change.read().unwrap().checkpoint();
+                backing_db_process_change.checkpoint();
             }
         }
     });
 
+    // Re-read as people come and go, and on leaving the admin panel.
+    let max_occupancy = use_resource(move || {
+        let backing_db = backing_db_occupancy.clone();
+        mentor_rows.read();
+        student_rows.read();
+        guest_rows.read();
+        pane.read();
+        async move {
+            DbErrorBanner(db_error)
+                .check(backing_db.try_run(|db| db.max_occupancy()).await)
+                .flatten()
+        }
+    });
+
     let mut resolution_select = use_signal(|| "Change Resolution");
+    let unsupervised_warning = unsupervised_since()
+        .map(|since| format!("No mentor present since {}", since.format("%H:%M")));
+    // Follows the lists, so every way in or out is counted.
+    let present_count =
+        mentor_rows.read().len() + student_rows.read().len() + guest_rows.read().len();
+    let over_capacity = max_occupancy().flatten().filter(|max| present_count > *max);
 
     rsx! {
         document::Stylesheet { href: MAIN_CSS }
+        // The webview draws its own pointer over the window's.
+        if kiosk_options.hide_cursor {
+            document::Style { "* {{ cursor: none !important; }}" }
+        }
 
         div {
-            class: "centered_horizontally",
+            class: if over_capacity.is_some() { "centered_horizontally over_capacity" } else { "centered_horizontally" },
             h1 { "Attendance Tracker" }
+            h3 { "Present: {present_count}" }
+            if let Some(max) = over_capacity {
+                h2 { "OVER CAPACITY: {present_count}/{max}" }
+            }
+            // Each opens its pane, or returns to attendance when open.
+            for (target, label) in [
+                (Pane::Admin, "Admin"),
+                (Pane::History, "History"),
+                (Pane::Audit, "Audit"),
+            ] {
+                button {
+                    onclick: move |_| {
+                        if pane() == target {
+                            pane.set(Pane::Attendance);
+                        } else if target.locked() {
+                            lock.guard(Callback::new(move |()| pane.set(target)));
+                        } else {
+                            pane.set(target);
+                        }
+                    },
+                    if pane() == target { "Attendance" } else { "{label}" }
+                }
+            }
             hr {}
             h3 { color: "blue", "{process_change}" }
+            if !undo_stack.read().is_empty() {
+                button {
+                    onclick: move |_| {
+                        let backing_db = backing_db_undo.clone();
+                        let refresh_tx = refresh_undo_tx.clone();
+                        lock.guard(Callback::new(move |()| {
+                            let Some(entry) = undo_stack.write().pop() else {
+                                return;
+                            };
+                            let backing_db = backing_db.clone();
+                            let refresh_tx = refresh_tx.clone();
+                            spawn(async move {
+                                // Reverts the lists as well, through the reload.
+                                match backing_db.remove_scan(&entry.name, entry.time).await {
+                                    Ok(()) => {
+                                        tracing::info!(target: "attendance", name = %entry.name, signed_in = entry.signed_in, "Undid scan");
+                                        process_change.set(if entry.signed_in {
+                                            format!("UNDID SIGN-IN {}", entry.name)
+                                        } else {
+                                            format!("UNDID SIGN-OUT {}", entry.name)
+                                        });
+                                    }
+                                    Err(e) => {
+                                        tracing::error!(target: "attendance", name = %entry.name, "Failed to undo scan: {e}");
+                                        process_change.set(format!("COULD NOT UNDO {}", entry.name));
+                                    }
+                                }
+                                let _ = refresh_tx.try_send(());
+                            });
+                        }));
+                    },
+                    "Undo"
+                }
+            }
+            if let Some(warning) = unsupervised_warning {
+                h3 { color: "orange", "{warning}" }
+            }
+            if let Some(error) = CAMERA_ERROR.get() {
+                h3 { color: "red", "{error}" }
+            }
+            if let Some(error) = db_error() {
+                h3 { color: "red", "{error}" }
+            }
+            if pending_scans() > 0 {
+                h3 { color: "yellow", "{pending_scans} scans pending save" }
+            }
+            if let Some(offset) = clock_drift() {
+                h3 {
+                    color: "red",
+                    "System clock is off by {offset.abs():.0} seconds, scan times are wrong until it is fixed"
+                }
+            }
+        }
+
+        EscalationMonitor { unsupervised_since }
+        SignOutPrompt {}
+        if lock.prompting() {
+            PinPrompt {}
+        }
+        ScanFeedback { latest: scan_feedback }
+
+        if let Some(latest) = update_available() {
+            div { class: "toast", "Update available: {latest}" }
         }
 
         div {
             class: "split left",
             div {
                 class: "centered",
-                img {
-                    src: VIDEO_SOCKET_HTTP,
-                    max_width: img_dims.0,
-                    max_height: img_dims.1,
-                    object_fit: "contain"
+                match camera_state() {
+                    CameraState::Searching => rsx! {
+                        h3 { class: "searching", "{NO_CAMERA}, still looking" }
+                    },
+                    CameraState::Failed(error) => rsx! {
+                        h3 { "{error}" }
+                    },
+                    CameraState::Ready(_) => rsx! {},
+                }
+                // Stays connected to the stream while hidden, so frames show
+                // again as soon as a camera is plugged back in.
+                if let Some(url) = video_url() {
+                    img {
+                        hidden: !matches!(camera_state(), CameraState::Ready(_)),
+                        src: "{url}",
+                        max_width: img_dims.0,
+                        max_height: img_dims.1,
+                        object_fit: "contain"
+                    }
+                    br {}
+                    small { "{url}" }
                 }
                 br {}
-                button {
-                    onclick: move |_| {
-                        resolution_select.set("Change Resolution");
-                        let resolution = camera_resolution_list
-                            .first()
-                            .copied();
-
-                        if let Some(resolution) = &resolution {
-                            backing_db_select_reset
-                                .write()
-                                .unwrap()
-                                .set_resolution(*resolution);
-                        }
+                // Filled in once the camera is up, and again when it is
+                // replaced. Hidden while locked.
+                if let CameraState::Ready(resolutions) = camera_state().clone()
+                    && lock.is_unlocked()
+                {
+                    button {
+                        onclick: move |_| {
+                            resolution_select.set("Change Resolution");
+                            let resolution = camera_state.read().resolutions().first().copied();
 
-                        let tx = camera_resolution_select_tx_reset.clone();
-                        async move { if let Some(resolution) = resolution {
-                            tx.send(resolution).await.unwrap();
-                        }}
-                    },
-                    "Minimize Resolution"
-                }
-                select {
-                    onchange: move |e| {
-                        let tx = camera_resolution_select_tx.clone();
-                        let selected = e.value();
-                        let selected = selected.trim();
-                        let resolution = camera_resolution_list
-                            .iter()
-                            .find(|entry| selected == format!("{entry}").trim())
-                            .copied();
-
-                        if let Some(resolution) = &resolution {
-                            backing_db_select
-                                .write()
-                                .unwrap()
-                                .set_resolution(*resolution);
+                            let backing_db = backing_db_select_reset.clone();
+                            let tx = camera_resolution_select_tx_reset.clone();
+                            async move { if let Some(resolution) = resolution {
+                                if let Err(e) = backing_db.set_resolution(resolution).await {
+                                    tracing::warn!("Failed to save the camera resolution: {e}");
+                                }
+                                tx.send(resolution).await.unwrap();
+                            }}
+                        },
+                        "Minimize Resolution"
+                    }
+                    select {
+                        onchange: move |e| {
+                            let tx = camera_resolution_select_tx.clone();
+                            let selected = e.value();
+                            let selected = selected.trim();
+                            let resolution = camera_state
+                                .read()
+                                .resolutions()
+                                .iter()
+                                .find(|entry| selected == format!("{entry}").trim())
+                                .copied();
+
+                            let backing_db = backing_db_select.clone();
+                            async move { if let Some(resolution) = resolution {
+                                if let Err(e) = backing_db.set_resolution(resolution).await {
+                                    tracing::warn!("Failed to save the camera resolution: {e}");
+                                }
+                                tx.send(resolution).await.unwrap()
+                            }}
+                        },
+                        value: "{resolution_select}",
+                        option { disabled: true, "Change Resolution" }
+                        for resolution in resolutions {
+                            option { "{resolution}" }
                         }
+                    }
+                }
+                if camera_list.len() > 1 && lock.is_unlocked() {
+                    select {
+                        onchange: move |e| {
+                            let tx = camera_select_tx.clone();
+                            let index = e.value().parse::<u32>().ok();
 
-                        async move { if let Some(resolution) = resolution {
-                            tx.send(resolution).await.unwrap()
-                        }}
-                    },
-                    value: "{resolution_select}",
-                    option { disabled: true, "Change Resolution" }
-                    for resolution in camera_resolution_list {
-                        option { "{resolution}" }
+                            let backing_db = backing_db_camera_select.clone();
+                            async move { if let Some(index) = index {
+                                DbErrorBanner(db_error).check(backing_db.try_run(move |db| db.set_camera_index(index)).await);
+                                tx.send(index).await.unwrap()
+                            }}
+                        },
+                        value: "",
+                        option { disabled: true, value: "", "Change Camera" }
+                        for (index, name) in camera_list.iter() {
+                            option { value: "{index}", "{name}" }
+                        }
                     }
                 }
+                PipelineDiagnostics {}
             }
         }
 
         div {
             class: "split right",
+            // Activity in an unlocked pane keeps it unlocked.
+            onclick: move |_| lock.touch(),
+            onkeydown: move |_| lock.touch(),
 
-            div {
-                class: "centered",
-                h3 { "Mentors" }
-                pre { "{mentor_string}" }
+            if pane() == Pane::Admin {
+                div {
+                    class: "centered",
+                    AdminPanel {}
+                }
+            } else if pane() == Pane::History {
+                div {
+                    class: "centered",
+                    History {}
+                }
+            } else if pane() == Pane::Audit {
+                div {
+                    class: "centered",
+                    AuditLog {}
+                }
+            } else {
+                div {
+                    class: "centered",
+                    input {
+                        r#type: "search",
+                        placeholder: "Filter by name",
+                        value: "{list_filter}",
+                        oninput: move |e| list_filter.set(e.value()),
+                    }
+                    PresentList {
+                        title: "Mentors",
+                        detailed: true,
+                        entries: mentor_rows,
+                        filter: list_filter,
+                        sort: list_sort,
+                    }
 
-                hr {}
-                h3 { "Students" }
-                hr {}
-                pre { "{student_string}" }
+                    hr {}
+                    PresentList {
+                        title: "Students",
+                        detailed: true,
+                        entries: student_rows,
+                        filter: list_filter,
+                        sort: list_sort,
+                    }
 
-                h3 { "Guests" }
-                pre { "{guest_string}" }
+                    hr {}
+                    PresentList {
+                        title: "Guests",
+                        detailed: false,
+                        entries: guest_rows,
+                        filter: list_filter,
+                        sort: list_sort,
+                    }
+
+                    hr {}
+                    ManualScan {}
+                    RegisterGuest {}
+                    ExportButton {}
+                    ReportButton {}
+                }
             }
         }
     }
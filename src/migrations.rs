@@ -0,0 +1,579 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Ordered schema changes, applied when a database is opened.
+//!
+//! The number of applied steps is kept in SQLite's `user_version`. Each step
+//! runs in its own transaction along with the version bump, so a failed step
+//! leaves the database at the version before it. Schema changes go at the
+//! end of [`MIGRATIONS`], never in between.
+//!
+//! Databases from before versioning are at version 0 with any number of the
+//! early steps already done by hand, so those steps check before changing
+//! anything.
+
+use std::{fmt::Display, fs};
+
+use rusqlite::{Connection, Transaction};
+use tracing::info;
+
+use crate::sqlite::{KIOSK_ID_KEY, LAST_SEQ_KEY, RESOLUTION_KEY, generate_kiosk_id};
+
+struct Migration {
+    name: &'static str,
+    apply: fn(&Transaction) -> rusqlite::Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        name: "create tables",
+        apply: create_tables,
+    },
+    Migration {
+        name: "record scan origins",
+        apply: record_scan_origins,
+    },
+    Migration {
+        name: "record scan sources",
+        apply: |transaction| add_column(transaction, "attendance", "source", "TEXT"),
+    },
+    Migration {
+        name: "record scan snapshots",
+        apply: |transaction| add_column(transaction, "attendance", "snapshot", "TEXT"),
+    },
+    Migration {
+        name: "index scan times",
+        apply: |transaction| {
+            transaction.execute_batch(
+                "CREATE INDEX IF NOT EXISTS attendance_timestamp ON attendance (timestamp);",
+            )
+        },
+    },
+    Migration {
+        name: "store resolution as a setting",
+        apply: |transaction| {
+            // The table stays for older versions' databases, see
+            // `BackingDatabase::get_resolution`.
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO settings (key, value)
+SELECT ?1, x || 'x' || y FROM resolution LIMIT 1;",
+                    [RESOLUTION_KEY],
+                )
+                .map(|_| ())
+        },
+    },
+    Migration {
+        name: "record scan utc offsets",
+        apply: |transaction| add_column(transaction, "attendance", "utc_offset", "INTEGER"),
+    },
+    Migration {
+        name: "keep inactive members",
+        apply: |transaction| {
+            for table in ["mentors", "students"] {
+                add_column(
+                    transaction,
+                    table,
+                    "active",
+                    "BOOLEAN NOT NULL DEFAULT TRUE",
+                )?;
+            }
+            Ok(())
+        },
+    },
+    Migration {
+        name: "count scan sequence numbers",
+        apply: |transaction| {
+            // Continues from this kiosk's highest scan, so numbers peers
+            // have already pulled are never handed out again.
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO settings (key, value)
+SELECT ?1, COALESCE(MAX(seq), 0) FROM attendance
+WHERE kiosk_id = (SELECT value FROM settings WHERE key = ?2);",
+                    [LAST_SEQ_KEY, KIOSK_ID_KEY],
+                )
+                .map(|_| ())
+        },
+    },
+    Migration {
+        name: "record removed scans",
+        apply: |transaction| {
+            transaction.execute_batch(
+                "CREATE TABLE IF NOT EXISTS removed_scans (
+    kiosk_id TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    PRIMARY KEY (kiosk_id, seq)
+) WITHOUT ROWID;
+
+CREATE INDEX IF NOT EXISTS removed_scans_scan ON removed_scans (name, timestamp);",
+            )
+        },
+    },
+];
+
+/// Schema version of this build.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    /// Written by a newer version, which this one cannot read safely.
+    TooNew {
+        version: u32,
+    },
+    /// Copying the database before migrating failed, nothing was changed.
+    Backup(rusqlite::Error),
+    /// A step failed and was rolled back, earlier steps stay applied.
+    Step {
+        version: u32,
+        name: &'static str,
+        error: rusqlite::Error,
+    },
+    Sqlite(rusqlite::Error),
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooNew { version } => write!(
+                f,
+                "database schema version {version} is newer than this build supports ({}), update qr-tracker",
+                latest_version()
+            ),
+            Self::Backup(e) => write!(f, "backup before migrating failed: {e}"),
+            Self::Step {
+                version,
+                name,
+                error,
+            } => write!(f, "migration {version} ({name}) failed: {error}"),
+            Self::Sqlite(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+fn schema_version(conn: &Connection) -> Result<u32, MigrationError> {
+    conn.query_row("PRAGMA user_version;", [], |row| row.get(0))
+        .map_err(MigrationError::Sqlite)
+}
+
+/// Migrates the database file at `path`, first copying it to
+/// `<path>.bak.<version>` when it has tables to migrate.
+pub fn migrate_file(path: &str) -> Result<(), MigrationError> {
+    let mut conn = Connection::open(path).map_err(MigrationError::Sqlite)?;
+    let version = schema_version(&conn)?;
+
+    let has_tables: bool = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table');",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(MigrationError::Sqlite)?;
+    if version < latest_version() && has_tables {
+        let backup = format!("{path}.bak.{version}");
+        // Left by an earlier failed attempt, at the same version.
+        let _ = fs::remove_file(&backup);
+        conn.execute("VACUUM INTO ?1;", [&backup])
+            .map_err(MigrationError::Backup)?;
+        info!(target: "db", version, "Backed up to {backup} before migrating");
+    }
+
+    apply(&mut conn)
+}
+
+/// Applies every step past the database's version.
+pub fn apply(conn: &mut Connection) -> Result<(), MigrationError> {
+    let current = schema_version(conn)?;
+    if current > latest_version() {
+        return Err(MigrationError::TooNew { version: current });
+    }
+
+    for (version, migration) in (1..).zip(MIGRATIONS).skip(current as usize) {
+        let step = |error| MigrationError::Step {
+            version,
+            name: migration.name,
+            error,
+        };
+
+        let transaction = conn.transaction().map_err(step)?;
+        (migration.apply)(&transaction).map_err(step)?;
+        transaction
+            .pragma_update(None, "user_version", version)
+            .map_err(step)?;
+        transaction.commit().map_err(step)?;
+        info!(target: "db", version, name = migration.name, "Applied migration");
+    }
+    Ok(())
+}
+
+/// Adds `column` to `table`, unless a database from before versioning
+/// already has it.
+fn add_column(
+    transaction: &Transaction,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    let exists: bool = transaction.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2);",
+        [table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        transaction.execute_batch(&format!(
+            "ALTER TABLE {table} ADD COLUMN {column} {definition};"
+        ))?;
+    }
+    Ok(())
+}
+
+fn create_tables(transaction: &Transaction) -> rusqlite::Result<()> {
+    transaction.execute_batch(
+        "CREATE TABLE IF NOT EXISTS attendance (
+    name TEXT NOT NULL,
+    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (name, timestamp)
+) WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS current (
+    name TEXT PRIMARY KEY NOT NULL,
+    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    present BOOLEAN NOT NULL
+) WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS mentors (
+    name TEXT PRIMARY KEY NOT NULL
+) WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS students (
+    name TEXT PRIMARY KEY NOT NULL
+) WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS resolution (
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    PRIMARY KEY (x, y)
+) WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS audit (
+    id INTEGER PRIMARY KEY,
+    at DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    action TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    detail TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS aliases (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL
+) WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS meetings (
+    start DATETIME NOT NULL,
+    end DATETIME NOT NULL,
+    summary TEXT NOT NULL,
+    PRIMARY KEY (start, end, summary)
+) WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS contacts (
+    name TEXT NOT NULL,
+    phone TEXT NOT NULL,
+    notify_in BOOLEAN NOT NULL,
+    notify_out BOOLEAN NOT NULL,
+    PRIMARY KEY (name, phone)
+) WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS payroll_ids (
+    name TEXT PRIMARY KEY NOT NULL,
+    employee_id TEXT NOT NULL
+) WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS guests (
+    id TEXT PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL,
+    affiliation TEXT,
+    registered DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL
+) WITHOUT ROWID;
+
+CREATE TABLE IF NOT EXISTS settings (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+) WITHOUT ROWID;",
+    )
+}
+
+/// Scans from before peer sync are attributed to this kiosk, numbered in
+/// time order.
+fn record_scan_origins(transaction: &Transaction) -> rusqlite::Result<()> {
+    let has_origin: bool = transaction.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_table_info('attendance') WHERE name = 'seq');",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_origin {
+        transaction.execute(
+            "INSERT OR IGNORE INTO settings (key, value) VALUES (?1, ?2);",
+            [KIOSK_ID_KEY, &generate_kiosk_id()],
+        )?;
+        let kiosk_id: String = transaction.query_row(
+            "SELECT value FROM settings WHERE key = ?1;",
+            [KIOSK_ID_KEY],
+            |row| row.get(0),
+        )?;
+
+        transaction.execute_batch(
+            "ALTER TABLE attendance ADD COLUMN kiosk_id TEXT;
+ALTER TABLE attendance ADD COLUMN seq INTEGER;",
+        )?;
+        transaction.execute(
+            "UPDATE attendance SET kiosk_id = ?1, seq = numbered.seq
+FROM (
+    SELECT name, timestamp, ROW_NUMBER() OVER (ORDER BY timestamp, name) AS seq
+    FROM attendance
+) AS numbered
+WHERE attendance.name = numbered.name AND attendance.timestamp = numbered.timestamp;",
+            [&kiosk_id],
+        )?;
+    }
+    transaction.execute_batch(
+        "CREATE INDEX IF NOT EXISTS attendance_origin ON attendance (kiosk_id, seq);",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::sqlite::BackingDatabase;
+
+    /// Schema and data of a kiosk from before versioning.
+    const BASELINE: &str = "CREATE TABLE attendance (
+    name TEXT NOT NULL,
+    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    PRIMARY KEY (name, timestamp)
+) WITHOUT ROWID;
+CREATE TABLE current (
+    name TEXT PRIMARY KEY NOT NULL,
+    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
+    present BOOLEAN NOT NULL
+) WITHOUT ROWID;
+CREATE TABLE mentors (name TEXT PRIMARY KEY NOT NULL) WITHOUT ROWID;
+CREATE TABLE students (name TEXT PRIMARY KEY NOT NULL) WITHOUT ROWID;
+CREATE TABLE resolution (
+    x INTEGER NOT NULL,
+    y INTEGER NOT NULL,
+    PRIMARY KEY (x, y)
+) WITHOUT ROWID;
+
+INSERT INTO mentors VALUES ('Ada');
+INSERT INTO students VALUES ('Bob');
+INSERT INTO resolution VALUES (1280, 720);
+-- Unix seconds, as the baseline wrote them: 2025-01-15 09:30 and 09:00 EST.
+INSERT INTO attendance VALUES
+    ('Bob', 1736951400),
+    ('Ada', 1736949600);
+INSERT INTO current VALUES ('Ada', 1736949600, TRUE);";
+
+    fn temp_db(name: &str) -> String {
+        let path = std::env::temp_dir()
+            .join(format!("{name}-{}.db", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        remove_db(&path);
+        path
+    }
+
+    fn remove_db(path: &str) {
+        let _ = fs::remove_file(path);
+        for version in [0, latest_version()] {
+            let _ = fs::remove_file(format!("{path}.bak.{version}"));
+        }
+    }
+
+    /// Every table's columns, as `table.column type`, in a stable order.
+    fn schema(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT m.name || '.' || c.name || ' ' || c.type
+FROM sqlite_master AS m, pragma_table_info(m.name) AS c
+WHERE m.type = 'table'
+ORDER BY m.name, c.name;",
+            )
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    fn indexes(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn
+            .prepare(
+                "SELECT name FROM sqlite_master
+WHERE type = 'index' AND sql IS NOT NULL ORDER BY name;",
+            )
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn baseline_migrated_with_data() {
+        let path = temp_db("migrate-baseline");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(BASELINE)
+            .unwrap();
+
+        migrate_file(&path).unwrap();
+        assert!(Path::new(&format!("{path}.bak.0")).exists());
+
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT name, timestamp, seq, kiosk_id IS NOT NULL FROM attendance ORDER BY seq;",
+            )
+            .unwrap();
+        let scans: Vec<(String, i64, i64, bool)> = stmt
+            .query_map([], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            scans,
+            [
+                ("Ada".to_string(), 1736949600, 1, true),
+                ("Bob".to_string(), 1736951400, 2, true)
+            ]
+        );
+
+        let active: (bool, bool) = conn
+            .query_row(
+                "SELECT (SELECT active FROM mentors WHERE name = 'Ada'),
+    (SELECT active FROM students WHERE name = 'Bob');",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(active, (true, true));
+
+        let resolution: String = conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = ?1;",
+                [RESOLUTION_KEY],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(resolution, "1280x720");
+        let last_seq: String = conn
+            .query_row(
+                "SELECT value FROM settings WHERE key = ?1;",
+                [LAST_SEQ_KEY],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(last_seq, "2");
+        let present: bool = conn
+            .query_row(
+                "SELECT present FROM current WHERE name = 'Ada';",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(present);
+        drop(stmt);
+        drop(conn);
+
+        let db = BackingDatabase::new(Some(&path)).unwrap();
+        assert_eq!(
+            db.get_present().unwrap(),
+            [(
+                "Ada".to_string(),
+                DateTime::from_timestamp_secs(1736949600).unwrap().into()
+            )]
+        );
+        drop(db);
+        remove_db(&path);
+    }
+
+    #[test]
+    fn baseline_ends_with_fresh_schema() {
+        let mut fresh = Connection::open_in_memory().unwrap();
+        apply(&mut fresh).unwrap();
+
+        let mut migrated = Connection::open_in_memory().unwrap();
+        migrated.execute_batch(BASELINE).unwrap();
+        apply(&mut migrated).unwrap();
+
+        assert_eq!(schema(&migrated), schema(&fresh));
+        assert_eq!(indexes(&migrated), indexes(&fresh));
+        assert!(schema(&fresh).contains(&"attendance.utc_offset INTEGER".to_string()));
+        assert_eq!(
+            indexes(&fresh),
+            [
+                "attendance_origin",
+                "attendance_timestamp",
+                "removed_scans_scan"
+            ]
+        );
+    }
+
+    #[test]
+    fn rerun_changes_nothing() {
+        let path = temp_db("migrate-rerun");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(BASELINE)
+            .unwrap();
+        migrate_file(&path).unwrap();
+
+        let snapshot = |conn: &Connection| {
+            let scans: Vec<(String, String, i64)> = conn
+                .prepare("SELECT name, kiosk_id, seq FROM attendance ORDER BY seq;")
+                .unwrap()
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            (schema(conn), indexes(conn), scans)
+        };
+        let before = snapshot(&Connection::open(&path).unwrap());
+
+        migrate_file(&path).unwrap();
+        let conn = Connection::open(&path).unwrap();
+        assert_eq!(snapshot(&conn), before);
+        assert_eq!(schema_version(&conn).unwrap(), latest_version());
+        // Nothing to migrate, so no backup at the current version.
+        assert!(!Path::new(&format!("{path}.bak.{}", latest_version())).exists());
+
+        drop(conn);
+        remove_db(&path);
+    }
+
+    #[test]
+    fn newer_schema_refused() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1)
+            .unwrap();
+        assert!(matches!(
+            apply(&mut conn),
+            Err(MigrationError::TooNew { version }) if version == latest_version() + 1
+        ));
+    }
+}
@@ -0,0 +1,282 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Outbound notifications to a user-configured webhook.
+//!
+//! Failures are only logged, a notification never blocks or breaks scanning.
+
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, Local};
+use serde_json::{Value, json};
+
+use crate::{
+    QrType,
+    db_worker::DbHandle,
+    observer::{ScanEvent, ScanObserver},
+};
+
+/// Setting holding the webhook URL. Notifications are skipped when unset.
+pub const WEBHOOK_URL_KEY: &str = "webhook.url";
+/// Setting selecting the payload format, see [`NotifyKind`].
+pub const KIND_KEY: &str = "notify.kind";
+
+/// Scan notifications within this window are sent as one message.
+const BATCH_WINDOW: Duration = Duration::from_secs(30);
+
+/// Payload format expected by the webhook receiver.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyKind {
+    /// Raw JSON events. Only escalations are sent.
+    #[default]
+    Generic,
+    /// Discord incoming webhook.
+    Discord,
+    /// Slack incoming webhook.
+    Slack,
+}
+
+impl FromStr for NotifyKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "generic" => Ok(Self::Generic),
+            "discord" => Ok(Self::Discord),
+            "slack" => Ok(Self::Slack),
+            _ => Err(()),
+        }
+    }
+}
+
+impl NotifyKind {
+    /// Wraps a human readable message, `None` for raw JSON receivers.
+    pub fn message(self, text: &str) -> Option<Value> {
+        match self {
+            Self::Generic => None,
+            Self::Discord => Some(json!({ "content": text })),
+            Self::Slack => Some(json!({ "text": text })),
+        }
+    }
+
+    /// Payload for students being present without a mentor since `since`.
+    pub fn no_mentor(self, since: DateTime<Local>) -> Value {
+        self.message(&format!(
+            "🚨 No mentor present since {}, students are unsupervised",
+            since.format("%H:%M")
+        ))
+        .unwrap_or_else(|| {
+            json!({
+                "event": "no_mentor",
+                "since": since.to_rfc3339(),
+            })
+        })
+    }
+}
+
+/// One line summarizing a scan, e.g.
+/// "✅ Alice checked in (Student) — 17 people present".
+pub fn scan_line(name: &str, qr_type: QrType, signed_in: bool, present: usize) -> String {
+    let (icon, action) = if signed_in {
+        ("✅", "checked in")
+    } else {
+        ("👋", "checked out")
+    };
+    let role = match qr_type {
+        QrType::Mentor => "Mentor",
+        QrType::Student => "Student",
+        QrType::Guest => "Guest",
+    };
+    let people = if present == 1 { "person" } else { "people" };
+
+    format!("{icon} {name} {action} ({role}) — {present} {people} present")
+}
+
+/// Sends lines from [`scan_line`] to Discord or Slack webhooks, one message
+/// per [`BATCH_WINDOW`] so a burst of scans does not flood the channel.
+pub async fn batch_scan_notifications(
+    backing_db: DbHandle,
+    lines_rx: async_channel::Receiver<String>,
+) {
+    while let Some(lines) = next_batch(&lines_rx, BATCH_WINDOW).await {
+        let (url, kind) = backing_db
+            .try_run(|db| {
+                Ok((
+                    db.get_setting::<String>(WEBHOOK_URL_KEY)?,
+                    db.get_setting::<NotifyKind>(KIND_KEY)?.unwrap_or_default(),
+                ))
+            })
+            .await
+            .unwrap_or_default();
+
+        if let Some(url) = url
+            && let Some(body) = kind.message(&lines.join("\n"))
+        {
+            tokio::spawn(async move { post_webhook(&url, &body).await });
+        }
+    }
+}
+
+/// Waits for a line, then collects those sent within `window` of it. `None`
+/// once the channel is closed and empty.
+async fn next_batch(
+    lines_rx: &async_channel::Receiver<String>,
+    window: Duration,
+) -> Option<Vec<String>> {
+    let first = lines_rx.recv().await.ok()?;
+    tokio::time::sleep(window).await;
+
+    let mut lines = vec![first];
+    while let Ok(line) = lines_rx.try_recv() {
+        lines.push(line);
+    }
+    Some(lines)
+}
+
+/// Queues a [`scan_line`] per scan for [`batch_scan_notifications`].
+pub struct ChatObserver {
+    lines_tx: async_channel::Sender<String>,
+}
+
+impl ChatObserver {
+    pub fn new(lines_tx: async_channel::Sender<String>) -> Self {
+        Self { lines_tx }
+    }
+}
+
+impl ScanObserver for ChatObserver {
+    fn name(&self) -> &str {
+        "chat"
+    }
+
+    fn on_scan(&self, event: &ScanEvent) {
+        let _ = self.lines_tx.try_send(scan_line(
+            &event.name,
+            event.role,
+            event.signed_in,
+            event.present,
+        ));
+    }
+}
+
+#[cfg(feature = "webhook")]
+pub async fn post_webhook(url: &str, body: &Value) {
+    match reqwest::Client::new().post(url).json(body).send().await {
+        Ok(response) if !response.status().is_success() => {
+            tracing::warn!(target: "notify", "Webhook rejected with status {}", response.status());
+        }
+        Ok(_) => (),
+        Err(e) => tracing::warn!(target: "notify", "Webhook failed: {e}"),
+    }
+}
+
+#[cfg(not(feature = "webhook"))]
+pub async fn post_webhook(_url: &str, _body: &Value) {
+    tracing::warn!(target: "notify", "Webhook configured, but built without the webhook feature");
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn scan_lines_read_naturally() {
+        assert_eq!(
+            scan_line("Alice", QrType::Student, true, 17),
+            "✅ Alice checked in (Student) — 17 people present"
+        );
+        assert_eq!(
+            scan_line("Bob", QrType::Mentor, false, 1),
+            "👋 Bob checked out (Mentor) — 1 person present"
+        );
+    }
+
+    #[test]
+    fn chat_payload_shapes() {
+        let text = "✅ Alice checked in (Student) — 1 person present";
+        assert_eq!(
+            NotifyKind::Discord.message(text),
+            Some(json!({ "content": text }))
+        );
+        assert_eq!(
+            NotifyKind::Slack.message(text),
+            Some(json!({ "text": text }))
+        );
+        assert_eq!(NotifyKind::Generic.message(text), None);
+    }
+
+    #[test]
+    fn escalation_payload_shapes() {
+        let since = Local.with_ymd_and_hms(2025, 1, 15, 18, 5, 0).unwrap();
+        let text = "🚨 No mentor present since 18:05, students are unsupervised";
+        assert_eq!(
+            NotifyKind::Discord.no_mentor(since),
+            json!({ "content": text })
+        );
+        assert_eq!(NotifyKind::Slack.no_mentor(since), json!({ "text": text }));
+        assert_eq!(
+            NotifyKind::Generic.no_mentor(since),
+            json!({ "event": "no_mentor", "since": since.to_rfc3339() })
+        );
+    }
+
+    #[test]
+    fn kind_parsed_ignoring_case() {
+        assert_eq!("Discord".parse(), Ok(NotifyKind::Discord));
+        assert_eq!("SLACK".parse(), Ok(NotifyKind::Slack));
+        assert_eq!("teams".parse::<NotifyKind>(), Err(()));
+    }
+
+    #[tokio::test]
+    async fn burst_sent_as_one_batch() {
+        let (lines_tx, lines_rx) = async_channel::unbounded();
+        lines_tx.send("first".to_string()).await.unwrap();
+
+        let batch = tokio::spawn(async move {
+            let batch = next_batch(&lines_rx, Duration::from_millis(100)).await;
+            (batch, lines_rx)
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        lines_tx.send("second".to_string()).await.unwrap();
+
+        let (batch, lines_rx) = batch.await.unwrap();
+        assert_eq!(batch, Some(vec!["first".to_string(), "second".to_string()]));
+
+        drop(lines_tx);
+        assert_eq!(next_batch(&lines_rx, Duration::ZERO).await, None);
+    }
+
+    #[test]
+    fn observer_never_blocks_scanning() {
+        let (lines_tx, lines_rx) = async_channel::bounded(1);
+        let observer = ChatObserver::new(lines_tx);
+        let event = ScanEvent {
+            name: "Alice".to_string(),
+            role: QrType::Student,
+            signed_in: true,
+            time: Local::now(),
+            present: 1,
+            source: None,
+        };
+
+        observer.on_scan(&event);
+        // Full, then closed: both dropped without waiting.
+        observer.on_scan(&event);
+        drop(lines_rx);
+        observer.on_scan(&event);
+    }
+
+    #[tokio::test]
+    async fn unreachable_webhook_only_logged() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        drop(listener);
+
+        post_webhook(&url, &json!({ "text": "hello" })).await;
+    }
+}
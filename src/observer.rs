@@ -0,0 +1,322 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Hooks for integrations that react to scans.
+//!
+//! Each observer runs on its own thread behind a bounded queue. A slow
+//! observer has events dropped once its queue fills, and a panicking one has
+//! that event dropped, neither delays scanning.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    panic::{AssertUnwindSafe, catch_unwind},
+    path::PathBuf,
+    sync::{
+        Arc,
+        mpsc::{Receiver, SyncSender, TrySendError, sync_channel},
+    },
+    thread,
+};
+
+use chrono::{DateTime, Local};
+use tracing::{error, warn};
+
+use crate::QrType;
+
+/// Setting holding a file each scan is appended to, see [`FileObserver`].
+pub const LOG_FILE_KEY: &str = "observer.log_file";
+
+/// Events queued per observer before new ones are dropped.
+const QUEUE_LEN: usize = 64;
+
+/// A scan that signed someone in or out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanEvent {
+    pub name: String,
+    pub role: QrType,
+    pub signed_in: bool,
+    pub time: DateTime<Local>,
+    /// People present after this scan.
+    pub present: usize,
+    /// Submitting device for API scans, `None` for the camera.
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CameraStatus {
+    Ready,
+    /// No camera can be used, e.g. access was denied.
+    Unavailable(String),
+}
+
+/// Receives scan and camera events, off the scanning task.
+pub trait ScanObserver: Send + Sync {
+    /// Identifies the observer in logs.
+    fn name(&self) -> &str;
+
+    fn on_scan(&self, event: &ScanEvent);
+
+    fn on_status(&self, _status: &CameraStatus) {}
+}
+
+enum Notification {
+    Scan(ScanEvent),
+    Status(CameraStatus),
+}
+
+#[derive(Clone)]
+struct Registered {
+    name: String,
+    queue: SyncSender<Notification>,
+}
+
+/// Observers the attendance loop notifies.
+#[derive(Clone, Default)]
+pub struct ObserverRegistry {
+    observers: Vec<Registered>,
+}
+
+impl ObserverRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts `observer` on its own thread.
+    pub fn register(&mut self, observer: impl ScanObserver + 'static) {
+        let observer: Arc<dyn ScanObserver> = Arc::new(observer);
+        let (queue, notifications) = sync_channel(QUEUE_LEN);
+        self.observers.push(Registered {
+            name: observer.name().to_string(),
+            queue,
+        });
+
+        thread::spawn(move || run_observer(observer, notifications));
+    }
+
+    pub fn scan(&self, event: &ScanEvent) {
+        self.notify(|| Notification::Scan(event.clone()));
+    }
+
+    pub fn status(&self, status: &CameraStatus) {
+        self.notify(|| Notification::Status(status.clone()));
+    }
+
+    fn notify(&self, notification: impl Fn() -> Notification) {
+        for observer in &self.observers {
+            match observer.queue.try_send(notification()) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    warn!(target: "observer", observer = observer.name, "Observer is behind, dropping event");
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    warn!(target: "observer", observer = observer.name, "Observer stopped, dropping event");
+                }
+            }
+        }
+    }
+}
+
+fn run_observer(observer: Arc<dyn ScanObserver>, notifications: Receiver<Notification>) {
+    for notification in notifications {
+        let handled = catch_unwind(AssertUnwindSafe(|| match &notification {
+            Notification::Scan(event) => observer.on_scan(event),
+            Notification::Status(status) => observer.on_status(status),
+        }));
+
+        if handled.is_err() {
+            error!(target: "observer", observer = observer.name(), "Observer panicked, event dropped");
+        }
+    }
+}
+
+/// Appends one line per scan to a file, e.g.
+/// `2025-01-06T18:02:11-05:00,Alice,student,in`.
+pub struct FileObserver {
+    path: PathBuf,
+}
+
+impl FileObserver {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl ScanObserver for FileObserver {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn on_scan(&self, event: &ScanEvent) {
+        let line = format!(
+            "{},{},{},{}\n",
+            event.time.to_rfc3339(),
+            event.name,
+            event.role,
+            if event.signed_in { "in" } else { "out" }
+        );
+
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()));
+        if let Err(e) = written {
+            warn!(target: "observer", "Failed to append to {}: {e}", self.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::mpsc::{self, Sender},
+        time::Duration,
+    };
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    fn event(name: &str) -> ScanEvent {
+        ScanEvent {
+            name: name.to_string(),
+            role: QrType::Student,
+            signed_in: true,
+            time: Local.timestamp_opt(1_700_000_000, 0).unwrap(),
+            present: 1,
+            source: None,
+        }
+    }
+
+    /// Forwards every event, to check what an observer was given.
+    struct Recorder(Sender<String>);
+
+    impl ScanObserver for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        fn on_scan(&self, event: &ScanEvent) {
+            let _ = self.0.send(event.name.clone());
+        }
+
+        fn on_status(&self, status: &CameraStatus) {
+            let _ = self.0.send(format!("{status:?}"));
+        }
+    }
+
+    /// Panics on every scan, but still reports camera status.
+    struct Panicker(Sender<String>);
+
+    impl ScanObserver for Panicker {
+        fn name(&self) -> &str {
+            "panicker"
+        }
+
+        fn on_scan(&self, event: &ScanEvent) {
+            panic!("observer failed on {}", event.name);
+        }
+
+        fn on_status(&self, status: &CameraStatus) {
+            let _ = self.0.send(format!("{status:?}"));
+        }
+    }
+
+    /// Blocks on each scan until `release` sends.
+    struct Stuck {
+        release: std::sync::Mutex<Receiver<()>>,
+        seen: Sender<String>,
+    }
+
+    impl ScanObserver for Stuck {
+        fn name(&self) -> &str {
+            "stuck"
+        }
+
+        fn on_scan(&self, event: &ScanEvent) {
+            let _ = self.release.lock().unwrap().recv();
+            let _ = self.seen.send(event.name.clone());
+        }
+    }
+
+    #[test]
+    fn panicking_observer_isolated() {
+        let (recorded_tx, recorded) = mpsc::channel();
+        let (survived_tx, survived) = mpsc::channel();
+        let mut registry = ObserverRegistry::new();
+        registry.register(Panicker(survived_tx));
+        registry.register(Recorder(recorded_tx));
+
+        for name in ["Alice", "Bob", "Carol"] {
+            registry.scan(&event(name));
+        }
+        registry.status(&CameraStatus::Ready);
+
+        let names: Vec<_> = (0..4)
+            .map(|_| recorded.recv_timeout(WAIT).unwrap())
+            .collect();
+        assert_eq!(names, ["Alice", "Bob", "Carol", "Ready"]);
+        // The panicking observer's thread kept handling events.
+        assert_eq!(survived.recv_timeout(WAIT).unwrap(), "Ready");
+    }
+
+    #[test]
+    fn slow_observer_drops_overflow() {
+        const SENT: usize = QUEUE_LEN + 16;
+
+        let (release_tx, release) = mpsc::channel();
+        let (seen_tx, seen) = mpsc::channel();
+        let (recorded_tx, recorded) = mpsc::channel();
+        let mut registry = ObserverRegistry::new();
+        registry.register(Stuck {
+            release: std::sync::Mutex::new(release),
+            seen: seen_tx,
+        });
+        registry.register(Recorder(recorded_tx));
+
+        // The other observer keeps getting every event while the stuck one
+        // never gets past its first.
+        for index in 0..SENT {
+            registry.scan(&event(&index.to_string()));
+            assert_eq!(recorded.recv_timeout(WAIT).unwrap(), index.to_string());
+        }
+
+        for _ in 0..SENT {
+            let _ = release_tx.send(());
+        }
+        drop(registry);
+        let delivered = seen.iter().count();
+        assert!(
+            (QUEUE_LEN..=QUEUE_LEN + 1).contains(&delivered),
+            "{delivered}"
+        );
+    }
+
+    #[test]
+    fn file_observer_appends_lines() {
+        let path =
+            std::env::temp_dir().join(format!("qr-tracker-observer-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let observer = FileObserver::new(path.clone());
+
+        observer.on_scan(&event("Alice"));
+        observer.on_scan(&ScanEvent {
+            signed_in: false,
+            ..event("Bob")
+        });
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let time = event("Alice").time.to_rfc3339();
+        assert_eq!(
+            written,
+            format!("{time},Alice,student,in\n{time},Bob,student,out\n")
+        );
+    }
+}
@@ -0,0 +1,371 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Badge QR payloads.
+//!
+//! Current badges carry compact versioned JSON,
+//! `{"v":1,"id":"s-042","n":"Alice","r":"student"}`, where `id` is stable
+//! across renames. Badges from other tools can use
+//! `GCQR:v1:<type>:<name>:<checksum>`, where the checksum is the first 8 hex
+//! digits of the SHA-256 of `<type>:<name>`. Older badges are the plain name,
+//! and are still accepted.
+//!
+//! The roster decides whether a name is a mentor or student, so promoted
+//! students keep their badges. Inactive members' badges are refused. Names
+//! starting with `Guest` are guests.
+
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    QrType,
+    sqlite::{BackingDatabase, DbError},
+};
+
+const CURRENT_VERSION: u64 = 1;
+const CHECKED_PREFIX: &str = "GCQR:v1:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload {
+    V1 {
+        id: String,
+        name: String,
+        role: Option<QrType>,
+    },
+    /// `GCQR:v1` badge with a verified checksum.
+    Checked { role: QrType, name: String },
+    /// Plain name badge.
+    Legacy(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayloadError {
+    /// Made for a newer version of this program.
+    UnsupportedVersion(u64),
+    /// Truncated or junk decode.
+    Malformed,
+    /// A `GCQR:v1` badge whose checksum does not match.
+    BadChecksum,
+}
+
+impl Display for PayloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => {
+                write!(f, "badge format v{version} needs a newer qr-tracker")
+            }
+            Self::Malformed => write!(f, "unreadable badge"),
+            Self::BadChecksum => write!(f, "badge checksum mismatch"),
+        }
+    }
+}
+
+/// Why a scan was not recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QrRejection {
+    Malformed(PayloadError),
+    /// Readable, but not on any roster and not a guest.
+    UnknownPerson(String),
+    /// On a roster but deactivated, e.g. graduated.
+    Inactive(String),
+}
+
+impl Display for QrRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(e) => write!(f, "{e}"),
+            Self::UnknownPerson(name) => write!(f, "{name} is not on the roster"),
+            Self::Inactive(name) => write!(f, "{name} is inactive"),
+        }
+    }
+}
+
+/// First 8 hex digits of the SHA-256 of `<type>:<name>`.
+fn checksum(role: QrType, name: &str) -> String {
+    Sha256::digest(format!("{role}:{name}").as_bytes())[..4]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Parses a decoded QR code and classifies the person it names.
+///
+/// Badge ids are resolved to their recorded names, see
+/// [`BackingDatabase::resolve_alias`]. The outer error is the lookup failing,
+/// not the badge.
+pub fn parse_qr(
+    payload: &str,
+    db: &mut BackingDatabase,
+) -> Result<Result<(QrType, String), QrRejection>, DbError> {
+    let name = match Payload::parse(payload) {
+        Ok(Payload::V1 { id, name, .. }) => db.resolve_alias(&id, &name)?,
+        Ok(Payload::Checked { name, .. } | Payload::Legacy(name)) => name,
+        Err(e) => return Ok(Err(QrRejection::Malformed(e))),
+    };
+
+    Ok(match db.roster_entry(&name)? {
+        Some((role, true)) => Ok((role, name)),
+        Some((_, false)) => Err(QrRejection::Inactive(name)),
+        None if name.starts_with("Guest") => Ok((QrType::Guest, name)),
+        None => Err(QrRejection::UnknownPerson(name)),
+    })
+}
+
+#[derive(Serialize, Deserialize)]
+struct PayloadV1 {
+    v: u64,
+    id: String,
+    n: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    r: Option<String>,
+}
+
+impl Payload {
+    /// Parses a decoded QR code. Never panics on arbitrary input.
+    pub fn parse(text: &str) -> Result<Self, PayloadError> {
+        let text = text.trim();
+        if let Some(checked) = text.strip_prefix(CHECKED_PREFIX) {
+            return Self::parse_checked(checked);
+        }
+        if !text.starts_with('{') {
+            return Ok(Self::Legacy(text.to_string()));
+        }
+
+        let value: Value = serde_json::from_str(text).map_err(|_| PayloadError::Malformed)?;
+        match value.get("v").and_then(Value::as_u64) {
+            Some(CURRENT_VERSION) => (),
+            Some(version) => return Err(PayloadError::UnsupportedVersion(version)),
+            None => return Err(PayloadError::Malformed),
+        }
+
+        let PayloadV1 { id, n, r, .. } =
+            serde_json::from_value(value).map_err(|_| PayloadError::Malformed)?;
+        let (id, name) = (id.trim(), n.trim());
+        if id.is_empty() || name.is_empty() {
+            return Err(PayloadError::Malformed);
+        }
+
+        Ok(Self::V1 {
+            id: id.to_string(),
+            name: name.to_string(),
+            role: r.and_then(|role| role.parse().ok()),
+        })
+    }
+
+    /// `<type>:<name>:<checksum>`, names may contain colons.
+    fn parse_checked(text: &str) -> Result<Self, PayloadError> {
+        let (role, rest) = text.split_once(':').ok_or(PayloadError::Malformed)?;
+        let (name, sum) = rest.rsplit_once(':').ok_or(PayloadError::Malformed)?;
+        let role: QrType = role.parse().map_err(|()| PayloadError::Malformed)?;
+        if name.trim().is_empty() {
+            return Err(PayloadError::Malformed);
+        }
+        if !sum.eq_ignore_ascii_case(&checksum(role, name)) {
+            return Err(PayloadError::BadChecksum);
+        }
+
+        Ok(Self::Checked {
+            role,
+            name: name.trim().to_string(),
+        })
+    }
+
+    /// Compact text to encode in a badge's QR code.
+    pub fn encode(&self) -> String {
+        match self {
+            Self::V1 { id, name, role } => serde_json::to_string(&PayloadV1 {
+                v: CURRENT_VERSION,
+                id: id.clone(),
+                n: name.clone(),
+                r: role.map(|role| role.to_string()),
+            })
+            .unwrap(),
+            Self::Checked { role, name } => {
+                format!("{CHECKED_PREFIX}{role}:{name}:{}", checksum(*role, name))
+            }
+            Self::Legacy(name) => name.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn badges() -> [Payload; 3] {
+        [
+            Payload::V1 {
+                id: "s-042".to_string(),
+                name: "Zoë Ng".to_string(),
+                role: Some(QrType::Student),
+            },
+            Payload::Checked {
+                role: QrType::Mentor,
+                name: "Ada: the Countess".to_string(),
+            },
+            Payload::Legacy("Ada".to_string()),
+        ]
+    }
+
+    #[test]
+    fn encoded_badges_parse_back() {
+        for badge in badges() {
+            assert_eq!(Payload::parse(&badge.encode()), Ok(badge));
+        }
+    }
+
+    #[test]
+    fn truncated_badges_rejected() {
+        for badge in badges().into_iter().take(2) {
+            let text = badge.encode();
+            for (end, _) in text.char_indices().skip(1) {
+                let parsed = Payload::parse(&text[..end]);
+                assert!(
+                    matches!(parsed, Err(_) | Ok(Payload::Legacy(_))),
+                    "{:?} parsed as {parsed:?}",
+                    &text[..end]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_json_rejected() {
+        for text in [
+            "{",
+            "{}",
+            r#"{"v":"1","id":"s-1","n":"Ada"}"#,
+            r#"{"v":1,"id":"s-1"}"#,
+            r#"{"v":1,"id":" ","n":"Ada"}"#,
+            r#"{"v":1,"id":"s-1","n":7}"#,
+        ] {
+            assert_eq!(Payload::parse(text), Err(PayloadError::Malformed), "{text}");
+        }
+        assert_eq!(
+            Payload::parse(r#"{"v":2,"id":"s-1","n":"Ada"}"#),
+            Err(PayloadError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn checked_badges_verified() {
+        let name = "Ada: the Countess";
+        let sum = checksum(QrType::Mentor, name);
+        assert_eq!(
+            Payload::parse(&format!("GCQR:v1:mentor:{name}:{}", sum.to_uppercase())),
+            Ok(Payload::Checked {
+                role: QrType::Mentor,
+                name: name.to_string(),
+            })
+        );
+        // Same name and checksum, but claiming another role.
+        assert_eq!(
+            Payload::parse(&format!("GCQR:v1:student:{name}:{sum}")),
+            Err(PayloadError::BadChecksum)
+        );
+        assert_eq!(
+            Payload::parse("GCQR:v1:mentor:Ada:00000000"),
+            Err(PayloadError::BadChecksum)
+        );
+        for text in ["GCQR:v1:", "GCQR:v1:mentor", "GCQR:v1:wizard:Ada:00000000"] {
+            assert_eq!(Payload::parse(text), Err(PayloadError::Malformed), "{text}");
+        }
+    }
+
+    #[test]
+    fn non_utf8_decode_does_not_panic() {
+        let badge = Payload::V1 {
+            id: "s-042".to_string(),
+            name: "Zoë".to_string(),
+            role: None,
+        }
+        .encode();
+        // Decoders hand over lossy text when a code holds invalid UTF-8.
+        for bytes in [
+            b"\xff\xfe\xfd".as_slice(),
+            b"GCQR:v1:mentor:\xc3:00000000",
+            &badge.as_bytes()[..badge.len() - 6],
+        ] {
+            let text = String::from_utf8_lossy(bytes);
+            assert!(!matches!(
+                Payload::parse(&text),
+                Ok(Payload::V1 { .. } | Payload::Checked { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn rejections_name_the_cause() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.add_mentor("Ada").unwrap();
+        db.add_student("Bob").unwrap();
+        db.set_member_active("Bob", false).unwrap();
+
+        assert_eq!(
+            parse_qr("Ada", &mut db).unwrap(),
+            Ok((QrType::Mentor, "Ada".to_string()))
+        );
+        assert_eq!(
+            parse_qr("Guest 3", &mut db).unwrap(),
+            Ok((QrType::Guest, "Guest 3".to_string()))
+        );
+        assert_eq!(
+            parse_qr("Bob", &mut db).unwrap(),
+            Err(QrRejection::Inactive("Bob".to_string()))
+        );
+        assert_eq!(
+            parse_qr("Eve", &mut db).unwrap(),
+            Err(QrRejection::UnknownPerson("Eve".to_string()))
+        );
+        assert_eq!(
+            parse_qr("{\"v\":1", &mut db).unwrap(),
+            Err(QrRejection::Malformed(PayloadError::Malformed))
+        );
+    }
+
+    #[test]
+    fn badge_ids_keep_their_first_name() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.add_student("Alice").unwrap();
+        let badge = |name: &str| {
+            Payload::V1 {
+                id: "s-042".to_string(),
+                name: name.to_string(),
+                role: Some(QrType::Student),
+            }
+            .encode()
+        };
+
+        assert_eq!(
+            parse_qr(&badge("Alice"), &mut db).unwrap(),
+            Ok((QrType::Student, "Alice".to_string()))
+        );
+        // A reprinted badge with another spelling still scans as Alice.
+        assert_eq!(
+            parse_qr(&badge("Alicia"), &mut db).unwrap(),
+            Ok((QrType::Student, "Alice".to_string()))
+        );
+    }
+
+    #[test]
+    fn future_versions_refused_politely() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        let rejection = parse_qr(r#"{"v":3,"id":"s-042","n":"Alice"}"#, &mut db)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(
+            rejection,
+            QrRejection::Malformed(PayloadError::UnsupportedVersion(3))
+        );
+        assert_eq!(
+            rejection.to_string(),
+            "badge format v3 needs a newer qr-tracker"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Time clock export for paid staff.
+//!
+//! One row per session, for people with an employee id only. Sessions pair
+//! scans the same way as [`BackingDatabase::hours_summary`]. Sessions without
+//! a sign-out scan, or longer than the session cap, end automatically at the
+//! cap and are marked for review.
+
+use std::{io::Write, str::FromStr};
+
+use chrono::{DateTime, Local, TimeZone};
+
+use crate::sqlite::{BackingDatabase, DbError, ExportError};
+
+/// Setting holding the minutes clock times are rounded to.
+pub const ROUNDING_MINUTES_KEY: &str = "payroll.rounding_minutes";
+/// Setting holding the rounding direction, see [`Rounding`].
+pub const ROUNDING_KEY: &str = "payroll.rounding";
+
+pub const DEFAULT_ROUNDING_MINUTES: i64 = 15;
+
+/// Column layout of the export, as HR's import expects.
+const HEADER: [&str; 6] = [
+    "Employee ID",
+    "Date",
+    "Clock In",
+    "Clock Out",
+    "Hours",
+    "Auto Checkout",
+];
+
+/// Direction clock times are rounded, applied to both clock-in and
+/// clock-out.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Rounding {
+    /// To the closest increment, halfway rounds up.
+    #[default]
+    Nearest,
+    Up,
+    Down,
+}
+
+impl FromStr for Rounding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Self::Nearest),
+            "up" => Ok(Self::Up),
+            "down" => Ok(Self::Down),
+            _ => Err(()),
+        }
+    }
+}
+
+/// One session.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PayrollRow {
+    pub employee_id: String,
+    /// Rounded.
+    pub clock_in: DateTime<Local>,
+    /// Rounded.
+    pub clock_out: DateTime<Local>,
+    /// Between the rounded clock times.
+    pub hours: f64,
+    /// The session had no sign-out, or ran past the session cap.
+    pub auto_checkout: bool,
+}
+
+/// Rounds `time` to a multiple of `minutes` past local midnight.
+pub fn round_time(time: DateTime<Local>, minutes: i64, rounding: Rounding) -> DateTime<Local> {
+    let step = minutes.max(1) * 60;
+    let secs = time.naive_local().and_utc().timestamp();
+    let rounded = match rounding {
+        Rounding::Nearest => (secs + step / 2).div_euclid(step) * step,
+        Rounding::Up => (secs + step - 1).div_euclid(step) * step,
+        Rounding::Down => secs.div_euclid(step) * step,
+    };
+
+    DateTime::from_timestamp(rounded, 0)
+        .and_then(|rounded| Local.from_local_datetime(&rounded.naive_utc()).earliest())
+        .unwrap_or(time)
+}
+
+/// Every session starting between `since` and `until` for people with an
+/// employee id, sorted by employee id then clock-in.
+pub fn payroll_report(
+    db: &BackingDatabase,
+    (since, until): (DateTime<Local>, DateTime<Local>),
+    minutes: i64,
+    rounding: Rounding,
+) -> Result<Vec<PayrollRow>, DbError> {
+    let max_session = db.max_session()?;
+    let now = Local::now();
+
+    let mut rows = Vec::new();
+    for (name, employee_id) in db.payroll_ids()? {
+        for session in db.all_scans(&name)?.chunks(2) {
+            let start = session[0];
+            if !(since..until).contains(&start) {
+                continue;
+            }
+
+            let (end, auto_checkout) = match session.get(1) {
+                Some(end) if *end - start <= max_session => (*end, false),
+                Some(_) => (start + max_session, true),
+                None => (start + (now - start).min(max_session), true),
+            };
+
+            let clock_in = round_time(start, minutes, rounding);
+            let clock_out = round_time(end, minutes, rounding).max(clock_in);
+            rows.push(PayrollRow {
+                employee_id: employee_id.clone(),
+                clock_in,
+                clock_out,
+                hours: (clock_out - clock_in).num_minutes() as f64 / 60.0,
+                auto_checkout,
+            });
+        }
+    }
+
+    rows.sort_by(|lhs, rhs| {
+        (&lhs.employee_id, lhs.clock_in).cmp(&(&rhs.employee_id, rhs.clock_in))
+    });
+    Ok(rows)
+}
+
+/// Writes `rows` as CSV, returning how many were written.
+pub fn write_csv(rows: &[PayrollRow], writer: impl Write) -> Result<usize, ExportError> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(HEADER).map_err(ExportError::Csv)?;
+
+    for row in rows {
+        writer
+            .write_record([
+                row.employee_id.clone(),
+                row.clock_in.format("%Y-%m-%d").to_string(),
+                row.clock_in.format("%H:%M").to_string(),
+                // Sessions past midnight show the day they ended.
+                if row.clock_out.date_naive() == row.clock_in.date_naive() {
+                    row.clock_out.format("%H:%M").to_string()
+                } else {
+                    row.clock_out.format("%Y-%m-%d %H:%M").to_string()
+                },
+                format!("{:.2}", row.hours),
+                if row.auto_checkout { "yes" } else { "no" }.to_string(),
+            ])
+            .map_err(ExportError::Csv)?;
+    }
+
+    writer.flush().map_err(|e| ExportError::Csv(e.into()))?;
+    Ok(rows.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, NaiveTime, TimeDelta};
+
+    use super::*;
+    use crate::sqlite::{MAX_SESSION_HOURS_KEY, local_datetime};
+
+    fn at(time: &str) -> DateTime<Local> {
+        local_datetime(
+            NaiveDate::from_ymd_opt(2025, 1, 15).unwrap(),
+            NaiveTime::parse_from_str(time, "%H:%M:%S").unwrap(),
+        )
+    }
+
+    fn day() -> (DateTime<Local>, DateTime<Local>) {
+        (at("00:00:00"), at("23:59:59"))
+    }
+
+    #[test]
+    fn halfway_rounds_up() {
+        let round = |time, rounding| round_time(at(time), 15, rounding);
+        assert_eq!(round("09:07:30", Rounding::Nearest), at("09:15:00"));
+        assert_eq!(round("09:07:29", Rounding::Nearest), at("09:00:00"));
+        assert_eq!(round("09:00:01", Rounding::Up), at("09:15:00"));
+        assert_eq!(round("09:00:00", Rounding::Up), at("09:00:00"));
+        assert_eq!(round("09:14:59", Rounding::Down), at("09:00:00"));
+    }
+
+    #[test]
+    fn hours_written_to_the_cent() {
+        let row = |minutes| PayrollRow {
+            employee_id: "E1".to_string(),
+            clock_in: at("09:00:00"),
+            clock_out: at("09:00:00") + TimeDelta::minutes(minutes),
+            hours: minutes as f64 / 60.0,
+            auto_checkout: false,
+        };
+        let mut out = Vec::new();
+        write_csv(&[row(20), row(40), row(50)], &mut out).unwrap();
+
+        let hours: Vec<_> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').nth(4).unwrap().to_string())
+            .collect();
+        assert_eq!(hours, ["0.33", "0.67", "0.83"]);
+    }
+
+    #[test]
+    fn capped_session_marked_for_review() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.set_setting(MAX_SESSION_HOURS_KEY, 6).unwrap();
+        db.set_payroll_id("Ada", "E1").unwrap();
+        db.add_scan("Ada", at("08:00:00"), None).unwrap();
+        db.add_scan("Ada", at("18:00:00"), None).unwrap();
+
+        let rows = payroll_report(&db, day(), 15, Rounding::Nearest).unwrap();
+        assert_eq!(
+            rows,
+            [PayrollRow {
+                employee_id: "E1".to_string(),
+                clock_in: at("08:00:00"),
+                clock_out: at("14:00:00"),
+                hours: 6.0,
+                auto_checkout: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn short_session_rounds_to_zero_hours() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.set_payroll_id("Ada", "E1").unwrap();
+        db.add_scan("Ada", at("09:01:00"), None).unwrap();
+        db.add_scan("Ada", at("09:05:00"), None).unwrap();
+        // Unmapped people are left out.
+        db.add_scan("Bob", at("09:00:00"), None).unwrap();
+
+        let rows = payroll_report(&db, day(), 15, Rounding::Nearest).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].clock_in, at("09:00:00"));
+        assert_eq!(rows[0].clock_out, at("09:00:00"));
+        assert_eq!(rows[0].hours, 0.0);
+        assert!(!rows[0].auto_checkout);
+    }
+}
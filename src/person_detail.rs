@@ -0,0 +1,235 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! One person's recent scans, opened by clicking their name in a present
+//! list.
+
+use chrono::{DateTime, Days, Local, NaiveTime, TimeDelta};
+use dioxus::prelude::*;
+
+use crate::{
+    AttendanceContext,
+    greeting::format_duration,
+    report,
+    sqlite::{ScanEditError, local_datetime},
+};
+
+/// Days shown in the timeline, including today.
+const TIMELINE_DAYS: u64 = 14;
+/// Source recorded for sign-outs forced from the detail panel.
+const FORCED_SOURCE: &str = "forced";
+
+/// Sessions for one local day, newest day first.
+///
+/// Scans within a day pair into sessions the same way as
+/// [`BackingDatabase::time_on`](crate::sqlite::BackingDatabase::time_on).
+fn timeline(scans: &[DateTime<Local>], now: DateTime<Local>) -> Vec<(String, Vec<String>)> {
+    let mut days: Vec<(String, Vec<String>)> = Vec::new();
+    for day in scans.chunk_by(|lhs, rhs| lhs.date_naive() == rhs.date_naive()) {
+        let label = day[0].format("%a %m-%d").to_string();
+        let sessions = day
+            .chunks(2)
+            .map(|session| {
+                let sign_in = session[0].format("%H:%M");
+                match session.get(1) {
+                    Some(sign_out) => format!(
+                        "in at {sign_in}, out at {}, {}",
+                        sign_out.format("%H:%M"),
+                        format_duration(*sign_out - session[0])
+                    ),
+                    None if session[0].date_naive() == now.date_naive() => format!(
+                        "in at {sign_in}, still here, {}",
+                        format_duration(now - session[0])
+                    ),
+                    None => format!("in at {sign_in}, no sign-out"),
+                }
+            })
+            .collect();
+        days.push((label, sessions));
+    }
+    days.reverse();
+    days
+}
+
+/// `name`'s scans for the past two weeks, season hours, and fixes for a
+/// missed or mistimed scan.
+///
+/// Re-read whenever `entries` changes, so a scan by `name` while open shows
+/// up right away.
+#[component]
+pub fn PersonDetail(
+    name: String,
+    entries: ReadSignal<Vec<(String, DateTime<Local>)>>,
+    close: EventHandler,
+) -> Element {
+    let AttendanceContext {
+        backing_db,
+        refresh_tx,
+        mut process_change,
+        db_error,
+        ..
+    } = use_context();
+    let backing_db_edit = backing_db.clone();
+    let backing_db_sign_out = backing_db.clone();
+    let refresh_edit_tx = refresh_tx.clone();
+
+    // Bumped to re-read scans after an edit.
+    let mut revision = use_signal(|| 0_u64);
+
+    let present = entries.read().iter().any(|(entry, _)| *entry == name);
+    // Keyed by `name` in the list, so a new name remounts rather than
+    // re-running this.
+    let loaded = use_resource({
+        let name = name.clone();
+        move || {
+            let backing_db = backing_db.clone();
+            let name = name.clone();
+            entries.read();
+            revision.read();
+            let now = Local::now();
+            async move {
+                let loaded = backing_db
+                    .try_run(move |db| {
+                        let since = now
+                            .date_naive()
+                            .checked_sub_days(Days::new(TIMELINE_DAYS - 1))
+                            .unwrap_or(now.date_naive());
+                        let since = local_datetime(since, NaiveTime::MIN);
+                        let season_since =
+                            local_datetime(report::season_start(db)?, NaiveTime::MIN);
+                        let season_hours = db
+                            .hours_summary(season_since, None)?
+                            .into_iter()
+                            .find(|(person, _)| *person == name)
+                            .map_or(TimeDelta::zero(), |(_, hours)| hours);
+                        // A second of slack so a scan recorded this instant is included.
+                        let until = now + TimeDelta::seconds(1);
+                        Ok((db.scans_for(&name, (since, until))?, season_hours))
+                    })
+                    .await;
+                (now, db_error.check(loaded))
+            }
+        }
+    });
+    let edit_last = use_callback({
+        let name = name.clone();
+        move |(old, new): (DateTime<Local>, DateTime<Local>)| {
+            let name = name.clone();
+            let backing_db = backing_db_edit.clone();
+            let refresh_tx = refresh_edit_tx.clone();
+            spawn(async move {
+                let result = backing_db
+                    .run({
+                        let name = name.clone();
+                        move |db| db.update_scan_timestamp(&name, old, new)
+                    })
+                    .await;
+
+                match result {
+                    Ok(Ok(())) => {
+                        process_change.set(format!(
+                            "EDITED {name} {} -> {}",
+                            old.format("%H:%M:%S"),
+                            new.format("%H:%M:%S")
+                        ));
+                        let _ = refresh_tx.try_send(());
+                    }
+                    Ok(Err(ScanEditError::Db(e))) | Err(e) => {
+                        process_change.set(format!("EDIT FAILED {name}"));
+                        db_error.check::<()>(Err(e));
+                    }
+                    Ok(Err(e)) => process_change.set(format!("EDIT REJECTED {name}: {e}")),
+                }
+                *revision.write() += 1;
+            });
+        }
+    });
+    let force_sign_out = use_callback({
+        let name = name.clone();
+        move |()| {
+            let name = name.clone();
+            let backing_db = backing_db_sign_out.clone();
+            let refresh_tx = refresh_tx.clone();
+            spawn(async move {
+                // Audited for its source, see `BackingDatabase::add_scan`.
+                match backing_db
+                    .add_scan(&name, Local::now(), Some(FORCED_SOURCE))
+                    .await
+                {
+                    Ok(()) => {
+                        process_change.set(format!("FORCED SIGN OUT {name}"));
+                        let _ = refresh_tx.try_send(());
+                    }
+                    Err(e) => process_change.set(format!("SIGN OUT FAILED {name}: {e}")),
+                }
+            });
+        }
+    });
+
+    let Some((now, loaded)) = loaded() else {
+        return rsx! {};
+    };
+    let Some((scans, season_hours)) = loaded else {
+        return rsx! {
+            div {
+                class: "person_detail",
+                h4 {
+                    "{name} "
+                    button { onclick: move |_| close(()), "Close" }
+                }
+                p { "Could not read {name}'s scans." }
+            }
+        };
+    };
+    let days = timeline(&scans, now);
+    let last_scan = scans
+        .last()
+        .map(|scan| (*scan, scan.format("%H:%M:%S").to_string()));
+
+    rsx! {
+        div {
+            class: "person_detail",
+            h4 {
+                "{name} "
+                button { onclick: move |_| close(()), "Close" }
+            }
+            p { "Season: {format_duration(season_hours)}" }
+            if present {
+                button { onclick: move |_| force_sign_out(()), "Force Sign Out" }
+            }
+            if let Some((last_scan, last_time)) = last_scan {
+                label {
+                    "Last scan: "
+                    input {
+                        r#type: "time",
+                        step: "1",
+                        value: "{last_time}",
+                        onchange: move |e| {
+                            let new_time = NaiveTime::parse_from_str(&e.value(), "%H:%M:%S")
+                                .or_else(|_| NaiveTime::parse_from_str(&e.value(), "%H:%M"));
+                            if let Ok(new_time) = new_time {
+                                let new_scan = local_datetime(last_scan.date_naive(), new_time);
+                                edit_last((last_scan, new_scan));
+                            }
+                        },
+                    }
+                }
+            }
+            if days.is_empty() {
+                p { "No scans in the past two weeks." }
+            }
+            for (day, sessions) in days {
+                div {
+                    key: "{day}",
+                    b { "{day}" }
+                    for session in sessions {
+                        div { "{session}" }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Window and camera setup that differs between platforms.
+
+use dioxus::desktop::{
+    WindowBuilder,
+    tao::{
+        monitor::MonitorHandle,
+        window::{Fullscreen, Window},
+    },
+};
+use tracing::{info, warn};
+
+/// Setting for whether the kiosk opens fullscreen, `true` when unset.
+pub const FULLSCREEN_KEY: &str = "kiosk.fullscreen";
+
+/// Kiosk window behavior from flags and the config file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KioskOptions {
+    /// Monitor index (from 0) or name, the primary monitor when unset.
+    pub monitor: Option<String>,
+    pub always_on_top: bool,
+    pub hide_cursor: bool,
+    /// A normal resizable window instead of borderless fullscreen, from
+    /// `--windowed` or [`FULLSCREEN_KEY`].
+    pub windowed: bool,
+}
+
+/// Borderless fullscreen and out of the taskbar, or a normal window when
+/// `options.windowed`.
+pub fn window(options: &KioskOptions) -> WindowBuilder {
+    let builder = WindowBuilder::new().with_always_on_top(options.always_on_top);
+    if options.windowed {
+        builder.with_resizable(true)
+    } else {
+        kiosk_window(builder.with_fullscreen(Some(Fullscreen::Borderless(None))))
+    }
+}
+
+/// Keeps the kiosk window out of the taskbar.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn kiosk_window(builder: WindowBuilder) -> WindowBuilder {
+    use dioxus::desktop::tao::platform::unix::WindowBuilderExtUnix;
+
+    builder.with_skip_taskbar(true)
+}
+
+/// Keeps the kiosk window out of the taskbar.
+#[cfg(target_os = "windows")]
+fn kiosk_window(builder: WindowBuilder) -> WindowBuilder {
+    use dioxus::desktop::tao::platform::windows::WindowBuilderExtWindows;
+
+    builder.with_skip_taskbar(true)
+}
+
+/// Hides the title bar, borderless fullscreen then covers the whole display
+/// as its own space.
+#[cfg(target_os = "macos")]
+fn kiosk_window(builder: WindowBuilder) -> WindowBuilder {
+    use dioxus::desktop::tao::platform::macos::WindowBuilderExtMacOS;
+
+    builder.with_titlebar_hidden(true)
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "windows",
+    target_os = "macos"
+)))]
+fn kiosk_window(builder: WindowBuilder) -> WindowBuilder {
+    builder
+}
+
+/// The monitor at index or named `wanted`, else the primary monitor.
+fn select_monitor(window: &Window, wanted: &str) -> Option<MonitorHandle> {
+    let monitors: Vec<_> = window.available_monitors().collect();
+    let found = match wanted.parse::<usize>() {
+        Ok(index) => monitors.get(index).cloned(),
+        Err(_) => monitors
+            .iter()
+            .find(|monitor| monitor.name().as_deref() == Some(wanted))
+            .cloned(),
+    };
+
+    if found.is_none() {
+        let names: Vec<_> = monitors
+            .iter()
+            .map(|monitor| monitor.name().unwrap_or_default())
+            .collect();
+        warn!(
+            target: "platform",
+            "No monitor {wanted}, using the primary monitor. Available: {}",
+            names.join(", ")
+        );
+    }
+    found.or_else(|| window.primary_monitor())
+}
+
+/// Applies the options that need the open window.
+///
+/// Monitors are only known once the event loop runs, so fullscreen is
+/// re-entered on the selected one.
+pub fn apply_kiosk_options(window: &Window, options: &KioskOptions) {
+    if !options.windowed
+        && let Some(wanted) = &options.monitor
+    {
+        let monitor = select_monitor(window, wanted);
+        if let Some(name) = monitor.as_ref().and_then(MonitorHandle::name) {
+            info!(target: "platform", "Showing the kiosk on {name}");
+        }
+        window.set_fullscreen(Some(Fullscreen::Borderless(monitor)));
+    }
+
+    if options.hide_cursor {
+        window.set_cursor_visible(false);
+    }
+
+    // GTK cannot raise windows under Wayland, the compositor decides.
+    #[cfg(target_os = "linux")]
+    if options.always_on_top && std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        warn!(
+            target: "platform",
+            "Always on top is ignored by most Wayland compositors, use the compositor's kiosk mode instead"
+        );
+    }
+}
+
+/// Asks for camera access, which macOS requires before any camera opens.
+#[cfg(target_os = "macos")]
+pub fn request_camera_access() -> Result<(), String> {
+    let (granted_tx, granted_rx) = std::sync::mpsc::channel();
+    nokhwa::nokhwa_initialize(move |granted| {
+        let _ = granted_tx.send(granted);
+    });
+
+    match granted_rx.recv() {
+        Ok(true) => Ok(()),
+        _ => Err(
+            "Camera access denied, allow qr-tracker under System Settings > \
+Privacy & Security > Camera and restart"
+                .to_string(),
+        ),
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn request_camera_access() -> Result<(), String> {
+    Ok(())
+}
+
+/// Camera indices to probe, in order.
+///
+/// V4L2 devices can be opened by any `/dev/video` number, so these count up
+/// to [`MAX_CAMERA_INDEX`].
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub fn camera_indices() -> impl Iterator<Item = u32> {
+    0..MAX_CAMERA_INDEX
+}
+
+/// Highest `/dev/video` number probed, exclusive. Each camera usually takes
+/// two numbers.
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const MAX_CAMERA_INDEX: u32 = 16;
+
+/// Camera indices to probe, in order.
+///
+/// Media Foundation and AVFoundation only open cameras by their position in
+/// the device list, so these come from that list instead of counting up
+/// forever.
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+pub fn camera_indices() -> impl Iterator<Item = u32> {
+    use nokhwa::utils::ApiBackend;
+
+    let cameras = nokhwa::query(ApiBackend::Auto).unwrap_or_else(|e| {
+        tracing::warn!(target: "video::camera", "Failed to list cameras: {e}");
+        Vec::new()
+    });
+    cameras
+        .into_iter()
+        .filter_map(|camera| camera.index().as_index().ok())
+        .collect::<Vec<_>>()
+        .into_iter()
+}
@@ -0,0 +1,384 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Lists of who is present and for how long, narrowed by a shared filter and
+//! sorted by a shared order.
+
+use std::{cmp::Ordering, fmt::Display, ops::Range, str::FromStr, time::Duration};
+
+use chrono::{DateTime, Local, TimeDelta};
+use dioxus::prelude::*;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    AttendanceContext, greeting::format_duration, lock::KioskLock, person_detail::PersonDetail,
+};
+
+/// Setting holding the list order, see [`ListSort`].
+pub const SORT_KEY: &str = "lists.sort";
+/// Setting holding how many columns of a name are shown before it is cut
+/// off with an ellipsis, see [`truncate_name`].
+const NAME_WIDTH_KEY: &str = "lists.name_width";
+
+const DEFAULT_NAME_WIDTH: u32 = 32;
+/// Setting holding the hours present after which someone is highlighted,
+/// likely having left without signing out.
+const LONG_STAY_KEY: &str = "lists.long_stay_hours";
+
+const DEFAULT_LONG_STAY_HOURS: i64 = 8;
+/// How often time present is brought up to date without a scan.
+const ELAPSED_TICK: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortColumn {
+    Name,
+    Time,
+}
+
+/// Order of every present list, chosen by clicking a column header.
+///
+/// Defaults to the most recent arrival first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListSort {
+    pub column: SortColumn,
+    pub descending: bool,
+}
+
+impl Default for ListSort {
+    fn default() -> Self {
+        Self {
+            column: SortColumn::Time,
+            descending: true,
+        }
+    }
+}
+
+impl ListSort {
+    /// Flips the direction when `column` is already sorted, otherwise sorts
+    /// by it alphabetically or most recent first.
+    pub fn toggle(self, column: SortColumn) -> Self {
+        if self.column == column {
+            Self {
+                descending: !self.descending,
+                ..self
+            }
+        } else {
+            Self {
+                column,
+                descending: column == SortColumn::Time,
+            }
+        }
+    }
+
+    /// Ties on one column are broken by the other, so the order is stable
+    /// across scans.
+    fn compare(
+        self,
+        (lhs_name, lhs_time): (&str, DateTime<Local>),
+        (rhs_name, rhs_time): (&str, DateTime<Local>),
+    ) -> Ordering {
+        let by_name = lhs_name
+            .to_lowercase()
+            .cmp(&rhs_name.to_lowercase())
+            .then_with(|| lhs_name.cmp(rhs_name));
+        let by_time = lhs_time.cmp(&rhs_time);
+
+        let ordering = match self.column {
+            SortColumn::Name => by_name.then(by_time),
+            SortColumn::Time => by_time.then(by_name),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    /// Header arrow for `column`, blank when sorting by the other.
+    fn arrow(self, column: SortColumn) -> &'static str {
+        match (self.column == column, self.descending) {
+            (false, _) => "",
+            (true, false) => " ▲",
+            (true, true) => " ▼",
+        }
+    }
+}
+
+impl FromStr for ListSort {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, descending) = match s.to_ascii_lowercase().as_str() {
+            "name_asc" => (SortColumn::Name, false),
+            "name_desc" => (SortColumn::Name, true),
+            "time_asc" => (SortColumn::Time, false),
+            "time_desc" => (SortColumn::Time, true),
+            _ => return Err(()),
+        };
+        Ok(Self { column, descending })
+    }
+}
+
+impl Display for ListSort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let column = match self.column {
+            SortColumn::Name => "name",
+            SortColumn::Time => "time",
+        };
+        let direction = if self.descending { "desc" } else { "asc" };
+        write!(f, "{column}_{direction}")
+    }
+}
+
+/// Where `filter` first appears in `name`, ignoring case.
+fn find_ignore_case(name: &str, filter: &str) -> Option<Range<usize>> {
+    name.char_indices().find_map(|(start, _)| {
+        let mut rest = name[start..].char_indices();
+        let mut end = start;
+        for expected in filter.chars() {
+            let (offset, c) = rest.next()?;
+            if !c.to_lowercase().eq(expected.to_lowercase()) {
+                return None;
+            }
+            end = start + offset + c.len_utf8();
+        }
+        Some(start..end)
+    })
+}
+
+/// `name` cut to `width` columns, the last being an ellipsis when any were
+/// dropped. Cut between grapheme clusters, so accents and emoji sequences
+/// are never split, and wide characters take two columns.
+fn truncate_name(name: &str, width: usize) -> String {
+    if name.graphemes(true).map(str::width).sum::<usize>() <= width {
+        return name.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let mut cut = String::new();
+    let mut used = 0;
+    for grapheme in name.graphemes(true) {
+        used += grapheme.width();
+        // The ellipsis takes the last column.
+        if used >= width {
+            break;
+        }
+        cut.push_str(grapheme);
+    }
+    cut.push('…');
+    cut
+}
+
+/// One role's present list, with `filter` matches highlighted.
+///
+/// Everyone is shown while `filter` is blank. Sorted when rendered, so
+/// scans only change the entries. With `detailed`, clicking a name opens
+/// their [`PersonDetail`] below the list.
+#[component]
+pub fn PresentList(
+    title: &'static str,
+    entries: ReadSignal<Vec<(String, DateTime<Local>)>>,
+    filter: ReadSignal<String>,
+    sort: Signal<ListSort>,
+    detailed: bool,
+) -> Element {
+    let AttendanceContext {
+        backing_db,
+        db_error,
+        ..
+    } = use_context();
+    // Kept open after they sign out, until closed.
+    let lock: KioskLock = use_context();
+    let mut selected = use_signal(|| None::<String>);
+    let display_settings = use_resource({
+        let backing_db = backing_db.clone();
+        move || {
+            let backing_db = backing_db.clone();
+            async move {
+                let settings = backing_db
+                    .try_run(|db| {
+                        Ok((
+                            db.get_setting(NAME_WIDTH_KEY)?
+                                .unwrap_or(DEFAULT_NAME_WIDTH),
+                            db.get_setting(LONG_STAY_KEY)?
+                                .unwrap_or(DEFAULT_LONG_STAY_HOURS),
+                        ))
+                    })
+                    .await;
+                db_error.check(settings)
+            }
+        }
+    });
+    // Re-renders on each tick, so time present counts up between scans.
+    let mut tick = use_signal(|| 0_u64);
+    use_future(move || async move {
+        loop {
+            tokio::time::sleep(ELAPSED_TICK).await;
+            tick += 1;
+        }
+    });
+    let sort_by = use_callback(move |column| {
+        let toggled = sort.peek().toggle(column);
+        let backing_db = backing_db.clone();
+        spawn(async move {
+            let saved = backing_db
+                .try_run(move |db| db.set_setting(SORT_KEY, toggled))
+                .await;
+            db_error.check(saved);
+        });
+        sort.set(toggled);
+    });
+
+    let filter = filter.read();
+    let filter = filter.trim();
+    let detail_entries = entries;
+    let entries = entries.read();
+    let order = sort();
+    tick.read();
+    let now = Local::now();
+    let (name_width, long_stay_hours) = display_settings()
+        .flatten()
+        .unwrap_or((DEFAULT_NAME_WIDTH, DEFAULT_LONG_STAY_HOURS));
+    let long_stay = TimeDelta::hours(long_stay_hours);
+
+    let mut sorted: Vec<_> = entries.iter().collect();
+    sorted.sort_by(|(lhs_name, lhs_time), (rhs_name, rhs_time)| {
+        order.compare((lhs_name, *lhs_time), (rhs_name, *rhs_time))
+    });
+    let shown: Vec<_> = sorted
+        .into_iter()
+        .filter_map(|(name, time)| {
+            if !filter.is_empty() {
+                find_ignore_case(name, filter)?;
+            }
+            // Matches in the cut off part are not highlighted.
+            let shown_name = truncate_name(name, name_width as usize);
+            let matched = find_ignore_case(&shown_name, filter).unwrap_or(0..0);
+            Some((
+                name.clone(),
+                shown_name[..matched.start].to_string(),
+                shown_name[matched.clone()].to_string(),
+                shown_name[matched.end..].to_string(),
+                time.format("%m-%d-%Y %H:%M:%S %p").to_string(),
+                now - *time,
+            ))
+        })
+        .collect();
+
+    rsx! {
+        h3 { "{title} ({entries.len()})" }
+        if !filter.is_empty() {
+            small { "{shown.len()} of {entries.len()} shown" }
+        }
+        table {
+            class: "present_list",
+            tr {
+                th {
+                    onclick: move |_| sort_by(SortColumn::Name),
+                    "Name{order.arrow(SortColumn::Name)}"
+                }
+                th {
+                    onclick: move |_| sort_by(SortColumn::Time),
+                    "Time In{order.arrow(SortColumn::Time)}"
+                }
+                th { "Here" }
+            }
+            for (name, before, matched, after, time, elapsed) in shown {
+                tr {
+                    class: if elapsed >= long_stay { "long_stay" },
+                    // Hovering shows the whole name.
+                    td {
+                        class: "name",
+                        title: "{name}",
+                        onclick: {
+                            let name = name.clone();
+                            move |_| {
+                                if detailed {
+                                    let name = name.clone();
+                                    lock.guard(
+                                        Callback::new(move |()| selected.set(Some(name.clone()))),
+                                    );
+                                }
+                            }
+                        },
+                        "{before}"
+                        if !matched.is_empty() {
+                            mark { "{matched}" }
+                        }
+                        "{after}"
+                    }
+                    td { "{time}" }
+                    td { "{format_duration(elapsed)}" }
+                }
+            }
+        }
+        if let Some(name) = selected() {
+            PersonDetail {
+                key: "{name}",
+                name,
+                entries: detail_entries,
+                close: move |()| selected.set(None),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_names_kept_whole() {
+        assert_eq!(truncate_name("Ada", 3), "Ada");
+        assert_eq!(truncate_name("", 3), "");
+        assert_eq!(truncate_name("Zoë 李", 6), "Zoë 李");
+    }
+
+    #[test]
+    fn long_names_end_in_ellipsis() {
+        assert_eq!(truncate_name("Ada Lovelace", 4), "Ada…");
+        assert_eq!(truncate_name("Ada", 1), "…");
+        assert_eq!(truncate_name("Ada", 0), "");
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(truncate_name("李小龍先生", 3), "李…");
+        assert_eq!(truncate_name("李小龍先生", 4), "李…");
+        assert_eq!(truncate_name("李小龍先生", 5), "李小…");
+        assert_eq!(truncate_name("李小龍先生", 10), "李小龍先生");
+        assert_eq!(truncate_name("Zoë 李", 5), "Zoë …");
+        assert_eq!(truncate_name("Zoë Ångström", 5), "Zoë …");
+    }
+
+    #[test]
+    fn graphemes_not_split() {
+        // "e" followed by a combining acute accent.
+        let name = "Rene\u{301}e Dupont";
+        assert_eq!(truncate_name(name, 6), "Rene\u{301}e…");
+        assert_eq!(truncate_name(name, 5), "Rene\u{301}…");
+
+        // Emoji widths vary between Unicode versions, so only check the cut
+        // fits and falls between graphemes.
+        for name in ["👨‍👩‍👧 Smith", "🇳🇿🇳🇿🇳🇿"] {
+            for width in 0..12 {
+                let cut = truncate_name(name, width);
+                assert!(cut.width() <= width, "{cut:?} wider than {width}");
+                let kept = cut.strip_suffix('…').unwrap_or(&cut);
+                assert!(
+                    name.grapheme_indices(true)
+                        .any(|(start, _)| start == kept.len())
+                        || kept.len() == name.len(),
+                    "{cut:?} splits a grapheme"
+                );
+                assert!(name.starts_with(kept));
+            }
+        }
+    }
+}
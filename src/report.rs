@@ -0,0 +1,151 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Hours summary for mentors, e.g. every Monday for the week before.
+//!
+//! Built by [`BackingDatabase::report`], which pairs scans the same way as
+//! [`BackingDatabase::hours_summary`].
+
+use std::fmt::Write;
+
+use chrono::{Datelike, Local, NaiveDate, TimeDelta};
+
+use crate::sqlite::{BackingDatabase, DateRange, DbError};
+
+/// Setting holding the first day of the current season.
+pub const SEASON_START_KEY: &str = "season.start";
+
+/// The configured season start, or the most recent September 1st.
+pub fn season_start(db: &BackingDatabase) -> Result<NaiveDate, DbError> {
+    Ok(db.get_setting(SEASON_START_KEY)?.unwrap_or_else(|| {
+        let today = Local::now().date_naive();
+        let year = if today.month() >= 9 {
+            today.year()
+        } else {
+            today.year() - 1
+        };
+        NaiveDate::from_ymd_opt(year, 9, 1).unwrap()
+    }))
+}
+
+/// One person's hours.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportRow {
+    pub name: String,
+    /// Sessions starting in the report range.
+    pub hours: TimeDelta,
+    pub sessions: usize,
+    /// Sessions in the range without a sign-out, or past the session cap.
+    /// They count up to the cap.
+    pub unpaired: usize,
+    /// Sessions starting from the season start to the end of the range.
+    pub season_hours: TimeDelta,
+}
+
+/// Everyone's hours, by role, sorted by name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub range: DateRange,
+    pub season_start: NaiveDate,
+    pub mentors: Vec<ReportRow>,
+    pub students: Vec<ReportRow>,
+    /// Scanned this season, but no longer on either roster.
+    pub former_members: Vec<ReportRow>,
+    /// On both rosters, reported as mentors.
+    pub on_both_rosters: Vec<String>,
+}
+
+fn hours(duration: TimeDelta) -> String {
+    format!("{:.2}", duration.num_minutes() as f64 / 60.0)
+}
+
+impl Report {
+    fn groups(&self) -> [(&'static str, &[ReportRow]); 3] {
+        [
+            ("Mentors", &self.mentors),
+            ("Students", &self.students),
+            ("Former members", &self.former_members),
+        ]
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let (since, until) = self.range;
+        let mut out = format!(
+            "# Attendance {} to {}\n\nSeason hours since {}.\n",
+            since.format("%Y-%m-%d"),
+            (until - TimeDelta::seconds(1)).format("%Y-%m-%d"),
+            self.season_start.format("%Y-%m-%d"),
+        );
+
+        if !self.on_both_rosters.is_empty() {
+            writeln!(
+                out,
+                "\n**Warning:** on both the mentor and student rosters, reported as mentors: {}",
+                self.on_both_rosters.join(", ")
+            )
+            .unwrap();
+        }
+
+        for (title, rows) in self.groups() {
+            if rows.is_empty() {
+                continue;
+            }
+            writeln!(out, "\n## {title}\n").unwrap();
+            writeln!(
+                out,
+                "| Name | Hours | Sessions | Missing Sign-outs | Season Hours |"
+            )
+            .unwrap();
+            writeln!(out, "| --- | --: | --: | --: | --: |").unwrap();
+            for row in rows {
+                writeln!(
+                    out,
+                    "| {} | {} | {} | {} | {} |",
+                    row.name.replace('|', "\\|"),
+                    hours(row.hours),
+                    row.sessions,
+                    row.unpaired,
+                    hours(row.season_hours),
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut out = Vec::new();
+        {
+            let mut writer = csv::Writer::from_writer(&mut out);
+            writer
+                .write_record([
+                    "Group",
+                    "Name",
+                    "Hours",
+                    "Sessions",
+                    "Missing Sign-outs",
+                    "Season Hours",
+                ])
+                .unwrap();
+            for (title, rows) in self.groups() {
+                for row in rows {
+                    writer
+                        .write_record([
+                            title.to_string(),
+                            row.name.clone(),
+                            hours(row.hours),
+                            row.sessions.to_string(),
+                            row.unpaired.to_string(),
+                            hours(row.season_hours),
+                        ])
+                        .unwrap();
+                }
+            }
+            writer.flush().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+}
@@ -0,0 +1,341 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Site specific accept/reject rules from a rhai script.
+//!
+//! The script defines `fn on_scan(name, role, hour, weekday)` returning
+//! `"accept"`, `"reject"`, or `"flag"`. Scripts can only read presence
+//! through `present_count(role)` and `is_mentor_present()`. Any script error,
+//! including running past the time limit, accepts the scan with a warning.
+//! Requires the `scripting` feature.
+
+use std::fmt::Display;
+
+/// Longest a single `on_scan` call may run.
+#[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+const TIME_LIMIT: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// What the script decided for a scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+pub enum RuleAction {
+    Accept,
+    Reject,
+    /// Accept, but record the scan for review.
+    Flag,
+}
+
+/// People present before the scan.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Presence {
+    pub mentors: usize,
+    pub students: usize,
+    pub guests: usize,
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+pub enum RulesError {
+    /// Built without the `scripting` feature.
+    #[cfg(not(feature = "scripting"))]
+    Disabled,
+    /// The script failed to read or parse.
+    Compile(String),
+    /// The script has no `on_scan` function.
+    MissingOnScan,
+}
+
+impl Display for RulesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(not(feature = "scripting"))]
+            Self::Disabled => write!(f, "built without the scripting feature"),
+            Self::Compile(e) => write!(f, "{e}"),
+            Self::MissingOnScan => write!(f, "no on_scan function"),
+        }
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+pub struct ScanRules;
+
+#[cfg(not(feature = "scripting"))]
+impl ScanRules {
+    pub fn load(_path: &std::path::Path) -> Result<Self, RulesError> {
+        Err(RulesError::Disabled)
+    }
+
+    pub fn check(
+        &self,
+        _name: &str,
+        _role: crate::QrType,
+        _time: chrono::DateTime<chrono::Local>,
+        _presence: Presence,
+    ) -> RuleAction {
+        RuleAction::Accept
+    }
+}
+
+#[cfg(feature = "scripting")]
+pub use script::ScanRules;
+
+#[cfg(feature = "scripting")]
+mod script {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    use chrono::{DateTime, Datelike, Local, Timelike};
+    use rhai::{AST, Dynamic, Engine, Scope};
+    use tracing::{info, warn};
+
+    use super::{Presence, RuleAction, RulesError, TIME_LIMIT};
+    use crate::QrType;
+
+    /// Backstop for scripts that never yield back to the progress check.
+    const MAX_OPERATIONS: u64 = 1_000_000;
+
+    /// A compiled rules script.
+    pub struct ScanRules {
+        engine: Engine,
+        ast: AST,
+        /// Read by the script API during a call.
+        presence: Arc<Mutex<Presence>>,
+        /// End of the current call's time limit.
+        deadline: Arc<Mutex<Instant>>,
+    }
+
+    impl ScanRules {
+        pub fn load(path: &Path) -> Result<Self, RulesError> {
+            let presence = Arc::new(Mutex::new(Presence::default()));
+            let deadline = Arc::new(Mutex::new(Instant::now()));
+
+            // Rhai has no file or network access, scripts only see the
+            // functions registered below.
+            let mut engine = Engine::new();
+            engine
+                .on_print(|text| info!(target: "rules", "{text}"))
+                .set_max_operations(MAX_OPERATIONS)
+                .set_max_call_levels(32)
+                .set_max_string_size(4096)
+                .set_max_array_size(1024)
+                .set_max_map_size(1024);
+
+            let call_deadline = deadline.clone();
+            engine.on_progress(move |_| {
+                (Instant::now() > *call_deadline.lock().unwrap()).then_some(Dynamic::UNIT)
+            });
+
+            let counts = presence.clone();
+            engine.register_fn("present_count", move |role: &str| -> i64 {
+                let presence = *counts.lock().unwrap();
+                match role.parse() {
+                    Ok(QrType::Mentor) => presence.mentors as i64,
+                    Ok(QrType::Student) => presence.students as i64,
+                    Ok(QrType::Guest) => presence.guests as i64,
+                    Err(_) => (presence.mentors + presence.students + presence.guests) as i64,
+                }
+            });
+            let mentors = presence.clone();
+            engine.register_fn("is_mentor_present", move || -> bool {
+                mentors.lock().unwrap().mentors > 0
+            });
+
+            let ast = engine
+                .compile_file(path.to_path_buf())
+                .map_err(|e| RulesError::Compile(e.to_string()))?;
+            if !ast.iter_functions().any(|f| f.name == "on_scan") {
+                return Err(RulesError::MissingOnScan);
+            }
+
+            Ok(Self {
+                engine,
+                ast,
+                presence,
+                deadline,
+            })
+        }
+
+        /// Runs `on_scan`, accepting on any script error.
+        pub fn check(
+            &self,
+            name: &str,
+            role: QrType,
+            time: DateTime<Local>,
+            presence: Presence,
+        ) -> RuleAction {
+            *self.presence.lock().unwrap() = presence;
+            *self.deadline.lock().unwrap() = Instant::now() + TIME_LIMIT;
+
+            let result = self.engine.call_fn::<String>(
+                &mut Scope::new(),
+                &self.ast,
+                "on_scan",
+                (
+                    name.to_string(),
+                    role.to_string(),
+                    time.hour() as i64,
+                    time.weekday().to_string(),
+                ),
+            );
+
+            match result.as_deref() {
+                Ok("accept") => RuleAction::Accept,
+                Ok("reject") => RuleAction::Reject,
+                Ok("flag") => RuleAction::Flag,
+                Ok(other) => {
+                    warn!(target: "rules", name, "on_scan returned {other:?}, accepting");
+                    RuleAction::Accept
+                }
+                Err(e) => {
+                    warn!(target: "rules", name, "on_scan failed, accepting: {e}");
+                    RuleAction::Accept
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "scripting"))]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        time::Instant,
+    };
+
+    use chrono::{DateTime, Local, TimeZone};
+
+    use super::*;
+    use crate::QrType;
+
+    const MENTOR_PRESENT: Presence = Presence {
+        mentors: 1,
+        students: 3,
+        guests: 0,
+    };
+
+    /// 2024-09-02 was a Monday, 2024-09-06 a Friday.
+    fn at(day: u32, hour: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2024, 9, day, hour, 30, 0).unwrap()
+    }
+
+    fn example() -> ScanRules {
+        ScanRules::load(&Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/rules.rhai")).unwrap()
+    }
+
+    fn script(name: &str, source: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "qr-tracker-rules-{name}-{}.rhai",
+            std::process::id()
+        ));
+        std::fs::write(&path, source).unwrap();
+        path
+    }
+
+    #[test]
+    fn example_accepts_and_rejects() {
+        let rules = example();
+        let check = |role, time, presence| rules.check("Alice", role, time, presence);
+
+        assert_eq!(
+            check(QrType::Student, at(2, 18), MENTOR_PRESENT),
+            RuleAction::Accept
+        );
+        assert_eq!(
+            check(QrType::Student, at(2, 22), MENTOR_PRESENT),
+            RuleAction::Reject
+        );
+        assert_eq!(
+            check(QrType::Student, at(6, 22), MENTOR_PRESENT),
+            RuleAction::Accept
+        );
+        assert_eq!(
+            check(QrType::Mentor, at(2, 22), Presence::default()),
+            RuleAction::Accept
+        );
+        assert_eq!(
+            check(QrType::Student, at(2, 18), Presence::default()),
+            RuleAction::Flag
+        );
+    }
+
+    #[test]
+    fn presence_visible_to_script() {
+        let path = script(
+            "presence",
+            r#"fn on_scan(name, role, hour, weekday) {
+                if present_count("student") >= 3 && present_count("anyone") == 4 {
+                    "flag"
+                } else {
+                    "accept"
+                }
+            }"#,
+        );
+        let rules = ScanRules::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            rules.check("Alice", QrType::Student, at(2, 18), MENTOR_PRESENT),
+            RuleAction::Flag
+        );
+        assert_eq!(
+            rules.check("Alice", QrType::Student, at(2, 18), Presence::default()),
+            RuleAction::Accept
+        );
+    }
+
+    #[test]
+    fn runaway_script_times_out_to_accept() {
+        let path = script(
+            "loop",
+            r#"fn on_scan(name, role, hour, weekday) {
+                loop {}
+                "reject"
+            }"#,
+        );
+        let rules = ScanRules::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let started = Instant::now();
+        assert_eq!(
+            rules.check("Alice", QrType::Student, at(2, 18), MENTOR_PRESENT),
+            RuleAction::Accept
+        );
+        assert!(
+            started.elapsed() < TIME_LIMIT * 20,
+            "{:?}",
+            started.elapsed()
+        );
+    }
+
+    #[test]
+    fn broken_scripts_fall_back_to_accept() {
+        let syntax = script("syntax", "fn on_scan(name, role { \"reject\" ");
+        let missing = script("missing", "fn on_badge(name) { \"reject\" }");
+        let loaded = (ScanRules::load(&syntax), ScanRules::load(&missing));
+        std::fs::remove_file(&syntax).unwrap();
+        std::fs::remove_file(&missing).unwrap();
+        assert!(matches!(loaded.0, Err(RulesError::Compile(_))));
+        assert!(matches!(loaded.1, Err(RulesError::MissingOnScan)));
+
+        let failing = script(
+            "failing",
+            r#"fn on_scan(name, role, hour, weekday) {
+                if hour > 12 { no_such_function() } else { "maybe" }
+            }"#,
+        );
+        let rules = ScanRules::load(&failing).unwrap();
+        std::fs::remove_file(&failing).unwrap();
+        for hour in [9, 18] {
+            assert_eq!(
+                rules.check("Alice", QrType::Student, at(2, hour), MENTOR_PRESENT),
+                RuleAction::Accept
+            );
+        }
+    }
+}
@@ -0,0 +1,267 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Holds accepted scans while the database cannot be written, e.g. a full
+//! disk or a dropped network mount, until it can.
+//!
+//! Queued scans are also appended to a JSON lines file next to the
+//! database, so a crash during the outage does not lose them. The file is
+//! replayed on startup and removed once its scans are recorded.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::sqlite::{BackingDatabase, DbError};
+
+/// Wait before the first retry, doubled after each failed one.
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+const MAX_RETRY: Duration = Duration::from_secs(60);
+/// Wait before retrying a scan once when another connection holds the
+/// write lock, usually a report or backup finishing within it.
+const BUSY_RETRY: Duration = Duration::from_millis(100);
+
+/// A scan accepted but not yet recorded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedScan {
+    pub name: String,
+    /// Unix seconds.
+    pub timestamp: i64,
+    /// Seconds east of UTC at the kiosk, missing from older queue files.
+    #[serde(default)]
+    pub utc_offset: Option<i32>,
+    pub source: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ScanQueue {
+    scans: VecDeque<QueuedScan>,
+    /// Leading scans of `scans` already appended to `file`.
+    persisted: usize,
+    file: Option<PathBuf>,
+    retry_at: Option<Instant>,
+    backoff: Duration,
+    /// Queued scan count, read by the UI.
+    len: Arc<AtomicUsize>,
+}
+
+impl ScanQueue {
+    /// Queue for `db`, holding any scans left in its file by a crash.
+    pub fn load(db: &BackingDatabase, len: Arc<AtomicUsize>) -> Self {
+        let file = db.pending_scans_path();
+        let scans: VecDeque<QueuedScan> = file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| {
+                contents
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .filter_map(|line| match serde_json::from_str(line) {
+                        Ok(scan) => Some(scan),
+                        // Most likely the last line, cut off by the crash.
+                        Err(e) => {
+                            warn!(target: "scan_queue", "Skipping unreadable queued scan {line:?}: {e}");
+                            None
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if !scans.is_empty() {
+            info!(target: "scan_queue", count = scans.len(), "Replaying queued scans");
+        }
+        len.store(scans.len(), Ordering::Relaxed);
+        Self {
+            persisted: scans.len(),
+            retry_at: (!scans.is_empty()).then(Instant::now),
+            scans,
+            file,
+            backoff: FIRST_RETRY,
+            len,
+        }
+    }
+
+    /// Records the scan, or queues it when the database cannot be written
+    /// right now. Scans are queued behind any already waiting, to keep
+    /// their order.
+    ///
+    /// A busy database is retried once after [`BUSY_RETRY`] before
+    /// queueing.
+    pub fn add(
+        &mut self,
+        db: &mut BackingDatabase,
+        name: &str,
+        timestamp: DateTime<Local>,
+        source: Option<&str>,
+    ) -> Result<(), DbError> {
+        if self.scans.is_empty() {
+            let recorded = match db.add_scan(name, timestamp, source) {
+                Err(e) if e.is_busy() => {
+                    warn!(target: "scan_queue", name, "Database busy, retrying scan: {e}");
+                    thread::sleep(BUSY_RETRY);
+                    db.add_scan(name, timestamp, source)
+                }
+                recorded => recorded,
+            };
+            match recorded {
+                Err(e) if e.is_transient() => {
+                    error!(target: "scan_queue", name, "Queueing scan, database unavailable: {e}");
+                    self.retry_at = Some(Instant::now() + self.backoff);
+                }
+                recorded => return recorded,
+            }
+        }
+
+        self.scans.push_back(QueuedScan {
+            name: name.to_string(),
+            timestamp: timestamp.timestamp(),
+            utc_offset: Some(timestamp.offset().local_minus_utc()),
+            source: source.map(str::to_string),
+        });
+        self.len.store(self.scans.len(), Ordering::Relaxed);
+        if let Err(e) = self.persist() {
+            // Still held in memory, only a crash now loses it.
+            error!(target: "scan_queue", "Failed to save queued scans: {e}");
+        }
+        Ok(())
+    }
+
+    /// Appends every scan not yet in the file, including any a failed
+    /// append missed.
+    fn persist(&mut self) -> io::Result<()> {
+        let Some(path) = &self.file else {
+            return Ok(());
+        };
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        for scan in self.scans.range(self.persisted..) {
+            writeln!(file, "{}", serde_json::to_string(scan)?)?;
+            self.persisted += 1;
+        }
+        file.sync_data()
+    }
+
+    /// Time left before the next retry, `None` with nothing queued.
+    pub fn retry_in(&self) -> Option<Duration> {
+        self.retry_at
+            .map(|retry_at| retry_at.saturating_duration_since(Instant::now()))
+    }
+
+    /// Records every queued scan in one transaction, backing off further on
+    /// failure.
+    pub fn flush(&mut self, db: &mut BackingDatabase) {
+        if self.scans.is_empty() {
+            return;
+        }
+
+        match db.add_scans(self.scans.make_contiguous()) {
+            Ok(()) => {
+                info!(target: "scan_queue", count = self.scans.len(), "Recorded queued scans");
+                self.scans.clear();
+                self.persisted = 0;
+                self.retry_at = None;
+                self.backoff = FIRST_RETRY;
+                self.len.store(0, Ordering::Relaxed);
+                if let Some(path) = &self.file
+                    && let Err(e) = fs::remove_file(path)
+                    && e.kind() != io::ErrorKind::NotFound
+                {
+                    // Replaying it would record the scans twice.
+                    error!(target: "scan_queue", "Failed to remove {}: {e}", path.display());
+                }
+            }
+            Err(e) => {
+                self.backoff = (self.backoff * 2).min(MAX_RETRY);
+                self.retry_at = Some(Instant::now() + self.backoff);
+                error!(
+                    target: "scan_queue",
+                    count = self.scans.len(),
+                    retry_secs = self.backoff.as_secs(),
+                    "Failed to record queued scans: {e}"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::*;
+
+    fn temp_db(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{name}-{}.db", std::process::id()));
+        remove_db(&path);
+        path
+    }
+
+    fn remove_db(path: &std::path::Path) {
+        for suffix in ["", "-wal", "-shm", "-pending.jsonl"] {
+            let mut file = path.as_os_str().to_owned();
+            file.push(suffix);
+            let _ = fs::remove_file(file);
+        }
+    }
+
+    #[test]
+    fn busy_scan_retried_once() {
+        let path = temp_db("busy_scan_retried_once");
+        let mut db = BackingDatabase::new(path.to_str()).unwrap();
+        db.fail_when_busy();
+        let mut queue = ScanQueue::load(&db, Arc::default());
+
+        let locker = Connection::open(&path).unwrap();
+        locker.execute_batch("BEGIN IMMEDIATE;").unwrap();
+        let release = thread::spawn(move || {
+            thread::sleep(BUSY_RETRY / 4);
+            locker.execute_batch("COMMIT;").unwrap();
+        });
+
+        queue.add(&mut db, "Ada", Local::now(), None).unwrap();
+        release.join().unwrap();
+        assert_eq!(queue.retry_in(), None);
+        assert_eq!(db.get_present().unwrap().len(), 1);
+
+        drop(db);
+        remove_db(&path);
+    }
+
+    #[test]
+    fn still_busy_scan_queued() {
+        let path = temp_db("still_busy_scan_queued");
+        let mut db = BackingDatabase::new(path.to_str()).unwrap();
+        db.fail_when_busy();
+        let len = Arc::new(AtomicUsize::new(0));
+        let mut queue = ScanQueue::load(&db, len.clone());
+
+        let locker = Connection::open(&path).unwrap();
+        locker.execute_batch("BEGIN IMMEDIATE;").unwrap();
+        queue.add(&mut db, "Ada", Local::now(), None).unwrap();
+        assert_eq!(len.load(Ordering::Relaxed), 1);
+
+        locker.execute_batch("COMMIT;").unwrap();
+        queue.flush(&mut db);
+        assert_eq!(len.load(Ordering::Relaxed), 0);
+        assert_eq!(db.get_present().unwrap().len(), 1);
+
+        drop(db);
+        remove_db(&path);
+    }
+}
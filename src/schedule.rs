@@ -0,0 +1,481 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Meeting windows imported from an iCal feed.
+//!
+//! The feed is a URL or file path in the config file. Events are expanded
+//! for the next four weeks, including weekly recurrences, and
+//! replace the upcoming meetings in the database. Parsing requires the
+//! `calendar` feature.
+
+use std::{fmt::Display, time::Duration};
+
+use chrono::{DateTime, Local, TimeDelta};
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    db_worker::DbHandle,
+    sqlite::{BackingDatabase, DbError, Meeting},
+};
+
+/// Setting holding the time of the last successful sync.
+pub const LAST_SYNC_KEY: &str = "schedule.last_sync";
+
+const SYNC_INTERVAL: TimeDelta = TimeDelta::days(1);
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "calendar"), allow(dead_code))]
+pub enum ScheduleError {
+    /// Built without the `calendar` feature.
+    #[cfg(not(feature = "calendar"))]
+    Disabled,
+    NotConfigured,
+    Fetch(String),
+    /// The feed as a whole is unreadable.
+    Parse(String),
+    /// A single event could not be understood.
+    Event {
+        event: String,
+        reason: String,
+    },
+    Db(DbError),
+}
+
+impl Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(not(feature = "calendar"))]
+            Self::Disabled => write!(f, "built without the calendar feature"),
+            Self::NotConfigured => write!(f, "set calendar in the config file"),
+            Self::Fetch(e) => write!(f, "failed to fetch calendar: {e}"),
+            Self::Parse(e) => write!(f, "failed to parse calendar: {e}"),
+            Self::Event { event, reason } => write!(f, "event \"{event}\": {reason}"),
+            Self::Db(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Fetches the configured feed and stores its upcoming meetings, returning
+/// how many were stored.
+pub async fn sync(backing_db: &DbHandle, config: &Config) -> Result<usize, ScheduleError> {
+    let source = config
+        .calendar
+        .as_deref()
+        .ok_or(ScheduleError::NotConfigured)?;
+
+    let now = Local::now();
+    let meetings = fetch_meetings(source, now).await?;
+
+    let count = meetings.len();
+    backing_db
+        .try_run(move |db| store(db, now, &meetings))
+        .await
+        .map_err(ScheduleError::Db)?;
+    Ok(count)
+}
+
+/// Stores fetched meetings and records the sync time.
+pub fn store(
+    db: &mut BackingDatabase,
+    now: DateTime<Local>,
+    meetings: &[Meeting],
+) -> Result<(), DbError> {
+    db.replace_meetings(now, meetings)?;
+    db.set_setting(LAST_SYNC_KEY, now.timestamp())
+}
+
+pub fn last_sync(db: &BackingDatabase) -> Result<Option<DateTime<Local>>, DbError> {
+    Ok(db
+        .get_setting(LAST_SYNC_KEY)?
+        .and_then(DateTime::from_timestamp_secs)
+        .map(Into::into))
+}
+
+/// Re-syncs once a day while a calendar is configured. Failures are logged
+/// and retried on the next check.
+pub async fn daily_sync(backing_db: DbHandle, config: Config) {
+    loop {
+        let due = config.calendar.is_some()
+            && backing_db
+                .try_run(|db| last_sync(db))
+                .await
+                .is_ok_and(|last| last.is_none_or(|last| Local::now() - last >= SYNC_INTERVAL));
+
+        if due {
+            match sync(&backing_db, &config).await {
+                Ok(count) => info!(target: "schedule", count, "Synced meetings"),
+                Err(e) => warn!(target: "schedule", "Schedule sync failed: {e}"),
+            }
+        }
+
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(not(feature = "calendar"))]
+pub async fn fetch_meetings(
+    _source: &str,
+    _now: DateTime<Local>,
+) -> Result<Vec<Meeting>, ScheduleError> {
+    Err(ScheduleError::Disabled)
+}
+
+#[cfg(feature = "calendar")]
+pub use feed::fetch_meetings;
+
+#[cfg(feature = "calendar")]
+mod feed {
+    use std::{fs, io::Cursor};
+
+    use chrono::{
+        DateTime, Datelike, Days, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone,
+        Utc, Weekday,
+    };
+    use chrono_tz::Tz;
+    use ical::{IcalParser, parser::ical::component::IcalEvent, property::Property};
+
+    use super::ScheduleError;
+    use crate::sqlite::{Meeting, local_datetime};
+
+    /// How far ahead recurring meetings are expanded.
+    const EXPAND_WEEKS: u64 = 4;
+
+    /// Fetches and expands meetings from an iCal URL or file path.
+    pub async fn fetch_meetings(
+        source: &str,
+        now: DateTime<Local>,
+    ) -> Result<Vec<Meeting>, ScheduleError> {
+        let feed = if let Some(url) = source.strip_prefix("webcal://") {
+            download(&format!("https://{url}")).await?
+        } else if source.starts_with("http://") || source.starts_with("https://") {
+            download(source).await?
+        } else {
+            fs::read_to_string(source)
+                .map_err(|e| ScheduleError::Fetch(format!("{source}: {e}")))?
+        };
+
+        let window_start = local_datetime(now.date_naive(), NaiveTime::MIN);
+        let window_end = window_start + Days::new(EXPAND_WEEKS * 7);
+
+        let mut meetings = Vec::new();
+        for calendar in IcalParser::new(Cursor::new(feed)) {
+            let calendar = calendar.map_err(|e| ScheduleError::Parse(e.to_string()))?;
+            for event in &calendar.events {
+                meetings.extend(expand(event, window_start, window_end)?);
+            }
+        }
+        meetings.sort_unstable_by_key(|meeting| meeting.start);
+
+        Ok(meetings)
+    }
+
+    async fn download(url: &str) -> Result<String, ScheduleError> {
+        let response = reqwest::Client::new()
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| ScheduleError::Fetch(e.to_string()))?;
+        if !response.status().is_success() {
+            return Err(ScheduleError::Fetch(format!("HTTP {}", response.status())));
+        }
+        response
+            .text()
+            .await
+            .map_err(|e| ScheduleError::Fetch(e.to_string()))
+    }
+
+    fn property<'a>(event: &'a IcalEvent, name: &str) -> Option<&'a Property> {
+        event
+            .properties
+            .iter()
+            .find(|property| property.name == name)
+    }
+
+    fn param<'a>(property: &'a Property, name: &str) -> Option<&'a str> {
+        property
+            .params
+            .as_ref()?
+            .iter()
+            .find(|(param, _)| param == name)
+            .and_then(|(_, values)| values.first())
+            .map(String::as_str)
+    }
+
+    /// Wall clock time in the event's time zone.
+    #[derive(Debug, Clone, Copy)]
+    enum Zone {
+        Utc,
+        /// Floating times are taken as kiosk local time.
+        Local,
+        Named(Tz),
+    }
+
+    impl Zone {
+        fn resolve(self, time: NaiveDateTime) -> Option<DateTime<Local>> {
+            match self {
+                Self::Utc => Some(Utc.from_utc_datetime(&time).with_timezone(&Local)),
+                Self::Local => Local.from_local_datetime(&time).earliest(),
+                Self::Named(tz) => tz
+                    .from_local_datetime(&time)
+                    .earliest()
+                    .map(|time| time.with_timezone(&Local)),
+            }
+        }
+    }
+
+    /// `None` for all-day dates, which are not meeting windows.
+    fn parse_time(property: &Property) -> Result<Option<(NaiveDateTime, Zone)>, String> {
+        let value = property.value.as_deref().unwrap_or_default().trim();
+        if param(property, "VALUE") == Some("DATE") || !value.contains('T') {
+            return Ok(None);
+        }
+
+        let (value, zone) = match (value.strip_suffix('Z'), param(property, "TZID")) {
+            (Some(utc), _) => (utc, Zone::Utc),
+            (None, Some(tzid)) => (
+                value,
+                Zone::Named(
+                    tzid.parse()
+                        .map_err(|_| format!("unknown time zone {tzid}"))?,
+                ),
+            ),
+            (None, None) => (value, Zone::Local),
+        };
+        let time = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+            .map_err(|e| format!("invalid {} {value}: {e}", property.name))?;
+
+        Ok(Some((time, zone)))
+    }
+
+    /// Parses an RFC 5545 duration such as `PT1H30M` or `P1D`.
+    fn parse_duration(value: &str) -> Option<TimeDelta> {
+        let value = value.strip_prefix('P')?;
+        let mut duration = TimeDelta::zero();
+        let mut number = String::new();
+
+        for c in value.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+
+            let amount: i64 = if c == 'T' { 0 } else { number.parse().ok()? };
+            duration += match c {
+                'T' => TimeDelta::zero(),
+                'W' => TimeDelta::weeks(amount),
+                'D' => TimeDelta::days(amount),
+                'H' => TimeDelta::hours(amount),
+                'M' => TimeDelta::minutes(amount),
+                'S' => TimeDelta::seconds(amount),
+                _ => return None,
+            };
+            number.clear();
+        }
+
+        Some(duration)
+    }
+
+    fn parse_weekday(day: &str) -> Option<Weekday> {
+        // Ordinal prefixes (e.g. 1MO) only apply to monthly rules.
+        match day.trim_start_matches(|c: char| c.is_ascii_digit() || c == '-' || c == '+') {
+            "MO" => Some(Weekday::Mon),
+            "TU" => Some(Weekday::Tue),
+            "WE" => Some(Weekday::Wed),
+            "TH" => Some(Weekday::Thu),
+            "FR" => Some(Weekday::Fri),
+            "SA" => Some(Weekday::Sat),
+            "SU" => Some(Weekday::Sun),
+            _ => None,
+        }
+    }
+
+    /// The subset of RRULE needed for weekly meetings.
+    struct WeeklyRule {
+        interval: u64,
+        count: Option<usize>,
+        until: Option<DateTime<Local>>,
+        days: Vec<Weekday>,
+    }
+
+    impl WeeklyRule {
+        fn parse(rule: &str, start: NaiveDateTime, zone: Zone) -> Result<Self, String> {
+            let mut parsed = Self {
+                interval: 1,
+                count: None,
+                until: None,
+                days: Vec::new(),
+            };
+
+            for part in rule.split(';').filter(|part| !part.is_empty()) {
+                let (key, value) = part
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid RRULE part {part}"))?;
+                match key {
+                    "FREQ" if value == "WEEKLY" => (),
+                    "FREQ" => return Err(format!("unsupported {value} recurrence")),
+                    "INTERVAL" => {
+                        parsed.interval = value
+                            .parse()
+                            .ok()
+                            .filter(|interval| *interval > 0)
+                            .ok_or_else(|| format!("invalid INTERVAL {value}"))?;
+                    }
+                    "COUNT" => {
+                        parsed.count = Some(
+                            value
+                                .parse()
+                                .map_err(|_| format!("invalid COUNT {value}"))?,
+                        );
+                    }
+                    "UNTIL" => {
+                        let until = Property {
+                            name: "UNTIL".to_string(),
+                            params: None,
+                            value: Some(value.to_string()),
+                        };
+                        parsed.until = match parse_time(&until)? {
+                            Some((until, until_zone)) => match until_zone {
+                                Zone::Utc => Zone::Utc.resolve(until),
+                                _ => zone.resolve(until),
+                            },
+                            // Date only, inclusive of the whole day.
+                            None => NaiveDate::parse_from_str(value, "%Y%m%d")
+                                .ok()
+                                .and_then(|date| date.succ_opt())
+                                .and_then(|date| zone.resolve(date.and_time(NaiveTime::MIN))),
+                        };
+                    }
+                    "BYDAY" => {
+                        parsed.days = value
+                            .split(',')
+                            .map(|day| {
+                                parse_weekday(day).ok_or_else(|| format!("invalid BYDAY {day}"))
+                            })
+                            .collect::<Result<_, _>>()?;
+                    }
+                    "WKST" => (),
+                    _ => return Err(format!("unsupported RRULE part {key}")),
+                }
+            }
+
+            if parsed.days.is_empty() {
+                parsed.days.push(start.weekday());
+            }
+            parsed
+                .days
+                .sort_unstable_by_key(Weekday::num_days_from_monday);
+
+            Ok(parsed)
+        }
+
+        /// Occurrence starts in order, from the first at `start`.
+        fn occurrences(&self, start: NaiveDateTime) -> impl Iterator<Item = NaiveDateTime> {
+            let first_monday = start.date().week(Weekday::Mon).first_day();
+            let time = start.time();
+
+            (0..)
+                .step_by(self.interval as usize)
+                .map_while(move |week| first_monday.checked_add_days(Days::new(week * 7)))
+                .flat_map(move |monday| {
+                    self.days.iter().filter_map(move |day| {
+                        monday
+                            .checked_add_days(Days::new(day.num_days_from_monday().into()))
+                            .map(|date| date.and_time(time))
+                    })
+                })
+                .filter(move |occurrence| *occurrence >= start)
+                .take(self.count.unwrap_or(usize::MAX))
+        }
+    }
+
+    /// Meetings from `event` overlapping the window.
+    fn expand(
+        event: &IcalEvent,
+        window_start: DateTime<Local>,
+        window_end: DateTime<Local>,
+    ) -> Result<Vec<Meeting>, ScheduleError> {
+        let summary = property(event, "SUMMARY")
+            .and_then(|summary| summary.value.clone())
+            .unwrap_or_else(|| "Meeting".to_string());
+        let event_error = |reason: String| ScheduleError::Event {
+            event: summary.clone(),
+            reason,
+        };
+
+        let Some(dtstart) = property(event, "DTSTART") else {
+            return Err(event_error("missing DTSTART".to_string()));
+        };
+        let Some((start, zone)) = parse_time(dtstart).map_err(event_error)? else {
+            return Ok(Vec::new());
+        };
+
+        let duration = if let Some(dtend) = property(event, "DTEND") {
+            let Some((end, end_zone)) = parse_time(dtend).map_err(event_error)? else {
+                return Ok(Vec::new());
+            };
+            match (zone.resolve(start), end_zone.resolve(end)) {
+                (Some(start), Some(end)) => end - start,
+                _ => return Err(event_error("nonexistent local time".to_string())),
+            }
+        } else if let Some(duration) = property(event, "DURATION") {
+            let value = duration.value.as_deref().unwrap_or_default();
+            parse_duration(value).ok_or_else(|| event_error(format!("invalid DURATION {value}")))?
+        } else {
+            TimeDelta::zero()
+        };
+
+        // Cancelled occurrences.
+        let mut excluded = Vec::new();
+        for exdate in event
+            .properties
+            .iter()
+            .filter(|property| property.name == "EXDATE")
+        {
+            for value in exdate.value.as_deref().unwrap_or_default().split(',') {
+                let single = Property {
+                    value: Some(value.to_string()),
+                    ..exdate.clone()
+                };
+                if let Some((time, exdate_zone)) = parse_time(&single).map_err(event_error)? {
+                    excluded.extend(exdate_zone.resolve(time));
+                }
+            }
+        }
+
+        let starts: Vec<NaiveDateTime> = match property(event, "RRULE") {
+            Some(rule) => {
+                let rule =
+                    WeeklyRule::parse(rule.value.as_deref().unwrap_or_default(), start, zone)
+                        .map_err(event_error)?;
+                let window_end = window_end.naive_local() + TimeDelta::days(1);
+                rule.occurrences(start)
+                    .take_while(|occurrence| *occurrence < window_end)
+                    .filter(|occurrence| {
+                        rule.until.is_none_or(|until| {
+                            zone.resolve(*occurrence)
+                                .is_some_and(|occurrence| occurrence <= until)
+                        })
+                    })
+                    .collect()
+            }
+            None => vec![start],
+        };
+
+        Ok(starts
+            .into_iter()
+            .filter_map(|start| zone.resolve(start))
+            .filter(|start| !excluded.contains(start))
+            .map(|start| Meeting {
+                start,
+                end: start + duration,
+                summary: summary.clone(),
+            })
+            .filter(|meeting| meeting.end > window_start && meeting.start < window_end)
+            .collect())
+    }
+}
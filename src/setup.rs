@@ -0,0 +1,259 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! First-run setup, shown when the kiosk starts without a database.
+//!
+//! Offers to move or copy a database left in the launch directory by older
+//! versions. Otherwise asks for the team name, an admin PIN, and an optional
+//! roster CSV, all stored as settings.
+
+use std::{
+    fmt::Display,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use dioxus::prelude::*;
+use tracing::{error, info};
+
+use crate::{DatabaseFile, QrType, lock, sqlite::BackingDatabase};
+
+pub const TEAM_NAME_KEY: &str = "team.name";
+
+/// Whether `database` has yet to be created.
+pub fn needs_setup(database: &str) -> bool {
+    !Path::new(database).exists()
+}
+
+/// `qr-tracker.db` in the launch directory, where older versions kept it.
+pub fn legacy_database() -> Option<PathBuf> {
+    // AppImage changes to a /usr directory, OWD is where it was launched.
+    let dir = std::env::var_os("OWD")
+        .map(PathBuf::from)
+        .or_else(|| std::env::current_dir().ok())?;
+    Some(dir.join("qr-tracker.db")).filter(|path| path.is_file())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Migration {
+    Move,
+    Copy,
+}
+
+impl Display for Migration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Move => write!(f, "moved"),
+            Self::Copy => write!(f, "copied"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Sqlite(rusqlite::Error),
+    Io(io::Error),
+}
+
+impl Display for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sqlite(e) => write!(f, "{e}"),
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Copies `legacy` to `database`, deleting `legacy` and its WAL and lock
+/// files when moving. The outcome is logged to the audit table.
+pub fn migrate(legacy: &Path, database: &str, migration: Migration) -> Result<(), MigrationError> {
+    BackingDatabase::open_read_only(&legacy.to_string_lossy())
+        .and_then(|db| db.copy_to(Path::new(database)))
+        .map_err(MigrationError::Sqlite)?;
+
+    if migration == Migration::Move {
+        for suffix in ["", "-wal", "-shm", ".lock"] {
+            match fs::remove_file(format!("{}{suffix}", legacy.display())) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(MigrationError::Io(e)),
+            }
+        }
+    }
+
+    info!(target: "setup", "{migration} {} to {database}", legacy.display());
+    let logged = BackingDatabase::new(Some(database)).and_then(|db| {
+        db.log_event(
+            "data_migrated",
+            &legacy.to_string_lossy(),
+            &migration.to_string(),
+        )
+    });
+    if let Err(e) = logged {
+        error!(target: "setup", "Failed to audit the migration: {e}");
+    }
+    Ok(())
+}
+
+/// Creates `database` with the answers from the setup form. Returns the
+/// message to show on failure.
+fn create(
+    database: &str,
+    team_name: &str,
+    pin: &str,
+    roster: &str,
+    roster_role: QrType,
+) -> Result<(), String> {
+    let mut db = BackingDatabase::new(Some(database))
+        .map_err(|e| format!("Creating the database failed: {e}"))?;
+
+    if !team_name.is_empty() {
+        db.set_setting(TEAM_NAME_KEY, team_name)
+            .map_err(|e| format!("Saving the team name failed: {e}"))?;
+    }
+    if !pin.is_empty() {
+        lock::set_pin(&mut db, Some(pin)).map_err(|e| format!("Saving the PIN failed: {e}"))?;
+    }
+    if !roster.is_empty() {
+        let report = db
+            .import_roster_csv(Path::new(roster), roster_role)
+            .map_err(|e| format!("Import of {roster} failed: {e}"))?;
+        info!(target: "setup", "Imported {roster}: {report}");
+    }
+    Ok(())
+}
+
+/// Sets `done` once the database exists.
+///
+/// Database work runs on tokio's blocking pool, off the UI thread.
+#[component]
+pub fn Setup(done: Signal<bool>) -> Element {
+    let DatabaseFile(database) = use_context();
+    let legacy = use_hook(legacy_database);
+
+    let mut offer_migration = use_signal(|| legacy.is_some());
+    let mut team_name = use_signal(String::new);
+    let mut pin = use_signal(String::new);
+    let mut roster = use_signal(String::new);
+    let mut roster_role = use_signal(|| QrType::Student);
+    let mut message = use_signal(String::new);
+    let mut busy = use_signal(|| false);
+
+    let migrate_legacy = use_callback({
+        let database = database.clone();
+        let legacy = legacy.clone();
+        move |migration: Migration| {
+            let Some(legacy) = legacy.clone() else {
+                return;
+            };
+            if busy() {
+                return;
+            }
+            busy.set(true);
+            let database = database.clone();
+            spawn(async move {
+                let migrated = tokio::task::spawn_blocking({
+                    let legacy = legacy.clone();
+                    move || migrate(&legacy, &database, migration)
+                })
+                .await;
+                busy.set(false);
+                match migrated {
+                    Ok(Ok(())) => done.set(true),
+                    Ok(Err(e)) => {
+                        error!(target: "setup", "Migrating {} failed: {e}", legacy.display());
+                        message.set(format!("Migration failed: {e}"));
+                    }
+                    Err(e) => message.set(format!("Migration failed: {e}")),
+                }
+            });
+        }
+    });
+
+    let finish = use_callback(move |()| {
+        let pin = pin.read().trim().to_string();
+        if !pin.is_empty() && !lock::valid_pin(&pin) {
+            message.set("The PIN must be 4 to 8 digits".to_string());
+            return;
+        }
+        if busy() {
+            return;
+        }
+        busy.set(true);
+
+        let database = database.clone();
+        let name = team_name.read().trim().to_string();
+        let roster = roster.read().trim().to_string();
+        let role = roster_role();
+        spawn(async move {
+            let created =
+                tokio::task::spawn_blocking(move || create(&database, &name, &pin, &roster, role))
+                    .await
+                    .unwrap_or_else(|e| Err(format!("Creating the database failed: {e}")));
+            busy.set(false);
+            match created {
+                Ok(()) => {
+                    info!(target: "setup", "First-run setup complete");
+                    done.set(true);
+                }
+                Err(e) => message.set(e),
+            }
+        });
+    });
+
+    rsx! {
+        div {
+            class: "centered_horizontally",
+            if let Some(legacy) = legacy.as_ref().filter(|_| offer_migration()) {
+                h1 { "Existing Data Found" }
+                p { "{legacy.display()} was created by an older version of qr-tracker." }
+                button { onclick: move |_| migrate_legacy(Migration::Move), "Move" }
+                button { onclick: move |_| migrate_legacy(Migration::Copy), "Copy" }
+                button { onclick: move |_| offer_migration.set(false), "Start Fresh" }
+            } else {
+                h1 { "Welcome to QR-Tracker" }
+                p {
+                    input {
+                        placeholder: "Team name",
+                        value: "{team_name}",
+                        oninput: move |e| team_name.set(e.value()),
+                    }
+                }
+                p {
+                    input {
+                        r#type: "password",
+                        inputmode: "numeric",
+                        placeholder: "Admin PIN, 4 to 8 digits (optional)",
+                        value: "{pin}",
+                        oninput: move |e| pin.set(e.value()),
+                    }
+                }
+                p {
+                    input {
+                        placeholder: "Roster CSV to import (optional)",
+                        value: "{roster}",
+                        oninput: move |e| roster.set(e.value()),
+                    }
+                    select {
+                        onchange: move |e| {
+                            roster_role.set(if e.value() == "Mentors" {
+                                QrType::Mentor
+                            } else {
+                                QrType::Student
+                            })
+                        },
+                        option { "Students" }
+                        option { "Mentors" }
+                    }
+                }
+                button { disabled: busy(), onclick: move |_| finish(()), "Start" }
+            }
+            if !message.read().is_empty() {
+                h3 { color: "red", "{message}" }
+            }
+        }
+    }
+}
@@ -0,0 +1,350 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Pushes weekly hours reports to a Google Sheets spreadsheet.
+//!
+//! Authenticates as a service account, so the spreadsheet must be shared
+//! with the account's email. Each week gets its own `Hours <monday>` sheet,
+//! which is overwritten when pushed again. Network access requires the
+//! `sheets` feature.
+
+use std::{fmt::Display, path::PathBuf, time::Duration};
+
+use chrono::{Days, Local, NaiveDate, TimeDelta, Weekday};
+use tracing::{info, warn};
+
+use crate::{
+    config::Config,
+    db_worker::DbHandle,
+    sqlite::{BackingDatabase, DbError, date_range},
+};
+
+/// Setting enabling the automatic push of last week's report.
+pub const WEEKLY_KEY: &str = "sheets.weekly";
+/// Setting holding the Monday of the last automatically pushed week.
+const LAST_WEEK_KEY: &str = "sheets.last_week";
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Spreadsheet to push to, from the config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SheetsTarget {
+    pub key_path: PathBuf,
+    pub spreadsheet_id: String,
+}
+
+impl SheetsTarget {
+    pub fn from_config(config: &Config) -> Result<Self, SheetsError> {
+        match (&config.sheets_key, &config.sheets_spreadsheet) {
+            (Some(key_path), Some(spreadsheet_id)) => Ok(Self {
+                key_path: key_path.clone(),
+                spreadsheet_id: spreadsheet_id.clone(),
+            }),
+            _ => Err(SheetsError::NotConfigured),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "sheets"), allow(dead_code))]
+pub enum SheetsError {
+    /// Built without the `sheets` feature.
+    #[cfg(not(feature = "sheets"))]
+    Disabled,
+    NotConfigured,
+    /// The service account key could not be read or used.
+    Key(String),
+    /// Google refused the service account.
+    Auth(String),
+    Quota,
+    Request(String),
+}
+
+impl Display for SheetsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(not(feature = "sheets"))]
+            Self::Disabled => write!(f, "built without the sheets feature"),
+            Self::NotConfigured => write!(
+                f,
+                "set sheets_key and sheets_spreadsheet in the config file"
+            ),
+            Self::Key(e) => write!(f, "service account key: {e}"),
+            Self::Auth(e) => write!(f, "Google rejected the service account: {e}"),
+            Self::Quota => write!(f, "Google Sheets quota exceeded, try again later"),
+            Self::Request(e) => write!(f, "Google Sheets request failed: {e}"),
+        }
+    }
+}
+
+/// Monday of the week containing `date`.
+pub fn week_start(date: NaiveDate) -> NaiveDate {
+    date.week(Weekday::Mon).first_day()
+}
+
+/// Hours per person for sessions starting in the week from `week_start`.
+pub fn week_hours(
+    db: &BackingDatabase,
+    week_start: NaiveDate,
+) -> Result<Vec<(String, TimeDelta)>, DbError> {
+    let (since, until) =
+        date_range(Some(week_start), week_start.checked_add_days(Days::new(6))).unwrap();
+    db.hours_summary(since, Some(until))
+}
+
+/// Pushes last week's report once a new week starts, while enabled.
+///
+/// Failures are logged and retried on the next check.
+pub async fn weekly_push(backing_db: DbHandle, config: Config) {
+    loop {
+        let pending = backing_db
+            .try_run(|db| {
+                let previous_week = week_start(Local::now().date_naive()) - Days::new(7);
+                let pushed = db
+                    .get_setting::<NaiveDate>(LAST_WEEK_KEY)?
+                    .is_some_and(|last_week| last_week >= previous_week);
+
+                if db.get_setting(WEEKLY_KEY)?.unwrap_or(false) && !pushed {
+                    Ok(Some((previous_week, week_hours(db, previous_week)?)))
+                } else {
+                    Ok(None)
+                }
+            })
+            .await
+            .unwrap_or_else(|e| {
+                warn!(target: "sheets", "Reading the weekly push state failed: {e}");
+                None
+            });
+
+        if let Some((week, hours)) = pending {
+            let result = match SheetsTarget::from_config(&config) {
+                Ok(target) => push_week(&target, week, &hours).await,
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(()) => {
+                    info!(target: "sheets", %week, "Pushed weekly hours");
+                    let marked = backing_db
+                        .try_run(move |db| {
+                            db.set_setting(LAST_WEEK_KEY, week)?;
+                            db.log_event("sheets_push", &week.to_string(), "")
+                        })
+                        .await;
+                    if let Err(e) = marked {
+                        warn!(target: "sheets", %week, "Recording the weekly push failed: {e}");
+                    }
+                }
+                Err(e) => warn!(target: "sheets", %week, "Weekly hours push failed: {e}"),
+            }
+        }
+
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(not(feature = "sheets"))]
+pub async fn push_week(
+    _target: &SheetsTarget,
+    _week_start: NaiveDate,
+    _hours: &[(String, TimeDelta)],
+) -> Result<(), SheetsError> {
+    Err(SheetsError::Disabled)
+}
+
+#[cfg(feature = "sheets")]
+pub use google::push_week;
+
+#[cfg(feature = "sheets")]
+mod google {
+    use std::{fs, time::Duration};
+
+    use base64::{
+        Engine,
+        engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    };
+    use chrono::{Local, NaiveDate, TimeDelta};
+    use ring::{rand::SystemRandom, signature};
+    use serde::Deserialize;
+    use serde_json::{Value, json};
+
+    use super::{SheetsError, SheetsTarget};
+
+    const SHEETS_API: &str = "https://sheets.googleapis.com/v4/spreadsheets";
+    const SCOPE: &str = "https://www.googleapis.com/auth/spreadsheets";
+    const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// The fields used from a downloaded service account JSON key.
+    #[derive(Deserialize)]
+    struct ServiceAccountKey {
+        client_email: String,
+        private_key: String,
+        token_uri: String,
+    }
+
+    impl ServiceAccountKey {
+        /// Signed RS256 JWT requesting spreadsheet access.
+        fn assertion(&self) -> Result<String, SheetsError> {
+            let now = Local::now().timestamp();
+            let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+            let claims = URL_SAFE_NO_PAD.encode(
+                json!({
+                    "iss": self.client_email,
+                    "scope": SCOPE,
+                    "aud": self.token_uri,
+                    "iat": now,
+                    "exp": now + 3600,
+                })
+                .to_string(),
+            );
+            let message = format!("{header}.{claims}");
+
+            let der: String = self
+                .private_key
+                .lines()
+                .filter(|line| !line.starts_with("-----"))
+                .collect();
+            let der = STANDARD
+                .decode(der.trim())
+                .map_err(|e| SheetsError::Key(format!("invalid private_key: {e}")))?;
+            let key_pair = signature::RsaKeyPair::from_pkcs8(&der)
+                .map_err(|e| SheetsError::Key(format!("invalid private_key: {e}")))?;
+
+            let mut signature = vec![0; key_pair.public().modulus_len()];
+            key_pair
+                .sign(
+                    &signature::RSA_PKCS1_SHA256,
+                    &SystemRandom::new(),
+                    message.as_bytes(),
+                    &mut signature,
+                )
+                .map_err(|_| SheetsError::Key("signing failed".to_string()))?;
+
+            Ok(format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature)))
+        }
+    }
+
+    fn request_error(e: reqwest::Error) -> SheetsError {
+        SheetsError::Request(e.to_string())
+    }
+
+    /// Maps an unsuccessful response to a readable error.
+    fn api_error(key: &ServiceAccountKey, status: u16, body: &str) -> SheetsError {
+        let body: Value = serde_json::from_str(body).unwrap_or_default();
+        let message = body["error"]["message"]
+            .as_str()
+            .or_else(|| body["error_description"].as_str())
+            .or_else(|| body["error"].as_str())
+            .unwrap_or("no details")
+            .to_string();
+
+        match status {
+            429 => SheetsError::Quota,
+            403 if message.to_ascii_lowercase().contains("quota") => SheetsError::Quota,
+            400 if body["error"] == "invalid_grant" => SheetsError::Auth(message),
+            401 | 403 => SheetsError::Auth(format!(
+                "{message} (is the spreadsheet shared with {}?)",
+                key.client_email
+            )),
+            404 => SheetsError::Request("spreadsheet not found".to_string()),
+            _ => SheetsError::Request(format!("HTTP {status}: {message}")),
+        }
+    }
+
+    async fn check(
+        key: &ServiceAccountKey,
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response, SheetsError> {
+        let status = response.status();
+        if status.is_success() {
+            Ok(response)
+        } else {
+            let body = response.text().await.unwrap_or_default();
+            Err(api_error(key, status.as_u16(), &body))
+        }
+    }
+
+    /// Writes `hours` to the `Hours <week_start>` sheet, creating it if needed.
+    pub async fn push_week(
+        target: &SheetsTarget,
+        week_start: NaiveDate,
+        hours: &[(String, TimeDelta)],
+    ) -> Result<(), SheetsError> {
+        let key = fs::read_to_string(&target.key_path)
+            .map_err(|e| SheetsError::Key(format!("{}: {e}", target.key_path.display())))?;
+        let key: ServiceAccountKey = serde_json::from_str(&key)
+            .map_err(|e| SheetsError::Key(format!("{}: {e}", target.key_path.display())))?;
+
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(request_error)?;
+
+        let response = client
+            .post(&key.token_uri)
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", &key.assertion()?),
+            ])
+            .send()
+            .await
+            .map_err(request_error)?;
+        let token: Value = check(&key, response)
+            .await?
+            .json()
+            .await
+            .map_err(request_error)?;
+        let token = token["access_token"]
+            .as_str()
+            .ok_or_else(|| SheetsError::Auth("no access token returned".to_string()))?;
+
+        let spreadsheet = format!("{SHEETS_API}/{}", target.spreadsheet_id);
+        let title = format!("Hours {week_start}");
+
+        // Fails when the sheet exists, which is then overwritten.
+        let response = client
+            .post(format!("{spreadsheet}:batchUpdate"))
+            .bearer_auth(token)
+            .json(&json!({ "requests": [{ "addSheet": { "properties": { "title": title } } }] }))
+            .send()
+            .await
+            .map_err(request_error)?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            if !(status.as_u16() == 400 && body.contains("already exists")) {
+                return Err(api_error(&key, status.as_u16(), &body));
+            }
+        }
+
+        let range = format!("'{title}'!A:B")
+            .replace(' ', "%20")
+            .replace('\'', "%27");
+        let response = client
+            .post(format!("{spreadsheet}/values/{range}:clear"))
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(request_error)?;
+        check(&key, response).await?;
+
+        let mut values = vec![json!(["Name", "Hours"])];
+        values.extend(hours.iter().map(|(name, duration)| {
+            let hours = (duration.num_minutes() as f64 / 60.0 * 100.0).round() / 100.0;
+            json!([name, hours])
+        }));
+        let response = client
+            .put(format!("{spreadsheet}/values/{range}?valueInputOption=RAW"))
+            .bearer_auth(token)
+            .json(&json!({ "values": values }))
+            .send()
+            .await
+            .map_err(request_error)?;
+        check(&key, response).await?;
+
+        Ok(())
+    }
+}
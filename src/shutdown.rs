@@ -0,0 +1,114 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Orderly exit on window close, SIGTERM, and SIGINT.
+//!
+//! Stops the video pipeline, releasing the camera, and checkpoints the WAL into the database file before
+//! exiting, bounded well below systemd's default 90 second stop timeout.
+
+use std::{
+    process::exit,
+    sync::{
+        Condvar, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use rusqlite::Connection;
+use tracing::{error, info, warn};
+
+/// Longest wait for the video threads to stop and release the camera.
+const VIDEO_TIMEOUT: Duration = Duration::from_secs(2);
+/// Longest wait for in progress writes before checkpointing.
+const CHECKPOINT_BUSY_TIMEOUT: Duration = Duration::from_secs(3);
+/// Exits regardless once shutdown takes this long.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Set once the video thread has returned, with the camera closed.
+static VIDEO_STOPPED: (Mutex<bool>, Condvar) = (Mutex::new(false), Condvar::new());
+
+/// True once shutdown has started, long running threads should stop.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Called once the video thread returns.
+pub fn video_stopped() {
+    let (stopped, changed) = &VIDEO_STOPPED;
+    *stopped.lock().unwrap() = true;
+    changed.notify_all();
+}
+
+/// Runs [`shutdown`] on the first SIGTERM or SIGINT.
+///
+/// `video` is whether a video thread needs to be waited on.
+#[cfg(unix)]
+pub fn handle_signals(database: String, video: bool) {
+    use signal_hook::{
+        consts::{SIGINT, SIGTERM},
+        iterator::Signals,
+    };
+
+    let mut signals = match Signals::new([SIGTERM, SIGINT]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            warn!(target: "shutdown", "Failed to install signal handlers: {e}");
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            info!(target: "shutdown", signal, "Received stop signal");
+            shutdown(&database, video);
+        }
+    });
+}
+
+/// Windows has no SIGTERM, services are stopped by closing the window.
+#[cfg(not(unix))]
+pub fn handle_signals(_database: String, _video: bool) {}
+
+/// Stops video, checkpoints `database`, and exits successfully.
+pub fn shutdown(database: &str, video: bool) -> ! {
+    // A second request (e.g. closing the window mid signal) waits on the first.
+    if REQUESTED.swap(true, Ordering::Relaxed) {
+        loop {
+            thread::park();
+        }
+    }
+    info!(target: "shutdown", "Shutting down");
+
+    thread::spawn(|| {
+        thread::sleep(SHUTDOWN_TIMEOUT);
+        error!(target: "shutdown", "Shutdown timed out, exiting anyway");
+        exit(1);
+    });
+
+    if video {
+        let (stopped, changed) = &VIDEO_STOPPED;
+        let (stopped, _) = changed
+            .wait_timeout_while(stopped.lock().unwrap(), VIDEO_TIMEOUT, |stopped| !*stopped)
+            .unwrap();
+        if !*stopped {
+            warn!(target: "shutdown", "Video did not stop in time");
+        }
+    }
+
+    let checkpoint = Connection::open(database).and_then(|conn| {
+        conn.busy_timeout(CHECKPOINT_BUSY_TIMEOUT)?;
+        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")
+    });
+    match checkpoint {
+        Ok(()) => info!(target: "shutdown", "Checkpointed {database}"),
+        Err(e) => error!(target: "shutdown", "Final checkpoint of {database} failed: {e}"),
+    }
+
+    exit(0)
+}
@@ -0,0 +1,82 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Signs out everyone still present at a set time each night.
+//!
+//! Sign-outs are recorded as scans at the cutoff with the `auto-signout`
+//! source, so hours end at the cutoff instead of running on to the next scan.
+//! A cutoff missed while the kiosk was off is applied on startup.
+
+use std::time::Duration;
+
+use chrono::{DateTime, Days, Local, NaiveTime};
+use tracing::{info, warn};
+
+use crate::{db_worker::DbHandle, sqlite::local_datetime};
+
+/// Setting holding the local time of the nightly sign-out, e.g. `23:59`.
+pub const TIME_KEY: &str = "signout.time";
+/// Recorded as the source of each automatic sign-out.
+pub const SOURCE: &str = "auto-signout";
+
+/// Longest wait before re-reading the sign-out time, so changes apply
+/// without a restart.
+const RECHECK: Duration = Duration::from_secs(60);
+
+pub const DEFAULT_TIME: NaiveTime = NaiveTime::from_hms_opt(23, 59, 0).unwrap();
+
+/// The latest cutoff at or before `now`.
+fn last_cutoff(time: NaiveTime, now: DateTime<Local>) -> DateTime<Local> {
+    let today = now.date_naive();
+    let at = |date| local_datetime(date, time);
+
+    let cutoff = at(today);
+    if cutoff <= now {
+        cutoff
+    } else {
+        at(today - Days::new(1))
+    }
+}
+
+/// Signs out everyone present since before the configured time, at startup
+/// and then every night. `refresh_tx` reloads the kiosk's present lists.
+///
+/// The time is re-read every minute. Moving it earlier than now applies the
+/// new cutoff right away.
+pub async fn nightly_sign_out(backing_db: DbHandle, refresh_tx: async_channel::Sender<()>) {
+    let mut applied = None;
+    loop {
+        let time = backing_db
+            .try_run(|db| Ok(db.get_setting(TIME_KEY)?.unwrap_or(DEFAULT_TIME)))
+            .await
+            .unwrap_or(DEFAULT_TIME);
+        let now = Local::now();
+
+        let cutoff = last_cutoff(time, now);
+        if applied.is_none_or(|applied| cutoff > applied) {
+            // Left unapplied on failure, to retry at the next recheck.
+            match backing_db.sign_out_all(cutoff, SOURCE).await {
+                Ok(signed_out) => {
+                    applied = Some(cutoff);
+                    if !signed_out.is_empty() {
+                        info!(
+                            target: "attendance",
+                            count = signed_out.len(),
+                            %cutoff,
+                            "Signed out everyone still present"
+                        );
+                        let _ = refresh_tx.try_send(());
+                    }
+                }
+                Err(e) => warn!(target: "attendance", %cutoff, "Failed to sign out everyone: {e}"),
+            }
+        }
+
+        let next = last_cutoff(time, now + Days::new(1));
+        let wait = (next - now).to_std().unwrap_or(RECHECK).min(RECHECK);
+        tokio::time::sleep(wait).await;
+    }
+}
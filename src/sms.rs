@@ -0,0 +1,381 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Texts contacts, e.g. parents, when someone checks in or out.
+//!
+//! Sent through Twilio, with credentials from the config file or environment
+//! only. Nothing runs unless all three Twilio options are set. Sending
+//! requires the `sms` feature.
+
+use std::{collections::BTreeMap, fmt::Display, time::Duration};
+
+use crate::{
+    config::Config,
+    db_worker::DbHandle,
+    observer::{ScanEvent, ScanObserver},
+    sqlite::Contact,
+};
+
+/// Setting holding the check-in message, `{name}` and `{time}` are replaced.
+pub const TEMPLATE_IN_KEY: &str = "sms.template_in";
+/// Setting holding the check-out message, `{name}` and `{time}` are replaced.
+pub const TEMPLATE_OUT_KEY: &str = "sms.template_out";
+
+const DEFAULT_TEMPLATE_IN: &str = "{name} checked in at {time}";
+const DEFAULT_TEMPLATE_OUT: &str = "{name} checked out at {time}";
+
+/// Scans within this window are sent as one message per phone.
+const BATCH_WINDOW: Duration = Duration::from_secs(30);
+/// Twilio queues messages from a long code number past one per second.
+const SEND_SPACING: Duration = Duration::from_secs(1);
+#[cfg_attr(not(feature = "sms"), allow(dead_code))]
+const TWILIO_API: &str = "https://api.twilio.com";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TwilioCredentials {
+    pub account_sid: String,
+    pub auth_token: String,
+    /// Twilio number messages are sent from.
+    pub from: String,
+    /// [`TWILIO_API`] outside of tests.
+    pub api_base: String,
+}
+
+impl TwilioCredentials {
+    /// `None` unless every Twilio option is set.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        Some(Self {
+            account_sid: config.twilio_account_sid.clone()?,
+            auth_token: config.twilio_auth_token.clone()?,
+            from: config.twilio_from.clone()?,
+            api_base: TWILIO_API.to_string(),
+        })
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "sms"), allow(dead_code))]
+pub enum SmsError {
+    /// Built without the `sms` feature.
+    #[cfg(not(feature = "sms"))]
+    Disabled,
+    Http(String),
+    Status(String),
+}
+
+impl Display for SmsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(not(feature = "sms"))]
+            Self::Disabled => write!(f, "built without the sms feature"),
+            Self::Http(e) => write!(f, "{e}"),
+            Self::Status(status) => write!(f, "Twilio responded {status}"),
+        }
+    }
+}
+
+/// Forwards scans to [`send_sms_notifications`].
+pub struct SmsObserver {
+    events_tx: async_channel::Sender<ScanEvent>,
+}
+
+impl SmsObserver {
+    pub fn new(events_tx: async_channel::Sender<ScanEvent>) -> Self {
+        Self { events_tx }
+    }
+}
+
+impl ScanObserver for SmsObserver {
+    fn name(&self) -> &str {
+        "sms"
+    }
+
+    fn on_scan(&self, event: &ScanEvent) {
+        let _ = self.events_tx.try_send(event.clone());
+    }
+}
+
+fn render(template: &str, event: &ScanEvent) -> String {
+    template
+        .replace("{name}", &event.name)
+        .replace("{time}", &event.time.format("%H:%M").to_string())
+}
+
+/// Message lines for each phone with a contact wanting one of `events`.
+fn messages(
+    events: &[ScanEvent],
+    contacts: &[Contact],
+    template_in: &str,
+    template_out: &str,
+) -> BTreeMap<String, Vec<String>> {
+    let mut messages: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for event in events {
+        for contact in contacts.iter().filter(|contact| {
+            contact.name == event.name
+                && if event.signed_in {
+                    contact.notify_in
+                } else {
+                    contact.notify_out
+                }
+        }) {
+            let template = if event.signed_in {
+                template_in
+            } else {
+                template_out
+            };
+            messages
+                .entry(contact.phone.clone())
+                .or_default()
+                .push(render(template, event));
+        }
+    }
+    messages
+}
+
+/// Texts each contact of the scanned people, one message per phone per
+/// [`BATCH_WINDOW`]. Failures are logged to the audit table.
+pub async fn send_sms_notifications(
+    backing_db: DbHandle,
+    credentials: TwilioCredentials,
+    events_rx: async_channel::Receiver<ScanEvent>,
+) {
+    while let Ok(first) = events_rx.recv().await {
+        tokio::time::sleep(BATCH_WINDOW).await;
+
+        let mut events = vec![first];
+        while let Ok(event) = events_rx.try_recv() {
+            events.push(event);
+        }
+
+        let (template_in, template_out, contacts) = backing_db
+            .try_run(|db| {
+                Ok((
+                    db.get_setting::<String>(TEMPLATE_IN_KEY)?
+                        .unwrap_or_else(|| DEFAULT_TEMPLATE_IN.to_string()),
+                    db.get_setting::<String>(TEMPLATE_OUT_KEY)?
+                        .unwrap_or_else(|| DEFAULT_TEMPLATE_OUT.to_string()),
+                    db.get_contacts()?,
+                ))
+            })
+            .await
+            .unwrap_or_else(|e| {
+                tracing::warn!(target: "sms", "Failed to read contacts, texts dropped: {e}");
+                Default::default()
+            });
+
+        for (phone, lines) in messages(&events, &contacts, &template_in, &template_out) {
+            match send(&credentials, &phone, &lines.join("\n")).await {
+                Ok(()) => tracing::debug!(target: "sms", phone, "Sent text"),
+                Err(e) => {
+                    tracing::warn!(target: "sms", phone, "Text failed: {e}");
+                    let detail = e.to_string();
+                    let _ = backing_db
+                        .try_run(move |db| db.log_event("sms_failed", &phone, &detail))
+                        .await;
+                }
+            }
+            tokio::time::sleep(SEND_SPACING).await;
+        }
+    }
+}
+
+#[cfg(feature = "sms")]
+async fn send(credentials: &TwilioCredentials, to: &str, body: &str) -> Result<(), SmsError> {
+    let url = format!(
+        "{}/2010-04-01/Accounts/{}/Messages.json",
+        credentials.api_base, credentials.account_sid
+    );
+    let response = reqwest::Client::new()
+        .post(url)
+        .basic_auth(&credentials.account_sid, Some(&credentials.auth_token))
+        .form(&[("To", to), ("From", &credentials.from), ("Body", body)])
+        .send()
+        .await
+        .map_err(|e| SmsError::Http(e.to_string()))?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(SmsError::Status(response.status().to_string()))
+    }
+}
+
+#[cfg(not(feature = "sms"))]
+async fn send(_credentials: &TwilioCredentials, _to: &str, _body: &str) -> Result<(), SmsError> {
+    Err(SmsError::Disabled)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use super::*;
+
+    fn event(name: &str, signed_in: bool) -> ScanEvent {
+        ScanEvent {
+            name: name.to_string(),
+            role: crate::QrType::Student,
+            signed_in,
+            time: Local.with_ymd_and_hms(2024, 9, 3, 18, 5, 0).unwrap(),
+            present: 1,
+            source: None,
+        }
+    }
+
+    fn contact(name: &str, phone: &str, notify_in: bool, notify_out: bool) -> Contact {
+        Contact {
+            name: name.to_string(),
+            phone: phone.to_string(),
+            notify_in,
+            notify_out,
+        }
+    }
+
+    #[test]
+    fn inert_without_every_credential() {
+        let mut config = Config::default();
+        assert_eq!(TwilioCredentials::from_config(&config), None);
+
+        config.twilio_account_sid = Some("AC123".to_string());
+        config.twilio_auth_token = Some("secret".to_string());
+        assert_eq!(TwilioCredentials::from_config(&config), None);
+
+        config.twilio_from = Some("+15555550100".to_string());
+        assert_eq!(
+            TwilioCredentials::from_config(&config),
+            Some(TwilioCredentials {
+                account_sid: "AC123".to_string(),
+                auth_token: "secret".to_string(),
+                from: "+15555550100".to_string(),
+                api_base: TWILIO_API.to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn messages_follow_contact_choices() {
+        let contacts = [
+            contact("Alice", "+15555550001", true, false),
+            contact("Bob", "+15555550001", true, true),
+            contact("Bob", "+15555550002", false, true),
+        ];
+        let events = [
+            event("Alice", true),
+            event("Bob", true),
+            event("Alice", false),
+            event("Bob", false),
+            event("Carol", true),
+        ];
+
+        let messages = messages(&events, &contacts, "{name} in {time}", "{name} out {time}");
+        assert_eq!(
+            messages.into_iter().collect::<Vec<_>>(),
+            [
+                (
+                    "+15555550001".to_string(),
+                    vec![
+                        "Alice in 18:05".to_string(),
+                        "Bob in 18:05".to_string(),
+                        "Bob out 18:05".to_string(),
+                    ]
+                ),
+                (
+                    "+15555550002".to_string(),
+                    vec!["Bob out 18:05".to_string()]
+                ),
+            ]
+        );
+    }
+
+    /// Answers one request with `status`, passing on the request line,
+    /// authorization, and body.
+    #[cfg(feature = "sms")]
+    fn fake_twilio(status: &'static str) -> (String, std::sync::mpsc::Receiver<[String; 3]>) {
+        use std::{
+            io::{BufRead, BufReader, Read, Write},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (request_tx, request_rx) = std::sync::mpsc::channel();
+
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let (mut length, mut authorization) = (0, String::new());
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = line.split_once(':').unwrap();
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                } else if name.eq_ignore_ascii_case("authorization") {
+                    authorization = value.trim().to_string();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+
+            let _ = stream.write_all(
+                format!("HTTP/1.1 {status}\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}")
+                    .as_bytes(),
+            );
+            let _ = request_tx.send([
+                request_line.trim_end().to_string(),
+                authorization,
+                String::from_utf8(body).unwrap(),
+            ]);
+        });
+        (url, request_rx)
+    }
+
+    #[cfg(feature = "sms")]
+    #[tokio::test]
+    async fn twilio_request_shape() {
+        let (url, requests) = fake_twilio("201 Created");
+        let credentials = TwilioCredentials {
+            account_sid: "AC123".to_string(),
+            auth_token: "secret".to_string(),
+            from: "+15555550100".to_string(),
+            api_base: url,
+        };
+
+        send(&credentials, "+15555550001", "Alice in 18:05\nBob in 18:05")
+            .await
+            .unwrap();
+        let [request_line, authorization, body] = requests.recv().unwrap();
+        assert_eq!(
+            request_line,
+            "POST /2010-04-01/Accounts/AC123/Messages.json HTTP/1.1"
+        );
+        // "AC123:secret"
+        assert_eq!(authorization, "Basic QUMxMjM6c2VjcmV0");
+        assert_eq!(
+            body,
+            "To=%2B15555550001&From=%2B15555550100&Body=Alice+in+18%3A05%0ABob+in+18%3A05"
+        );
+
+        let (url, _requests) = fake_twilio("401 Unauthorized");
+        let refused = send(
+            &TwilioCredentials {
+                api_base: url,
+                ..credentials
+            },
+            "+15555550001",
+            "Alice in 18:05",
+        )
+        .await;
+        assert!(matches!(refused, Err(SmsError::Status(status)) if status.starts_with("401")));
+    }
+}
@@ -0,0 +1,122 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Saves the camera frame behind each accepted camera scan, for auditing.
+//!
+//! Frames are written to `snapshots/<name>/<timestamp>.jpg` in the data
+//! directory, with the path recorded on the scan. Snapshots past the
+//! retention period are deleted on startup.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Local, TimeDelta};
+use tracing::{info, warn};
+
+use crate::{
+    CAMERA_CONNECTED, LATEST_FRAME,
+    config::{self, DataPath},
+    sqlite::{BackingDatabase, DbError},
+};
+
+/// Setting controlling whether snapshots are saved at all.
+pub const ENABLED_KEY: &str = "snapshot.enabled";
+/// Setting holding the days snapshots are kept.
+pub const RETENTION_DAYS_KEY: &str = "snapshot.retention_days";
+
+pub const DEFAULT_RETENTION_DAYS: u32 = 30;
+
+pub fn enabled(db: &BackingDatabase) -> Result<bool, DbError> {
+    Ok(db.get_setting(ENABLED_KEY)?.unwrap_or(true))
+}
+
+/// `name` with anything but letters, digits, and `-` replaced by `_`, so it
+/// is a single path component.
+fn dir_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Writes the latest camera frame for `name`'s scan at `time`, returning
+/// where it was written. `None` without a connected camera.
+pub fn save(name: &str, time: DateTime<Local>) -> Option<PathBuf> {
+    if !CAMERA_CONNECTED.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let dir = config::data_path(DataPath::Snapshots).join(dir_name(name));
+    let path = dir.join(format!("{}.jpg", time.format("%Y%m%dT%H%M%S")));
+    let written = {
+        let frame = LATEST_FRAME.read().unwrap();
+        if frame.is_empty() {
+            return None;
+        }
+        fs::create_dir_all(&dir).and_then(|()| fs::write(&path, &*frame))
+    };
+
+    match written {
+        Ok(()) => Some(path),
+        Err(e) => {
+            warn!(target: "snapshot", "Failed to write {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+/// Deletes snapshot files older than the retention period, and forgets them
+/// on their scans.
+pub fn prune(db: &mut BackingDatabase) -> Result<(), DbError> {
+    let days = db
+        .get_setting(RETENTION_DAYS_KEY)?
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+    let cutoff = Local::now() - TimeDelta::days(days.into());
+    let max_age = Duration::from_secs(u64::from(days) * 24 * 60 * 60);
+
+    let mut removed = 0;
+    let root = config::data_path(DataPath::Snapshots);
+    for dir in read_dir(&root) {
+        for file in read_dir(&dir) {
+            let expired = fs::metadata(&file)
+                .and_then(|metadata| metadata.modified())
+                .is_ok_and(|modified| {
+                    SystemTime::now()
+                        .duration_since(modified)
+                        .is_ok_and(|age| age > max_age)
+                });
+            if expired {
+                match fs::remove_file(&file) {
+                    Ok(()) => removed += 1,
+                    Err(e) => warn!(target: "snapshot", "Failed to delete {}: {e}", file.display()),
+                }
+            }
+        }
+        // Only succeeds once everyone's snapshots are gone.
+        let _ = fs::remove_dir(&dir);
+    }
+
+    db.clear_snapshots_before(cutoff)?;
+    if removed > 0 {
+        info!(target: "snapshot", removed, days, "Pruned snapshots");
+    }
+    Ok(())
+}
+
+fn read_dir(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir)
+        .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+        .unwrap_or_default()
+}
@@ -4,163 +4,3111 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use chrono::{DateTime, Local};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs,
+    hash::{BuildHasher, RandomState},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveTime, TimeDelta, TimeZone};
 use nokhwa::utils::Resolution;
-use rusqlite::Connection;
+use rusqlite::{Connection, ErrorCode, OpenFlags, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+
+use crate::{
+    QrType,
+    config::{self, DataPath},
+    db_worker,
+    migrations::{self, MigrationError},
+    report::{self, Report, ReportRow},
+    scan_queue::QueuedScan,
+    setup::TEAM_NAME_KEY,
+};
+
+/// Source of scans from cameras other than the main one, followed by the
+/// camera index.
+pub const CAMERA_SOURCE_PREFIX: &str = "camera-";
+
+/// Start and end (exclusive) of a span of time.
+pub type DateRange = (DateTime<Local>, DateTime<Local>);
+
+/// Each person's scans, oldest first, sorted by name.
+pub type ScansByName = Vec<(String, Vec<DateTime<Local>>)>;
+
+/// Reasons a scan timestamp edit is refused.
+#[derive(Debug)]
+pub enum ScanEditError {
+    /// No scan exists for the name at the original timestamp.
+    MissingScan,
+    /// The new timestamp would move the scan past a neighboring scan,
+    /// flipping the in/out meaning of every scan after it.
+    Reorders,
+    Db(DbError),
+}
+
+impl Display for ScanEditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingScan => write!(f, "scan no longer exists"),
+            Self::Reorders => write!(f, "new time must stay between the surrounding scans"),
+            Self::Db(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Failures of reads and writes the kiosk keeps running through, e.g. a
+/// database file locked by another program.
+#[derive(Debug)]
+pub enum DbError {
+    Sqlite(rusqlite::Error),
+    /// A stored timestamp is out of range, the row is skipped.
+    CorruptTimestamp {
+        name: String,
+        timestamp: i64,
+    },
+    /// The row to change no longer exists.
+    MissingRow,
+    /// The database worker stopped, or panicked on the request.
+    WorkerFailed,
+    /// Bringing the schema up to date on open failed.
+    Migration(MigrationError),
+}
+
+impl DbError {
+    /// Another connection holds the write lock.
+    pub fn is_busy(&self) -> bool {
+        matches!(
+            self,
+            Self::Sqlite(rusqlite::Error::SqliteFailure(e, _))
+                if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+        )
+    }
+
+    /// The database could not be reached or written, rather than refusing
+    /// the change, e.g. a full disk or a dropped network mount. Worth
+    /// retrying later.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::Sqlite(rusqlite::Error::SqliteFailure(e, _))
+                if matches!(
+                    e.code,
+                    ErrorCode::DatabaseBusy
+                        | ErrorCode::DatabaseLocked
+                        | ErrorCode::DiskFull
+                        | ErrorCode::SystemIoFailure
+                        | ErrorCode::CannotOpen
+                )
+        )
+    }
+}
+
+impl Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Sqlite(e) => write!(f, "{e}"),
+            Self::CorruptTimestamp { name, timestamp } => {
+                write!(f, "corrupt timestamp {timestamp} for {name}")
+            }
+            Self::MissingRow => write!(f, "row no longer exists"),
+            Self::WorkerFailed => write!(f, "database worker failed"),
+            Self::Migration(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+pub const BACKUP_DIR_KEY: &str = "backup.dir";
+pub const MAX_SESSION_HOURS_KEY: &str = "hours.max_session";
+
+/// Sessions without a sign-out are capped at this many hours.
+const DEFAULT_MAX_SESSION_HOURS: i64 = 6;
+pub const LAST_BACKUP_KEY: &str = "backup.last";
+/// Latest backup failure, cleared by the next backup.
+pub const LAST_BACKUP_ERROR_KEY: &str = "backup.last_error";
+/// Hours between automatic backups, 0 turns them off.
+pub const BACKUP_INTERVAL_HOURS_KEY: &str = "backup.interval_hours";
+/// Backups kept in [`BackingDatabase::backup_dir`], older ones are deleted.
+pub const BACKUP_KEEP_KEY: &str = "backup.keep";
+
+pub const DEFAULT_BACKUP_INTERVAL_HOURS: i64 = 24;
+pub const DEFAULT_BACKUP_KEEP: usize = 7;
+/// In every backup's file name, after any team name.
+const BACKUP_STEM: &str = "qr-tracker-";
+/// Days audit entries are kept, pruned on startup. Unset keeps them all.
+const AUDIT_RETENTION_DAYS_KEY: &str = "audit.retention_days";
+/// Camera picked in the UI.
+const CAMERA_INDEX_KEY: &str = "camera.index";
+/// Camera resolution picked in the UI, as `<width>x<height>`.
+pub const RESOLUTION_KEY: &str = "camera.resolution";
+/// Setting holding the seconds required between two scans of one badge.
+const SCAN_SPACING_KEY: &str = "scan.spacing_secs";
+/// Setting holding the seconds after signing in before a scan signs out.
+const MIN_DWELL_KEY: &str = "scan.min_dwell_secs";
+/// Setting holding the most people allowed present at once, unset for no
+/// limit.
+const MAX_OCCUPANCY_KEY: &str = "occupancy.max";
+
+/// File usage of the database.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DbStats {
+    /// `None` for in-memory databases.
+    pub path: Option<PathBuf>,
+    pub size: u64,
+    pub wal_size: u64,
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    Io(io::Error),
+    Sqlite(rusqlite::Error),
+    /// The database has no file to copy.
+    InMemory,
+    /// Another backup is still being written.
+    InProgress,
+    Db(DbError),
+}
+
+impl Display for BackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Sqlite(e) => write!(f, "{e}"),
+            Self::InMemory => write!(f, "in-memory databases cannot be backed up"),
+            Self::InProgress => write!(f, "a backup is already running"),
+            Self::Db(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Outcome of [`BackingDatabase::import_roster_csv`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    pub added: usize,
+    pub skipped_duplicate: usize,
+    /// Already on the other roster.
+    pub skipped_other_role: usize,
+    pub malformed: usize,
+}
+
+impl Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} added, {} duplicates skipped, {} on the other roster skipped, {} malformed",
+            self.added, self.skipped_duplicate, self.skipped_other_role, self.malformed
+        )
+    }
+}
+
+/// Someone on the mentor or student roster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    pub name: String,
+    pub role: QrType,
+    /// Inactive members stay in reports but their badges are refused.
+    pub active: bool,
+}
+
+/// A roster change that was refused, or failed.
+#[derive(Debug)]
+pub enum RosterError {
+    /// Already on the roster for `role`. A name on both would only count as
+    /// a mentor.
+    DuplicateAcrossRoles {
+        name: String,
+        role: QrType,
+    },
+    Db(DbError),
+}
+
+impl Display for RosterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DuplicateAcrossRoles { name, role } => write!(f, "{name} is already a {role}"),
+            Self::Db(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Inconsistent data found by [`BackingDatabase::integrity_check`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue {
+    /// On both rosters, counted as a mentor.
+    OnBothRosters(String),
+    /// In the `current` table without being on a roster or a guest.
+    OrphanedPresence(String),
+    /// A scan from before 2000 or more than a day ahead.
+    ImpossibleTimestamp { name: String, timestamp: i64 },
+}
+
+impl Display for IntegrityIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::OnBothRosters(name) => write!(f, "{name} is both a mentor and a student"),
+            Self::OrphanedPresence(name) => {
+                write!(f, "{name} has a presence entry but is not on a roster")
+            }
+            Self::ImpossibleTimestamp { name, timestamp } => {
+                write!(f, "{name} has a scan at impossible Unix time {timestamp}")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Csv(csv::Error),
+    Sqlite(rusqlite::Error),
+    Db(DbError),
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Csv(e) => write!(f, "{e}"),
+            Self::Sqlite(e) => write!(f, "{e}"),
+            Self::Db(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Csv(csv::Error),
+    Sqlite(rusqlite::Error),
+}
+
+impl Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Csv(e) => write!(f, "{e}"),
+            Self::Sqlite(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// `time` on `date` in the kiosk's timezone.
+///
+/// A time skipped by a DST change, e.g. midnight in America/Santiago, moves
+/// an hour later. A time repeated by one is its first occurrence.
+pub fn local_datetime(date: NaiveDate, time: NaiveTime) -> DateTime<Local> {
+    datetime_in(&Local, date, time)
+}
+
+fn datetime_in<Tz: TimeZone>(tz: &Tz, date: NaiveDate, time: NaiveTime) -> DateTime<Tz> {
+    let naive = date.and_time(time);
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| {
+            naive
+                .checked_add_signed(TimeDelta::hours(1))
+                .and_then(|later| tz.from_local_datetime(&later).earliest())
+        })
+        .unwrap_or_else(|| tz.from_utc_datetime(&naive))
+}
+
+/// Start and end (exclusive) of a local calendar day, in timestamp seconds.
+fn day_bounds(date: NaiveDate) -> (i64, i64) {
+    let start_of = |date: NaiveDate| local_datetime(date, NaiveTime::MIN).timestamp();
+    (start_of(date), start_of(date.succ_opt().unwrap()))
+}
+
+/// [`day_bounds`] widened by a day each way, holding every scan that could
+/// fall on `date` under any UTC offset. Narrow down with [`scan_day`].
+fn scan_day_bounds(date: NaiveDate) -> (i64, i64) {
+    const DAY_SECS: i64 = 24 * 60 * 60;
+    let (start, end) = day_bounds(date);
+    (start - DAY_SECS, end + DAY_SECS)
+}
+
+/// Stored Unix seconds in the kiosk's timezone.
+fn local_time(timestamp: i64) -> Option<DateTime<Local>> {
+    DateTime::from_timestamp_secs(timestamp).map(|timestamp| timestamp.with_timezone(&Local))
+}
+
+/// Seconds east of UTC, stored with each scan.
+fn utc_offset(time: &DateTime<Local>) -> i32 {
+    time.offset().local_minus_utc()
+}
+
+/// Local calendar day a scan was made on, by the UTC offset recorded with it.
+/// A timezone or DST change since then does not move it to another day.
+/// Scans from before offsets were recorded use the current timezone.
+fn scan_day(timestamp: i64, utc_offset: Option<i32>) -> Option<NaiveDate> {
+    let time = DateTime::from_timestamp_secs(timestamp)?;
+    match utc_offset.and_then(FixedOffset::east_opt) {
+        Some(offset) => Some(time.with_timezone(&offset).date_naive()),
+        None => Some(time.with_timezone(&Local).date_naive()),
+    }
+}
+
+/// A scan's time as shown in the audit log, in the UTC offset recorded with
+/// it.
+fn scan_time_label(timestamp: i64, utc_offset: Option<i32>) -> String {
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S %:z";
+    match (
+        DateTime::from_timestamp_secs(timestamp),
+        utc_offset.and_then(FixedOffset::east_opt),
+    ) {
+        (Some(time), Some(offset)) => time.with_timezone(&offset).format(FORMAT).to_string(),
+        (Some(time), None) => time.with_timezone(&Local).format(FORMAT).to_string(),
+        (None, _) => timestamp.to_string(),
+    }
+}
+
+/// Read-only views for external dashboards such as Grafana.
+///
+/// Timestamps are stored as UTC Unix seconds. Dates and weeks use the UTC
+/// offset recorded with each scan, like [`scan_day`], so they match the
+/// kiosk's own reports wherever the query runs and across DST changes. Scans
+/// from before offsets were recorded fall back to SQLite's `localtime`, the
+/// timezone of the machine running the query. Weeks start on Monday. Hours pair scans the same way as
+/// [`BackingDatabase::hours_summary`], by the week each session starts in.
+fn reporting_views() -> String {
+    format!(
+        "CREATE VIEW v_daily_attendance AS
+SELECT COALESCE(
+        date(attendance.timestamp + attendance.utc_offset, 'unixepoch'),
+        date(attendance.timestamp, 'unixepoch', 'localtime')
+    ) AS date,
+    CASE
+        WHEN mentors.name IS NOT NULL THEN 'mentor'
+        WHEN students.name IS NOT NULL THEN 'student'
+        WHEN attendance.name LIKE 'Guest%' THEN 'guest'
+        ELSE 'unknown'
+    END AS role,
+    COUNT(DISTINCT attendance.name) AS distinct_people
+FROM attendance
+LEFT JOIN mentors ON mentors.name = attendance.name
+LEFT JOIN students ON students.name = attendance.name
+GROUP BY 1, 2;
+
+CREATE VIEW v_person_hours AS
+WITH scans AS (
+    SELECT name, timestamp, utc_offset,
+        ROW_NUMBER() OVER (PARTITION BY name ORDER BY timestamp) AS n,
+        LEAD(timestamp) OVER (PARTITION BY name ORDER BY timestamp) AS next
+    FROM attendance
+)
+SELECT name,
+    COALESCE(
+        date(timestamp + utc_offset, 'unixepoch', 'weekday 0', '-6 days'),
+        date(timestamp, 'unixepoch', 'localtime', 'weekday 0', '-6 days')
+    ) AS week,
+    SUM(MIN(
+        COALESCE(next, CAST(strftime('%s', 'now') AS INTEGER)) - timestamp,
+        COALESCE(
+            (SELECT CAST(value AS INTEGER) FROM settings WHERE key = '{MAX_SESSION_HOURS_KEY}'),
+            {DEFAULT_MAX_SESSION_HOURS}
+        ) * 3600
+    )) / 3600.0 AS hours
+FROM scans
+WHERE n % 2 = 1
+GROUP BY 1, 2;
+
+CREATE VIEW v_current_present AS
+SELECT current.name,
+    CASE
+        WHEN mentors.name IS NOT NULL THEN 'mentor'
+        WHEN students.name IS NOT NULL THEN 'student'
+        WHEN current.name LIKE 'Guest%' THEN 'guest'
+        ELSE 'unknown'
+    END AS role,
+    COALESCE(
+        datetime(current.timestamp + attendance.utc_offset, 'unixepoch'),
+        datetime(current.timestamp, 'unixepoch', 'localtime')
+    ) AS since
+FROM current
+LEFT JOIN attendance
+    ON attendance.name = current.name AND attendance.timestamp = current.timestamp
+LEFT JOIN mentors ON mentors.name = current.name
+LEFT JOIN students ON students.name = current.name
+WHERE current.present = TRUE;"
+    )
+}
+
+/// Converts an inclusive day range into timestamps, open ended on missing
+/// sides. `None` when neither side is set.
+pub fn date_range(
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+) -> Option<(DateTime<Local>, DateTime<Local>)> {
+    let start_of = |date: NaiveDate| local_datetime(date, NaiveTime::MIN);
+
+    (from.is_some() || to.is_some()).then(|| {
+        (
+            from.map_or(DateTime::<Local>::MIN_UTC.into(), start_of),
+            to.and_then(|to| to.succ_opt())
+                .map_or(DateTime::<Local>::MAX_UTC.into(), start_of),
+        )
+    })
+}
+
+/// A phone number texted when `name` checks in or out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    pub name: String,
+    pub phone: String,
+    pub notify_in: bool,
+    pub notify_out: bool,
+}
+
+/// One row of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub at: DateTime<Local>,
+    pub action: String,
+    pub subject: String,
+    pub detail: String,
+}
+
+/// A single scan with the scanned person's role.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttendanceRow {
+    pub name: String,
+    pub timestamp: DateTime<Local>,
+    /// Local day when scanned, see [`scan_day`].
+    pub day: NaiveDate,
+    /// `mentor`, `student`, `guest`, or `unknown`.
+    pub qr_type: String,
+}
+
+/// A scheduled meeting window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Meeting {
+    pub start: DateTime<Local>,
+    pub end: DateTime<Local>,
+    pub summary: String,
+}
+
+/// Prefix of identifiers generated by [`BackingDatabase::register_guest`].
+/// They start with `Guest`, so they are scanned as guests.
+pub const REGISTERED_GUEST_PREFIX: &str = "Guest:";
+
+/// Setting identifying this kiosk's scans to peers. Generated on first use.
+pub const KIOSK_ID_KEY: &str = "sync.kiosk_id";
+/// Setting holding the last sequence number given to one of this kiosk's
+/// changes.
+pub const LAST_SEQ_KEY: &str = "sync.last_seq";
+
+pub fn generate_kiosk_id() -> String {
+    format!("{:016x}", RandomState::new().hash_one(Local::now()))
+}
+
+/// A scan recorded or removed by one kiosk, as exchanged with peers.
+///
+/// `seq` increases with every change made by the originating kiosk, and is
+/// never reused.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyncedScan {
+    pub name: String,
+    /// Unix seconds.
+    pub timestamp: i64,
+    /// Seconds east of UTC at the scanning kiosk, missing from older
+    /// versions.
+    #[serde(default)]
+    pub utc_offset: Option<i32>,
+    pub seq: i64,
+    /// The scan of `name` at `timestamp` was undone, or moved by an edit.
+    #[serde(default)]
+    pub removed: bool,
+}
+
+/// [`Resolution`] stored as a setting, written as `<width>x<height>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CameraResolution(pub Resolution);
+
+impl FromStr for CameraResolution {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str = "expected <width>x<height>, e.g. 1280x720";
+        let (x, y) = s.split_once('x').ok_or(EXPECTED)?;
+        Ok(Self(Resolution::new(
+            x.trim().parse().map_err(|_| EXPECTED)?,
+            y.trim().parse().map_err(|_| EXPECTED)?,
+        )))
+    }
+}
 
 #[derive(Debug)]
 pub struct BackingDatabase {
     conn: Connection,
+    kiosk_id: String,
 }
 
 impl BackingDatabase {
-    pub fn new(conn_file: Option<&str>) -> Self {
-        let conn = if let Some(conn_file) = conn_file {
+    pub fn new(conn_file: Option<&str>) -> Result<Self, DbError> {
+        let mut conn = if let Some(conn_file) = conn_file {
             Connection::open(conn_file)
         } else {
             Connection::open_in_memory()
         }
-        .unwrap();
+        .map_err(DbError::Sqlite)?;
 
-        // Initialize all the tables.
-        conn.execute_batch(
-            "PRAGMA journal_mode=WAL;
-BEGIN TRANSACTION;
-CREATE TABLE IF NOT EXISTS attendance (
-    name TEXT NOT NULL,
-    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
-    PRIMARY KEY (name, timestamp)
-) WITHOUT ROWID;
-
-CREATE TABLE IF NOT EXISTS current (
-    name TEXT PRIMARY KEY NOT NULL,
-    timestamp DATETIME DEFAULT CURRENT_TIMESTAMP NOT NULL,
-    present BOOLEAN NOT NULL
-) WITHOUT ROWID;
-
-CREATE TABLE IF NOT EXISTS mentors (
-    name TEXT PRIMARY KEY NOT NULL
-) WITHOUT ROWID;
-
-CREATE TABLE IF NOT EXISTS students (
-    name TEXT PRIMARY KEY NOT NULL
-) WITHOUT ROWID;
-
-CREATE TABLE IF NOT EXISTS resolution (
-    x INTEGER NOT NULL,
-    y INTEGER NOT NULL,
-    PRIMARY KEY (x, y)
-) WITHOUT ROWID;
+        conn.execute_batch("PRAGMA journal_mode=WAL;")
+            .map_err(DbError::Sqlite)?;
+        // Files are migrated before opening, see `migrations::migrate_file`.
+        migrations::apply(&mut conn).map_err(DbError::Migration)?;
 
+        let mut db = Self {
+            conn,
+            kiosk_id: String::new(),
+        };
+        db.kiosk_id = match db.get_setting(KIOSK_ID_KEY)? {
+            Some(kiosk_id) => kiosk_id,
+            None => {
+                let kiosk_id = generate_kiosk_id();
+                db.set_setting(KIOSK_ID_KEY, &kiosk_id)?;
+                kiosk_id
+            }
+        };
+
+        // Recreated on every start, so definitions follow this version.
+        db.conn
+            .execute_batch(&format!(
+                "BEGIN TRANSACTION;
+DROP VIEW IF EXISTS v_daily_attendance;
+DROP VIEW IF EXISTS v_person_hours;
+DROP VIEW IF EXISTS v_current_present;
+{}
 COMMIT;",
-        )
-        .unwrap();
+                reporting_views()
+            ))
+            .map_err(DbError::Sqlite)?;
+
+        Ok(db)
+    }
+
+    /// Opens an existing database without write access or table setup.
+    ///
+    /// Used for queries that must never interfere with scan writes.
+    pub fn open_read_only(conn_file: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open_with_flags(
+            conn_file,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+
+        let mut db = Self {
+            conn,
+            kiosk_id: String::new(),
+        };
+        db.kiosk_id = db
+            .get_setting(KIOSK_ID_KEY)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        Ok(db)
+    }
+
+    /// Debug builds panic on the UI thread, queries belong on a
+    /// [`db_worker`].
+    fn conn(&self) -> &Connection {
+        debug_assert!(
+            !db_worker::on_ui_thread(),
+            "database queried on the UI thread"
+        );
+        &self.conn
+    }
+
+    fn conn_mut(&mut self) -> &mut Connection {
+        debug_assert!(
+            !db_worker::on_ui_thread(),
+            "database queried on the UI thread"
+        );
+        &mut self.conn
+    }
+
+    /// Fails writes at once while another connection holds the lock,
+    /// instead of waiting the default five seconds.
+    #[cfg(test)]
+    pub(crate) fn fail_when_busy(&self) {
+        self.conn.busy_timeout(std::time::Duration::ZERO).unwrap();
+    }
+
+    pub fn kiosk_id(&self) -> &str {
+        &self.kiosk_id
+    }
+
+    /// Scans recorded and removed by this kiosk after `since`, in the order
+    /// they happened.
+    pub fn changes_since(&self, since: i64, limit: usize) -> Result<Vec<SyncedScan>, DbError> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached(
+                "SELECT name, timestamp, utc_offset, seq, FALSE FROM attendance
+WHERE kiosk_id = ?1 AND seq > ?2
+UNION ALL
+SELECT name, timestamp, NULL, seq, TRUE FROM removed_scans
+WHERE kiosk_id = ?1 AND seq > ?2
+ORDER BY seq
+LIMIT ?3;",
+            )
+            .map_err(DbError::Sqlite)?;
 
-        Self { conn }
+        stmt.query_map((&self.kiosk_id, since, limit as i64), |row| {
+            Ok(SyncedScan {
+                name: row.get(0)?,
+                timestamp: row.get(1)?,
+                utc_offset: row.get(2)?,
+                seq: row.get(3)?,
+                removed: row.get(4)?,
+            })
+        })
+        .map_err(DbError::Sqlite)?
+        .collect::<Result<_, _>>()
+        .map_err(DbError::Sqlite)
     }
 
-    pub fn add_scan(&mut self, name: &str, timestamp: DateTime<Local>) {
-        let transaction = self.conn.transaction().unwrap();
+    /// Applies changes made by another kiosk, returning how many scans were
+    /// added or removed here.
+    ///
+    /// Scans already present are skipped, so replaying changes is harmless.
+    /// Removals are kept, so a removed scan pulled again from a kiosk that
+    /// has yet to see the removal stays removed.
+    /// Presence of everyone with new scans is re-derived from their full
+    /// scan history, each scan toggling in and out.
+    pub fn merge_changes(
+        &mut self,
+        kiosk_id: &str,
+        changes: &[SyncedScan],
+        dedupe_secs: i64,
+    ) -> Result<usize, DbError> {
+        let transaction = self.conn_mut().transaction().map_err(DbError::Sqlite)?;
+        let mut merged = 0;
         {
-            let mut attendance_stmt = transaction
-                .prepare_cached("INSERT INTO attendance (name, timestamp) VALUES (?1, ?2);")
-                .unwrap();
+            // A scan within `dedupe_secs` of another kiosk's scan of the
+            // same person is the same visit, seen at both doors. Every kiosk
+            // keeps the lowest (timestamp, kiosk_id) of them, whichever
+            // order they arrive in, so all count the same toggle.
+            let mut earlier_stmt = transaction
+                .prepare_cached(
+                    "SELECT EXISTS (
+    SELECT 1 FROM attendance
+    WHERE name = ?1 AND kiosk_id IS NOT ?3 AND ABS(timestamp - ?2) < MAX(?4, 1)
+        AND (timestamp < ?2 OR (timestamp = ?2 AND kiosk_id < ?3))
+);",
+                )
+                .map_err(DbError::Sqlite)?;
+            let mut later_stmt = transaction
+                .prepare_cached(
+                    "DELETE FROM attendance
+WHERE name = ?1 AND kiosk_id IS NOT ?3 AND ABS(timestamp - ?2) < MAX(?4, 1);",
+                )
+                .map_err(DbError::Sqlite)?;
+            let mut removed_stmt = transaction
+                .prepare_cached(
+                    "SELECT EXISTS (
+    SELECT 1 FROM removed_scans
+    WHERE name = ?1 AND timestamp = ?2 AND NOT (kiosk_id = ?3 AND seq < ?4)
+);",
+                )
+                .map_err(DbError::Sqlite)?;
+            let mut remove_stmt = transaction
+                .prepare_cached("DELETE FROM attendance WHERE name = ?1 AND timestamp = ?2;")
+                .map_err(DbError::Sqlite)?;
+            let mut removal_stmt = transaction
+                .prepare_cached(
+                    "INSERT OR IGNORE INTO removed_scans (kiosk_id, seq, name, timestamp)
+VALUES (?1, ?2, ?3, ?4);",
+                )
+                .map_err(DbError::Sqlite)?;
+            let mut insert_stmt = transaction
+                .prepare_cached(
+                    "INSERT OR IGNORE INTO attendance (name, timestamp, kiosk_id, seq, utc_offset)
+VALUES (?1, ?2, ?3, ?4, ?5);",
+                )
+                .map_err(DbError::Sqlite)?;
+            // Anyone left without scans is forgotten.
+            let mut forget_stmt = transaction
+                .prepare_cached("DELETE FROM current WHERE name = ?1;")
+                .map_err(DbError::Sqlite)?;
             let mut current_stmt = transaction
                 .prepare_cached(
-                    "INSERT INTO current (name, timestamp, present) VALUES (?1, ?2, TRUE)
+                    "INSERT INTO current (name, timestamp, present)
+SELECT name, MAX(timestamp), COUNT(*) % 2 = 1 FROM attendance WHERE name = ?1 GROUP BY name
 ON CONFLICT(name) DO UPDATE
-SET timestamp = ?2, present = NOT present;",
+SET timestamp = excluded.timestamp, present = excluded.present;",
                 )
-                .unwrap();
+                .map_err(DbError::Sqlite)?;
+
+            let mut changed_names = HashSet::new();
+            for change in changes {
+                if change.removed {
+                    removal_stmt
+                        .execute((kiosk_id, change.seq, &change.name, change.timestamp))
+                        .map_err(DbError::Sqlite)?;
+                    let removed = remove_stmt
+                        .execute((&change.name, change.timestamp))
+                        .map_err(DbError::Sqlite)?;
+                    merged += removed;
+                    if removed > 0 {
+                        changed_names.insert(change.name.as_str());
+                    }
+                    continue;
+                }
+                // Removed by another kiosk, or by the same kiosk since.
+                if removed_stmt
+                    .query_row(
+                        (&change.name, change.timestamp, kiosk_id, change.seq),
+                        |row| row.get::<_, bool>(0),
+                    )
+                    .map_err(DbError::Sqlite)?
+                {
+                    continue;
+                }
+                let window = (&change.name, change.timestamp, kiosk_id, dedupe_secs);
+                if earlier_stmt
+                    .query_row(window, |row| row.get::<_, bool>(0))
+                    .map_err(DbError::Sqlite)?
+                {
+                    continue;
+                }
+                let replaced = later_stmt.execute(window).map_err(DbError::Sqlite)?;
+                let inserted = insert_stmt
+                    .execute((
+                        &change.name,
+                        change.timestamp,
+                        kiosk_id,
+                        change.seq,
+                        change.utc_offset,
+                    ))
+                    .map_err(DbError::Sqlite)?;
+                merged += inserted;
+                if inserted + replaced > 0 {
+                    changed_names.insert(change.name.as_str());
+                }
+            }
+
+            for name in changed_names {
+                forget_stmt.execute([name]).map_err(DbError::Sqlite)?;
+                current_stmt.execute([name]).map_err(DbError::Sqlite)?;
+            }
+
+            if merged > 0 {
+                Self::audit(
+                    &transaction,
+                    "sync_merge",
+                    kiosk_id,
+                    &format!("{merged} changes"),
+                )?;
+            }
+        }
+        transaction.commit().map_err(DbError::Sqlite)?;
+
+        Ok(merged)
+    }
+
+    /// Records a scan. `source` names the device that submitted it, `None`
+    /// for this kiosk's camera. Scans not from a camera are audited.
+    pub fn add_scan(
+        &mut self,
+        name: &str,
+        timestamp: DateTime<Local>,
+        source: Option<&str>,
+    ) -> Result<(), DbError> {
+        let kiosk_id = self.kiosk_id.clone();
+        let transaction = self.conn_mut().transaction().map_err(DbError::Sqlite)?;
+        Self::insert_scan(
+            &transaction,
+            &kiosk_id,
+            name,
+            timestamp.timestamp(),
+            Some(utc_offset(&timestamp)),
+            source,
+        )?;
+        transaction.commit().map_err(DbError::Sqlite)?;
+        debug!(target: "db", name, %timestamp, source, "Recorded scan");
+        Ok(())
+    }
 
-            attendance_stmt
-                .execute((name, timestamp.timestamp()))
-                .unwrap();
-            current_stmt.execute((name, timestamp.timestamp())).unwrap();
+    /// Records every scan in `scans`, in order, or none of them.
+    pub fn add_scans(&mut self, scans: &[QueuedScan]) -> Result<(), DbError> {
+        let kiosk_id = self.kiosk_id.clone();
+        let transaction = self.conn_mut().transaction().map_err(DbError::Sqlite)?;
+        for scan in scans {
+            Self::insert_scan(
+                &transaction,
+                &kiosk_id,
+                &scan.name,
+                scan.timestamp,
+                scan.utc_offset,
+                scan.source.as_deref(),
+            )?;
         }
-        transaction.commit().unwrap();
+        transaction.commit().map_err(DbError::Sqlite)?;
+        debug!(target: "db", count = scans.len(), "Recorded queued scans");
+        Ok(())
     }
 
-    pub fn get_present(&self) -> Vec<(String, DateTime<Local>)> {
+    /// Next number in this kiosk's sequence of changes.
+    fn next_seq(conn: &Connection) -> Result<i64, DbError> {
+        conn.prepare_cached(
+            "INSERT INTO settings (key, value) VALUES (?1, 1)
+ON CONFLICT(key) DO UPDATE SET value = CAST(value AS INTEGER) + 1
+RETURNING CAST(value AS INTEGER);",
+        )
+        .and_then(|mut stmt| stmt.query_row([LAST_SEQ_KEY], |row| row.get(0)))
+        .map_err(DbError::Sqlite)
+    }
+
+    /// Keeps the removal of the scan of `name` at `timestamp` for peers.
+    fn record_removal(
+        conn: &Connection,
+        kiosk_id: &str,
+        name: &str,
+        timestamp: i64,
+    ) -> Result<(), DbError> {
+        let seq = Self::next_seq(conn)?;
+        conn.prepare_cached(
+            "INSERT INTO removed_scans (kiosk_id, seq, name, timestamp) VALUES (?1, ?2, ?3, ?4);",
+        )
+        .and_then(|mut stmt| stmt.execute((kiosk_id, seq, name, timestamp)))
+        .map_err(DbError::Sqlite)?;
+        Ok(())
+    }
+
+    fn insert_scan(
+        conn: &Connection,
+        kiosk_id: &str,
+        name: &str,
+        timestamp: i64,
+        utc_offset: Option<i32>,
+        source: Option<&str>,
+    ) -> Result<(), DbError> {
+        let seq = Self::next_seq(conn)?;
+        let mut attendance_stmt = conn
+            .prepare_cached(
+                "INSERT INTO attendance (name, timestamp, kiosk_id, seq, source, utc_offset)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+            )
+            .map_err(DbError::Sqlite)?;
+        let mut current_stmt = conn
+            .prepare_cached(
+                "INSERT INTO current (name, timestamp, present) VALUES (?1, ?2, TRUE)
+ON CONFLICT(name) DO UPDATE
+SET timestamp = ?2, present = NOT present;",
+            )
+            .map_err(DbError::Sqlite)?;
+
+        attendance_stmt
+            .execute((name, timestamp, kiosk_id, seq, source, utc_offset))
+            .map_err(DbError::Sqlite)?;
+        current_stmt
+            .execute((name, timestamp))
+            .map_err(DbError::Sqlite)?;
+        // Anything but a camera is someone entering scans by hand or from
+        // another device.
+        if let Some(source) = source
+            && !source.starts_with(CAMERA_SOURCE_PREFIX)
+        {
+            Self::audit(
+                conn,
+                "scan_added",
+                name,
+                &format!("{source} at {}", scan_time_label(timestamp, utc_offset)),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the scan of `name` at `timestamp`, re-deriving their presence
+    /// from the scans left. Peers remove it too.
+    pub fn remove_scan(&mut self, name: &str, timestamp: DateTime<Local>) -> Result<(), DbError> {
+        let kiosk_id = self.kiosk_id.clone();
+        let transaction = self.conn_mut().transaction().map_err(DbError::Sqlite)?;
+        let removed = transaction
+            .execute(
+                "DELETE FROM attendance WHERE name = ?1 AND timestamp = ?2;",
+                (name, timestamp.timestamp()),
+            )
+            .map_err(DbError::Sqlite)?;
+        if removed == 0 {
+            return Err(DbError::MissingRow);
+        }
+        Self::record_removal(&transaction, &kiosk_id, name, timestamp.timestamp())?;
+
+        transaction
+            .execute("DELETE FROM current WHERE name = ?1;", [name])
+            .map_err(DbError::Sqlite)?;
+        transaction
+            .execute(
+                "INSERT INTO current (name, timestamp, present)
+SELECT name, MAX(timestamp), COUNT(*) % 2 = 1 FROM attendance WHERE name = ?1 GROUP BY name;",
+                [name],
+            )
+            .map_err(DbError::Sqlite)?;
+        Self::audit(&transaction, "scan_undone", name, &timestamp.to_rfc3339())?;
+        transaction.commit().map_err(DbError::Sqlite)?;
+
+        debug!(target: "db", name, %timestamp, "Removed scan");
+        Ok(())
+    }
+
+    /// Records the camera frame saved for a scan.
+    pub fn set_scan_snapshot(
+        &mut self,
+        name: &str,
+        timestamp: DateTime<Local>,
+        path: &Path,
+    ) -> Result<(), DbError> {
+        self.conn()
+            .execute(
+                "UPDATE attendance SET snapshot = ?3 WHERE name = ?1 AND timestamp = ?2;",
+                (name, timestamp.timestamp(), path.to_string_lossy()),
+            )
+            .map_err(DbError::Sqlite)?;
+        Ok(())
+    }
+
+    /// Forgets snapshots of scans before `cutoff`, once their files are
+    /// deleted.
+    pub fn clear_snapshots_before(&mut self, cutoff: DateTime<Local>) -> Result<(), DbError> {
+        self.conn()
+            .execute(
+                "UPDATE attendance SET snapshot = NULL
+WHERE snapshot IS NOT NULL AND timestamp < ?1;",
+                [cutoff.timestamp()],
+            )
+            .map_err(DbError::Sqlite)?;
+        Ok(())
+    }
+
+    /// Signs out everyone who has been present since before `cutoff`, with a
+    /// scan at `cutoff`. Returns who was signed out.
+    ///
+    /// Stops at the first failure, leaving the rest present.
+    pub fn sign_out_all(
+        &mut self,
+        cutoff: DateTime<Local>,
+        source: &str,
+    ) -> Result<Vec<String>, DbError> {
+        let names: Vec<_> = self
+            .get_present()?
+            .into_iter()
+            .filter(|(_, since)| *since < cutoff)
+            .map(|(name, _)| name)
+            .collect();
+
+        for name in &names {
+            self.add_scan(name, cutoff, Some(source))?;
+        }
+        Ok(names)
+    }
+
+    /// Everyone present, with when they signed in. Rows with corrupt
+    /// timestamps are logged and left out.
+    pub fn get_present(&self) -> Result<Vec<(String, DateTime<Local>)>, DbError> {
         let mut stmt = self
-            .conn
+            .conn()
             .prepare_cached("SELECT name, timestamp FROM current WHERE present = TRUE;")
-            .unwrap();
-
-        let row_iter = stmt
-            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
-            .unwrap()
-            .flatten();
+            .map_err(DbError::Sqlite)?;
 
-        row_iter
-            .map(|(name, timestamp)| {
-                (
-                    name,
-                    DateTime::from_timestamp_secs(timestamp).unwrap().into(),
-                )
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
             })
-            .collect()
+            .map_err(DbError::Sqlite)?;
+
+        let mut present = Vec::new();
+        for row in rows {
+            let (name, timestamp) = row.map_err(DbError::Sqlite)?;
+            match local_time(timestamp) {
+                Some(since) => present.push((name, since)),
+                None => {
+                    warn!(target: "db", "Skipping {}", DbError::CorruptTimestamp { name, timestamp })
+                }
+            }
+        }
+        Ok(present)
+    }
+
+    /// Name recorded for a badge `id`, registering `name` for it on first
+    /// sight. Later badges with the same id resolve to the same name, so
+    /// history stays attached to it.
+    pub fn resolve_alias(&mut self, id: &str, name: &str) -> Result<String, DbError> {
+        let mut insert_stmt = self
+            .conn()
+            .prepare_cached("INSERT OR IGNORE INTO aliases (id, name) VALUES (?1, ?2);")
+            .map_err(DbError::Sqlite)?;
+        insert_stmt.execute((id, name)).map_err(DbError::Sqlite)?;
+
+        let mut select_stmt = self
+            .conn()
+            .prepare_cached("SELECT name FROM aliases WHERE id = ?1;")
+            .map_err(DbError::Sqlite)?;
+        select_stmt
+            .query_row([id], |row| row.get(0))
+            .map_err(DbError::Sqlite)
     }
 
-    pub fn get_mentors(&self) -> Vec<String> {
+    /// A badge id already registered for `name`, if any.
+    pub fn alias_id(&self, name: &str) -> Result<Option<String>, DbError> {
         let mut stmt = self
-            .conn
-            .prepare_cached("SELECT name FROM mentors;")
-            .unwrap();
+            .conn()
+            .prepare_cached("SELECT id FROM aliases WHERE name = ?1 ORDER BY id LIMIT 1;")
+            .map_err(DbError::Sqlite)?;
+        stmt.query_row([name], |row| row.get(0))
+            .optional()
+            .map_err(DbError::Sqlite)
+    }
+
+    /// Active mentors, the ones scans match.
+    pub fn get_mentors(&self) -> Result<Vec<String>, DbError> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT name FROM mentors WHERE active;")
+            .map_err(DbError::Sqlite)?;
 
         stmt.query_map([], |row| row.get(0))
-            .unwrap()
-            .flatten()
-            .collect()
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<_, _>>()
+            .map_err(DbError::Sqlite)
     }
 
-    pub fn get_students(&self) -> Vec<String> {
+    /// Active students, the ones scans match.
+    pub fn get_students(&self) -> Result<Vec<String>, DbError> {
         let mut stmt = self
-            .conn
-            .prepare_cached("SELECT name FROM students;")
-            .unwrap();
+            .conn()
+            .prepare_cached("SELECT name FROM students WHERE active;")
+            .map_err(DbError::Sqlite)?;
 
         stmt.query_map([], |row| row.get(0))
-            .unwrap()
-            .flatten()
-            .collect()
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<_, _>>()
+            .map_err(DbError::Sqlite)
     }
 
-    pub fn get_resolution(&self) -> Option<Resolution> {
+    /// Everyone on a roster by name, mentors first. Reports include inactive
+    /// members so their hours keep their role.
+    pub fn get_all_members(&self, include_inactive: bool) -> Result<Vec<Member>, DbError> {
         let mut stmt = self
-            .conn
-            .prepare_cached("SELECT x, y FROM resolution;")
-            .unwrap();
+            .conn()
+            .prepare_cached(
+                "SELECT name, 'mentor', active FROM mentors WHERE active OR ?1
+UNION ALL
+SELECT name, 'student', active FROM students WHERE active OR ?1
+ORDER BY 2, 1;",
+            )
+            .map_err(DbError::Sqlite)?;
 
-        let res = stmt
-            .query_map([], |row| Ok(Resolution::new(row.get(0)?, row.get(1)?)))
-            .unwrap()
-            .next()
-            .map(|x| x.unwrap());
-        res
+        stmt.query_map([include_inactive], |row| {
+            Ok(Member {
+                name: row.get(0)?,
+                role: if row.get_ref(1)?.as_str()? == "mentor" {
+                    QrType::Mentor
+                } else {
+                    QrType::Student
+                },
+                active: row.get(2)?,
+            })
+        })
+        .map_err(DbError::Sqlite)?
+        .collect::<Result<_, _>>()
+        .map_err(DbError::Sqlite)
     }
 
-    pub fn set_resolution(&mut self, resolution: Resolution) {
-        let transaction = self.conn.transaction().unwrap();
-        {
-            let mut delete_stmt = transaction
-                .prepare_cached("DELETE FROM resolution;")
-                .unwrap();
-            let mut insert_stmt = transaction
-                .prepare_cached("INSERT INTO resolution(x, y) VALUES (?1, ?2);")
-                .unwrap();
+    /// Which roster `name` is on and whether they are active, mentors first.
+    pub fn roster_entry(&self, name: &str) -> Result<Option<(QrType, bool)>, DbError> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached(
+                "SELECT (SELECT active FROM mentors WHERE name = ?1),
+    (SELECT active FROM students WHERE name = ?1);",
+            )
+            .map_err(DbError::Sqlite)?;
+
+        Ok(
+            match stmt
+                .query_row([name], |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(DbError::Sqlite)?
+            {
+                (Some(active), _) => Some((QrType::Mentor, active)),
+                (None, Some(active)) => Some((QrType::Student, active)),
+                (None, None) => None,
+            },
+        )
+    }
 
-            delete_stmt.execute(()).unwrap();
-            insert_stmt
-                .execute((resolution.x(), resolution.y()))
-                .unwrap();
+    /// Which roster `name` is on, active or not, mentors first.
+    pub fn roster_role(&self, name: &str) -> Result<Option<QrType>, DbError> {
+        Ok(self.roster_entry(name)?.map(|(role, _)| role))
+    }
+
+    /// Deactivating takes `name` off the scan roster, e.g. on graduation,
+    /// while reports keep their role. Whether anything changed.
+    pub fn set_member_active(&mut self, name: &str, active: bool) -> Result<bool, DbError> {
+        let action = if active {
+            "roster_reactivate"
+        } else {
+            "roster_deactivate"
+        };
+        self.audited(action, name, "", |transaction| {
+            let mut changed = 0;
+            for table in ["mentors", "students"] {
+                changed += transaction.execute(
+                    &format!("UPDATE {table} SET active = ?2 WHERE name = ?1 AND active != ?2;"),
+                    (name, active),
+                )?;
+            }
+            Ok(changed)
+        })
+    }
+
+    /// Whether `name` was added, `false` when already a mentor.
+    pub fn add_mentor(&mut self, name: &str) -> Result<bool, RosterError> {
+        if self.roster_role(name).map_err(RosterError::Db)? == Some(QrType::Student) {
+            return Err(RosterError::DuplicateAcrossRoles {
+                name: name.to_string(),
+                role: QrType::Student,
+            });
         }
-        transaction.commit().unwrap();
+        self.audited("roster_add", name, "mentor", |transaction| {
+            transaction
+                .prepare_cached("INSERT OR IGNORE INTO mentors (name) VALUES (?1);")?
+                .execute([name])
+        })
+        .map_err(RosterError::Db)
     }
 
-    pub fn checkpoint(&self) {
-        self.conn
-            .execute_batch("PRAGMA wal_checkpoint(PASSIVE);")
-            .unwrap();
+    /// Whether `name` was added, `false` when already a student.
+    pub fn add_student(&mut self, name: &str) -> Result<bool, RosterError> {
+        if self.roster_role(name).map_err(RosterError::Db)? == Some(QrType::Mentor) {
+            return Err(RosterError::DuplicateAcrossRoles {
+                name: name.to_string(),
+                role: QrType::Mentor,
+            });
+        }
+        self.audited("roster_add", name, "student", |transaction| {
+            transaction
+                .prepare_cached("INSERT OR IGNORE INTO students (name) VALUES (?1);")?
+                .execute([name])
+        })
+        .map_err(RosterError::Db)
+    }
+
+    /// Attendance history is kept.
+    pub fn remove_mentor(&mut self, name: &str) -> Result<bool, DbError> {
+        self.audited("roster_remove", name, "mentor", |transaction| {
+            transaction
+                .prepare_cached("DELETE FROM mentors WHERE name = ?1;")?
+                .execute([name])
+        })
+    }
+
+    /// Attendance history is kept.
+    pub fn remove_student(&mut self, name: &str) -> Result<bool, DbError> {
+        self.audited("roster_remove", name, "student", |transaction| {
+            transaction
+                .prepare_cached("DELETE FROM students WHERE name = ?1;")?
+                .execute([name])
+        })
+    }
+
+    /// Moves a student to the mentor roster, e.g. on graduation. Scans are
+    /// stored by name, so attendance history carries over.
+    pub fn move_student_to_mentor(&mut self, name: &str) -> Result<bool, DbError> {
+        self.audited("roster_promote", name, "student to mentor", |transaction| {
+            let removed = transaction.execute("DELETE FROM students WHERE name = ?1;", [name])?;
+            if removed > 0 {
+                transaction.execute("INSERT OR IGNORE INTO mentors (name) VALUES (?1);", [name])?;
+            }
+            Ok(removed)
+        })
+    }
+
+    /// Finds names on both rosters, presence entries for names on neither,
+    /// and scans with impossible times.
+    pub fn integrity_check(&self) -> Result<Vec<IntegrityIssue>, DbError> {
+        /// 2000-01-01, older than any kiosk.
+        const EARLIEST_SCAN: i64 = 946_684_800;
+        let latest_scan = Local::now().timestamp() + 24 * 60 * 60;
+
+        let mut issues: Vec<_> = self
+            .conn()
+            .prepare_cached("SELECT name FROM mentors INTERSECT SELECT name FROM students;")
+            .map_err(DbError::Sqlite)?
+            .query_map([], |row| row.get(0))
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DbError::Sqlite)?
+            .into_iter()
+            .map(IntegrityIssue::OnBothRosters)
+            .collect();
+
+        issues.extend(
+            self.conn()
+                .prepare_cached(
+                    "SELECT name FROM current
+WHERE name NOT IN (SELECT name FROM mentors)
+    AND name NOT IN (SELECT name FROM students)
+    AND name NOT LIKE 'Guest%'
+ORDER BY name;",
+                )
+                .map_err(DbError::Sqlite)?
+                .query_map([], |row| row.get(0))
+                .map_err(DbError::Sqlite)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(DbError::Sqlite)?
+                .into_iter()
+                .map(IntegrityIssue::OrphanedPresence),
+        );
+
+        issues.extend(
+            self.conn()
+                .prepare_cached(
+                    "SELECT name, timestamp FROM attendance
+WHERE timestamp < ?1 OR timestamp > ?2
+ORDER BY timestamp;",
+                )
+                .map_err(DbError::Sqlite)?
+                .query_map((EARLIEST_SCAN, latest_scan), |row| {
+                    Ok(IntegrityIssue::ImpossibleTimestamp {
+                        name: row.get(0)?,
+                        timestamp: row.get(1)?,
+                    })
+                })
+                .map_err(DbError::Sqlite)?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(DbError::Sqlite)?,
+        );
+
+        Ok(issues)
+    }
+
+    /// Forgets whether `name` is present, see
+    /// [`IntegrityIssue::OrphanedPresence`]. Their scans are kept.
+    pub fn remove_presence(&mut self, name: &str) -> Result<bool, DbError> {
+        self.audited("presence_removed", name, "not on a roster", |transaction| {
+            transaction
+                .prepare_cached("DELETE FROM current WHERE name = ?1;")?
+                .execute([name])
+        })
+    }
+
+    /// Logs every [`IntegrityIssue`], run on startup.
+    pub fn warn_integrity_issues(&self) -> Result<(), DbError> {
+        for issue in self.integrity_check()? {
+            warn!(target: "db", "Integrity check: {issue}");
+        }
+        Ok(())
+    }
+
+    /// Registers a guest without a badge, returning their generated
+    /// identifier to scan in with.
+    pub fn register_guest(
+        &mut self,
+        name: &str,
+        affiliation: Option<&str>,
+    ) -> Result<String, DbError> {
+        let id = format!(
+            "{REGISTERED_GUEST_PREFIX}{:016x}",
+            RandomState::new().hash_one((Local::now(), name))
+        );
+        self.audited("guest_registered", &id, name, |transaction| {
+            transaction
+                .prepare_cached("INSERT INTO guests (id, name, affiliation) VALUES (?1, ?2, ?3);")?
+                .execute((&id, name, affiliation))
+        })?;
+        Ok(id)
+    }
+
+    /// The registered name for generated guest identifiers, with their
+    /// affiliation, otherwise `name` unchanged.
+    pub fn display_name(&self, name: &str) -> Result<String, DbError> {
+        if !name.starts_with(REGISTERED_GUEST_PREFIX) {
+            return Ok(name.to_string());
+        }
+
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT name, affiliation FROM guests WHERE id = ?1;")
+            .map_err(DbError::Sqlite)?;
+        Ok(stmt
+            .query_row([name], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+            })
+            .optional()
+            .map_err(DbError::Sqlite)?
+            .map_or_else(
+                || name.to_string(),
+                |(guest, affiliation)| match affiliation {
+                    Some(affiliation) => format!("{guest} ({affiliation})"),
+                    None => guest,
+                },
+            ))
+    }
+
+    /// Employee ids of paid staff, sorted by name.
+    pub fn payroll_ids(&self) -> Result<Vec<(String, String)>, DbError> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT name, employee_id FROM payroll_ids ORDER BY name;")
+            .map_err(DbError::Sqlite)?;
+
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<_, _>>()
+            .map_err(DbError::Sqlite)
+    }
+
+    pub fn set_payroll_id(&mut self, name: &str, employee_id: &str) -> Result<(), DbError> {
+        self.audited("payroll_id_set", name, employee_id, |transaction| {
+            transaction
+                .prepare_cached(
+                    "INSERT INTO payroll_ids (name, employee_id) VALUES (?1, ?2)
+ON CONFLICT(name) DO UPDATE SET employee_id = ?2;",
+                )?
+                .execute([name, employee_id])
+        })?;
+        Ok(())
+    }
+
+    /// Every contact, sorted by name then phone.
+    pub fn get_contacts(&self) -> Result<Vec<Contact>, DbError> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached(
+                "SELECT name, phone, notify_in, notify_out FROM contacts ORDER BY name, phone;",
+            )
+            .map_err(DbError::Sqlite)?;
+
+        stmt.query_map([], |row| {
+            Ok(Contact {
+                name: row.get(0)?,
+                phone: row.get(1)?,
+                notify_in: row.get(2)?,
+                notify_out: row.get(3)?,
+            })
+        })
+        .map_err(DbError::Sqlite)?
+        .collect::<Result<_, _>>()
+        .map_err(DbError::Sqlite)
+    }
+
+    /// Adds `contact`, or updates which scans it is notified of.
+    pub fn set_contact(&mut self, contact: &Contact) -> Result<(), DbError> {
+        self.audited(
+            "contact_set",
+            &contact.name,
+            &contact.phone,
+            |transaction| {
+                transaction
+                    .prepare_cached(
+                        "INSERT INTO contacts (name, phone, notify_in, notify_out) VALUES (?1, ?2, ?3, ?4)
+ON CONFLICT(name, phone) DO UPDATE
+SET notify_in = ?3, notify_out = ?4;",
+                    )?
+                    .execute((
+                        &contact.name,
+                        &contact.phone,
+                        contact.notify_in,
+                        contact.notify_out,
+                    ))
+            },
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_contact(&mut self, name: &str, phone: &str) -> Result<bool, DbError> {
+        self.audited("contact_remove", name, phone, |transaction| {
+            transaction
+                .prepare_cached("DELETE FROM contacts WHERE name = ?1 AND phone = ?2;")?
+                .execute([name, phone])
+        })
+    }
+
+    /// Adds every name in a CSV file to the roster in a single transaction.
+    ///
+    /// Names come from the `name` column, or the first column when there is
+    /// no `name` header. An optional `type` column (mentor or student)
+    /// overrides `qr_type` per row. Rows with empty names or other types are
+    /// counted as malformed.
+    pub fn import_roster_csv(
+        &mut self,
+        path: &Path,
+        qr_type: QrType,
+    ) -> Result<ImportReport, ImportError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .flexible(true)
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(ImportError::Csv)?;
+
+        let headers = reader.headers().map_err(ImportError::Csv)?.clone();
+        let find_column = |column: &str| {
+            headers
+                .iter()
+                .position(|header| header.eq_ignore_ascii_case(column))
+        };
+        let name_column = find_column("name");
+        let type_column = find_column("type");
+
+        // Without a name header, the header row is the first entry.
+        let header_row = name_column.is_none().then_some(headers.clone());
+        let name_column = name_column.unwrap_or(0);
+
+        let mut report = ImportReport::default();
+        let transaction = self.conn_mut().transaction().map_err(ImportError::Sqlite)?;
+        {
+            let mut mentor_stmt = transaction
+                .prepare_cached("INSERT OR IGNORE INTO mentors (name) VALUES (?1);")
+                .map_err(ImportError::Sqlite)?;
+            let mut student_stmt = transaction
+                .prepare_cached("INSERT OR IGNORE INTO students (name) VALUES (?1);")
+                .map_err(ImportError::Sqlite)?;
+            let mut on_mentors_stmt = transaction
+                .prepare_cached("SELECT EXISTS (SELECT 1 FROM mentors WHERE name = ?1);")
+                .map_err(ImportError::Sqlite)?;
+            let mut on_students_stmt = transaction
+                .prepare_cached("SELECT EXISTS (SELECT 1 FROM students WHERE name = ?1);")
+                .map_err(ImportError::Sqlite)?;
+
+            for record in header_row.into_iter().map(Ok).chain(reader.records()) {
+                let Ok(record) = record else {
+                    report.malformed += 1;
+                    continue;
+                };
+
+                let name = record.get(name_column).unwrap_or_default();
+                let row_type = match type_column.and_then(|column| record.get(column)) {
+                    Some(row_type) if !row_type.is_empty() => row_type.parse().ok(),
+                    _ => Some(qr_type),
+                };
+
+                let stmts = match row_type {
+                    _ if name.is_empty() => None,
+                    Some(QrType::Mentor) => Some((&mut mentor_stmt, &mut on_students_stmt)),
+                    Some(QrType::Student) => Some((&mut student_stmt, &mut on_mentors_stmt)),
+                    Some(QrType::Guest) | None => None,
+                };
+                let Some((stmt, other_role_stmt)) = stmts else {
+                    report.malformed += 1;
+                    continue;
+                };
+                if other_role_stmt
+                    .query_row([name], |row| row.get(0))
+                    .map_err(ImportError::Sqlite)?
+                {
+                    report.skipped_other_role += 1;
+                    continue;
+                }
+
+                if stmt.execute([name]).map_err(ImportError::Sqlite)? > 0 {
+                    report.added += 1;
+                } else {
+                    report.skipped_duplicate += 1;
+                }
+            }
+
+            if report.added > 0 {
+                Self::audit(
+                    &transaction,
+                    "roster_import",
+                    &path.display().to_string(),
+                    &report.to_string(),
+                )
+                .map_err(ImportError::Db)?;
+            }
+        }
+        transaction.commit().map_err(ImportError::Sqlite)?;
+
+        Ok(report)
+    }
+
+    /// Every scan in `range` (all scans for `None`), oldest first.
+    pub fn attendance(
+        &self,
+        range: Option<(DateTime<Local>, DateTime<Local>)>,
+    ) -> rusqlite::Result<Vec<AttendanceRow>> {
+        let (start, end) = range.map_or((i64::MIN, i64::MAX), |(start, end)| {
+            (start.timestamp(), end.timestamp())
+        });
+
+        let mut stmt = self.conn().prepare_cached(
+            "SELECT attendance.name, attendance.timestamp, attendance.utc_offset,
+    CASE
+        WHEN mentors.name IS NOT NULL THEN 'mentor'
+        WHEN students.name IS NOT NULL THEN 'student'
+        WHEN attendance.name LIKE 'Guest%' THEN 'guest'
+        ELSE 'unknown'
+    END
+FROM attendance
+LEFT JOIN mentors ON mentors.name = attendance.name
+LEFT JOIN students ON students.name = attendance.name
+WHERE attendance.timestamp >= ?1 AND attendance.timestamp < ?2
+ORDER BY attendance.timestamp, attendance.name;",
+        )?;
+        let rows = stmt.query_map((start, end), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, Option<i32>>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let mut attendance = Vec::new();
+        for row in rows {
+            let (name, timestamp, offset, qr_type) = row?;
+            if let (Some(time), Some(day)) = (local_time(timestamp), scan_day(timestamp, offset)) {
+                attendance.push(AttendanceRow {
+                    name,
+                    timestamp: time,
+                    day,
+                    qr_type,
+                });
+            }
+        }
+
+        Ok(attendance)
+    }
+
+    /// Writes every scan in `range` (all scans for `None`) as
+    /// `name,timestamp,type` CSV rows, returning the number of scans written.
+    ///
+    /// Timestamps are local time with the UTC offset, so spreadsheets do not
+    /// reinterpret them.
+    pub fn export_attendance_csv(
+        &self,
+        range: Option<(DateTime<Local>, DateTime<Local>)>,
+        writer: impl Write,
+    ) -> Result<usize, ExportError> {
+        let rows = self.attendance(range).map_err(ExportError::Sqlite)?;
+
+        let mut writer = csv::Writer::from_writer(writer);
+        writer
+            .write_record(["name", "timestamp", "type"])
+            .map_err(ExportError::Csv)?;
+
+        for row in &rows {
+            let timestamp = row.timestamp.format("%Y-%m-%dT%H:%M:%S%:z").to_string();
+            writer
+                .write_record([row.name.as_str(), &timestamp, &row.qr_type])
+                .map_err(ExportError::Csv)?;
+        }
+        writer.flush().map_err(|e| ExportError::Csv(e.into()))?;
+
+        Ok(rows.len())
+    }
+
+    /// Longest a session counts for when the sign-out scan is missing.
+    pub fn max_session(&self) -> Result<TimeDelta, DbError> {
+        Ok(TimeDelta::hours(
+            self.get_setting(MAX_SESSION_HOURS_KEY)?
+                .unwrap_or(DEFAULT_MAX_SESSION_HOURS),
+        ))
+    }
+
+    /// Every scan of `name`, oldest first.
+    pub fn all_scans(&self, name: &str) -> Result<Vec<DateTime<Local>>, DbError> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT timestamp FROM attendance WHERE name = ?1 ORDER BY timestamp;")
+            .map_err(DbError::Sqlite)?;
+
+        Ok(stmt
+            .query_map([name], |row| row.get::<_, i64>(0))
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DbError::Sqlite)?
+            .into_iter()
+            .filter_map(local_time)
+            .collect())
+    }
+
+    /// Total time present per person for sessions starting on or after
+    /// `since` and before `until` (if set), sorted by name.
+    ///
+    /// Scans alternate between signing in and out, starting from each
+    /// person's first ever scan. Sessions are capped at the configured
+    /// maximum, so a forgotten sign-out does not count until the next scan
+    /// days later. A session still open is counted up to now.
+    pub fn hours_summary(
+        &self,
+        since: DateTime<Local>,
+        until: Option<DateTime<Local>>,
+    ) -> Result<Vec<(String, chrono::Duration)>, DbError> {
+        let max_session = self.max_session()?;
+        let since = since.timestamp();
+        let until = until.map_or(i64::MAX, |until| until.timestamp());
+        let now = Local::now().timestamp();
+
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT name, timestamp FROM attendance ORDER BY name, timestamp;")
+            .map_err(DbError::Sqlite)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DbError::Sqlite)?
+            .into_iter();
+
+        let mut scans: HashMap<String, Vec<i64>> = HashMap::new();
+        for (name, timestamp) in rows {
+            scans.entry(name).or_default().push(timestamp);
+        }
+
+        let mut summary: Vec<_> = scans
+            .into_iter()
+            .filter_map(|(name, timestamps)| {
+                let total = timestamps
+                    .chunks(2)
+                    .filter(|session| (since..until).contains(&session[0]))
+                    .map(|session| {
+                        let end = session.get(1).copied().unwrap_or(now);
+                        TimeDelta::seconds(end - session[0]).min(max_session)
+                    })
+                    .sum::<TimeDelta>();
+                (total > TimeDelta::zero()).then_some((name, total))
+            })
+            .collect();
+        summary.sort_unstable_by(|(lhs, _), (rhs, _)| lhs.cmp(rhs));
+
+        Ok(summary)
+    }
+
+    /// Hours of everyone on the rosters for sessions starting in `range`,
+    /// alongside their hours since the season start.
+    ///
+    /// Pairs scans and caps sessions the same way as
+    /// [`Self::hours_summary`]. Guests are left out.
+    pub fn report(&self, range: DateRange) -> Result<Report, DbError> {
+        let (since, until) = range;
+        let season_start = report::season_start(self)?;
+        let season_since = local_datetime(season_start, NaiveTime::MIN).min(since);
+        let max_session = self.max_session()?;
+        let now = Local::now();
+
+        // Inactive members keep their role, see `set_member_active`.
+        let mut mentors = HashSet::new();
+        let mut students = HashSet::new();
+        for member in self.get_all_members(true)? {
+            match member.role {
+                QrType::Mentor => mentors.insert(member.name),
+                _ => students.insert(member.name),
+            };
+        }
+        let mut on_both_rosters: Vec<_> = mentors.intersection(&students).cloned().collect();
+        on_both_rosters.sort();
+        for name in &on_both_rosters {
+            warn!(target: "report", %name, "On both rosters, reporting as a mentor");
+        }
+
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT name, timestamp FROM attendance ORDER BY name, timestamp;")
+            .map_err(DbError::Sqlite)?;
+        let mut scans: HashMap<String, Vec<DateTime<Local>>> = HashMap::new();
+        for (name, timestamp) in stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DbError::Sqlite)?
+            .into_iter()
+        {
+            if let Some(timestamp) = local_time(timestamp) {
+                scans.entry(name).or_default().push(timestamp);
+            }
+        }
+
+        let row_for = |name: &String| {
+            let mut row = ReportRow {
+                name: name.clone(),
+                hours: TimeDelta::zero(),
+                sessions: 0,
+                unpaired: 0,
+                season_hours: TimeDelta::zero(),
+            };
+            for session in scans.get(name).map_or(&[][..], Vec::as_slice).chunks(2) {
+                let start = session[0];
+                let end = session.get(1).copied().unwrap_or(now);
+                let duration = (end - start).min(max_session);
+
+                if (season_since..until).contains(&start) {
+                    row.season_hours += duration;
+                }
+                if (since..until).contains(&start) {
+                    row.hours += duration;
+                    row.sessions += 1;
+                    if session.len() == 1 || end - start > max_session {
+                        row.unpaired += 1;
+                    }
+                }
+            }
+            row
+        };
+
+        let sorted = |names: Vec<&String>| {
+            let mut rows: Vec<_> = names.into_iter().map(&row_for).collect();
+            rows.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+            rows
+        };
+        let mentor_rows = sorted(mentors.iter().collect());
+        let student_rows = sorted(students.difference(&mentors).collect());
+        // Only those who scanned this season.
+        let mut former_members = sorted(
+            scans
+                .keys()
+                .filter(|name| {
+                    !mentors.contains(*name)
+                        && !students.contains(*name)
+                        && !name.starts_with("Guest")
+                })
+                .collect(),
+        );
+        former_members.retain(|row| row.sessions > 0 || !row.season_hours.is_zero());
+
+        Ok(Report {
+            range,
+            season_start,
+            mentors: mentor_rows,
+            students: student_rows,
+            former_members,
+            on_both_rosters,
+        })
+    }
+
+    /// Falls back to the `resolution` table older versions wrote.
+    pub fn get_resolution(&self) -> Result<Option<Resolution>, DbError> {
+        if let Some(CameraResolution(resolution)) = self.get_setting(RESOLUTION_KEY)? {
+            return Ok(Some(resolution));
+        }
+
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT x, y FROM resolution;")
+            .map_err(DbError::Sqlite)?;
+        stmt.query_row([], |row| Ok(Resolution::new(row.get(0)?, row.get(1)?)))
+            .optional()
+            .map_err(DbError::Sqlite)
+    }
+
+    pub fn set_resolution(&mut self, resolution: Resolution) -> Result<(), DbError> {
+        self.set_setting(RESOLUTION_KEY, resolution)
+    }
+
+    pub fn get_camera_index(&self) -> Result<Option<u32>, DbError> {
+        self.get_setting(CAMERA_INDEX_KEY)
+    }
+
+    pub fn set_camera_index(&mut self, index: u32) -> Result<(), DbError> {
+        self.set_setting(CAMERA_INDEX_KEY, index)
+    }
+
+    /// Saved seconds between two scans of one badge, the config file's take
+    /// precedence.
+    pub fn scan_spacing_secs(&self) -> Result<Option<i64>, DbError> {
+        self.get_setting(SCAN_SPACING_KEY)
+    }
+
+    pub fn set_scan_spacing_secs(&mut self, secs: i64) -> Result<(), DbError> {
+        self.set_setting(SCAN_SPACING_KEY, secs)
+    }
+
+    pub fn min_dwell_secs(&self) -> Result<i64, DbError> {
+        Ok(self.get_setting(MIN_DWELL_KEY)?.unwrap_or(0))
+    }
+
+    pub fn set_min_dwell_secs(&mut self, secs: i64) -> Result<(), DbError> {
+        self.set_setting(MIN_DWELL_KEY, secs)
+    }
+
+    pub fn max_occupancy(&self) -> Result<Option<usize>, DbError> {
+        self.get_setting(MAX_OCCUPANCY_KEY)
+    }
+
+    /// `None` removes the limit.
+    pub fn set_max_occupancy(&mut self, max: Option<usize>) -> Result<(), DbError> {
+        match max {
+            Some(max) => self.set_setting(MAX_OCCUPANCY_KEY, max),
+            // Unparseable, so no limit.
+            None => self.set_setting(MAX_OCCUPANCY_KEY, ""),
+        }
+    }
+
+    /// File holding scans waiting on the database, next to it. `None` for
+    /// in-memory and read-only databases, which never queue scans to disk.
+    pub fn pending_scans_path(&self) -> Option<PathBuf> {
+        if self.conn().is_readonly(rusqlite::MAIN_DB).unwrap_or(true) {
+            return None;
+        }
+        let mut path = self.stats().path?.into_os_string();
+        path.push("-pending.jsonl");
+        Some(path.into())
+    }
+
+    pub fn stats(&self) -> DbStats {
+        let path = self
+            .conn()
+            .path()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let file_size = |path: &Path| fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+
+        let (size, wal_size) = path.as_ref().map_or((0, 0), |path| {
+            let mut wal_path = path.clone().into_os_string();
+            wal_path.push("-wal");
+            (file_size(path), file_size(Path::new(&wal_path)))
+        });
+
+        DbStats {
+            path,
+            size,
+            wal_size,
+        }
+    }
+
+    /// Directory for backups, defaulting to `backups` in the data directory.
+    pub fn backup_dir(&self) -> Result<PathBuf, DbError> {
+        Ok(self
+            .get_setting(BACKUP_DIR_KEY)?
+            .unwrap_or_else(|| config::data_path(DataPath::Backups)))
+    }
+
+    pub fn last_backup(&self) -> Result<Option<DateTime<Local>>, DbError> {
+        Ok(self.get_setting(LAST_BACKUP_KEY)?.and_then(local_time))
+    }
+
+    /// Where a backup started now is written, in [`Self::backup_dir`] and
+    /// named after the team, e.g. `gearcats-qr-tracker-<date>.db`.
+    pub fn next_backup_path(&self) -> Result<PathBuf, DbError> {
+        let team: String = self
+            .get_setting::<String>(TEAM_NAME_KEY)?
+            .unwrap_or_default()
+            .chars()
+            .map(|c| {
+                if c.is_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        let team = team.trim_matches('-');
+        let prefix = if team.is_empty() {
+            String::new()
+        } else {
+            format!("{team}-")
+        };
+
+        Ok(self.backup_dir()?.join(format!(
+            "{prefix}{BACKUP_STEM}{}.db",
+            Local::now().format("%Y-%m-%d_%H%M%S")
+        )))
+    }
+
+    /// A second, read-only connection to the database file, so a backup can
+    /// copy from it while this connection keeps recording scans.
+    pub fn backup_source(&self) -> Result<Connection, BackupError> {
+        let path = self.stats().path.ok_or(BackupError::InMemory)?;
+        Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(BackupError::Sqlite)
+    }
+
+    /// Records the outcome of a backup. Successes delete all but the newest
+    /// [`BACKUP_KEEP_KEY`] backups, failures are kept for the admin panel.
+    pub fn finish_backup(&mut self, result: &Result<PathBuf, BackupError>) -> Result<(), DbError> {
+        let path = match result {
+            Ok(path) => path,
+            Err(e) => {
+                self.set_setting(LAST_BACKUP_ERROR_KEY, e)?;
+                return self.log_event("backup_failed", "", &e.to_string());
+            }
+        };
+
+        self.set_setting(LAST_BACKUP_KEY, Local::now().timestamp())?;
+        self.set_setting(LAST_BACKUP_ERROR_KEY, "")?;
+        info!(target: "db", "Backed up to {}", path.display());
+
+        let keep = self
+            .get_setting(BACKUP_KEEP_KEY)?
+            .unwrap_or(DEFAULT_BACKUP_KEEP);
+        let mut backups: Vec<_> = fs::read_dir(self.backup_dir()?)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| {
+                        let name = entry.file_name().to_string_lossy().to_string();
+                        name.contains(BACKUP_STEM) && name.ends_with(".db")
+                    })
+                    .filter_map(|entry| {
+                        Some((entry.metadata().ok()?.modified().ok()?, entry.path()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        // Newest first.
+        backups.sort_by(|lhs, rhs| rhs.cmp(lhs));
+
+        for (_, old) in backups.into_iter().skip(keep.max(1)) {
+            match fs::remove_file(&old) {
+                Ok(()) => info!(target: "db", "Deleted old backup {}", old.display()),
+                Err(e) => warn!(target: "db", "Failed to delete old backup {}: {e}", old.display()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a compacted copy of the database to `path`, including anything
+    /// still in the WAL.
+    pub fn copy_to(&self, path: &Path) -> rusqlite::Result<()> {
+        self.conn()
+            .execute("VACUUM INTO ?1;", [path.to_string_lossy()])
+            .map(|_| ())
+    }
+
+    pub fn checkpoint(&self) -> Result<(), DbError> {
+        self.conn()
+            .execute_batch("PRAGMA wal_checkpoint(PASSIVE);")
+            .map_err(DbError::Sqlite)?;
+        debug!(target: "db", "Checkpointed WAL");
+        Ok(())
+    }
+
+    /// All scans for `name` on the local calendar day `date`, oldest first.
+    pub fn scans_on(&self, name: &str, date: NaiveDate) -> Result<Vec<DateTime<Local>>, DbError> {
+        let (start, end) = scan_day_bounds(date);
+        let mut stmt = self
+            .conn()
+            .prepare_cached(
+                "SELECT timestamp, utc_offset FROM attendance
+WHERE name = ?1 AND timestamp >= ?2 AND timestamp < ?3
+ORDER BY timestamp;",
+            )
+            .map_err(DbError::Sqlite)?;
+
+        Ok(stmt
+            .query_map((name, start, end), |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DbError::Sqlite)?
+            .into_iter()
+            .filter(|&(timestamp, offset)| scan_day(timestamp, offset) == Some(date))
+            .filter_map(|(timestamp, _)| local_time(timestamp))
+            .collect())
+    }
+
+    /// How many scans `name` has from before `time`. Each scan toggles, so
+    /// an even count means the scan at `time` signs in.
+    pub fn scan_count_before(&self, name: &str, time: DateTime<Local>) -> Result<usize, DbError> {
+        self.conn()
+            .prepare_cached("SELECT COUNT(*) FROM attendance WHERE name = ?1 AND timestamp < ?2;")
+            .map_err(DbError::Sqlite)?
+            .query_row((name, time.timestamp()), |row| row.get::<_, i64>(0))
+            .map_err(DbError::Sqlite)
+            .map(|count| count as usize)
+    }
+
+    /// All scans for `name` within `range`, oldest first.
+    pub fn scans_for(&self, name: &str, range: DateRange) -> Result<Vec<DateTime<Local>>, DbError> {
+        let (since, until) = range;
+        let mut stmt = self
+            .conn()
+            .prepare_cached(
+                "SELECT timestamp FROM attendance
+WHERE name = ?1 AND timestamp >= ?2 AND timestamp < ?3
+ORDER BY timestamp;",
+            )
+            .map_err(DbError::Sqlite)?;
+
+        Ok(stmt
+            .query_map((name, since.timestamp(), until.timestamp()), |row| {
+                row.get(0)
+            })
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DbError::Sqlite)?
+            .into_iter()
+            .filter_map(local_time)
+            .collect())
+    }
+
+    /// Scans of each person on the local calendar day `date`, oldest first,
+    /// sorted by name.
+    pub fn attendance_on(&self, date: NaiveDate) -> Result<ScansByName, DbError> {
+        let (start, end) = scan_day_bounds(date);
+        let mut stmt = self
+            .conn()
+            .prepare_cached(
+                "SELECT name, timestamp, utc_offset FROM attendance
+WHERE timestamp >= ?1 AND timestamp < ?2
+ORDER BY name, timestamp;",
+            )
+            .map_err(DbError::Sqlite)?;
+        let rows = stmt
+            .query_map((start, end), |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<i32>>(2)?,
+                ))
+            })
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DbError::Sqlite)?
+            .into_iter();
+
+        let mut attendance: Vec<(String, Vec<DateTime<Local>>)> = Vec::new();
+        for (name, timestamp, offset) in rows {
+            if scan_day(timestamp, offset) != Some(date) {
+                continue;
+            }
+            let Some(timestamp) = local_time(timestamp) else {
+                continue;
+            };
+            match attendance.last_mut() {
+                Some((last, scans)) if *last == name => scans.push(timestamp),
+                _ => attendance.push((name, vec![timestamp])),
+            }
+        }
+        Ok(attendance)
+    }
+
+    /// Number of distinct local days with a scan by `name`, starting on `since`.
+    pub fn days_attended(&self, name: &str, since: NaiveDate) -> Result<usize, DbError> {
+        let (start, _) = day_bounds(since);
+        // Days by the offset recorded with each scan, see `scan_day`.
+        let mut stmt = self
+            .conn()
+            .prepare_cached(
+                "SELECT COUNT(DISTINCT COALESCE(
+    date(timestamp + utc_offset, 'unixepoch'),
+    date(timestamp, 'unixepoch', 'localtime')
+))
+FROM attendance
+WHERE name = ?1 AND timestamp >= ?2;",
+            )
+            .map_err(DbError::Sqlite)?;
+
+        stmt.query_row((name, start), |row| row.get::<_, i64>(0))
+            .map_err(DbError::Sqlite)
+            .map(|days| days as usize)
+    }
+
+    /// Total time between paired in and out scans for `name` on `date`.
+    ///
+    /// A trailing unpaired scan (still present) is not counted.
+    pub fn time_on(&self, name: &str, date: NaiveDate) -> Result<TimeDelta, DbError> {
+        Ok(self
+            .scans_on(name, date)?
+            .chunks_exact(2)
+            .map(|pair| pair[1] - pair[0])
+            .sum())
+    }
+
+    /// Moves a single scan from `old` to `new`.
+    ///
+    /// Presence is derived from scan order, so the new timestamp has to stay
+    /// strictly between the neighboring scans for `name`. The `current` row is
+    /// updated when the latest scan moves, and the change is audited. Peers
+    /// see the edit as this kiosk removing the old scan and recording the new
+    /// one.
+    pub fn update_scan_timestamp(
+        &mut self,
+        name: &str,
+        old: DateTime<Local>,
+        new: DateTime<Local>,
+    ) -> Result<(), ScanEditError> {
+        let new_offset = utc_offset(&new);
+        let (old, new) = (old.timestamp(), new.timestamp());
+        let sqlite = |e| ScanEditError::Db(DbError::Sqlite(e));
+
+        let kiosk_id = self.kiosk_id.clone();
+        let transaction = self.conn_mut().transaction().map_err(sqlite)?;
+        {
+            let mut neighbor_stmt = transaction
+                .prepare_cached(
+                    "SELECT
+    (SELECT MAX(timestamp) FROM attendance WHERE name = ?1 AND timestamp < ?2),
+    (SELECT MIN(timestamp) FROM attendance WHERE name = ?1 AND timestamp > ?2),
+    EXISTS (SELECT 1 FROM attendance WHERE name = ?1 AND timestamp = ?2),
+    (SELECT utc_offset FROM attendance WHERE name = ?1 AND timestamp = ?2);",
+                )
+                .map_err(sqlite)?;
+            let (previous, next, exists, old_offset): (
+                Option<i64>,
+                Option<i64>,
+                bool,
+                Option<i32>,
+            ) = neighbor_stmt
+                .query_row((name, old), |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })
+                .map_err(sqlite)?;
+
+            if !exists {
+                return Err(ScanEditError::MissingScan);
+            }
+            if previous.is_some_and(|previous| new <= previous)
+                || next.is_some_and(|next| new >= next)
+            {
+                return Err(ScanEditError::Reorders);
+            }
+
+            // Updated in place to keep the scan's source and snapshot.
+            Self::record_removal(&transaction, &kiosk_id, name, old).map_err(ScanEditError::Db)?;
+            let seq = Self::next_seq(&transaction).map_err(ScanEditError::Db)?;
+            let mut update_stmt = transaction
+                .prepare_cached(
+                    "UPDATE attendance SET timestamp = ?3, utc_offset = ?4, kiosk_id = ?5, seq = ?6
+WHERE name = ?1 AND timestamp = ?2;",
+                )
+                .map_err(sqlite)?;
+            update_stmt
+                .execute((name, old, new, new_offset, &kiosk_id, seq))
+                .map_err(sqlite)?;
+
+            // The latest scan is mirrored into the current table.
+            if next.is_none() {
+                let mut current_stmt = transaction
+                    .prepare_cached("UPDATE current SET timestamp = ?2 WHERE name = ?1;")
+                    .map_err(sqlite)?;
+                current_stmt.execute((name, new)).map_err(sqlite)?;
+            }
+
+            Self::audit(
+                &transaction,
+                "edit_scan",
+                name,
+                &format!(
+                    "{} -> {}",
+                    scan_time_label(old, old_offset),
+                    scan_time_label(new, Some(new_offset))
+                ),
+            )
+            .map_err(ScanEditError::Db)?;
+        }
+        transaction.commit().map_err(sqlite)?;
+
+        Ok(())
+    }
+
+    /// Replaces every meeting ending after `from` with `meetings`, keeping
+    /// the record of past meetings.
+    pub fn replace_meetings(
+        &mut self,
+        from: DateTime<Local>,
+        meetings: &[Meeting],
+    ) -> Result<(), DbError> {
+        let transaction = self.conn_mut().transaction().map_err(DbError::Sqlite)?;
+        {
+            let mut delete_stmt = transaction
+                .prepare_cached("DELETE FROM meetings WHERE end > ?1;")
+                .map_err(DbError::Sqlite)?;
+            let mut insert_stmt = transaction
+                .prepare_cached(
+                    "INSERT OR IGNORE INTO meetings (start, end, summary) VALUES (?1, ?2, ?3);",
+                )
+                .map_err(DbError::Sqlite)?;
+
+            delete_stmt
+                .execute([from.timestamp()])
+                .map_err(DbError::Sqlite)?;
+            for meeting in meetings.iter().filter(|meeting| meeting.end > from) {
+                insert_stmt
+                    .execute((
+                        meeting.start.timestamp(),
+                        meeting.end.timestamp(),
+                        &meeting.summary,
+                    ))
+                    .map_err(DbError::Sqlite)?;
+            }
+
+            Self::audit(
+                &transaction,
+                "schedule_sync",
+                "meetings",
+                &format!("{} meetings", meetings.len()),
+            )?;
+        }
+        transaction.commit().map_err(DbError::Sqlite)?;
+        Ok(())
+    }
+
+    /// Meetings overlapping `from` to `to`, earliest first.
+    pub fn meetings_between(
+        &self,
+        from: DateTime<Local>,
+        to: DateTime<Local>,
+    ) -> Result<Vec<Meeting>, DbError> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached(
+                "SELECT start, end, summary FROM meetings
+WHERE end > ?1 AND start < ?2
+ORDER BY start;",
+            )
+            .map_err(DbError::Sqlite)?;
+
+        Ok(stmt
+            .query_map((from.timestamp(), to.timestamp()), |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DbError::Sqlite)?
+            .into_iter()
+            .filter_map(|(start, end, summary)| {
+                Some(Meeting {
+                    start: local_time(start)?,
+                    end: local_time(end)?,
+                    summary,
+                })
+            })
+            .collect())
+    }
+
+    /// `None` when unset or not a valid `T`, so callers fall back to their
+    /// default.
+    pub fn get_setting<T: FromStr>(&self, key: &str) -> Result<Option<T>, DbError> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached("SELECT value FROM settings WHERE key = ?1;")
+            .map_err(DbError::Sqlite)?;
+
+        let value: Option<String> = stmt
+            .query_row([key], |row| row.get(0))
+            .optional()
+            .map_err(DbError::Sqlite)?;
+        Ok(value.and_then(|value| {
+            let parsed = value.parse().ok();
+            // Blank values are left on purpose to unset a setting.
+            if parsed.is_none() && !value.is_empty() {
+                warn!(target: "db", key, value, "Ignoring unparsable setting");
+            }
+            parsed
+        }))
+    }
+
+    pub fn set_setting<T: ToString>(&mut self, key: &str, value: T) -> Result<(), DbError> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached(
+                "INSERT INTO settings (key, value) VALUES (?1, ?2)
+ON CONFLICT(key) DO UPDATE
+SET value = ?2;",
+            )
+            .map_err(DbError::Sqlite)?;
+        stmt.execute((key, value.to_string()))
+            .map_err(DbError::Sqlite)?;
+        Ok(())
+    }
+
+    /// Number of audit entries, for paging [`Self::audit_entries`].
+    pub fn audit_count(&self) -> Result<usize, DbError> {
+        self.conn()
+            .prepare_cached("SELECT COUNT(*) FROM audit;")
+            .map_err(DbError::Sqlite)?
+            .query_row([], |row| row.get::<_, i64>(0))
+            .map_err(DbError::Sqlite)
+            .map(|count| count as usize)
+    }
+
+    /// Up to `limit` audit entries, newest first, skipping the `offset`
+    /// newest.
+    pub fn audit_entries(&self, offset: usize, limit: usize) -> Result<Vec<AuditEntry>, DbError> {
+        let mut stmt = self
+            .conn()
+            .prepare_cached(
+                "SELECT at, action, subject, detail FROM audit
+ORDER BY id DESC
+LIMIT ?1 OFFSET ?2;",
+            )
+            .map_err(DbError::Sqlite)?;
+
+        Ok(stmt
+            .query_map((limit as i64, offset as i64), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })
+            .map_err(DbError::Sqlite)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(DbError::Sqlite)?
+            .into_iter()
+            .filter_map(|(at, action, subject, detail)| {
+                Some(AuditEntry {
+                    at: local_time(at)?,
+                    action,
+                    subject,
+                    detail,
+                })
+            })
+            .collect())
+    }
+
+    /// Deletes audit entries older than the `audit.retention_days` setting,
+    /// noting how many in the audit log.
+    pub fn prune_audit(&mut self) -> Result<(), DbError> {
+        let Some(days) = self
+            .get_setting::<i64>(AUDIT_RETENTION_DAYS_KEY)?
+            .filter(|days| *days > 0)
+        else {
+            return Ok(());
+        };
+        let cutoff = (Local::now() - TimeDelta::days(days)).timestamp();
+
+        let transaction = self.conn_mut().transaction().map_err(DbError::Sqlite)?;
+        let pruned = transaction
+            .execute("DELETE FROM audit WHERE at < ?1;", [cutoff])
+            .map_err(DbError::Sqlite)?;
+        if pruned > 0 {
+            Self::audit(
+                &transaction,
+                "audit_pruned",
+                "",
+                &format!("{pruned} entries older than {days} days"),
+            )?;
+        }
+        transaction.commit().map_err(DbError::Sqlite)?;
+        if pruned > 0 {
+            info!(target: "db", pruned, "Pruned audit log");
+        }
+        Ok(())
+    }
+
+    /// Records an event that is not itself a database modification.
+    pub fn log_event(&self, action: &str, subject: &str, detail: &str) -> Result<(), DbError> {
+        Self::audit(self.conn(), action, subject, detail)
+    }
+
+    /// Records a manual modification alongside the change it describes.
+    /// Runs `change` in a transaction, recording `action` in the audit log
+    /// within it when any rows changed. Whether any did.
+    fn audited(
+        &mut self,
+        action: &str,
+        subject: &str,
+        detail: &str,
+        change: impl FnOnce(&Transaction) -> rusqlite::Result<usize>,
+    ) -> Result<bool, DbError> {
+        let transaction = self.conn_mut().transaction().map_err(DbError::Sqlite)?;
+        let changed = change(&transaction).map_err(DbError::Sqlite)? > 0;
+        if changed {
+            Self::audit(&transaction, action, subject, detail)?;
+        }
+        transaction.commit().map_err(DbError::Sqlite)?;
+        Ok(changed)
+    }
+
+    fn audit(conn: &Connection, action: &str, subject: &str, detail: &str) -> Result<(), DbError> {
+        let mut stmt = conn
+            .prepare_cached(
+                "INSERT INTO audit (at, action, subject, detail) VALUES (?1, ?2, ?3, ?4);",
+            )
+            .map_err(DbError::Sqlite)?;
+        stmt.execute((Local::now().timestamp(), action, subject, detail))
+            .map_err(DbError::Sqlite)?;
+        debug!(target: "db", action, subject, detail, "Audit event");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{LocalResult, NaiveDateTime, Utc};
+
+    use super::*;
+
+    /// America/Santiago around its 2024-2025 summer time, which starts and
+    /// ends at midnight.
+    #[derive(Debug, Clone, Copy)]
+    struct Santiago;
+
+    impl Santiago {
+        fn offsets() -> [FixedOffset; 2] {
+            [
+                FixedOffset::west_opt(3 * 3600).unwrap(),
+                FixedOffset::west_opt(4 * 3600).unwrap(),
+            ]
+        }
+    }
+
+    impl TimeZone for Santiago {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Self
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset> {
+            let valid: Vec<_> = Self::offsets()
+                .into_iter()
+                .filter(|offset| {
+                    self.offset_from_utc_datetime(
+                        &(*local - TimeDelta::seconds(offset.local_minus_utc().into())),
+                    ) == *offset
+                })
+                .collect();
+            match valid[..] {
+                [] => LocalResult::None,
+                [offset] => LocalResult::Single(offset),
+                [earliest, latest] => LocalResult::Ambiguous(earliest, latest),
+                _ => unreachable!(),
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            let utc = Utc.from_utc_datetime(utc);
+            let [summer, winter] = Self::offsets();
+            if Utc.with_ymd_and_hms(2024, 9, 8, 4, 0, 0).unwrap() <= utc
+                && utc < Utc.with_ymd_and_hms(2025, 4, 6, 3, 0, 0).unwrap()
+            {
+                summer
+            } else {
+                winter
+            }
+        }
+    }
+
+    fn santiago(date: &str, time: &str) -> DateTime<Santiago> {
+        datetime_in(
+            &Santiago,
+            date.parse().unwrap(),
+            NaiveTime::parse_from_str(time, "%H:%M").unwrap(),
+        )
+    }
+
+    fn utc(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn skipped_midnight_moves_an_hour_later() {
+        // Clocks jump from 00:00 to 01:00.
+        assert_eq!(santiago("2024-09-08", "00:00"), utc("2024-09-08T04:00:00Z"));
+        assert_eq!(santiago("2024-09-08", "00:30"), utc("2024-09-08T04:30:00Z"));
+        assert_eq!(santiago("2024-09-08", "01:00"), utc("2024-09-08T04:00:00Z"));
+        assert_eq!(santiago("2024-09-07", "23:59"), utc("2024-09-08T03:59:00Z"));
+    }
+
+    #[test]
+    fn repeated_hour_is_first_occurrence() {
+        // Clocks fall back from 00:00 to 23:00 the day before.
+        assert_eq!(santiago("2025-04-05", "23:30"), utc("2025-04-06T02:30:00Z"));
+        assert_eq!(santiago("2025-04-06", "00:00"), utc("2025-04-06T04:00:00Z"));
+        // The day the hour repeats on is 25 hours long.
+        assert_eq!(
+            santiago("2025-04-06", "00:00") - santiago("2025-04-05", "00:00"),
+            TimeDelta::hours(25)
+        );
+    }
+
+    #[test]
+    fn day_bounds_cover_the_day() {
+        let date = NaiveDate::from_ymd_opt(2024, 9, 8).unwrap();
+        let (start, end) = day_bounds(date);
+        assert!(start < end);
+        assert_eq!(
+            date_range(Some(date), Some(date)).map(|(from, to)| (from.timestamp(), to.timestamp())),
+            Some((start, end))
+        );
+        assert_eq!(date_range(None, None), None);
+    }
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn pull(from: &BackingDatabase, into: &mut BackingDatabase) {
+        let changes = from.changes_since(0, 1000).unwrap();
+        into.merge_changes(from.kiosk_id(), &changes, 30).unwrap();
+    }
+
+    fn sorted_present(db: &BackingDatabase) -> Vec<(String, DateTime<Local>)> {
+        let mut present = db.get_present().unwrap();
+        present.sort();
+        present
+    }
+
+    /// Two kiosks that both saw Ada's visit, and one scan each of their
+    /// own, synced in either order.
+    fn converge(a_first: bool) {
+        let mut a = BackingDatabase::new(None).unwrap();
+        let mut b = BackingDatabase::new(None).unwrap();
+        a.add_scan("Ada", at(0), None).unwrap();
+        b.add_scan("Ada", at(5), None).unwrap();
+        b.add_scan("Bob", at(60), None).unwrap();
+        a.add_scan("Cy", at(120), None).unwrap();
+
+        for _ in 0..2 {
+            if a_first {
+                pull(&a, &mut b);
+                pull(&b, &mut a);
+            } else {
+                pull(&b, &mut a);
+                pull(&a, &mut b);
+            }
+        }
+
+        assert_eq!(sorted_present(&a), sorted_present(&b));
+        assert_eq!(
+            sorted_present(&a),
+            [
+                ("Ada".to_string(), at(0)),
+                ("Bob".to_string(), at(60)),
+                ("Cy".to_string(), at(120)),
+            ]
+        );
+        assert_eq!(a.all_scans("Ada").unwrap(), [at(0)]);
+        assert_eq!(b.all_scans("Ada").unwrap(), [at(0)]);
+    }
+
+    #[test]
+    fn two_kiosks_converge() {
+        converge(true);
+        converge(false);
+    }
+
+    #[test]
+    fn interleaved_scans_converge() {
+        let mut a = BackingDatabase::new(None).unwrap();
+        let mut b = BackingDatabase::new(None).unwrap();
+        // Ada alternates doors, Bob signs in at one and out at the other.
+        a.add_scan("Ada", at(0), None).unwrap();
+        b.add_scan("Bob", at(100), None).unwrap();
+        b.add_scan("Ada", at(3600), None).unwrap();
+        a.add_scan("Bob", at(4000), None).unwrap();
+        a.add_scan("Ada", at(7200), None).unwrap();
+
+        pull(&a, &mut b);
+        pull(&b, &mut a);
+
+        assert_eq!(sorted_present(&a), [("Ada".to_string(), at(7200))]);
+        assert_eq!(sorted_present(&a), sorted_present(&b));
+        assert_eq!(a.all_scans("Ada").unwrap(), b.all_scans("Ada").unwrap());
+    }
+
+    #[test]
+    fn deactivated_member_keeps_history() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.add_student("Ada").unwrap();
+        db.add_scan("Ada", at(0), None).unwrap();
+
+        assert!(db.set_member_active("Ada", false).unwrap());
+        assert!(!db.set_member_active("Ada", false).unwrap());
+        assert!(db.get_students().unwrap().is_empty());
+        assert_eq!(
+            db.roster_entry("Ada").unwrap(),
+            Some((QrType::Student, false))
+        );
+        assert_eq!(db.all_scans("Ada").unwrap(), [at(0)]);
+
+        assert!(db.set_member_active("Ada", true).unwrap());
+        assert_eq!(db.get_students().unwrap(), ["Ada"]);
+    }
+
+    #[test]
+    fn counts_scans_before() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        for secs in [0, 3600, 86_400] {
+            db.add_scan("Ada", at(secs), None).unwrap();
+        }
+        db.add_scan("Bob", at(60), None).unwrap();
+
+        assert_eq!(db.scan_count_before("Ada", at(0)).unwrap(), 0);
+        assert_eq!(db.scan_count_before("Ada", at(86_400)).unwrap(), 2);
+        assert_eq!(db.scan_count_before("Ada", at(90_000)).unwrap(), 3);
+        assert_eq!(db.scan_count_before("Cy", at(90_000)).unwrap(), 0);
+    }
+
+    fn hours(db: &BackingDatabase, since: i64, until: Option<i64>) -> Vec<(String, TimeDelta)> {
+        db.hours_summary(at(since), until.map(at)).unwrap()
+    }
+
+    #[test]
+    fn hours_pair_consecutive_scans() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        for secs in [0, 3600, 7200, 9000] {
+            db.add_scan("Ada", at(secs), None).unwrap();
+        }
+        db.add_scan("Bob", at(60), None).unwrap();
+        db.add_scan("Bob", at(120), None).unwrap();
+
+        assert_eq!(
+            hours(&db, 0, None),
+            [
+                ("Ada".to_string(), TimeDelta::seconds(3600 + 1800)),
+                ("Bob".to_string(), TimeDelta::seconds(60)),
+            ]
+        );
+    }
+
+    #[test]
+    fn hours_capped_at_max_session() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.set_setting(MAX_SESSION_HOURS_KEY, 6).unwrap();
+        db.add_scan("Ada", at(0), None).unwrap();
+        db.add_scan("Ada", at(10 * 3600), None).unwrap();
+
+        assert_eq!(
+            hours(&db, 0, None),
+            [("Ada".to_string(), TimeDelta::hours(6))]
+        );
+    }
+
+    #[test]
+    fn open_session_counts_until_now() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        let signed_in = Local::now() - TimeDelta::hours(1);
+        db.add_scan("Ada", signed_in, None).unwrap();
+
+        let summary = db.hours_summary(signed_in, None).unwrap();
+        let [(name, total)] = &summary[..] else {
+            panic!("expected one person, got {summary:?}");
+        };
+        assert_eq!(name, "Ada");
+        assert!((TimeDelta::hours(1)..TimeDelta::minutes(61)).contains(total));
+    }
+
+    #[test]
+    fn hours_bounded_by_session_start() {
+        const DAY: i64 = 86_400;
+        let mut db = BackingDatabase::new(None).unwrap();
+        for secs in [0, 3600, DAY, DAY + 1800] {
+            db.add_scan("Ada", at(secs), None).unwrap();
+        }
+        let ada = |secs| vec![("Ada".to_string(), TimeDelta::seconds(secs))];
+
+        assert_eq!(hours(&db, 0, None), ada(5400));
+        assert_eq!(hours(&db, 1, None), ada(1800));
+        assert_eq!(hours(&db, DAY, None), ada(1800));
+        assert_eq!(hours(&db, 0, Some(DAY)), ada(3600));
+        // Sessions starting inside the range count in full.
+        assert_eq!(hours(&db, 0, Some(1)), ada(3600));
+        assert_eq!(hours(&db, DAY + 1, None), []);
+    }
+
+    #[test]
+    fn merge_is_idempotent() {
+        let mut a = BackingDatabase::new(None).unwrap();
+        let mut b = BackingDatabase::new(None).unwrap();
+        a.add_scan("Ada", at(0), None).unwrap();
+        a.add_scan("Ada", at(3600), None).unwrap();
+
+        let changes = a.changes_since(0, 1000).unwrap();
+        assert_eq!(b.merge_changes(a.kiosk_id(), &changes, 30).unwrap(), 2);
+        assert_eq!(b.merge_changes(a.kiosk_id(), &changes, 30).unwrap(), 0);
+        assert!(b.get_present().unwrap().is_empty());
+    }
+
+    /// Pulls the changes past `since`, moving it on like the sync loop.
+    fn pull_since(from: &BackingDatabase, into: &mut BackingDatabase, since: &mut i64) {
+        let changes = from.changes_since(*since, 1000).unwrap();
+        into.merge_changes(from.kiosk_id(), &changes, 30).unwrap();
+        if let Some(last) = changes.last() {
+            *since = last.seq;
+        }
+    }
+
+    #[test]
+    fn undone_scan_numbers_not_reused() {
+        let mut a = BackingDatabase::new(None).unwrap();
+        let mut b = BackingDatabase::new(None).unwrap();
+        let mut since = 0;
+
+        a.add_scan("Ada", at(0), None).unwrap();
+        pull_since(&a, &mut b, &mut since);
+        a.remove_scan("Ada", at(0)).unwrap();
+        a.add_scan("Bob", at(60), None).unwrap();
+        pull_since(&a, &mut b, &mut since);
+
+        assert_eq!(sorted_present(&b), [("Bob".to_string(), at(60))]);
+        assert_eq!(sorted_present(&a), sorted_present(&b));
+    }
+
+    #[test]
+    fn edits_synced() {
+        let mut a = BackingDatabase::new(None).unwrap();
+        let mut b = BackingDatabase::new(None).unwrap();
+        let mut c = BackingDatabase::new(None).unwrap();
+        let (mut a_since, mut c_since) = (0, 0);
+
+        // C's scan, moved at A, reaches B from C after the edit from A.
+        c.add_scan("Ada", at(0), None).unwrap();
+        pull_since(&c, &mut a, &mut 0);
+        a.update_scan_timestamp("Ada", at(0), at(10)).unwrap();
+        pull_since(&a, &mut b, &mut a_since);
+        pull_since(&c, &mut b, &mut c_since);
+        pull_since(&a, &mut c, &mut 0);
+
+        for db in [&a, &b, &c] {
+            assert_eq!(sorted_present(db), [("Ada".to_string(), at(10))]);
+        }
+        assert_eq!(b.scans_on("Ada", at(10).date_naive()).unwrap(), [at(10)]);
+    }
+
+    /// A week of meetings, a session past midnight, a capped session, and
+    /// one still open the week after.
+    fn reporting_db() -> BackingDatabase {
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.add_mentor("Mo").unwrap();
+        db.add_student("Ada").unwrap();
+        db.add_student("Bob").unwrap();
+        db.set_setting(MAX_SESSION_HOURS_KEY, 6).unwrap();
+
+        let scans = [
+            ("Ada", "2024-03-04", "18:00"),
+            ("Ada", "2024-03-04", "21:00"),
+            ("Bob", "2024-03-04", "18:10"),
+            ("Bob", "2024-03-04", "20:40"),
+            ("Mo", "2024-03-04", "17:45"),
+            ("Mo", "2024-03-04", "21:15"),
+            ("Guest Gil", "2024-03-04", "19:00"),
+            ("Guest Gil", "2024-03-04", "20:00"),
+            ("Ada", "2024-03-04", "23:30"),
+            ("Ada", "2024-03-05", "00:30"),
+            ("Bob", "2024-03-10", "10:00"),
+            ("Bob", "2024-03-10", "20:00"),
+            ("Mo", "2024-03-11", "18:00"),
+            ("Mo", "2024-03-11", "19:30"),
+            ("Ada", "2024-03-20", "18:00"),
+        ];
+        for (name, date, time) in scans {
+            let date = date.parse().unwrap();
+            let time = NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+            db.add_scan(name, local_datetime(date, time), None).unwrap();
+        }
+        db
+    }
+
+    fn role_of(db: &BackingDatabase, name: &str) -> String {
+        match db.roster_role(name).unwrap() {
+            Some(role) => role.to_string(),
+            None if name.starts_with("Guest") => "guest".to_string(),
+            None => "unknown".to_string(),
+        }
+    }
+
+    #[test]
+    fn daily_view_matches_attendance_on() {
+        let db = reporting_db();
+        let mut stmt = db
+            .conn()
+            .prepare("SELECT date, role, distinct_people FROM v_daily_attendance ORDER BY 1, 2;")
+            .unwrap();
+        let view: Vec<(String, String, i64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        let mut expected = Vec::new();
+        for date in [
+            "2024-03-04",
+            "2024-03-05",
+            "2024-03-10",
+            "2024-03-11",
+            "2024-03-20",
+        ] {
+            let mut roles: Vec<_> = db
+                .attendance_on(date.parse().unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| role_of(&db, &name))
+                .collect();
+            roles.sort();
+            for people in roles.chunk_by(|lhs, rhs| lhs == rhs) {
+                expected.push((date.to_string(), people[0].clone(), people.len() as i64));
+            }
+        }
+        assert_eq!(view, expected);
+        assert_eq!(
+            view[..3],
+            [
+                ("2024-03-04".to_string(), "guest".to_string(), 1),
+                ("2024-03-04".to_string(), "mentor".to_string(), 1),
+                ("2024-03-04".to_string(), "student".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn hours_view_matches_hours_summary() {
+        let db = reporting_db();
+        // The open session's hours grow while the test runs.
+        let mut stmt = db
+            .conn()
+            .prepare("SELECT name, week, hours FROM v_person_hours WHERE week < '2024-03-18' ORDER BY 2, 1;")
+            .unwrap();
+        let view: Vec<(String, String, i64)> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    (row.get::<_, f64>(2)? * 3600.0).round() as i64,
+                ))
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        let mut expected = Vec::new();
+        for week in ["2024-03-04", "2024-03-11"] {
+            let monday: NaiveDate = week.parse().unwrap();
+            let since = local_datetime(monday, NaiveTime::MIN);
+            let until = local_datetime(monday + TimeDelta::days(7), NaiveTime::MIN);
+            for (name, hours) in db.hours_summary(since, Some(until)).unwrap() {
+                expected.push((name, week.to_string(), hours.num_seconds()));
+            }
+        }
+        assert_eq!(view, expected);
+        // Bob's Sunday ran past the six hour cap, and is in Monday's week.
+        assert!(view.contains(&(
+            "Bob".to_string(),
+            "2024-03-04".to_string(),
+            (150 + 360) * 60
+        )));
+    }
+
+    #[test]
+    fn present_view_matches_get_present() {
+        let db = reporting_db();
+        let mut stmt = db
+            .conn()
+            .prepare("SELECT name, role, since FROM v_current_present ORDER BY name;")
+            .unwrap();
+        let view: Vec<(String, String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        let expected: Vec<_> = sorted_present(&db)
+            .into_iter()
+            .map(|(name, since)| {
+                let role = role_of(&db, &name);
+                (name, role, since.format("%Y-%m-%d %H:%M:%S").to_string())
+            })
+            .collect();
+        assert_eq!(view, expected);
+        assert_eq!(view[0].2, "2024-03-20 18:00:00");
+    }
+
+    #[test]
+    fn views_use_recorded_offsets_across_fall_back() {
+        // Ada's visit runs through Santiago's repeated hour, Bob arrives
+        // after midnight, whatever timezone the tests run in.
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.add_mentor("Ada").unwrap();
+        db.add_student("Bob").unwrap();
+        let scan = |name: &str, time: DateTime<Santiago>| QueuedScan {
+            name: name.to_string(),
+            timestamp: time.timestamp(),
+            utc_offset: Some(time.offset().local_minus_utc()),
+            source: None,
+        };
+        let ada_in = santiago("2025-04-05", "22:00");
+        let ada_out = Santiago
+            .from_local_datetime(&"2025-04-05T23:30:00".parse().unwrap())
+            .latest()
+            .unwrap();
+        let bob_in = santiago("2025-04-06", "00:30");
+        assert_eq!(ada_out - ada_in, TimeDelta::minutes(150));
+        db.add_scans(&[
+            scan("Ada", ada_in),
+            scan("Ada", ada_out),
+            scan("Bob", bob_in),
+        ])
+        .unwrap();
+
+        let daily: Vec<(String, String, i64)> = db
+            .conn()
+            .prepare("SELECT date, role, distinct_people FROM v_daily_attendance ORDER BY 1, 2;")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            daily,
+            [
+                ("2025-04-05".to_string(), "mentor".to_string(), 1),
+                ("2025-04-06".to_string(), "student".to_string(), 1),
+            ]
+        );
+        for (date, name) in [("2025-04-05", "Ada"), ("2025-04-06", "Bob")] {
+            let names: Vec<_> = db
+                .attendance_on(date.parse().unwrap())
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
+            assert_eq!(names, [name], "{date}");
+        }
+
+        let hours: (String, f64) = db
+            .conn()
+            .query_row(
+                "SELECT week, hours FROM v_person_hours WHERE name = 'Ada';",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(hours, ("2025-03-31".to_string(), 2.5));
+        let (since, until) = (ada_in.with_timezone(&Local), bob_in.with_timezone(&Local));
+        assert_eq!(
+            db.hours_summary(since, Some(until)).unwrap(),
+            [("Ada".to_string(), TimeDelta::minutes(150))]
+        );
+        let report = db.report((since, until)).unwrap();
+        assert_eq!(report.mentors[0].hours, TimeDelta::minutes(150));
+        assert_eq!(report.mentors[0].sessions, 1);
+
+        let since: String = db
+            .conn()
+            .query_row(
+                "SELECT since FROM v_current_present WHERE name = 'Bob';",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(since, "2025-04-06 00:30:00");
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        assert_eq!(db.get_setting::<i64>("test.number").unwrap(), None);
+        db.set_setting("test.number", 42).unwrap();
+        db.set_setting("test.number", 43).unwrap();
+        assert_eq!(db.get_setting::<i64>("test.number").unwrap(), Some(43));
+        assert_eq!(
+            db.get_setting::<String>("test.number").unwrap().as_deref(),
+            Some("43")
+        );
+
+        db.set_scan_spacing_secs(30).unwrap();
+        db.set_min_dwell_secs(60).unwrap();
+        db.set_max_occupancy(Some(25)).unwrap();
+        db.set_camera_index(2).unwrap();
+        db.set_resolution(Resolution::new(1280, 720)).unwrap();
+        assert_eq!(db.scan_spacing_secs().unwrap(), Some(30));
+        assert_eq!(db.min_dwell_secs().unwrap(), 60);
+        assert_eq!(db.max_occupancy().unwrap(), Some(25));
+        assert_eq!(db.get_camera_index().unwrap(), Some(2));
+        assert_eq!(
+            db.get_resolution().unwrap(),
+            Some(Resolution::new(1280, 720))
+        );
+
+        db.set_max_occupancy(None).unwrap();
+        assert_eq!(db.max_occupancy().unwrap(), None);
+    }
+
+    #[test]
+    fn unparsable_settings_fall_back() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        for key in [
+            SCAN_SPACING_KEY,
+            MIN_DWELL_KEY,
+            MAX_OCCUPANCY_KEY,
+            CAMERA_INDEX_KEY,
+            RESOLUTION_KEY,
+            MAX_SESSION_HOURS_KEY,
+        ] {
+            db.set_setting(key, "not a number").unwrap();
+        }
+        assert_eq!(db.get_setting::<i64>(SCAN_SPACING_KEY).unwrap(), None);
+        assert_eq!(db.scan_spacing_secs().unwrap(), None);
+        assert_eq!(db.min_dwell_secs().unwrap(), 0);
+        assert_eq!(db.max_occupancy().unwrap(), None);
+        assert_eq!(db.get_camera_index().unwrap(), None);
+        assert_eq!(db.get_resolution().unwrap(), None);
+
+        // Out of range for the type is as unparsable as text.
+        db.set_setting(MAX_OCCUPANCY_KEY, -1).unwrap();
+        db.set_setting(CAMERA_INDEX_KEY, "99999999999").unwrap();
+        db.set_setting(RESOLUTION_KEY, "1280x").unwrap();
+        assert_eq!(db.max_occupancy().unwrap(), None);
+        assert_eq!(db.get_camera_index().unwrap(), None);
+        assert_eq!(db.get_resolution().unwrap(), None);
+
+        // Falls back to the default cap rather than none.
+        db.add_scan("Ada", at(0), None).unwrap();
+        db.add_scan("Ada", at(10 * 3600), None).unwrap();
+        assert_eq!(
+            hours(&db, 0, None),
+            [(
+                "Ada".to_string(),
+                TimeDelta::hours(DEFAULT_MAX_SESSION_HOURS)
+            )]
+        );
+    }
+
+    #[test]
+    fn resolution_read_from_old_table() {
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.conn()
+            .execute("INSERT INTO resolution VALUES (640, 480);", [])
+            .unwrap();
+        assert_eq!(
+            db.get_resolution().unwrap(),
+            Some(Resolution::new(640, 480))
+        );
+
+        // Unparsable settings still leave the old table to fall back on.
+        db.set_setting(RESOLUTION_KEY, "garbage").unwrap();
+        assert_eq!(
+            db.get_resolution().unwrap(),
+            Some(Resolution::new(640, 480))
+        );
+
+        db.set_resolution(Resolution::new(1920, 1080)).unwrap();
+        assert_eq!(
+            db.get_resolution().unwrap(),
+            Some(Resolution::new(1920, 1080))
+        );
+    }
+
+    #[test]
+    fn edits_audited_as_local_times() {
+        // 2024-09-02 18:00 in New York.
+        const SCANNED: i64 = 1_725_314_400;
+        let mut db = BackingDatabase::new(None).unwrap();
+        db.add_scans(&[QueuedScan {
+            name: "Ada".to_string(),
+            timestamp: SCANNED,
+            utc_offset: Some(-4 * 3600),
+            source: None,
+        }])
+        .unwrap();
+
+        let old = local_time(SCANNED).unwrap();
+        let new = local_time(SCANNED + 90).unwrap();
+        db.update_scan_timestamp("Ada", old, new).unwrap();
+        let audit = db.audit_entries(0, 1).unwrap();
+        assert_eq!(audit[0].action, "edit_scan");
+        assert_eq!(
+            audit[0].detail,
+            format!(
+                "2024-09-02 18:00:00 -04:00 -> {}",
+                new.format("%Y-%m-%d %H:%M:%S %:z")
+            )
+        );
     }
 }
@@ -0,0 +1,333 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Pulls scans from other kiosks so every kiosk shows the same attendance.
+//!
+//! Each kiosk serves its own scans on `/api/changes`, and pulls from every
+//! configured peer. Presence is re-derived from the merged scans with each
+//! scan toggling in and out. A peer's scan within the scan spacing of another
+//! kiosk's scan of the same person is dropped as the same visit, seen at both
+//! doors. Undone scans are removed everywhere, and edits move the scan
+//! everywhere.
+
+use std::{fmt::Display, io, time::Duration};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    MIN_SCAN_SPACING_SECS,
+    api::{CHANGES_PAGE_SIZE, TOKEN_KEY},
+    db_worker::DbHandle,
+    sqlite::{DbError, SyncedScan},
+};
+
+/// Comma separated peer base URLs, e.g. `http://10.0.0.2:2344`.
+pub const PEERS_KEY: &str = "sync.peers";
+pub const INTERVAL_KEY: &str = "sync.interval_secs";
+/// Prefix of the settings holding the last merged sequence per peer.
+const CURSOR_KEY_PREFIX: &str = "sync.cursor.";
+
+const DEFAULT_INTERVAL_SECS: u64 = 30;
+/// Shortest pause between pulls, whatever the setting says.
+const MIN_INTERVAL_SECS: u64 = 1;
+/// Each page a peer takes longer to send is retried next interval.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Upper bound on a peer response.
+const MAX_RESPONSE_LEN: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct Changes {
+    kiosk_id: String,
+    changes: Vec<SyncedScan>,
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    /// Only plain `http://host:port` peers are supported.
+    Url,
+    Io(io::Error),
+    Timeout,
+    Status(String),
+    Json(serde_json::Error),
+    Db(DbError),
+}
+
+impl Display for SyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Url => write!(f, "peer URL must look like http://host:port"),
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Timeout => write!(f, "timed out"),
+            Self::Status(status) => write!(f, "peer responded {status}"),
+            Self::Json(e) => write!(f, "invalid response: {e}"),
+            Self::Db(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Pulls from the configured peers every interval, asking the scan loop to
+/// reload whenever new scans arrive. Idles while no peers are configured.
+pub async fn sync_peers(backing_db: DbHandle, refresh_tx: async_channel::Sender<()>) {
+    loop {
+        let (peers, token, interval) = backing_db
+            .try_run(|db| {
+                Ok((
+                    db.get_setting::<String>(PEERS_KEY)?.unwrap_or_default(),
+                    db.get_setting::<String>(TOKEN_KEY)?.unwrap_or_default(),
+                    db.get_setting(INTERVAL_KEY)?
+                        .unwrap_or(DEFAULT_INTERVAL_SECS),
+                ))
+            })
+            .await
+            .unwrap_or((String::new(), String::new(), DEFAULT_INTERVAL_SECS));
+
+        for peer in peers
+            .split(',')
+            .map(str::trim)
+            .filter(|peer| !peer.is_empty())
+        {
+            match pull(&backing_db, peer, &token).await {
+                Ok(0) => debug!(target: "sync", peer, "No new scans"),
+                Ok(merged) => {
+                    info!(target: "sync", peer, merged, "Merged scans");
+                    let _ = refresh_tx.try_send(());
+                }
+                Err(e) => warn!(target: "sync", peer, "Sync failed: {e}"),
+            }
+        }
+
+        tokio::time::sleep(Duration::from_secs(interval.max(MIN_INTERVAL_SECS))).await;
+    }
+}
+
+/// Merges every new scan from `peer`, returning how many were new here.
+async fn pull(backing_db: &DbHandle, peer: &str, token: &str) -> Result<usize, SyncError> {
+    let cursor_key = format!("{CURSOR_KEY_PREFIX}{peer}");
+    let mut merged = 0;
+
+    loop {
+        let cursor = cursor_key.clone();
+        let since: i64 = backing_db
+            .try_run(move |db| Ok(db.get_setting(&cursor)?.unwrap_or(0)))
+            .await
+            .map_err(SyncError::Db)?;
+        // A long backlog is fine as long as every page keeps arriving.
+        let response: Changes = tokio::time::timeout(
+            REQUEST_TIMEOUT,
+            get_json(peer, &format!("/api/changes?since={since}"), token),
+        )
+        .await
+        .map_err(|_| SyncError::Timeout)??;
+        let last_page = response.changes.len() < CHANGES_PAGE_SIZE;
+
+        // `None` when the peer is this kiosk.
+        let cursor = cursor_key.clone();
+        let page = backing_db
+            .try_run(move |db| {
+                if response.kiosk_id == db.kiosk_id() {
+                    return Ok(None);
+                }
+                let dedupe_secs = db.scan_spacing_secs()?.unwrap_or(MIN_SCAN_SPACING_SECS);
+                let merged =
+                    db.merge_changes(&response.kiosk_id, &response.changes, dedupe_secs)?;
+                if let Some(last) = response.changes.last() {
+                    db.set_setting(&cursor, last.seq)?;
+                }
+                Ok(Some(merged))
+            })
+            .await
+            .map_err(SyncError::Db)?;
+        let Some(page) = page else {
+            warn!(target: "sync", peer, "Peer is this kiosk, skipping");
+            return Ok(merged);
+        };
+
+        merged += page;
+        if last_page {
+            return Ok(merged);
+        }
+    }
+}
+
+/// Minimal HTTP/1.1 GET for the API served by [`crate::api`].
+async fn get_json<T: for<'de> Deserialize<'de>>(
+    peer: &str,
+    path: &str,
+    token: &str,
+) -> Result<T, SyncError> {
+    let host = peer
+        .strip_prefix("http://")
+        .map(|host| host.trim_end_matches('/'))
+        .filter(|host| !host.is_empty() && !host.contains('/'))
+        .ok_or(SyncError::Url)?;
+
+    let mut stream = TcpStream::connect(host).await.map_err(SyncError::Io)?;
+    stream
+        .write_all(
+            format!(
+                "GET {path} HTTP/1.1\r\nHost: {host}\r\nAuthorization: Bearer {token}\r\nConnection: close\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await
+        .map_err(SyncError::Io)?;
+
+    let mut response = Vec::new();
+    stream
+        .take(MAX_RESPONSE_LEN)
+        .read_to_end(&mut response)
+        .await
+        .map_err(SyncError::Io)?;
+
+    let split = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| SyncError::Status("an incomplete response".to_string()))?;
+    let head = String::from_utf8_lossy(&response[..split]);
+    let status = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_once(' '))
+        .map_or("", |(_, status)| status);
+    if !status.starts_with("200") {
+        return Err(SyncError::Status(status.to_string()));
+    }
+
+    serde_json::from_slice(&response[split + 4..]).map_err(SyncError::Json)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+    use serde_json::json;
+    use tokio::{io::AsyncBufReadExt, net::TcpListener};
+
+    use super::*;
+    use crate::sqlite::BackingDatabase;
+
+    /// Serves `/api/changes` for `kiosk_id` until dropped, answering every
+    /// request with `status` instead when set.
+    async fn fake_peer(
+        kiosk_id: String,
+        scans: Vec<SyncedScan>,
+        status: Option<&'static str>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (read, mut write) = stream.split();
+                let mut lines = tokio::io::BufReader::new(read).lines();
+                let request_line = lines.next_line().await.unwrap().unwrap_or_default();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if line.is_empty() {
+                        break;
+                    }
+                }
+
+                let since: i64 = request_line
+                    .split_once("since=")
+                    .and_then(|(_, rest)| rest.split(' ').next()?.parse().ok())
+                    .unwrap_or(0);
+                let changes: Vec<_> = scans.iter().filter(|scan| scan.seq > since).collect();
+                let (status, body) = match status {
+                    Some(status) => (status, String::new()),
+                    None => (
+                        "200 OK",
+                        json!({ "kiosk_id": kiosk_id, "changes": changes }).to_string(),
+                    ),
+                };
+                let _ = write
+                    .write_all(
+                        format!("HTTP/1.1 {status}\r\nConnection: close\r\n\r\n{body}").as_bytes(),
+                    )
+                    .await;
+            }
+        });
+        peer
+    }
+
+    fn at(secs: i64) -> chrono::DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn pulls_interleaved_scans_once() {
+        // Ada signs in here, out at the peer, and in again here. The peer is
+        // filled first, spawning a worker marks this as the UI thread.
+        let mut peer_db = BackingDatabase::new(None).unwrap();
+        peer_db.add_scan("Ada", at(3600), None).unwrap();
+        peer_db.add_scan("Bob", at(3700), None).unwrap();
+        let peer = fake_peer(
+            peer_db.kiosk_id().to_string(),
+            peer_db.changes_since(0, 1000).unwrap(),
+            None,
+        )
+        .await;
+
+        let handle = DbHandle::spawn(|| BackingDatabase::new(None).unwrap());
+        handle
+            .run(|db| db.add_scan("Ada", at(0), None))
+            .await
+            .unwrap()
+            .unwrap();
+        handle
+            .run(|db| db.add_scan("Ada", at(7200), None))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pull(&handle, &peer, "token").await.unwrap(), 2);
+        // Only past the saved cursor the second time.
+        assert_eq!(pull(&handle, &peer, "token").await.unwrap(), 0);
+
+        let mut present = handle.try_run(|db| db.get_present()).await.unwrap();
+        present.sort();
+        assert_eq!(
+            present,
+            [("Ada".to_string(), at(7200)), ("Bob".to_string(), at(3700))]
+        );
+    }
+
+    #[tokio::test]
+    async fn own_changes_skipped() {
+        let handle = DbHandle::spawn(|| BackingDatabase::new(None).unwrap());
+        handle
+            .run(|db| db.add_scan("Ada", at(0), None))
+            .await
+            .unwrap()
+            .unwrap();
+        let (kiosk_id, scans) = handle
+            .try_run(|db| Ok((db.kiosk_id().to_string(), db.changes_since(0, 1000)?)))
+            .await
+            .unwrap();
+
+        let peer = fake_peer(kiosk_id, scans, None).await;
+        assert_eq!(pull(&handle, &peer, "token").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn peer_errors_reported() {
+        let handle = DbHandle::spawn(|| BackingDatabase::new(None).unwrap());
+        let peer = fake_peer(String::new(), Vec::new(), Some("401 Unauthorized")).await;
+        assert!(matches!(
+            pull(&handle, &peer, "wrong").await,
+            Err(SyncError::Status(status)) if status == "401 Unauthorized"
+        ));
+
+        for url in ["https://10.0.0.2:2344", "http://", "http://10.0.0.2/api"] {
+            assert!(
+                matches!(pull(&handle, url, "token").await, Err(SyncError::Url)),
+                "{url}"
+            );
+        }
+    }
+}
@@ -0,0 +1,117 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Build identifiers and the optional update check.
+//!
+//! The check reads `update_url`, a JSON file such as `{"version": "0.2.0"}`,
+//! once a day. It never blocks the kiosk, and failures are only logged at
+//! debug level. Requires the `update-check` feature.
+
+use std::{fmt::Display, time::Duration};
+
+use dioxus::prelude::*;
+use tracing::{debug, info};
+
+use crate::config::Config;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short commit hash the binary was built from.
+pub const GIT_HASH: &str = env!("GIT_HASH");
+/// As printed by `--version`.
+pub const LONG_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), " (", env!("GIT_HASH"), ")");
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+#[cfg_attr(not(feature = "update-check"), allow(dead_code))]
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+#[cfg_attr(not(feature = "update-check"), allow(dead_code))]
+pub enum UpdateError {
+    /// Built without the `update-check` feature.
+    #[cfg(not(feature = "update-check"))]
+    Disabled,
+    Fetch(String),
+    /// No `version` string in the response.
+    Parse,
+}
+
+impl Display for UpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            #[cfg(not(feature = "update-check"))]
+            Self::Disabled => write!(f, "built without the update-check feature"),
+            Self::Fetch(e) => write!(f, "{e}"),
+            Self::Parse => write!(f, "no version in response"),
+        }
+    }
+}
+
+/// Whether `latest` is a later dotted version than `current`. Pre-release
+/// suffixes are ignored.
+fn is_newer(latest: &str, current: &str) -> bool {
+    let parts = |version: &str| -> Vec<u64> {
+        version
+            .trim()
+            .trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse().unwrap_or(0))
+            .collect()
+    };
+    parts(latest) > parts(current)
+}
+
+/// Checks `update_url` once a day while configured, setting `available` to
+/// the latest version when it is newer than this build.
+pub async fn check_updates(config: Config, mut available: Signal<Option<String>>) {
+    let Some(url) = config.update_url else {
+        return;
+    };
+
+    loop {
+        match fetch_latest(&url).await {
+            Ok(latest) if is_newer(&latest, VERSION) => {
+                info!(target: "version", latest, "Update available");
+                available.set(Some(latest));
+            }
+            Ok(latest) => debug!(target: "version", latest, "Up to date"),
+            Err(e) => debug!(target: "version", "Update check failed: {e}"),
+        }
+
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+#[cfg(feature = "update-check")]
+async fn fetch_latest(url: &str) -> Result<String, UpdateError> {
+    let response = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| UpdateError::Fetch(e.to_string()))?
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| UpdateError::Fetch(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(UpdateError::Fetch(format!("HTTP {}", response.status())));
+    }
+
+    let body: serde_json::Value = response
+        .json()
+        .await
+        .map_err(|e| UpdateError::Fetch(e.to_string()))?;
+    body["version"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or(UpdateError::Parse)
+}
+
+#[cfg(not(feature = "update-check"))]
+async fn fetch_latest(_url: &str) -> Result<String, UpdateError> {
+    Err(UpdateError::Disabled)
+}
@@ -4,50 +4,957 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use image::{DynamicImage, codecs::jpeg::JpegDecoder};
+use image::{DynamicImage, GrayImage, codecs::jpeg::JpegDecoder};
 use nokhwa::{
     Camera,
     pixel_format::RgbFormat,
-    utils::{CameraIndex, FrameFormat, RequestedFormat, RequestedFormatType, Resolution},
+    utils::{
+        ApiBackend, CameraIndex, FrameFormat, RequestedFormat, RequestedFormatType, Resolution,
+    },
 };
 use opencv::{
-    core::{Mat, MatTrait, MatTraitConst, Point, Size, Vector},
+    core::{Mat, MatTrait, MatTraitConst, Point, Point2f, Scalar, Size, Vector},
     imgcodecs::{
         IMREAD_COLOR, IMREAD_GRAYSCALE, IMREAD_REDUCED_GRAYSCALE_2, IMREAD_REDUCED_GRAYSCALE_4,
-        IMREAD_REDUCED_GRAYSCALE_8, IMREAD_UNCHANGED, imdecode, imread, imwrite_def,
+        IMREAD_REDUCED_GRAYSCALE_8, IMREAD_UNCHANGED, imdecode, imencode_def, imread, imwrite_def,
     },
     imgproc::{
-        CHAIN_APPROX_SIMPLE, INTER_CUBIC, RETR_EXTERNAL, RETR_LIST, RETR_TREE, THRESH_BINARY,
-        THRESH_OTSU, bounding_rect, find_contours_def, gaussian_blur_def, resize, threshold,
+        CHAIN_APPROX_SIMPLE, INTER_CUBIC, LINE_AA, RETR_EXTERNAL, RETR_LIST, RETR_TREE,
+        THRESH_BINARY, THRESH_OTSU, bounding_rect, find_contours_def, gaussian_blur_def, polylines,
+        resize, threshold,
+    },
+    objdetect::{BarcodeDetector, QRCodeDetector},
+    prelude::{
+        GraphicalCodeDetectorTraitConst, QRCodeDetectorTraitConst, VideoCaptureTrait,
+        VideoCaptureTraitConst,
+    },
+    videoio::{
+        CAP_ANY, CAP_PROP_FPS, CAP_PROP_FRAME_HEIGHT, CAP_PROP_FRAME_WIDTH, CAP_PROP_POS_FRAMES,
+        VideoCapture,
     },
-    objdetect::QRCodeDetector,
-    prelude::QRCodeDetectorTraitConst,
 };
 use rqrr::PreparedImage;
 
 use std::{
-    fs::File,
-    io::{Cursor, Read, Write},
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Cursor, Read, Write},
+    path::{Path, PathBuf},
     process::exit,
-    sync::atomic::{AtomicBool, Ordering},
+    str::FromStr,
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{self, SyncSender, TrySendError},
+    },
+    time::{Duration, Instant},
+};
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    thread,
 };
-use std::{net::TcpListener, thread};
+
+use socket2::{Domain, Protocol, Socket, Type};
+use tracing::{debug, debug_span, error, info, info_span, trace, warn};
+
+use qr_tracker::atomic_buf::{AtomicBuffer, AtomicBufferWriter, BufferStats, LagPolicy, ReadError};
 
 use crate::{
-    CAMERA_RESOLUTION_LIST, VIDEO_SOCKET,
-    atomic_buf::{AtomicBuffer, AtomicBufferSplit},
+    API_STREAM_CLIENTS, CAMERA_CONNECTED, CAMERA_ERROR, CAMERA_RESOLUTION_LIST, LATEST_FRAME,
+    PIPELINE_METRICS, SCAN_MODE, VIDEO_ADDR, platform, shutdown,
 };
 
 /// Arbitrary buffer length to allow streaming/analysis to catch up with input.
 const FRAME_BUFFER_SIZE: usize = 128;
 
-type FrameBuffer = AtomicBuffer<Box<[u8]>, FRAME_BUFFER_SIZE, 5>;
+/// The stream writer, then one analysis thread per scale.
+const FRAME_READERS: usize = 5;
+/// Frames are analysed at full, half, quarter, and eighth scale.
+pub const ANALYSIS_SCALES: usize = FRAME_READERS - 1;
+
+/// The main camera, then the optional second camera.
+const FRAME_WRITERS: usize = 2;
+
+/// Camera id of the main camera, the one shown on the preview stream.
+pub const PRIMARY_CAMERA: usize = 0;
+/// Camera id of the optional second camera.
+const SECOND_CAMERA: usize = 1;
+
+/// A JPEG frame and the camera it came from.
+///
+/// Filled in place, so each slot's allocation is reused once it fits the
+/// largest frame.
+#[derive(Debug, Default)]
+struct TaggedFrame {
+    camera: usize,
+    jpeg: Vec<u8>,
+}
+
+impl TaggedFrame {
+    fn fill(&mut self, camera: usize, jpeg: &[u8]) {
+        self.camera = camera;
+        self.jpeg.clear();
+        self.jpeg.extend_from_slice(jpeg);
+    }
+}
+
+type FrameBuffer = AtomicBuffer<TaggedFrame, FRAME_BUFFER_SIZE, FRAME_READERS>;
+
+/// A decoded code and the camera that saw it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CameraRead {
+    pub payload: String,
+    /// [`PRIMARY_CAMERA`], or the second camera.
+    pub camera: usize,
+}
+
+/// Camera behavior from the config file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoOptions {
+    pub max_frame_failures: u32,
+    pub qr_fallback: bool,
+    /// Index of a second camera scanned alongside the first, e.g. at a
+    /// second door.
+    pub second_camera: Option<u32>,
+}
+
+/// Frames the full resolution analysis may fall behind before its oldest
+/// are skipped. It is the slowest reader at high resolutions, and would
+/// otherwise fall ever further behind the camera.
+const FULL_SCALE_MAX_LAG: usize = 16;
+
+/// Time between frame buffer statistics logs.
+const BUFFER_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time between looking for a camera while none is connected.
+const CAMERA_RETRY: Duration = Duration::from_secs(3);
+
+/// Consecutive failed frame reads before the camera is given up on.
+pub const DEFAULT_MAX_FRAME_FAILURES: u32 = 10;
+/// Wait before reopening the camera after the first failed frame read,
+/// doubled for each further failure.
+const FRAME_RETRY_BASE: Duration = Duration::from_millis(250);
+const FRAME_RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// Longest an analysis thread waits on a frame before checking for flushes
+/// and shutdown.
+const ANALYSIS_POLL: Duration = Duration::from_millis(100);
+
+/// Identical reads within this window, from any analysis thread, are sent
+/// once.
+const DEDUPE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Setting holding the [`ScanMode`].
+pub const SCAN_MODE_KEY: &str = "scan.mode";
+
+/// Setting holding the preview stream's `address:port`.
+pub const VIDEO_BIND_KEY: &str = "video.bind";
+/// Time between checks for new stream connections and address changes.
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+
+/// Longest accepted HTTP request head on the video port.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+/// Connections that send no complete request in this time are closed.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const FRAME_RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Frames queued per stream client, newer frames are skipped for clients
+/// this far behind.
+const STREAM_CLIENT_BACKLOG: usize = 2;
+
+/// How long a detection stays outlined on the stream.
+const OVERLAY_TTL: Duration = Duration::from_millis(500);
+const OVERLAY_THICKNESS: i32 = 3;
+
+/// Files without a readable frame rate play at this rate.
+const DEFAULT_FILE_FPS: f64 = 30.0;
+
+/// Where frames come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VideoSource {
+    /// A camera, trying the preferred index first.
+    Camera(Option<u32>),
+    /// A recorded video file, e.g. MJPEG, looped.
+    File(PathBuf),
+    /// A directory of JPEG frames, played in file name order and looped.
+    Dir(PathBuf),
+}
+
+impl FromStr for VideoSource {
+    type Err = String;
+
+    /// `camera`, `camera:<index>`, `file:<path>`, or `dir:<path>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "camera" => Ok(Self::Camera(None)),
+            Some(("camera", index)) => index
+                .parse()
+                .map(|index| Self::Camera(Some(index)))
+                .map_err(|e| format!("invalid camera index {index:?}: {e}")),
+            Some(("file", path)) if !path.is_empty() => Ok(Self::File(path.into())),
+            Some(("dir", path)) if !path.is_empty() => Ok(Self::Dir(path.into())),
+            _ => Err(format!(
+                "expected camera, camera:<index>, file:<path>, or dir:<path>, got {s:?}"
+            )),
+        }
+    }
+}
+
+/// Which codes the analysis threads look for.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ScanMode {
+    #[default]
+    QrOnly,
+    /// Linear barcodes, e.g. on school ID cards.
+    BarcodeOnly,
+    /// Alternates between QR codes and barcodes each frame, so a frame costs
+    /// no more than either alone.
+    Both,
+}
+
+impl ScanMode {
+    pub const ALL: [Self; 3] = [Self::QrOnly, Self::BarcodeOnly, Self::Both];
+
+    /// Shown in the mode select.
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::QrOnly => "QR codes",
+            Self::BarcodeOnly => "Barcodes",
+            Self::Both => "QR codes and barcodes",
+        }
+    }
+}
+
+impl FromStr for ScanMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "qr" => Ok(Self::QrOnly),
+            "barcode" => Ok(Self::BarcodeOnly),
+            "both" => Ok(Self::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for ScanMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::QrOnly => write!(f, "qr"),
+            Self::BarcodeOnly => write!(f, "barcode"),
+            Self::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// Why a frame could not be read.
+#[derive(Debug)]
+pub enum FrameError {
+    /// The camera or video decoder failed, e.g. the camera was unplugged.
+    Device(String),
+    Io(io::Error),
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Device(e) => write!(f, "{e}"),
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Produces JPEG frames for the frame buffer.
+trait FrameSource {
+    /// Blocks until the next frame is available.
+    fn next_frame(&mut self) -> Result<Cow<'_, [u8]>, FrameError>;
+
+    fn set_resolution(&mut self, resolution: Resolution) -> Result<(), FrameError>;
+
+    /// Resolutions offered for [`Self::set_resolution`], sorted.
+    fn resolutions(&self) -> Vec<Resolution>;
+
+    /// Resolution frames are currently produced at.
+    fn resolution(&self) -> Resolution;
+
+    /// Releases the underlying device.
+    fn stop(&mut self) {}
+}
+
+/// Consecutive failed reads from the main camera, deciding when to reopen
+/// it and when to give up.
+struct FrameFailures {
+    count: u32,
+    max: u32,
+}
+
+impl FrameFailures {
+    fn new(max: u32) -> Self {
+        Self { count: 0, max }
+    }
+
+    fn succeeded(&mut self) {
+        self.count = 0;
+    }
+
+    /// Wait before reopening the camera, `None` once `max` reads in a row
+    /// have failed.
+    fn failed(&mut self) -> Option<Duration> {
+        self.count += 1;
+        (self.count < self.max).then(|| {
+            FRAME_RETRY_BASE
+                .saturating_mul(1 << (self.count - 1).min(16))
+                .min(FRAME_RETRY_MAX)
+        })
+    }
+}
+
+/// Sleeps until `next_frame`, then schedules the frame after it.
+fn pace(next_frame: &mut Instant, frame_interval: Duration) {
+    let now = Instant::now();
+    if *next_frame > now {
+        thread::sleep(*next_frame - now);
+    }
+    *next_frame = (*next_frame).max(now) + frame_interval;
+}
+
+/// An open camera streaming MJPEG.
+struct CameraSource {
+    camera: Camera,
+    /// At the highest frame rate the camera supports.
+    resolutions: Vec<Resolution>,
+}
+
+impl FrameSource for CameraSource {
+    fn next_frame(&mut self) -> Result<Cow<'_, [u8]>, FrameError> {
+        self.camera
+            .frame_raw()
+            .map_err(|e| FrameError::Device(e.to_string()))
+    }
+
+    fn set_resolution(&mut self, resolution: Resolution) -> Result<(), FrameError> {
+        let device = |e: nokhwa::NokhwaError| FrameError::Device(e.to_string());
+        self.camera.stop_stream().map_err(device)?;
+        self.camera.set_resolution(resolution).map_err(device)?;
+        self.camera.open_stream().map_err(device)
+    }
+
+    fn resolutions(&self) -> Vec<Resolution> {
+        self.resolutions.clone()
+    }
+
+    fn resolution(&self) -> Resolution {
+        self.camera.resolution()
+    }
+
+    fn stop(&mut self) {
+        let _ = self.camera.stop_stream();
+    }
+}
+
+/// Plays a video file at its native frame rate, looping at the end.
+struct FileSource {
+    capture: VideoCapture,
+    resolution: Resolution,
+    frame_interval: Duration,
+    next_frame: Instant,
+    frame: Mat,
+    jpeg: Vector<u8>,
+}
+
+impl FileSource {
+    fn open(path: &Path) -> Self {
+        let capture = VideoCapture::from_file(&path.to_string_lossy(), CAP_ANY).unwrap();
+        if !capture.is_opened().unwrap_or(false) {
+            panic!("Failed to open video file {}", path.display());
+        }
+
+        let fps = capture
+            .get(CAP_PROP_FPS)
+            .ok()
+            .filter(|fps| *fps > 0.0)
+            .unwrap_or(DEFAULT_FILE_FPS);
+        let width = capture.get(CAP_PROP_FRAME_WIDTH).unwrap_or(0.0) as u32;
+        let height = capture.get(CAP_PROP_FRAME_HEIGHT).unwrap_or(0.0) as u32;
+
+        Self {
+            capture,
+            resolution: Resolution::new(width, height),
+            frame_interval: Duration::from_secs_f64(1.0 / fps),
+            next_frame: Instant::now(),
+            frame: Mat::default(),
+            jpeg: Vector::new(),
+        }
+    }
+}
+
+impl FrameSource for FileSource {
+    fn next_frame(&mut self) -> Result<Cow<'_, [u8]>, FrameError> {
+        pace(&mut self.next_frame, self.frame_interval);
+        let device = |e: opencv::Error| FrameError::Device(e.to_string());
+
+        if !self.capture.read(&mut self.frame).map_err(device)? || self.frame.empty() {
+            self.capture.set(CAP_PROP_POS_FRAMES, 0.0).map_err(device)?;
+            self.capture.read(&mut self.frame).map_err(device)?;
+        }
+
+        // Encoded to match camera MJPEG frames.
+        imencode_def(".jpg", &self.frame, &mut self.jpeg).map_err(device)?;
+        Ok(Cow::Borrowed(self.jpeg.as_slice()))
+    }
+
+    /// Only the file's own resolution is offered.
+    fn set_resolution(&mut self, _resolution: Resolution) -> Result<(), FrameError> {
+        Ok(())
+    }
+
+    fn resolutions(&self) -> Vec<Resolution> {
+        vec![self.resolution]
+    }
+
+    fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+}
+
+/// Plays a directory of JPEG frames at [`DEFAULT_FILE_FPS`], looping at the
+/// end.
+struct DirSource {
+    frames: Vec<PathBuf>,
+    position: usize,
+    resolution: Resolution,
+    next_frame: Instant,
+}
+
+impl DirSource {
+    fn open(dir: &Path) -> Self {
+        let mut frames: Vec<_> = fs::read_dir(dir)
+            .unwrap_or_else(|e| panic!("Failed to read frame directory {}: {e}", dir.display()))
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        extension.eq_ignore_ascii_case("jpg")
+                            || extension.eq_ignore_ascii_case("jpeg")
+                    })
+            })
+            .collect();
+        frames.sort();
+        if frames.is_empty() {
+            panic!("No JPEG frames in {}", dir.display());
+        }
+
+        let (width, height) = image::image_dimensions(&frames[0]).unwrap_or_default();
+
+        Self {
+            frames,
+            position: 0,
+            resolution: Resolution::new(width, height),
+            next_frame: Instant::now(),
+        }
+    }
+}
+
+impl FrameSource for DirSource {
+    fn next_frame(&mut self) -> Result<Cow<'_, [u8]>, FrameError> {
+        pace(
+            &mut self.next_frame,
+            Duration::from_secs_f64(1.0 / DEFAULT_FILE_FPS),
+        );
+
+        let path = &self.frames[self.position];
+        self.position = (self.position + 1) % self.frames.len();
+        fs::read(path).map(Cow::Owned).map_err(FrameError::Io)
+    }
+
+    /// Only the first frame's resolution is offered.
+    fn set_resolution(&mut self, _resolution: Resolution) -> Result<(), FrameError> {
+        Ok(())
+    }
+
+    fn resolutions(&self) -> Vec<Resolution> {
+        vec![self.resolution]
+    }
+
+    fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+}
+
+/// Last send of each recent read, shared by the analysis threads since each
+/// decodes the same code at its own scale.
+#[derive(Default)]
+struct RecentReads(Mutex<HashMap<String, Instant>>);
+
+impl RecentReads {
+    /// True unless `text` was already sent within [`DEDUPE_WINDOW`].
+    fn first_sighting(&self, text: &str) -> bool {
+        let now = Instant::now();
+        let mut recent = self.0.lock().unwrap();
+        recent.retain(|_, seen| now - *seen < DEDUPE_WINDOW);
+
+        if recent.contains_key(text) {
+            false
+        } else {
+            recent.insert(text.to_string(), now);
+            true
+        }
+    }
+}
+
+/// Outlines of the latest detection, in full resolution pixels.
+#[derive(Default)]
+struct DetectionOverlay(RwLock<Option<(Instant, Vector<Vector<Point>>)>>);
+
+impl DetectionOverlay {
+    /// `corners` from a frame read at `1 / reduction` scale, four per code.
+    fn set(&self, corners: &Vector<Point2f>, reduction: f32) {
+        let corners: Vec<_> = corners
+            .iter()
+            .map(|corner| Point::new((corner.x * reduction) as i32, (corner.y * reduction) as i32))
+            .collect();
+        let quads = corners
+            .chunks_exact(4)
+            .map(|quad| Vector::from(quad.to_vec()))
+            .collect();
+
+        *self.0.write().unwrap() = Some((Instant::now(), quads));
+    }
+
+    /// Outlines detected within [`OVERLAY_TTL`].
+    fn fresh(&self) -> Option<Vector<Vector<Point>>> {
+        self.0
+            .read()
+            .unwrap()
+            .as_ref()
+            .filter(|(detected, _)| detected.elapsed() < OVERLAY_TTL)
+            .map(|(_, quads)| quads.clone())
+    }
+}
+
+/// Draws `quads` onto the JPEG `frame`, re-encoded into `out`.
+fn draw_overlay(
+    frame: &[u8],
+    quads: &Vector<Vector<Point>>,
+    out: &mut Vector<u8>,
+) -> opencv::Result<()> {
+    let mut image = imdecode(&frame, IMREAD_COLOR)?;
+    polylines(
+        &mut image,
+        quads,
+        true,
+        Scalar::new(0.0, 255.0, 0.0, 0.0),
+        OVERLAY_THICKNESS,
+        LINE_AA,
+        0,
+    )?;
+    imencode_def(".jpg", &image, out)?;
+    Ok(())
+}
+
+/// Viewers of the preview stream, each written to from its own thread.
+#[derive(Default)]
+pub struct StreamClients(Mutex<Vec<SyncSender<Arc<[u8]>>>>);
+
+impl StreamClients {
+    pub const fn new() -> Self {
+        Self(Mutex::new(Vec::new()))
+    }
+
+    /// Sends the multipart response header and starts forwarding frames.
+    pub fn add(&self, mut stream: TcpStream) {
+        let peer = stream
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        if let Err(e) = stream.write_all(
+            "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=frame\r\n\r\n"
+                .as_bytes(),
+        ) {
+            warn!(target: "video::stream", peer, "Stream write failed: {e}");
+            return;
+        }
+
+        debug!(target: "video::stream", peer, "Stream client joined");
+        let (packets_tx, packets_rx) = mpsc::sync_channel::<Arc<[u8]>>(STREAM_CLIENT_BACKLOG);
+        thread::spawn(move || {
+            while let Ok(packet) = packets_rx.recv() {
+                if let Err(e) = stream.write_all(&packet) {
+                    debug!(target: "video::stream", peer, "Stream client left: {e}");
+                    return;
+                }
+            }
+        });
+
+        self.0.lock().unwrap().push(packets_tx);
+    }
+
+    fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Disconnects every client.
+    fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    /// Queues `frame` for every client, skipping clients that are behind
+    /// and forgetting ones that left.
+    fn send(&self, frame: &[u8]) {
+        let mut clients = self.0.lock().unwrap();
+        if clients.is_empty() {
+            return;
+        }
+
+        let header = format!(
+            "--frame\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+            frame.len()
+        );
+        let packet: Arc<[u8]> = [header.as_bytes(), frame, b"\r\n"].concat().into();
+
+        clients.retain(|client| match client.try_send(packet.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
+
+/// Frames per second, averaged over [`FRAME_RATE_WINDOW`].
+struct FrameRate {
+    window_start: Instant,
+    frames: u32,
+    fps: f64,
+}
+
+impl Default for FrameRate {
+    fn default() -> Self {
+        Self {
+            window_start: Instant::now(),
+            frames: 0,
+            fps: 0.0,
+        }
+    }
+}
+
+impl FrameRate {
+    fn tick(&mut self) {
+        self.frames += 1;
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= FRAME_RATE_WINDOW {
+            self.fps = f64::from(self.frames) / elapsed.as_secs_f64();
+            self.frames = 0;
+            self.window_start = Instant::now();
+        }
+    }
+
+    /// Zero once frames stop.
+    fn fps(&self) -> f64 {
+        if self.window_start.elapsed() >= 2 * FRAME_RATE_WINDOW {
+            0.0
+        } else {
+            self.fps
+        }
+    }
+}
+
+/// Reported by `GET /health`.
+#[derive(Default)]
+struct VideoStatus {
+    resolution: Mutex<Option<Resolution>>,
+    /// Of frames sent to the stream.
+    frame_rate: Mutex<FrameRate>,
+    buffer: Mutex<BufferStats<FRAME_READERS>>,
+}
+
+/// Running totals from the video threads, for the diagnostics panel. Reset
+/// whenever the resolution or camera changes.
+#[derive(Debug)]
+pub struct PipelineMetrics {
+    frames_captured: AtomicU64,
+    /// Per analysis scale, full scale first.
+    frames_analyzed: [AtomicU64; ANALYSIS_SCALES],
+    /// Frames a code was found in.
+    detections: AtomicU64,
+    /// Codes read out of detections.
+    decodes: AtomicU64,
+    dropped_writes: AtomicU64,
+    /// Frames waiting for the stream, then each analysis scale.
+    buffer_queued: [AtomicUsize; FRAME_READERS],
+    /// Reads waiting for the scan loop.
+    qr_queued: AtomicUsize,
+}
+
+/// One reading of [`PipelineMetrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricsSample {
+    pub frames_captured: u64,
+    pub frames_analyzed: [u64; ANALYSIS_SCALES],
+    pub detections: u64,
+    pub decodes: u64,
+    pub dropped_writes: u64,
+    pub buffer_queued: [usize; FRAME_READERS],
+    pub qr_queued: usize,
+}
+
+impl PipelineMetrics {
+    pub const fn new() -> Self {
+        Self {
+            frames_captured: AtomicU64::new(0),
+            frames_analyzed: [const { AtomicU64::new(0) }; ANALYSIS_SCALES],
+            detections: AtomicU64::new(0),
+            decodes: AtomicU64::new(0),
+            dropped_writes: AtomicU64::new(0),
+            buffer_queued: [const { AtomicUsize::new(0) }; FRAME_READERS],
+            qr_queued: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn sample(&self) -> MetricsSample {
+        MetricsSample {
+            frames_captured: self.frames_captured.load(Ordering::Relaxed),
+            frames_analyzed: self
+                .frames_analyzed
+                .each_ref()
+                .map(|count| count.load(Ordering::Relaxed)),
+            detections: self.detections.load(Ordering::Relaxed),
+            decodes: self.decodes.load(Ordering::Relaxed),
+            dropped_writes: self.dropped_writes.load(Ordering::Relaxed),
+            buffer_queued: self
+                .buffer_queued
+                .each_ref()
+                .map(|queued| queued.load(Ordering::Relaxed)),
+            qr_queued: self.qr_queued.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        self.frames_captured.store(0, Ordering::Relaxed);
+        for count in &self.frames_analyzed {
+            count.store(0, Ordering::Relaxed);
+        }
+        self.detections.store(0, Ordering::Relaxed);
+        self.decodes.store(0, Ordering::Relaxed);
+        self.dropped_writes.store(0, Ordering::Relaxed);
+    }
+
+    fn record_buffer(
+        &self,
+        stats: &BufferStats<FRAME_READERS>,
+        dropped_writes: u64,
+        qr_queued: usize,
+    ) {
+        for (queued, count) in self.buffer_queued.iter().zip(stats.queued) {
+            queued.store(count, Ordering::Relaxed);
+        }
+        self.dropped_writes.store(dropped_writes, Ordering::Relaxed);
+        self.qr_queued.store(qr_queued, Ordering::Relaxed);
+    }
+}
+
+/// [`TcpListener::bind`] with `SO_REUSEADDR` on Unix, so a restart can take
+/// the port back while connections from before the exit are in TIME_WAIT.
+/// Windows lets another process take a port with it, so it is left off.
+fn listen(addr: impl ToSocketAddrs) -> io::Result<TcpListener> {
+    let listen_on = |addr: SocketAddr| -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(128)?;
+        Ok(socket.into())
+    };
+
+    let mut last_error = None;
+    for addr in addr.to_socket_addrs()? {
+        match listen_on(addr) {
+            Ok(listener) => return Ok(listener),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no addresses to bind")))
+}
+
+/// Listens on `bind`, or on a free port of the same host when that fails,
+/// e.g. because another instance has the port. Publishes the address to
+/// [`VIDEO_ADDR`].
+fn bind_stream(bind: &str) -> Option<TcpListener> {
+    let listener = listen(bind).or_else(|e| {
+        let host = bind.rsplit_once(':').map_or(bind, |(host, _port)| host);
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        warn!(target: "video::stream", "Failed to bind {bind}: {e}, using a free port");
+        listen((host, 0))
+    });
+    let listener = listener.and_then(|listener| {
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    });
+
+    let addr = match &listener {
+        Ok(listener) => listener.local_addr().ok(),
+        Err(e) => {
+            error!(target: "video::stream", "Failed to bind the stream to {bind}: {e}");
+            None
+        }
+    };
+    if let Some(addr) = addr {
+        info!(target: "video::stream", "Stream listening on {addr}");
+    }
+    *VIDEO_ADDR.write().unwrap() = addr;
 
-fn get_camera(resolution: Option<Resolution>) -> Camera {
-    // Get first valid camera idx.
-    let mut camera = (0..=u32::MAX)
+    listener.ok()
+}
+
+/// Reads a request head and returns the method and path of its request
+/// line, or the status code to fail with.
+fn read_request(stream: &mut TcpStream) -> Result<(String, String), &'static str> {
+    const BAD_REQUEST: &str = "400 Bad Request";
+
+    stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .map_err(|_| BAD_REQUEST)?;
+
+    let mut request = Vec::new();
+    let mut chunk = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_LEN {
+            return Err("431 Request Header Fields Too Large");
+        }
+
+        match stream.read(&mut chunk) {
+            Ok(0) => return Err(BAD_REQUEST),
+            Ok(read) => request.extend_from_slice(&chunk[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(_) => return Err("408 Request Timeout"),
+        }
+    }
+
+    let line = request
+        .split(|byte| *byte == b'\r')
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .ok_or(BAD_REQUEST)?;
+    match line.split(' ').collect::<Vec<_>>()[..] {
+        [method, path, version] if version.starts_with("HTTP/") => {
+            Ok((method.to_string(), path.to_string()))
+        }
+        _ => Err(BAD_REQUEST),
+    }
+}
+
+/// Writes a complete response and closes the connection.
+fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        debug!(target: "video::stream", "Response write failed: {e}");
+    }
+}
+
+/// Routes one connection: `GET /stream` joins the preview stream,
+/// `GET /health` reports the video pipeline, anything else is a 404.
+fn handle_request(
+    mut stream: TcpStream,
+    stream_clients: &StreamClients,
+    video_status: &VideoStatus,
+    queued_reads: &async_channel::Sender<CameraRead>,
+) {
+    let (method, path) = match read_request(&mut stream) {
+        Ok(request) => request,
+        Err(status) => return respond(stream, status, "text/plain", status),
+    };
+
+    match (method.as_str(), path.as_str()) {
+        ("GET", "/stream") => stream_clients.add(stream),
+        ("GET", "/health") => {
+            let resolution = *video_status.resolution.lock().unwrap();
+            let buffer = *video_status.buffer.lock().unwrap();
+            let body = serde_json::json!({
+                "camera": if CAMERA_CONNECTED.load(Ordering::Relaxed) { "ready" } else { "unavailable" },
+                "error": CAMERA_ERROR.get(),
+                "resolution": resolution.map(|resolution| resolution.to_string()),
+                "fps": video_status.frame_rate.lock().unwrap().fps(),
+                "queue_depth": queued_reads.len(),
+                "stream_clients": stream_clients.len() + API_STREAM_CLIENTS.len(),
+                "buffer": {
+                    // Stream writer first, then analysis by scale.
+                    "queued": buffer.queued,
+                    "dropped_frames": buffer.dropped_writes,
+                },
+            });
+            respond(stream, "200 OK", "application/json", &body.to_string());
+        }
+        _ => respond(stream, "404 Not Found", "text/plain", "404 Not Found"),
+    }
+}
+
+/// Second decode pass with rqrr, for codes OpenCV detects but cannot read,
+/// e.g. on slightly blurred badges. `frame` must be grayscale.
+fn decode_rqrr(frame: &Mat) -> Vec<String> {
+    let Some(image) = frame.data_bytes().ok().and_then(|bytes| {
+        GrayImage::from_raw(frame.cols() as u32, frame.rows() as u32, bytes.to_vec())
+    }) else {
+        return Vec::new();
+    };
+    decode_gray(image)
+}
+
+/// Every non-blank QR code rqrr reads in `image`.
+pub(crate) fn decode_gray(image: GrayImage) -> Vec<String> {
+    PreparedImage::prepare(image)
+        .detect_grids()
+        .into_iter()
+        .filter_map(|grid| grid.decode().ok())
+        .map(|(_meta, content)| content)
+        .filter(|content| !content.trim().is_empty())
+        .collect()
+}
+
+/// `(index, name)` of every camera the platform reports.
+pub fn list_cameras() -> Vec<(u32, String)> {
+    nokhwa::query(ApiBackend::Auto)
+        .unwrap_or_else(|e| {
+            warn!(target: "video::camera", "Failed to list cameras: {e}");
+            Vec::new()
+        })
+        .into_iter()
+        .filter_map(|camera| Some((camera.index().as_index().ok()?, camera.human_name())))
+        .collect()
+}
+
+/// Opens the first usable camera, trying any preferred camera first and
+/// skipping `reserved_index`. `None` when no camera is connected.
+///
+/// Falls back to the lowest resolution when `resolution` is not supported.
+fn get_camera(
+    resolution: Option<Resolution>,
+    preferred_index: Option<u32>,
+    reserved_index: Option<u32>,
+) -> Option<CameraSource> {
+    open_camera(
+        resolution,
+        preferred_index
+            .into_iter()
+            .chain(platform::camera_indices())
+            .filter(|idx| Some(*idx) != reserved_index),
+    )
+}
+
+/// Opens the first usable camera out of `indices`.
+fn open_camera(
+    resolution: Option<Resolution>,
+    indices: impl IntoIterator<Item = u32>,
+) -> Option<CameraSource> {
+    let mut camera = indices
+        .into_iter()
         .flat_map(|idx| {
-            println!("Test camera idx: {idx}");
+            trace!(target: "video::camera", "Test camera idx: {idx}");
             let mut camera = Camera::new(
                 CameraIndex::Index(idx),
                 RequestedFormat::new::<RgbFormat>(RequestedFormatType::AbsoluteHighestFrameRate),
@@ -58,182 +965,514 @@ fn get_camera(resolution: Option<Resolution>) -> Camera {
                 .ok()?;
             Some(camera)
         })
-        .next()
-        .unwrap();
+        .next()?;
 
-    let resolution = resolution.unwrap_or_else(|| {
-        let resolutions = CAMERA_RESOLUTION_LIST.get_or_init(|| {
-            let resolution_pairs: Vec<(_, _)> = camera
-                .compatible_list_by_resolution(FrameFormat::MJPEG)
-                .unwrap()
-                .into_iter()
-                .collect();
-            let max_framerate = resolution_pairs
-                .iter()
-                .flat_map(|(_, framerate)| framerate)
-                .max()
-                .cloned()
-                .unwrap_or(0);
-            let mut resolutions: Box<[_]> = resolution_pairs
-                .into_iter()
-                .filter(|(_resolution, framerate)| framerate.contains(&max_framerate))
-                .map(|(resolution, _framerate)| resolution)
-                .collect();
-            resolutions.sort_unstable();
-            resolutions
-        });
+    let resolution_pairs: Vec<(_, _)> = camera
+        .compatible_list_by_resolution(FrameFormat::MJPEG)
+        .ok()?
+        .into_iter()
+        .collect();
+    let max_framerate = resolution_pairs
+        .iter()
+        .flat_map(|(_, framerate)| framerate)
+        .max()
+        .cloned()
+        .unwrap_or(0);
+    let mut resolutions: Vec<_> = resolution_pairs
+        .into_iter()
+        .filter(|(_resolution, framerate)| framerate.contains(&max_framerate))
+        .map(|(resolution, _framerate)| resolution)
+        .collect();
+    resolutions.sort_unstable();
 
-        *resolutions.iter().min().unwrap_or(&Resolution::default())
-    });
+    let resolution = resolution
+        .filter(|resolution| resolutions.contains(resolution))
+        .or_else(|| resolutions.first().copied())
+        .unwrap_or_default();
 
-    camera.set_resolution(resolution).unwrap();
-    camera.set_frame_format(FrameFormat::MJPEG).unwrap();
-    camera.open_stream().unwrap();
+    camera.set_resolution(resolution).ok()?;
+    camera.set_frame_format(FrameFormat::MJPEG).ok()?;
+    camera.open_stream().ok()?;
 
-    camera
+    Some(CameraSource {
+        camera,
+        resolutions,
+    })
 }
 
+/// Reads the second camera into `frame_write` until shutdown, reopening it
+/// whenever it fails. Never gives up, the main camera is unaffected.
+fn second_camera_routine(
+    index: u32,
+    frame_write: &mut AtomicBufferWriter<'_, TaggedFrame, FRAME_BUFFER_SIZE, FRAME_READERS>,
+) {
+    let _span = info_span!(target: "video::camera", "second_camera", index).entered();
+    let mut missing_logged = false;
+    while !shutdown::requested() {
+        let Some(mut camera) = open_camera(None, [index]) else {
+            if !missing_logged {
+                missing_logged = true;
+                warn!(target: "video::camera", "Second camera {index} not detected, retrying every {}s", CAMERA_RETRY.as_secs());
+            }
+            thread::sleep(CAMERA_RETRY);
+            continue;
+        };
+        missing_logged = false;
+        info!(target: "video::camera", "Second camera loaded");
+
+        while !shutdown::requested() {
+            match camera.next_frame() {
+                Ok(frame) => {
+                    let _ =
+                        frame_write.write_overwrite_with(|slot| slot.fill(SECOND_CAMERA, &frame));
+                }
+                Err(e) => {
+                    warn!(target: "video::camera", "Second camera read failed, reopening in {}s: {e}", CAMERA_RETRY.as_secs());
+                    break;
+                }
+            }
+        }
+        camera.stop();
+        if !shutdown::requested() {
+            thread::sleep(CAMERA_RETRY);
+        }
+    }
+    info!(target: "video::camera", "Second camera released");
+}
+
+/// Reads frames, serves the preview stream, and sends decoded codes to
+/// `qr_reads_tx`. Returns once every thread has stopped on shutdown.
 pub fn video_routine(
-    qr_reads_tx: async_channel::Sender<String>,
+    qr_reads_tx: async_channel::Sender<CameraRead>,
     camera_resolution_select_rx: async_channel::Receiver<Resolution>,
+    camera_select_rx: async_channel::Receiver<u32>,
+    video_bind_rx: async_channel::Receiver<String>,
+    source: VideoSource,
+    options: VideoOptions,
 ) {
+    let VideoOptions {
+        max_frame_failures,
+        qr_fallback,
+        second_camera,
+    } = options;
+    // Only cameras are paired, files and directories replay one recording.
+    let second_camera = second_camera.filter(|_| matches!(source, VideoSource::Camera(_)));
+
+    if let VideoSource::Camera(_) = source
+        && let Err(e) = platform::request_camera_access()
+    {
+        error!(target: "video::camera", "{e}");
+        let _ = CAMERA_ERROR.set(e);
+        return;
+    }
+
     let mut buffer = FrameBuffer::new();
-    let AtomicBufferSplit {
-        write_ptr: mut frame_write,
-        mut read_ptrs,
-    } = buffer.split();
+    let ([mut frame_write, mut second_frame_write], mut read_ptrs) =
+        buffer.split_writers::<FRAME_WRITERS>();
+    if second_camera.is_none() {
+        second_frame_write.close();
+    }
 
     let (frame_streaming, frame_analysis) = read_ptrs.split_first_mut().unwrap();
+    frame_analysis[0].set_lag_policy(LagPolicy::SkipOldest {
+        max_lag: FULL_SCALE_MAX_LAG,
+    });
 
     let flush_qr = AtomicBool::new(false);
+    let recent_reads = RecentReads::default();
+    let overlay = DetectionOverlay::default();
+    let stream_clients = StreamClients::default();
+    let video_status = VideoStatus::default();
 
     thread::scope(|s| {
+        let second_camera_reader = second_camera.map(|index| {
+            s.spawn(move || {
+                second_camera_routine(index, &mut second_frame_write);
+                second_frame_write.close();
+            })
+        });
+
         let camera_reader = s.spawn(|| {
+            let _span = info_span!(target: "video::camera", "camera").entered();
             let mut resolution = None;
+            let mut camera_index = match &source {
+                VideoSource::Camera(index) => *index,
+                VideoSource::File(_) | VideoSource::Dir(_) => None,
+            };
+            let mut missing_logged = false;
+            let mut frame_failures = FrameFailures::new(max_frame_failures);
+            let mut last_stats = Instant::now();
+            // Buffer drops before the last metrics reset.
+            let mut dropped_before_reset = 0;
             'new_camera: loop {
-                let mut camera = get_camera(resolution);
-                println!("Camera Loaded");
+                let mut camera: Box<dyn FrameSource> = match &source {
+                    VideoSource::Camera(_) => match get_camera(resolution, camera_index, second_camera) {
+                        Some(camera) => Box::new(camera),
+                        None => {
+                            if !missing_logged {
+                                missing_logged = true;
+                                warn!(target: "video::camera", "No camera detected, retrying every {}s", CAMERA_RETRY.as_secs());
+                            }
+
+                            // Picked while disconnected, applied once a camera
+                            // appears.
+                            if let Ok(new_resolution) = camera_resolution_select_rx.try_recv() {
+                                resolution = Some(new_resolution);
+                            }
+                            if let Ok(new_index) = camera_select_rx.try_recv() {
+                                camera_index = Some(new_index);
+                            }
+
+                            thread::sleep(CAMERA_RETRY);
+                            if shutdown::requested() {
+                                frame_write.close();
+                                return;
+                            }
+                            continue 'new_camera;
+                        }
+                    },
+                    VideoSource::File(path) => Box::new(FileSource::open(path)),
+                    VideoSource::Dir(path) => Box::new(DirSource::open(path)),
+                };
+                if let Some(resolution) = resolution
+                    && let Err(e) = camera.set_resolution(resolution)
+                {
+                    warn!(target: "video::camera", "Failed to set resolution {resolution}: {e}");
+                }
+                *CAMERA_RESOLUTION_LIST.write().unwrap() = camera.resolutions();
+                *video_status.resolution.lock().unwrap() = Some(camera.resolution());
+                missing_logged = false;
+                CAMERA_CONNECTED.store(true, Ordering::Relaxed);
+                info!(target: "video::camera", "Camera Loaded");
 
                 loop {
+                    if shutdown::requested() {
+                        camera.stop();
+                        drop(camera);
+                        info!(target: "video::camera", "Camera Released");
+                        frame_write.close();
+                        return;
+                    }
+
                     if let Ok(new_resolution) = camera_resolution_select_rx.try_recv() {
                         resolution = Some(new_resolution);
                         flush_qr.store(true, Ordering::Relaxed);
+                        dropped_before_reset = frame_write.dropped_writes();
+                        PIPELINE_METRICS.reset();
+                        // A failed change shows up as a failed read.
+                        if let Err(e) = camera.set_resolution(new_resolution) {
+                            warn!(target: "video::camera", "Failed to set resolution {new_resolution}: {e}");
+                        }
+                        *video_status.resolution.lock().unwrap() = Some(camera.resolution());
+                    }
+
+                    if let Ok(new_index) = camera_select_rx.try_recv()
+                        && let VideoSource::Camera(_) = source
+                    {
+                        camera_index = Some(new_index);
+                        flush_qr.store(true, Ordering::Relaxed);
+                        dropped_before_reset = frame_write.dropped_writes();
+                        PIPELINE_METRICS.reset();
+                        camera.stop();
                         continue 'new_camera;
                     }
 
-                    let frame = camera.frame_raw().unwrap();
-                    // Discard frames whenever readers are behind.
-                    let _ = frame_write.try_write(frame.as_ref());
+                    let frame = match camera.next_frame() {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            CAMERA_CONNECTED.store(false, Ordering::Relaxed);
+                            CAMERA_RESOLUTION_LIST.write().unwrap().clear();
+                            *video_status.resolution.lock().unwrap() = None;
+                            camera.stop();
+
+                            let backoff = frame_failures.failed();
+                            let failures = frame_failures.count;
+                            let Some(backoff) = backoff else {
+                                error!(target: "video::camera", "Camera failed {failures} reads in a row, giving up");
+                                let _ = CAMERA_ERROR.set(format!(
+                                    "Camera stopped working after {failures} failed reads, restart to try again"
+                                ));
+                                drop(camera);
+                                // Stops the stream and analysis, /health keeps
+                                // reporting the error.
+                                frame_write.close();
+                                return;
+                            };
+
+                            warn!(
+                                target: "video::camera",
+                                "Camera read failed ({failures}/{max_frame_failures}), reopening in {}ms: {e}",
+                                backoff.as_millis()
+                            );
+                            thread::sleep(backoff);
+                            continue 'new_camera;
+                        }
+                    };
+                    frame_failures.succeeded();
+                    PIPELINE_METRICS.frames_captured.fetch_add(1, Ordering::Relaxed);
+                    // Readers that are behind skip their oldest frames, so
+                    // the stream catches up to the camera after a stall.
+                    let _ = frame_write
+                        .write_overwrite_with(|slot| slot.fill(PRIMARY_CAMERA, &frame));
+
+                    if last_stats.elapsed() >= BUFFER_STATS_INTERVAL {
+                        last_stats = Instant::now();
+                        let stats = frame_write.stats();
+                        debug!(
+                            target: "video::camera",
+                            queued = ?stats.queued,
+                            dropped = stats.dropped_writes,
+                            "Frame buffer"
+                        );
+                        PIPELINE_METRICS.record_buffer(
+                            &stats,
+                            stats.dropped_writes - dropped_before_reset,
+                            qr_reads_tx.len(),
+                        );
+                        *video_status.buffer.lock().unwrap() = stats;
+                    }
                 }
             }
         });
 
-        let _stream_writer = s.spawn(|| {
-            let mut packet = Vec::new();
-            let mut frame_header_len = 0;
-            let mut cur_frame_len = 0;
+        let overlay = &overlay;
+        let stream_clients = &stream_clients;
+        let video_status = &video_status;
+        // Only read for its queue depth.
+        let queued_reads = qr_reads_tx.clone();
+        let _stream_listener = s.spawn(move || {
+            let _span = info_span!(target: "video::stream", "listener").entered();
+            let mut listener = None;
 
-            'new_stream: loop {
-                let listener = TcpListener::bind(VIDEO_SOCKET).unwrap();
-                let (mut stream, _) = listener.accept().expect("Failed to accept connection");
-
-                stream
-                    .write_all(
-                        "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary=--frame\r\n\r\n"
-                            .as_bytes(),
-                    )
-                    .unwrap();
+            while !shutdown::requested() {
+                if let Ok(new_bind) = video_bind_rx.try_recv() {
+                    // Released first, the new address may reuse the port.
+                    drop(listener.take());
+                    stream_clients.clear();
+                    listener = bind_stream(&new_bind);
+                }
 
-                println!("Stream Loaded");
+                let Some(accepted) = listener.as_ref().map(TcpListener::accept) else {
+                    thread::sleep(ACCEPT_POLL);
+                    continue;
+                };
+                match accepted {
+                    Ok((stream, _)) => {
+                        if let Err(e) = stream.set_nonblocking(false) {
+                            warn!(target: "video::stream", "Stream accept failed: {e}");
+                            continue;
+                        }
 
-                loop {
-                    let frame = frame_streaming.read_spin();
-
-                    // Camera frame size changed.
-                    if frame.len() != cur_frame_len {
-                        cur_frame_len = frame.len();
-                        let frame_header = format!(
-                            "--frame\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
-                            frame.len()
-                        );
-                        frame_header_len = frame_header.len();
-                        packet = frame_header.as_bytes().to_vec();
-                        println!("Updated Frame Size");
+                        // Requests arrive at the client's pace, off the
+                        // accept loop.
+                        let queued_reads = queued_reads.clone();
+                        s.spawn(move || {
+                            handle_request(stream, stream_clients, video_status, &queued_reads)
+                        });
                     }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+                    Err(e) => warn!(target: "video::stream", "Stream accept failed: {e}"),
+                }
+            }
+        });
 
-                    packet.extend_from_slice(&frame);
-                    packet.extend_from_slice(b"\r\n");
-                    if let Err(e) = stream.write_all(&packet) {
-                        // Print errors for diagnostics, but loop for
-                        // reconnects.
-                        eprintln!("{:#?}", e);
-                        continue 'new_stream;
-                    }
+        let _stream_writer = s.spawn(move || {
+            let _span = info_span!(target: "video::stream", "stream").entered();
+            let mut overlay_frame = Vector::new();
+            // Time the last overlay took to draw, and when the last frame
+            // arrived.
+            let mut overlay_cost = Duration::ZERO;
+            let mut last_frame = Instant::now();
 
-                    // Reduce back to just the header
-                    packet.truncate(frame_header_len);
+            loop {
+                // Frames queued behind a stall are skipped, viewers only
+                // want the newest.
+                let raw_frame = if frame_streaming.is_empty() {
+                    frame_streaming.read_spin()
+                } else {
+                    frame_streaming.read_latest()
+                };
+                let Ok(raw_frame) = raw_frame else {
+                    return;
+                };
+                // The second camera is only scanned, not shown.
+                if raw_frame.camera != PRIMARY_CAMERA {
+                    continue;
                 }
+                let raw_frame = &raw_frame.jpeg;
+                let frame_interval = last_frame.elapsed();
+                last_frame = Instant::now();
+
+                // Kept without the overlay, for snapshots.
+                {
+                    let mut latest_frame = LATEST_FRAME.write().unwrap();
+                    latest_frame.clear();
+                    latest_frame.extend_from_slice(raw_frame);
+                }
+
+                let mut frame: &[u8] = raw_frame;
+                if let Some(quads) = overlay.fresh() {
+                    // Drawing slower than frames arrive would back up the
+                    // buffer, so the overlay is skipped instead. The cost
+                    // decays so drawing is retried.
+                    if overlay_cost > frame_interval {
+                        overlay_cost /= 2;
+                    } else {
+                        let start = Instant::now();
+                        match draw_overlay(frame, &quads, &mut overlay_frame) {
+                            Ok(()) => frame = overlay_frame.as_slice(),
+                            Err(e) => warn!(target: "video::stream", "Overlay failed: {e}"),
+                        }
+                        overlay_cost = start.elapsed();
+                    }
+                }
+
+                video_status.frame_rate.lock().unwrap().tick();
+                stream_clients.send(frame);
+                API_STREAM_CLIENTS.send(frame);
             }
         });
 
-        for (scale, frame_reader) in [
-            IMREAD_GRAYSCALE,
-            IMREAD_REDUCED_GRAYSCALE_2,
-            IMREAD_REDUCED_GRAYSCALE_4,
-            IMREAD_REDUCED_GRAYSCALE_8,
+        for (scale_idx, ((scale, reduction), frame_reader)) in [
+            (IMREAD_GRAYSCALE, 1.0),
+            (IMREAD_REDUCED_GRAYSCALE_2, 2.0),
+            (IMREAD_REDUCED_GRAYSCALE_4, 4.0),
+            (IMREAD_REDUCED_GRAYSCALE_8, 8.0),
         ]
         .into_iter()
         .zip(frame_analysis)
+        .enumerate()
         {
             let flush_qr = &flush_qr;
+            let recent_reads = &recent_reads;
             let qr_reads_tx = qr_reads_tx.clone();
             let _analysis = s.spawn(move || {
+                let _span = info_span!(target: "video::analysis", "analysis", scale).entered();
                 let detector = QRCodeDetector::default().unwrap();
+                let barcode_detector = BarcodeDetector::default().unwrap();
+                // Picks the format in ScanMode::Both.
+                let mut frame_count: u64 = 0;
                 let mut decoded_info = Vector::new();
-                let mut points = Mat::default();
+                // Four corners per detected code.
+                let mut points = Vector::<Point2f>::new();
 
-                println!("Analysis Loaded");
+                info!(target: "video::analysis", "Analysis Loaded");
                 loop {
                     // Whenever the resolution changes, flush QR processing.
                     // This prevents an oversized window from lagging up the
                     // pipeline with old instructions.
                     if flush_qr.load(Ordering::Relaxed) {
                         flush_qr.store(false, Ordering::Relaxed);
-                        while frame_reader.try_read().is_some() {}
+                        frame_reader.drain();
                     }
 
-                    let next_frame = frame_reader.read_spin();
+                    // Times out so flushes are seen without waiting on a
+                    // frame.
+                    let next_frame = match frame_reader.read_timeout(ANALYSIS_POLL) {
+                        Ok(next_frame) => next_frame,
+                        Err(ReadError::Empty) => continue,
+                        Err(ReadError::Closed) => return,
+                    };
 
-                    match imdecode(&&**next_frame, scale) {
+                    let camera = next_frame.camera;
+                    let _frame_span =
+                        debug_span!(target: "video::analysis", "frame", camera, frame = frame_count)
+                            .entered();
+                    match imdecode(&&*next_frame.jpeg, scale) {
                         Ok(mat_frame)
                             if mat_frame.size().is_err()
                                 || mat_frame.size().is_ok_and(|size| size == Size::new(0, 0)) =>
                         {
-                            eprintln!("OpenCV error! Empty image!");
+                            warn!(target: "video::analysis", "OpenCV error! Empty image!");
                         }
                         Ok(mat_frame) => {
-                            let detection = detector.detect_multi(&mat_frame, &mut points).unwrap();
-                            if detection {
-                                println!("Trigger: {scale}");
-
-                                detector
-                                    .decode_multi_def(&mat_frame, &points, &mut decoded_info)
-                                    .unwrap();
-                                for text in &decoded_info {
-                                    if !text.trim().is_empty() {
-                                        qr_reads_tx.try_send(text).unwrap();
+                            PIPELINE_METRICS.frames_analyzed[scale_idx].fetch_add(1, Ordering::Relaxed);
+                            frame_count = frame_count.wrapping_add(1);
+                            let read_barcodes = match *SCAN_MODE.read().unwrap() {
+                                ScanMode::QrOnly => false,
+                                ScanMode::BarcodeOnly => true,
+                                ScanMode::Both => frame_count.is_multiple_of(2),
+                            };
+
+                            let texts = if read_barcodes {
+                                let decoded = barcode_detector
+                                    .detect_and_decode_multi_def(&mat_frame, &mut decoded_info)
+                                    .inspect_err(|e| {
+                                        warn!(target: "video::analysis", "OpenCV barcode error: {e}")
+                                    })
+                                    .unwrap_or(false);
+                                if !decoded {
+                                    continue;
+                                }
+                                PIPELINE_METRICS.detections.fetch_add(1, Ordering::Relaxed);
+                                debug!(target: "video::analysis", "Barcode trigger: {scale}");
+
+                                decoded_info
+                                    .iter()
+                                    .filter(|text| !text.trim().is_empty())
+                                    .collect()
+                            } else {
+                                let detection = detector
+                                    .detect_multi(&mat_frame, &mut points)
+                                    .inspect_err(|e| {
+                                        warn!(target: "video::analysis", "OpenCV detect error: {e}")
+                                    })
+                                    .unwrap_or(false);
+                                if !detection {
+                                    continue;
+                                }
+                                PIPELINE_METRICS.detections.fetch_add(1, Ordering::Relaxed);
+                                debug!(target: "video::analysis", "Trigger: {scale}");
+                                // Outlines are drawn on the main camera's
+                                // stream only.
+                                if camera == PRIMARY_CAMERA {
+                                    overlay.set(&points, reduction);
+                                }
+
+                                if let Err(e) = detector.decode_multi_def(
+                                    &mat_frame,
+                                    &points,
+                                    &mut decoded_info,
+                                ) {
+                                    warn!(target: "video::analysis", "OpenCV decode error: {e}");
+                                    continue;
+                                }
+                                let mut texts: Vec<_> = decoded_info
+                                    .iter()
+                                    .filter(|text| !text.trim().is_empty())
+                                    .collect();
+                                if texts.is_empty() && qr_fallback {
+                                    texts = decode_rqrr(&mat_frame);
+                                    if !texts.is_empty() {
+                                        debug!(target: "video::analysis", "rqrr fallback decoded: {scale}");
                                     }
                                 }
+                                texts
+                            };
 
-                                if decoded_info.iter().any(|text| !text.trim().is_empty()) {
-                                    // Flush out remaining frames, they are probably duplicates.
-                                    drop(next_frame);
-                                    while frame_reader.try_read().is_some() {}
+                            PIPELINE_METRICS
+                                .decodes
+                                .fetch_add(texts.len() as u64, Ordering::Relaxed);
+                            // Barcodes share the QR path, matched against the
+                            // roster the same way.
+                            for text in texts.iter().filter(|text| recent_reads.first_sighting(text)) {
+                                let read = CameraRead {
+                                    payload: text.clone(),
+                                    camera,
+                                };
+                                if let Err(e) = qr_reads_tx.try_send(read) {
+                                    warn!(target: "video::analysis", "Dropped QR read: {e}");
                                 }
                             }
+
+                            if !texts.is_empty() {
+                                // Flush out remaining frames, they are probably duplicates.
+                                drop(next_frame);
+                                frame_reader.drain();
+                            }
                         }
                         Err(e) => {
-                            eprintln!("OpenCV read error: {e}");
+                            warn!(target: "video::analysis", "OpenCV read error: {e}");
                         }
                     }
                 }
@@ -241,6 +1480,321 @@ pub fn video_routine(
         }
 
         camera_reader.join().unwrap();
+        if let Some(second_camera_reader) = second_camera_reader {
+            second_camera_reader.join().unwrap();
+        }
         _stream_writer.join().unwrap();
     });
+    info!(target: "video", "Video stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    /// Replays scripted reads, `None` standing for a failed read.
+    struct FlakySource {
+        reads: VecDeque<Option<&'static [u8]>>,
+        stops: usize,
+    }
+
+    impl FrameSource for FlakySource {
+        fn next_frame(&mut self) -> Result<Cow<'_, [u8]>, FrameError> {
+            match self.reads.pop_front() {
+                Some(Some(frame)) => Ok(Cow::Borrowed(frame)),
+                _ => Err(FrameError::Device("unplugged".to_string())),
+            }
+        }
+
+        fn set_resolution(&mut self, _resolution: Resolution) -> Result<(), FrameError> {
+            Ok(())
+        }
+
+        fn resolutions(&self) -> Vec<Resolution> {
+            Vec::new()
+        }
+
+        fn resolution(&self) -> Resolution {
+            Resolution::new(0, 0)
+        }
+
+        fn stop(&mut self) {
+            self.stops += 1;
+        }
+    }
+
+    /// What the camera reader does with each read, until it gives up or the
+    /// script runs out.
+    #[derive(Debug, PartialEq, Eq)]
+    enum Step {
+        Frame(Vec<u8>),
+        Reopen(Duration),
+        GiveUp,
+    }
+
+    fn read_all(source: &mut FlakySource, max_failures: u32) -> Vec<Step> {
+        let mut failures = FrameFailures::new(max_failures);
+        let mut steps = Vec::new();
+        while !source.reads.is_empty() {
+            match source.next_frame() {
+                Ok(frame) => {
+                    failures.succeeded();
+                    steps.push(Step::Frame(frame.into_owned()));
+                }
+                Err(_) => {
+                    source.stop();
+                    match failures.failed() {
+                        Some(backoff) => steps.push(Step::Reopen(backoff)),
+                        None => {
+                            steps.push(Step::GiveUp);
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        steps
+    }
+
+    #[test]
+    fn reopens_with_backoff_after_failures() {
+        let mut source = FlakySource {
+            reads: VecDeque::from([Some(&b"a"[..]), None, None, Some(b"b"), None, Some(b"c")]),
+            stops: 0,
+        };
+
+        assert_eq!(
+            read_all(&mut source, 3),
+            [
+                Step::Frame(b"a".to_vec()),
+                Step::Reopen(FRAME_RETRY_BASE),
+                Step::Reopen(FRAME_RETRY_BASE * 2),
+                Step::Frame(b"b".to_vec()),
+                // A good frame resets the count.
+                Step::Reopen(FRAME_RETRY_BASE),
+                Step::Frame(b"c".to_vec()),
+            ]
+        );
+        assert_eq!(source.stops, 3);
+    }
+
+    #[test]
+    fn gives_up_after_max_failures() {
+        let mut source = FlakySource {
+            reads: VecDeque::from([Some(&b"a"[..]), None, None, None, Some(b"b")]),
+            stops: 0,
+        };
+
+        assert_eq!(
+            read_all(&mut source, 3),
+            [
+                Step::Frame(b"a".to_vec()),
+                Step::Reopen(FRAME_RETRY_BASE),
+                Step::Reopen(FRAME_RETRY_BASE * 2),
+                Step::GiveUp,
+            ]
+        );
+        assert_eq!(source.reads.len(), 1);
+    }
+
+    #[test]
+    fn backoff_capped() {
+        let mut failures = FrameFailures::new(u32::MAX);
+        let backoffs: Vec<_> = (0..40).map(|_| failures.failed().unwrap()).collect();
+        assert!(backoffs.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(backoffs.last(), Some(&FRAME_RETRY_MAX));
+    }
+
+    #[test]
+    fn sources_parsed() {
+        assert_eq!("camera".parse(), Ok(VideoSource::Camera(None)));
+        assert_eq!("camera:2".parse(), Ok(VideoSource::Camera(Some(2))));
+        assert_eq!(
+            "file:clips/a:b.avi".parse(),
+            Ok(VideoSource::File("clips/a:b.avi".into()))
+        );
+        assert_eq!("dir:frames".parse(), Ok(VideoSource::Dir("frames".into())));
+        for invalid in ["", "camera:front", "file:", "dir:", "usb:0"] {
+            assert!(invalid.parse::<VideoSource>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn dir_frames_looped_in_name_order() {
+        let dir = std::env::temp_dir().join(format!("qr-tracker-frames-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut jpeg = Vec::new();
+        image::GrayImage::new(8, 6)
+            .write_to(&mut io::Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+            .unwrap();
+        fs::write(dir.join("1.jpg"), &jpeg).unwrap();
+        fs::write(dir.join("0.JPEG"), b"first").unwrap();
+        fs::write(dir.join("notes.txt"), b"skipped").unwrap();
+
+        let mut source = DirSource::open(&dir);
+        let frames: Vec<_> = (0..3)
+            .map(|_| source.next_frame().unwrap().into_owned())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(frames, [b"first".to_vec(), jpeg.clone(), b"first".to_vec()]);
+        // Sized by the first frame, which is not a readable image here.
+        assert_eq!(source.resolutions(), [Resolution::new(0, 0)]);
+    }
+
+    /// Requests `/stream` from a fresh server connection handed to `clients`.
+    fn stream_client(clients: &'static StreamClients) -> io::BufReader<TcpStream> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(b"GET /stream HTTP/1.1\r\n\r\n").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let (server, _) = listener.accept().unwrap();
+        let (queued_reads, _) = async_channel::unbounded();
+        handle_request(server, clients, &VideoStatus::default(), &queued_reads);
+        io::BufReader::new(client)
+    }
+
+    /// Header lines up to the blank line ending them.
+    fn read_head(reader: &mut io::BufReader<TcpStream>) -> Vec<String> {
+        use std::io::BufRead;
+
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                return lines;
+            }
+            lines.push(line.trim_end().to_string());
+        }
+    }
+
+    /// The body of the next part, checking it opens with `--<boundary>`.
+    fn read_part(reader: &mut io::BufReader<TcpStream>, boundary: &str) -> Vec<u8> {
+        let head = read_head(reader);
+        assert_eq!(head[0], format!("--{boundary}"));
+        assert!(head.contains(&"Content-Type: image/jpeg".to_string()));
+        let length: usize = head
+            .iter()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let mut body = vec![0; length + 2];
+        reader.read_exact(&mut body).unwrap();
+        assert!(body.ends_with(b"\r\n"));
+        body.truncate(length);
+        body
+    }
+
+    #[test]
+    fn stream_fans_out_to_every_client() {
+        static CLIENTS: StreamClients = StreamClients::new();
+        let mut readers = [stream_client(&CLIENTS), stream_client(&CLIENTS)];
+        assert_eq!(CLIENTS.len(), 2);
+
+        let boundaries: Vec<_> = readers
+            .iter_mut()
+            .map(|reader| {
+                let head = read_head(reader);
+                assert_eq!(head[0], "HTTP/1.1 200 OK");
+                head.iter()
+                    .find_map(|line| {
+                        line.strip_prefix("Content-Type: multipart/x-mixed-replace; boundary=")
+                    })
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+
+        for frame in [&b"first frame"[..], b"second"] {
+            CLIENTS.send(frame);
+            for (reader, boundary) in readers.iter_mut().zip(&boundaries) {
+                assert_eq!(read_part(reader, boundary), frame);
+            }
+        }
+
+        drop(readers);
+        // Noticed on the next failed write.
+        let started = Instant::now();
+        while CLIENTS.len() > 0 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            CLIENTS.send(b"after");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Decode rate over `tests/clips/alice.jpg` at every analysis scale and
+    /// increasing blur, with and without the rqrr fallback. Run with
+    /// `cargo test --release qr_fallback_decode_rate -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark, needs OpenCV"]
+    fn qr_fallback_decode_rate() {
+        let clip = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/clips/alice.jpg");
+        let detector = QRCodeDetector::default().unwrap();
+        let mut points = Vector::<Point2f>::new();
+        let mut decoded_info = Vector::<String>::new();
+
+        let mut frames = 0;
+        let (mut opencv, mut fallback) = (0, 0);
+        let started = Instant::now();
+        for scale in [
+            IMREAD_GRAYSCALE,
+            IMREAD_REDUCED_GRAYSCALE_2,
+            IMREAD_REDUCED_GRAYSCALE_4,
+            IMREAD_REDUCED_GRAYSCALE_8,
+        ] {
+            let frame = imread(clip, scale).unwrap();
+            for kernel in [1, 3, 5, 7, 9, 11, 13, 15] {
+                let mut blurred = Mat::default();
+                gaussian_blur_def(&frame, &mut blurred, Size::new(kernel, kernel), 0.0).unwrap();
+                frames += 1;
+
+                // As in the analysis threads, rqrr only retries detections.
+                if !detector.detect_multi(&blurred, &mut points).unwrap() {
+                    continue;
+                }
+                detector
+                    .decode_multi_def(&blurred, &points, &mut decoded_info)
+                    .unwrap();
+                if decoded_info.iter().any(|text| !text.trim().is_empty()) {
+                    opencv += 1;
+                    fallback += 1;
+                } else if !decode_rqrr(&blurred).is_empty() {
+                    fallback += 1;
+                }
+            }
+        }
+
+        println!(
+            "{frames} frames in {:?}: OpenCV decoded {opencv}, with the rqrr fallback {fallback}",
+            started.elapsed()
+        );
+        assert!(fallback >= opencv);
+    }
+
+    #[test]
+    fn stalled_client_skipped() {
+        static CLIENTS: StreamClients = StreamClients::new();
+        let _stalled = stream_client(&CLIENTS);
+        let mut reader = stream_client(&CLIENTS);
+        assert_eq!(read_head(&mut reader).len(), 2);
+
+        // Far more than the stalled client's socket buffers hold.
+        let frame = vec![7; 256 * 1024];
+        let started = Instant::now();
+        for _ in 0..64 {
+            CLIENTS.send(&frame);
+            assert_eq!(read_part(&mut reader, "frame"), frame);
+        }
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(CLIENTS.len(), 2);
+    }
 }
@@ -0,0 +1,202 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The HTTP/1.1 side of the [`api`](crate::api) server: reading a request
+//! and writing the response. Each connection serves one request.
+
+use std::io;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Take},
+    net::tcp::{ReadHalf, WriteHalf},
+};
+
+/// Requests with a longer head are cut off.
+const MAX_HEAD_LEN: u64 = 8 * 1024;
+/// Larger request bodies are refused.
+const MAX_BODY_LEN: usize = 4 * 1024;
+
+pub type RequestReader<'a> = BufReader<Take<ReadHalf<'a>>>;
+
+/// The request line and the headers the API uses.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Head {
+    pub method: String,
+    pub path: String,
+    /// Everything after `?`, still percent-encoded.
+    pub query: String,
+    /// From an `Authorization: Bearer` header.
+    pub bearer: Option<String>,
+    /// `usize::MAX` when unreadable, so the body is refused.
+    pub content_length: usize,
+}
+
+impl Head {
+    /// Reads up to the blank line ending the head.
+    pub async fn read(read: ReadHalf<'_>) -> io::Result<(Self, RequestReader<'_>)> {
+        let mut reader = BufReader::new(read.take(MAX_HEAD_LEN));
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut head = Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            ..Self::default()
+        };
+
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+                break;
+            }
+
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("authorization") {
+                    head.bearer = value.trim().strip_prefix("Bearer ").map(str::to_string);
+                } else if name.eq_ignore_ascii_case("content-length") {
+                    head.content_length = value.trim().parse().unwrap_or(usize::MAX);
+                }
+            }
+        }
+
+        Ok((head, reader))
+    }
+}
+
+/// The request body, `None` when over [`MAX_BODY_LEN`].
+pub async fn read_body(
+    reader: &mut RequestReader<'_>,
+    content_length: usize,
+) -> io::Result<Option<Vec<u8>>> {
+    if content_length > MAX_BODY_LEN {
+        return Ok(None);
+    }
+    // The head limit does not apply to the body.
+    reader.get_mut().set_limit(content_length as u64);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
+/// Writes the whole response and closes the connection. `headers` are
+/// extra header lines, each ending in `\r\n`.
+pub async fn respond(
+    write: &mut WriteHalf<'_>,
+    status: &str,
+    content_type: &str,
+    headers: &str,
+    body: &str,
+) -> io::Result<()> {
+    write
+        .write_all(
+            format!(
+                "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n{headers}Connection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await?;
+    write.shutdown().await
+}
+
+/// `%XX` escapes in a URL path segment decoded, `None` when malformed.
+pub fn percent_decode(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Decoded value of `key` in a `key=value&...` query string.
+pub fn query_param(query: &str, key: &str) -> Option<String> {
+    form_urlencoded::parse(query.as_bytes())
+        .find(|(name, _)| name == key)
+        .map(|(_, value)| value.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_head_and_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        client
+            .write_all(
+                b"POST /api/scan?x=1 HTTP/1.1\r\nauthorization: Bearer secret\r\nContent-Length: 4\r\n\r\nbody",
+            )
+            .await
+            .unwrap();
+
+        let (mut server, _) = listener.accept().await.unwrap();
+        let (read, _) = server.split();
+        let (head, mut reader) = Head::read(read).await.unwrap();
+        assert_eq!(
+            head,
+            Head {
+                method: "POST".to_string(),
+                path: "/api/scan".to_string(),
+                query: "x=1".to_string(),
+                bearer: Some("secret".to_string()),
+                content_length: 4,
+            }
+        );
+        assert_eq!(
+            read_body(&mut reader, head.content_length).await.unwrap(),
+            Some(b"body".to_vec())
+        );
+        assert_eq!(
+            read_body(&mut reader, MAX_BODY_LEN + 1).await.unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn decodes_path_segments() {
+        assert_eq!(
+            percent_decode("Ada%20Lovelace").as_deref(),
+            Some("Ada Lovelace")
+        );
+        assert_eq!(percent_decode("Z%C3%B6e").as_deref(), Some("Zöe"));
+        assert_eq!(percent_decode("bad%2"), None);
+        assert_eq!(percent_decode("bad%zz"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn finds_query_params() {
+        assert_eq!(
+            query_param("since=5&token=abc", "token").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            query_param("token=a%2Bb%2Fc%3D", "token").as_deref(),
+            Some("a+b/c=")
+        );
+        assert_eq!(query_param("token=a+b", "token").as_deref(), Some("a b"));
+        assert_eq!(query_param("since=5&token", "token").as_deref(), Some(""));
+        assert_eq!(query_param("", "token"), None);
+    }
+}
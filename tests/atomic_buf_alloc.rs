@@ -0,0 +1,97 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Frames written with [`AtomicBufferWriter::write_with`] reuse the slot's
+//! allocation, so a steady stream of frames allocates nothing.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use qr_tracker::atomic_buf::{AtomicBuffer, AtomicBufferSplit, AtomicBufferWriter};
+
+/// Counts allocations made on threads that opted in, so the test harness
+/// does not add to the count.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+}
+
+fn count() {
+    if COUNTING.with(Cell::get) {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// SAFETY: forwards to the system allocator.
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+const N: usize = 8;
+
+fn write_frame(writer: &mut AtomicBufferWriter<'_, Vec<u8>, N, 1>, frame: &[u8]) {
+    assert!(writer.write_with(|slot| {
+        slot.clear();
+        slot.extend_from_slice(frame);
+    }));
+}
+
+#[test]
+fn steady_state_frames_do_not_allocate() {
+    let mut buffer = AtomicBuffer::<Vec<u8>, N, 1>::new();
+    let AtomicBufferSplit {
+        write_ptr: mut writer,
+        read_ptrs: [mut reader],
+    } = buffer.split();
+    // Sized like a small JPEG, varying so slots see shorter and longer
+    // frames.
+    let frames: Vec<Vec<u8>> = (0..4_u8)
+        .map(|idx| vec![idx; 60_000 + usize::from(idx) * 100])
+        .collect();
+
+    // Grows every slot to the largest frame.
+    for _ in 0..N {
+        write_frame(&mut writer, frames.last().unwrap());
+        drop(reader.try_read().unwrap());
+    }
+
+    COUNTING.with(|counting| counting.set(true));
+    let mut read_bytes = 0;
+    for frame in frames.iter().cycle().take(10_000) {
+        write_frame(&mut writer, frame);
+        read_bytes += reader.try_read().unwrap().len();
+    }
+    COUNTING.with(|counting| counting.set(false));
+
+    assert!(read_bytes > 0);
+    assert_eq!(ALLOCATIONS.load(Ordering::Relaxed), 0);
+}
@@ -0,0 +1,461 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Subcommands and headless mode run against a temporary database, without
+//! the GUI.
+
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+use chrono::{Local, TimeDelta};
+use rusqlite::Connection;
+
+/// A data directory, config, and database of its own, removed on drop.
+struct Kiosk {
+    dir: PathBuf,
+}
+
+impl Kiosk {
+    fn new(name: &str) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("qr-tracker-cli-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("config.toml"), "").unwrap();
+        Self { dir }
+    }
+
+    fn database(&self) -> PathBuf {
+        self.dir.join("qr-tracker.db")
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_qr-tracker"));
+        command
+            .arg("--config")
+            .arg(self.dir.join("config.toml"))
+            .arg("--database")
+            .arg(self.database())
+            .args(args)
+            .env("QR_TRACKER_DATA_DIR", &self.dir)
+            .env("XDG_RUNTIME_DIR", &self.dir)
+            .env_remove("QR_TRACKER_LOG");
+        command
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    /// A video source showing nothing to scan.
+    fn blank_frames(&self) -> String {
+        let frames = self.dir.join("frames");
+        fs::create_dir(&frames).unwrap();
+        image::GrayImage::new(64, 64)
+            .save(frames.join("0.jpg"))
+            .unwrap();
+        format!("dir:{}", frames.display())
+    }
+
+    /// Scans `name` at each of `times`, bypassing the kiosk.
+    fn scan(&self, name: &str, times: &[i64]) {
+        let conn = Connection::open(self.database()).unwrap();
+        for time in times {
+            conn.execute(
+                "INSERT INTO attendance (name, timestamp) VALUES (?1, ?2);",
+                (name, time),
+            )
+            .unwrap();
+        }
+    }
+}
+
+impl Drop for Kiosk {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn roster_add_is_idempotent() {
+    let kiosk = Kiosk::new("roster-add");
+    let added = kiosk.run(&["roster", "add", "--student", "Alice"]);
+    assert!(added.status.success(), "{}", stderr(&added));
+
+    let again = kiosk.run(&["roster", "add", "--student", "Alice"]);
+    assert!(again.status.success());
+    assert!(stderr(&again).contains("Already on the roster"));
+
+    let missing_role = kiosk.run(&["roster", "add", "Alice"]);
+    assert!(!missing_role.status.success());
+}
+
+#[test]
+fn roster_import_reports_failures() {
+    let kiosk = Kiosk::new("roster-import");
+    let csv = kiosk.dir.join("roster.csv");
+    fs::write(&csv, "name,type\nAlice,student\nBob,mentor\n").unwrap();
+
+    let imported = kiosk.run(&["roster", "import", csv.to_str().unwrap()]);
+    assert!(imported.status.success(), "{}", stderr(&imported));
+    assert!(!stdout(&imported).is_empty());
+
+    let missing = kiosk.run(&["roster", "import", "no-such-roster.csv"]);
+    assert!(!missing.status.success());
+    assert!(stderr(&missing).contains("Import of no-such-roster.csv failed"));
+}
+
+#[test]
+fn export_writes_csv() {
+    let kiosk = Kiosk::new("export");
+    assert!(
+        kiosk
+            .run(&["roster", "add", "--student", "Alice"])
+            .status
+            .success()
+    );
+    let now = Local::now();
+    kiosk.scan(
+        "Alice",
+        &[(now - TimeDelta::hours(2)).timestamp(), now.timestamp()],
+    );
+
+    let out = kiosk.dir.join("week.csv");
+    let exported = kiosk.run(&["export", "--out", out.to_str().unwrap()]);
+    assert!(exported.status.success(), "{}", stderr(&exported));
+    assert!(stderr(&exported).contains("Exported 2 scans"));
+
+    let csv = fs::read_to_string(&out).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(lines.next(), Some("name,timestamp,type"));
+    assert_eq!(lines.filter(|line| line.starts_with("Alice,")).count(), 2);
+
+    let unwritable = kiosk.run(&["export", "--out", "/no-such-dir/week.csv"]);
+    assert!(!unwritable.status.success());
+    assert!(stderr(&unwritable).contains("Failed to create"));
+}
+
+#[test]
+fn report_hours_lists_people() {
+    let kiosk = Kiosk::new("report");
+    assert!(
+        kiosk
+            .run(&["roster", "add", "--mentor", "Bob"])
+            .status
+            .success()
+    );
+    let now = Local::now();
+    kiosk.scan(
+        "Bob",
+        &[(now - TimeDelta::minutes(90)).timestamp(), now.timestamp()],
+    );
+
+    let report = kiosk.run(&["report", "hours", "--season"]);
+    assert!(report.status.success(), "{}", stderr(&report));
+    assert_eq!(stdout(&report), "Bob\t1.50\n");
+
+    let conflicting = kiosk.run(&["report", "hours", "--season", "--since", "2024-01-01"]);
+    assert!(!conflicting.status.success());
+}
+
+#[test]
+fn unreadable_database_fails() {
+    let kiosk = Kiosk::new("bad-db");
+    fs::write(kiosk.database(), "not a database").unwrap();
+
+    let report = kiosk.run(&["report", "hours"]);
+    assert!(!report.status.success());
+    assert!(!stderr(&report).is_empty());
+    // Left for the user to inspect.
+    assert_eq!(fs::read(kiosk.database()).unwrap(), b"not a database");
+}
+
+/// A headless kiosk, killed on drop if still running.
+#[cfg(unix)]
+struct Headless(std::process::Child);
+
+#[cfg(unix)]
+impl Headless {
+    /// Returns once the kiosk is running with video from `source`.
+    fn start(kiosk: &Kiosk, source: &str) -> Self {
+        use std::{
+            io::{BufRead, BufReader},
+            process::Stdio,
+            sync::mpsc,
+            thread,
+            time::{Duration, Instant},
+        };
+
+        let mut child = kiosk
+            .command(&[
+                "--headless",
+                "--source",
+                source,
+                "--video-bind",
+                "127.0.0.1:0",
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let (lines_tx, lines_rx) = mpsc::channel();
+        let stderr = child.stderr.take().unwrap();
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                let _ = lines_tx.send(line);
+            }
+        });
+        let headless = Self(child);
+        let started = Instant::now();
+        loop {
+            let line = lines_rx
+                .recv_timeout(Duration::from_secs(30).saturating_sub(started.elapsed()))
+                .expect("headless kiosk did not start");
+            if line.contains("Running headless") {
+                return headless;
+            }
+        }
+    }
+
+    /// Sends SIGTERM and waits for the kiosk to exit.
+    fn terminate(&mut self) -> std::process::ExitStatus {
+        use std::{
+            thread,
+            time::{Duration, Instant},
+        };
+
+        let killed = Command::new("kill")
+            .args(["-TERM", &self.0.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(killed.success());
+
+        let stopping = Instant::now();
+        loop {
+            if let Some(status) = self.0.try_wait().unwrap() {
+                return status;
+            }
+            assert!(
+                stopping.elapsed() < Duration::from_secs(15),
+                "no exit after SIGTERM"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Headless {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn sigterm_stops_headless_cleanly() {
+    let kiosk = Kiosk::new("sigterm");
+    assert!(
+        kiosk
+            .run(&["roster", "add", "--student", "Alice"])
+            .status
+            .success()
+    );
+    let now = Local::now();
+    kiosk.scan(
+        "Alice",
+        &[(now - TimeDelta::hours(1)).timestamp(), now.timestamp()],
+    );
+
+    let status = Headless::start(&kiosk, &kiosk.blank_frames()).terminate();
+    assert!(status.success(), "{status}");
+
+    // Checkpointed, so everything is in the database file itself.
+    let wal = kiosk.dir.join("qr-tracker.db-wal");
+    assert!(!wal.exists() || fs::metadata(&wal).unwrap().len() == 0);
+    let conn = Connection::open(kiosk.database()).unwrap();
+    let integrity: String = conn
+        .query_row("PRAGMA integrity_check;", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(integrity, "ok");
+    let scans: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM attendance WHERE name = 'Alice';",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(scans, 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn ctl_drives_headless_kiosk() {
+    use std::{
+        os::unix::fs::PermissionsExt,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use serde_json::Value;
+
+    let kiosk = Kiosk::new("ctl");
+    for (role, name) in [("--student", "Alice"), ("--mentor", "Bob")] {
+        assert!(kiosk.run(&["roster", "add", role, name]).status.success());
+    }
+    let mut headless = Headless::start(&kiosk, &kiosk.blank_frames());
+
+    let socket = kiosk.dir.join("qr-tracker.sock");
+    let waiting = Instant::now();
+    while !socket.exists() {
+        assert!(
+            waiting.elapsed() < Duration::from_secs(10),
+            "no control socket"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+    let mode = fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let ctl = |args: &[&str]| {
+        let output = kiosk.run(&[&["ctl"], args].concat());
+        let response: Value = serde_json::from_str(&stdout(&output)).unwrap();
+        (output.status.success(), response)
+    };
+    let names = |present: &Value, role: &str| -> Vec<String> {
+        present[role]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["name"].as_str().unwrap().to_string())
+            .collect()
+    };
+
+    let (ok, present) = ctl(&["present"]);
+    assert!(ok, "{present}");
+    assert!(names(&present, "students").is_empty());
+
+    for name in ["Alice", "Bob"] {
+        let (ok, scanned) = ctl(&["scan", name]);
+        assert!(ok, "{scanned}");
+        assert_eq!(scanned["action"], "ADDED");
+        assert_eq!(scanned["name"], name);
+    }
+    let (_, unknown) = ctl(&["scan", "Mallory"]);
+    assert_eq!(unknown["action"], "REJECTED");
+
+    let (_, present) = ctl(&["present"]);
+    assert_eq!(names(&present, "students"), ["Alice"]);
+    assert_eq!(names(&present, "mentors"), ["Bob"]);
+
+    // Scans are stored to the second, signing out in the same second as
+    // signing in would collide.
+    thread::sleep(Duration::from_millis(1100));
+    let (ok, checked_out) = ctl(&["checkout-all"]);
+    assert!(ok, "{checked_out}");
+    assert_eq!(checked_out["checked_out"], 2);
+    let (_, present) = ctl(&["present"]);
+    assert!(names(&present, "students").is_empty());
+    assert!(names(&present, "mentors").is_empty());
+
+    let out = kiosk.dir.join("scans.csv");
+    let (ok, exported) = ctl(&["export", out.to_str().unwrap()]);
+    assert!(ok, "{exported}");
+    assert_eq!(exported["exported"], 4);
+    assert_eq!(fs::read_to_string(&out).unwrap().lines().count(), 5);
+
+    let status = headless.terminate();
+    assert!(status.success(), "{status}");
+
+    // Nothing listening once the kiosk exits.
+    let (ok, refused) = {
+        let output = kiosk.run(&["ctl", "present"]);
+        (output.status.success(), stderr(&output))
+    };
+    assert!(!ok);
+    assert!(refused.contains("Failed to reach the kiosk"));
+}
+
+/// Runs a headless kiosk on `source` until it records a scan, which must be
+/// Alice's.
+#[cfg(unix)]
+fn assert_scans_alice(kiosk: &Kiosk, source: &str) {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    assert!(
+        kiosk
+            .run(&["roster", "add", "--student", "Alice"])
+            .status
+            .success()
+    );
+    let mut headless = Headless::start(kiosk, source);
+
+    let conn = Connection::open(kiosk.database()).unwrap();
+    let first_scan = || {
+        conn.query_row(
+            "SELECT name FROM attendance ORDER BY timestamp LIMIT 1;",
+            [],
+            |row| row.get::<_, String>(0),
+        )
+        .ok()
+    };
+    let waiting = Instant::now();
+    let scanned = loop {
+        if let Some(name) = first_scan() {
+            break name;
+        }
+        assert!(
+            waiting.elapsed() < Duration::from_secs(30),
+            "badge never scanned"
+        );
+        thread::sleep(Duration::from_millis(100));
+    };
+    assert_eq!(scanned, "Alice");
+
+    let status = headless.terminate();
+    assert!(status.success(), "{status}");
+}
+
+/// `tests/clips/alice.avi` is an MJPEG clip of a blank second, then two seconds
+/// of a badge reading "Alice".
+#[cfg(unix)]
+#[test]
+fn recorded_badge_scanned() {
+    let kiosk = Kiosk::new("clip");
+    let clip = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/clips/alice.avi");
+    assert_scans_alice(&kiosk, &format!("file:{clip}"));
+}
+
+/// `tests/clips/alice.jpg` is a single frame of the same badge.
+#[cfg(unix)]
+#[test]
+fn badge_frames_scanned() {
+    let kiosk = Kiosk::new("frames");
+    let frames = kiosk.dir.join("frames");
+    fs::create_dir(&frames).unwrap();
+    fs::copy(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/clips/alice.jpg"),
+        frames.join("0.jpg"),
+    )
+    .unwrap();
+    assert_scans_alice(&kiosk, &format!("dir:{}", frames.display()));
+}